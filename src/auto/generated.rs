@@ -67,6 +67,8 @@ impl Axecutor {
             Not => self.mnemonic_not(i),
             Pop => self.mnemonic_pop(i),
             Push => self.mnemonic_push(i),
+            Rdtsc => self.mnemonic_rdtsc(i),
+            Rdtscp => self.mnemonic_rdtscp(i),
             Ret => self.mnemonic_ret(i),
             Setb => self.mnemonic_setb(i),
             Sete => self.mnemonic_sete(i),
@@ -144,6 +146,8 @@ pub enum SupportedMnemonic {
     Not = 466,
     Pop = 590,
     Push = 640,
+    Rdtsc = 659,
+    Rdtscp = 660,
     Ret = 662,
     Setb = 688,
     Sete = 690,
@@ -222,6 +226,8 @@ impl TryFrom<Mnemonic> for SupportedMnemonic {
             Not => SupportedMnemonic::Not,
             Pop => SupportedMnemonic::Pop,
             Push => SupportedMnemonic::Push,
+            Rdtsc => SupportedMnemonic::Rdtsc,
+            Rdtscp => SupportedMnemonic::Rdtscp,
             Ret => SupportedMnemonic::Ret,
             Setb => SupportedMnemonic::Setb,
             Sete => SupportedMnemonic::Sete,
@@ -1,17 +1,34 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+use crate::elf::dwarf::DebugInfo;
+use crate::elf::elf::RelroState;
+use crate::elf::symbols::{Symbol, SymbolBinding, SymbolTable, SymbolType};
+use crate::helpers::coverage::Coverage;
 use crate::helpers::debug::debug_log;
+use crate::helpers::fuzz::Fuzzer;
+use crate::helpers::instruction_trace::InstructionTrace;
+use crate::helpers::profile::Profile;
+use crate::helpers::signals::SignalState;
+use crate::helpers::stack::UnwindInfo;
+use crate::helpers::strace::SyscallLogEntry;
 use crate::helpers::syscalls::SyscallState;
 use crate::state::flags::FLAG_TO_NAMES;
 
 use crate::helpers::errors::AxError;
 use crate::helpers::trace::{TraceEntry, TraceVariant};
 use crate::state::hooks::HookProcessor;
-use crate::state::memory::{MemoryArea, PROT_EXEC, PROT_READ};
+use crate::state::memory::{deserialize_memory_areas, MemoryArea, PROT_EXEC, PROT_READ};
 use crate::state::registers::{randomized_register_set, randomized_xmm_set, SupportedRegister};
+use crate::state::replay::ReplayState;
+use crate::state::reverse::Checkpoints;
+use crate::state::sanitizer::Sanitizer;
+use crate::state::shared_log::SharedLog;
+use crate::state::snapshots::Snapshots;
+use crate::state::taint::TaintState;
 
 extern crate console_error_panic_hook;
 
@@ -41,12 +58,46 @@ pub struct Axecutor {
     pub(crate) hooks: HookProcessor,
 
     #[serde(skip)]
-    pub(crate) symbol_table: HashMap<u64, String>,
+    pub(crate) symbol_table: SymbolTable,
+
+    #[serde(skip)]
+    pub(crate) debug_info: DebugInfo,
+
+    #[serde(skip)]
+    pub(crate) unwind_info: UnwindInfo,
+
+    #[serde(skip)]
+    pub(crate) snapshots: Snapshots,
+
+    #[serde(skip)]
+    pub(crate) replay: RefCell<ReplayState>,
+
+    #[serde(skip)]
+    pub(crate) checkpoints: Checkpoints,
+
+    #[serde(skip)]
+    pub(crate) instruction_trace: InstructionTrace,
+
+    #[serde(skip)]
+    pub(crate) coverage: Option<Coverage>,
+
+    #[serde(skip)]
+    pub(crate) fuzzer: Option<Fuzzer>,
+
+    #[serde(skip)]
+    pub(crate) sanitizer: Option<Sanitizer>,
+
+    #[serde(skip)]
+    pub(crate) taint: Option<TaintState>,
+
+    #[serde(skip)]
+    pub(crate) profile: Option<Profile>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct MachineState {
     // TODO: memory could better be modeled with some kind of interval tree that allows storing additional data with each interval
+    #[serde(deserialize_with = "deserialize_memory_areas")]
     pub(crate) memory: Vec<MemoryArea>,
     pub(crate) registers: HashMap<SupportedRegister, u64>,
     pub(crate) xmm_registers: HashMap<SupportedRegister, u128>,
@@ -65,11 +116,21 @@ pub(crate) struct MachineState {
     // syscalls holds state for syscalls, e.g. the program break for brk
     pub(crate) syscalls: SyscallState,
 
+    // signals holds installed signal handlers, the signal mask and pending signals
+    #[serde(default)]
+    pub(crate) signals: SignalState,
+
     // call_stack holds u64 values that are the target addresses of calls. This is used to provide stack traces using the symbol table
     pub(crate) call_stack: Vec<u64>,
 
-    // trace holds a list of all executed calls, returns and jumps
-    pub(crate) trace: Vec<TraceEntry>,
+    // trace holds a list of all executed calls, returns, jumps and syscalls
+    pub(crate) trace: SharedLog<TraceEntry>,
+
+    // syscall_log holds the executed syscalls if the syscall log is enabled
+    pub(crate) syscall_log: Option<SharedLog<SyscallLogEntry>>,
+
+    // relro holds the PT_GNU_RELRO ranges that still have to be made read-only
+    pub(crate) relro: RelroState,
 }
 
 #[wasm_bindgen]
@@ -80,7 +141,18 @@ impl Axecutor {
             stack_top: 0,
             hooks: HookProcessor::default(),
             code_end_addr: 0,
-            symbol_table: HashMap::new(),
+            symbol_table: SymbolTable::default(),
+            debug_info: DebugInfo::default(),
+            unwind_info: UnwindInfo::default(),
+            snapshots: Snapshots::default(),
+            replay: RefCell::default(),
+            checkpoints: Checkpoints::default(),
+            instruction_trace: InstructionTrace::default(),
+            coverage: None,
+            fuzzer: None,
+            sanitizer: None,
+            taint: None,
+            profile: None,
             state: MachineState {
                 finished: false,
                 executed_instructions_count: 0,
@@ -94,8 +166,11 @@ impl Axecutor {
                 gs: 0,
                 max_instructions: None,
                 syscalls: SyscallState::default(),
+                signals: SignalState::default(),
                 call_stack: Vec::new(),
-                trace: Vec::new(),
+                trace: SharedLog::default(),
+                syscall_log: None,
+                relro: RelroState::default(),
             },
         }
     }
@@ -122,7 +197,14 @@ impl Axecutor {
 
         // Pretend to call _start
         ax.state.call_stack.push(initial_rip);
-        ax.symbol_table.insert(initial_rip, "_start".to_string());
+        ax.symbol_table.insert(Symbol {
+            name: "_start".to_string(),
+            address: initial_rip,
+            size: ax.code_end_addr.saturating_sub(initial_rip),
+            symbol_type: SymbolType::Function,
+            binding: SymbolBinding::Global,
+            section: None,
+        });
         ax.state.trace.push(TraceEntry {
             instr_ip: 0,
             target: initial_rip,
@@ -253,21 +335,23 @@ impl Axecutor {
             return Err(AxError::from("Cannot call state_from_committed() with falsy value. Note that you *must* return either null or Axecutor.commit() from your hook"));
         }
 
-        let state: MachineState = serde_wasm_bindgen::from_value(value).map_err(|e| {
+        let mut state: MachineState = serde_wasm_bindgen::from_value(value).map_err(|e| {
             AxError::from(&*format!(
                 "state_from_committed: failed to deserialize state: {}\nNote that you *must* return either Axecutor.unchanged(), Axecutor.stop() or Axecutor.commit() from your hook",
                 e,
             ))
         })?;
 
+        // The state went through JS, so nothing is shared with snapshots and checkpoints anymore unless unchanged parts are shared again
+        state.share_unchanged(&self.state);
         self.state = state;
         Ok(())
     }
 
-    /// Get the symbol name for a given address. This only works if the ELF binary contains a symbol table.
-    /// If no symbol is found, None or undefined is returned.
+    /// Get the name of the symbol starting exactly at the given address. This only works if the ELF binary contains a symbol table.
+    /// If no symbol is found, None or undefined is returned. Use `symbol_at` to also resolve addresses within a symbol.
     pub fn resolve_symbol(&self, addr: u64) -> Option<String> {
-        self.symbol_table.get(&addr).map(|s| s.to_string())
+        self.symbol_table.get(addr).map(|s| s.name.clone())
     }
 }
 
@@ -363,11 +447,34 @@ impl MachineState {
     }
 }
 
+// Only needed for states that went through JS
+#[cfg(any(test, target_arch = "wasm32"))]
+impl MachineState {
+    // Shares memory pages and log chunks that have the same contents as in `old`
+    pub(crate) fn share_unchanged(&mut self, old: &MachineState) {
+        for area in &mut self.memory {
+            if let Some(old_area) = old
+                .memory
+                .iter()
+                .find(|a| a.start == area.start && a.length == area.length)
+            {
+                area.data.share_unchanged(&old_area.data);
+            }
+        }
+
+        self.trace.share_unchanged(&old.trace);
+        if let (Some(log), Some(old_log)) = (&mut self.syscall_log, &old.syscall_log) {
+            log.share_unchanged(old_log);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::helpers::tests::test_async;
 
+    use bincode::Options;
     use iced_x86::Register;
 
     test_async![test_rip; async {
@@ -389,4 +496,20 @@ mod tests {
         }
         assert!(ax.state.finished);
     }];
+
+    test_async![unchanged_pages_are_shared_again; async {
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).unwrap();
+        ax.mem_init_zero(0x10000, 0x4000).unwrap();
+        ax.mem_write_bytes(0x10000, &[1; 0x4000]).unwrap();
+
+        // Like a state committed by a JS hook that changed one byte
+        let options = bincode::DefaultOptions::new();
+        let mut state: MachineState = options.deserialize(&options.serialize(&ax.state).unwrap()).unwrap();
+        let area = state.memory.iter().position(|area| area.start == 0x10000).unwrap();
+        state.memory[area].data.write(0x1000, &[2]);
+
+        state.share_unchanged(&ax.state);
+        assert_eq!(state.memory[area].data.shares_pages_with(&ax.state.memory[area].data), 3);
+        assert_eq!(state.memory[area].data.read(0x1000, 1), vec![2]);
+    }];
 }
@@ -1,13 +1,21 @@
 #![cfg(not(any(test, target_arch = "wasm32")))]
 
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::path::Path;
 use std::println;
 
 use ax_x86::{
-    auto::generated::SupportedMnemonic::Syscall,
     axecutor::Axecutor,
-    helpers::errors::AxError,
-    state::{hooks::HookResult, registers::SupportedRegister},
+    gdb::GdbServer,
+    helpers::{
+        errors::{AxError, ErrorKind},
+        fuzz::FuzzStatus,
+        syscalls::{errno, Syscall, SyscallResult},
+    },
+    state::registers::SupportedRegister,
 };
+use rand::{seq::SliceRandom, Rng};
 
 fn main() {
     async_std::task::block_on(async {
@@ -23,78 +31,431 @@ fn main() {
     });
 }
 
-async fn main_impl() -> Result<i32, AxError> {
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    let envp: Vec<String> = std::env::vars().map(|(k, v)| format!("{k}={v}")).collect();
+const USAGE: &str = "Usage: ax [--core-dump <path>] [--gdb <address:port>] [--strace] [--sanitize] [--taint] [--coverage <path>] [--profile <path>] [--fuzz <function> [--fuzz-runs <n>] [--fuzz-crashes <dir>]] [--max-instructions <n>] [--save-state <path>] <binary> [args...]
+       ax [options] --load-state <path>";
+
+// Number of functions listed in the profile printed by --profile
+const PROFILE_TABLE_ROWS: u32 = 20;
+
+#[derive(Default)]
+struct Options {
+    // Where to write an ELF core dump if emulation fails
+    core_dump: Option<String>,
+    // Address to wait for a GDB connection on, the program only runs when GDB continues it
+    gdb: Option<String>,
+    // Print syscalls to stderr in strace format
+    strace: bool,
+    // Stop at the first read of uninitialized memory, heap overflow or invalid free
+    sanitize: bool,
+    // Print where data returned by read syscalls reaches a jump target, syscall argument or conditional jump
+    taint: bool,
+    // Where to write the basic block coverage in drcov format
+    coverage: Option<String>,
+    // Where to write the instructions executed per call stack in collapsed stack format
+    profile: Option<String>,
+    // Fuzz this function, which is called like LLVMFuzzerTestOneInput(data, size), instead of running the program to the end
+    fuzz: Option<String>,
+    // Number of inputs to run when fuzzing
+    fuzz_runs: Option<u64>,
+    // Directory crashing inputs are written to
+    fuzz_crashes: Option<String>,
+    // Stop after this many instructions, e.g. to pause the program with --save-state
+    max_instructions: Option<u64>,
+    // Where to save the emulator state if emulation stops before the program has exited
+    save_state: Option<String>,
+    // Resume the emulator state saved at this path instead of starting a binary
+    load_state: Option<String>,
+}
 
-    let (elf_path, argv) = args
+fn take_value(flag: &str, rest: &mut &[String]) -> Result<String, AxError> {
+    let (value, tail) = rest
         .split_first()
-        .ok_or_else(|| AxError::from("No arguments provided"))?;
+        .ok_or_else(|| AxError::from(format!("Missing value for {flag}\n{USAGE}")))?;
+    *rest = tail;
+    Ok(value.clone())
+}
 
-    let binary = std::fs::read(elf_path)
-        .map_err(|e| AxError::from(format!("Failed to read file {elf_path}: {e}")))?;
+// Options for ax come before the binary, everything after it is passed to the emulated program.
+// Returns the binary and its arguments, which are empty if a saved state is loaded
+fn parse_args(args: &[String]) -> Result<(Options, &[String]), AxError> {
+    let mut options = Options::default();
 
-    println!(
-        "Emulating {} with ax v{}, {}",
-        elf_path,
-        ax_x86::version_info::version(),
-        ax_x86::version_info::commit()
-    );
+    let mut rest = args;
+    while let Some((flag, tail)) = rest.split_first() {
+        if !flag.starts_with("--") {
+            break;
+        }
+        rest = tail;
+
+        match flag.as_str() {
+            "--core-dump" => options.core_dump = Some(take_value(flag, &mut rest)?),
+            "--gdb" => options.gdb = Some(take_value(flag, &mut rest)?),
+            "--strace" => options.strace = true,
+            "--sanitize" => options.sanitize = true,
+            "--taint" => options.taint = true,
+            "--coverage" => options.coverage = Some(take_value(flag, &mut rest)?),
+            "--profile" => options.profile = Some(take_value(flag, &mut rest)?),
+            "--fuzz" => options.fuzz = Some(take_value(flag, &mut rest)?),
+            "--fuzz-runs" => {
+                let value = take_value(flag, &mut rest)?;
+                options.fuzz_runs = Some(value.parse().map_err(|_| {
+                    AxError::from(format!("Invalid number of runs {value}\n{USAGE}"))
+                })?);
+            }
+            "--fuzz-crashes" => options.fuzz_crashes = Some(take_value(flag, &mut rest)?),
+            "--max-instructions" => {
+                let value = take_value(flag, &mut rest)?;
+                options.max_instructions = Some(value.parse().map_err(|_| {
+                    AxError::from(format!("Invalid instruction count {value}\n{USAGE}"))
+                })?);
+            }
+            "--save-state" => options.save_state = Some(take_value(flag, &mut rest)?),
+            "--load-state" => options.load_state = Some(take_value(flag, &mut rest)?),
+            other => return Err(AxError::from(format!("Unknown option {other}\n{USAGE}"))),
+        }
+    }
+
+    match (&options.load_state, rest.is_empty()) {
+        (Some(_), false) => Err(AxError::from(format!(
+            "Cannot pass a binary when loading a saved state\n{USAGE}"
+        ))),
+        (None, true) => Err(AxError::from(format!("No binary provided\n{USAGE}"))),
+        _ => Ok((options, rest)),
+    }
+}
 
-    let mut ax = Axecutor::from_binary(binary.as_slice())?;
+// Execute step by step, printing each syscall after it has returned
+async fn execute_with_strace(ax: &mut Axecutor) -> Result<(), AxError> {
+    ax.set_syscall_log_enabled(true);
 
-    ax.init_stack_program_start(0x2000, Vec::from(argv), envp)?;
+    let result = loop {
+        match ax.step().await {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(e) => break Err(e),
+        }
 
-    ax.hook_before_mnemonic_native(Syscall, &|ax: &mut Axecutor, _| {
-        let syscall_num = ax.reg_read_64(SupportedRegister::RAX)?;
-        let rdi = ax.reg_read_64(SupportedRegister::RDI)?;
-        let rsi = ax.reg_read_64(SupportedRegister::RSI)?;
-        let rdx = ax.reg_read_64(SupportedRegister::RDX)?;
+        for entry in ax.take_syscall_log() {
+            eprintln!("{entry}");
+        }
+    };
 
-        match syscall_num {
-            // Write
-            1 => {
-                // rdi must be 0-2 (stdin, stdout, stderr) -- yes, we allow writing to stdin
-                if rdi > 2 {
-                    return Err(AxError::from("write: invalid file descriptor").into());
-                }
+    // The syscall that caused an error never returned
+    for entry in ax.take_syscall_log() {
+        eprintln!("{entry}");
+    }
 
-                let result_buf = ax.mem_read_bytes(rsi, rdx).map_err(|e| {
-                    AxError::from(format!("write: failed to read memory at {rsi}: {e}"))
-                })?;
-                let output_text = String::from_utf8(result_buf).map_err(|e| {
-                    AxError::from(format!(
-                        "write: failed to convert memory at {rsi} to utf8: {e}"
-                    ))
-                })?;
-
-                if rdi == 2 {
-                    eprint!("{output_text}");
-                } else {
-                    print!("{output_text}");
-                }
+    result
+}
 
-                // Return number of bytes written
-                ax.reg_write_64(SupportedRegister::RAX, rdx).map_err(|e| {
-                    AxError::from(format!("write: failed to write return value to RAX: {e}"))
-                })?;
+// Largest input the fuzzer generates
+const FUZZ_MAX_INPUT: usize = 4096;
+// Instructions a single fuzzing input may take before it counts as a hang
+const FUZZ_BUDGET: u64 = 1_000_000;
+const FUZZ_INTERESTING: [u8; 9] = [0x00, 0x01, 0x20, 0x40, 0x7f, 0x80, 0xfe, 0xff, b'A'];
+
+// Applies a few random havoc-style mutations to an input from the corpus
+fn mutate(rng: &mut impl Rng, corpus: &[Vec<u8>]) -> Vec<u8> {
+    let mut input = corpus.choose(rng).cloned().unwrap_or_default();
+
+    for _ in 0..rng.gen_range(1..=4) {
+        match rng.gen_range(0..6) {
+            // Insert a random byte
+            0 => {
+                let at = rng.gen_range(0..=input.len());
+                input.insert(at, rng.gen());
+            }
+            // Delete a byte
+            1 if !input.is_empty() => {
+                let at = rng.gen_range(0..input.len());
+                input.remove(at);
+            }
+            // Flip a bit
+            2 if !input.is_empty() => {
+                let at = rng.gen_range(0..input.len());
+                input[at] ^= 1 << rng.gen_range(0..8);
+            }
+            // Set a byte to a value that often matters
+            3 if !input.is_empty() => {
+                let at = rng.gen_range(0..input.len());
+                input[at] = *FUZZ_INTERESTING.choose(rng).unwrap_or(&0);
             }
-            // Exit
-            60 => {
-                ax.stop();
+            // Splice in a part of another input
+            4 => {
+                let other = corpus.choose(rng).cloned().unwrap_or_default();
+                if !other.is_empty() {
+                    let from = rng.gen_range(0..other.len());
+                    let to = rng.gen_range(from..=other.len());
+                    let at = rng.gen_range(0..=input.len());
+                    input.splice(at..at, other[from..to].iter().copied());
+                }
+            }
+            // Set a byte to a random value
+            _ if !input.is_empty() => {
+                let at = rng.gen_range(0..input.len());
+                input[at] = rng.gen();
             }
-            _ => {
-                return Err(AxError::from(format!("Unsupported syscall: {syscall_num}")).into());
+            _ => input.push(rng.gen()),
+        }
+    }
+
+    input.truncate(FUZZ_MAX_INPUT);
+    input
+}
+
+// Runs the program until `function` is called, then calls it with mutated inputs from that point on.
+// Inputs that reach new edges are kept in the corpus, the first input for each kind of crash is written to the crash directory
+async fn fuzz(ax: &mut Axecutor, function: &str, options: &Options) -> Result<i32, AxError> {
+    let start = ax
+        .address_of(function)
+        .ok_or_else(|| AxError::from(format!("Cannot fuzz unknown function {function}")))?;
+    ax.add_breakpoint(start)?;
+    if ax.run_until_breakpoint().await? != Some(start) {
+        return Err(AxError::from(format!(
+            "Cannot fuzz {function}: the program finished without calling it"
+        )));
+    }
+    ax.remove_breakpoint(start)?;
+
+    // Fuzz a single call of the function, until it returns
+    let exit = ax.mem_read_64(ax.return_address_slot()?)?;
+    let input = ax.mem_init_zero_anywhere(FUZZ_MAX_INPUT as u64)?;
+    ax.reg_write_64(SupportedRegister::RDI, input)?;
+    ax.fuzz_init(start, input, FUZZ_MAX_INPUT as u64, exit, FUZZ_BUDGET)
+        .await?;
+    ax.fuzz_set_length_register(SupportedRegister::RSI)?;
+
+    let runs = options.fuzz_runs.unwrap_or(100_000);
+    let crash_dir = options.fuzz_crashes.as_deref().unwrap_or("crashes");
+    eprintln!(
+        "Fuzzing {function} at {start:#x} with {runs} inputs, crashes are written to {crash_dir}"
+    );
+
+    let mut rng = rand::thread_rng();
+    let mut corpus = vec![Vec::new()];
+    let mut crashes = HashSet::new();
+    let mut timeouts = 0;
+    for run in 1..=runs {
+        let input = mutate(&mut rng, &corpus);
+        let result = ax.fuzz_run(input.clone()).await?;
+
+        match result.status {
+            FuzzStatus::Ok => {}
+            FuzzStatus::Timeout => timeouts += 1,
+            FuzzStatus::Crash => {
+                // Crashes are told apart by their exception and where it happened
+                let name = match (result.exception, result.signal) {
+                    (Some(exception), _) => format!(
+                        "crash-{}-{:#x}",
+                        exception.kind.mnemonic().trim_start_matches('#'),
+                        exception.rip
+                    ),
+                    (None, Some(signal)) => format!("crash-signal-{signal}"),
+                    (None, None) => "crash".to_string(),
+                };
+
+                if crashes.insert(name.clone()) {
+                    std::fs::create_dir_all(crash_dir).map_err(|e| {
+                        AxError::from(format!("Failed to create directory {crash_dir}: {e}"))
+                    })?;
+                    let path = Path::new(crash_dir).join(&name);
+                    std::fs::write(&path, &input).map_err(|e| {
+                        AxError::from(format!("Failed to write crash to {}: {e}", path.display()))
+                    })?;
+                    eprintln!(
+                        "Run {run}: new crash {name}, input written to {}",
+                        path.display()
+                    );
+                }
             }
         }
 
-        Ok(HookResult::Handled)
+        if result.new_coverage {
+            corpus.push(input);
+        }
+        if run % 10_000 == 0 {
+            eprintln!(
+                "Run {run}: {} inputs in corpus, {} crashes, {timeouts} timeouts",
+                corpus.len(),
+                crashes.len()
+            );
+        }
+    }
+
+    eprintln!(
+        "Fuzzing finished: {} inputs in corpus, {} crashes, {timeouts} timeouts",
+        corpus.len(),
+        crashes.len()
+    );
+    Ok(if crashes.is_empty() { 0 } else { 1 })
+}
+
+async fn main_impl() -> Result<i32, AxError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let envp: Vec<String> = std::env::vars().map(|(k, v)| format!("{k}={v}")).collect();
+
+    let (options, binary) = parse_args(&args)?;
+
+    let mut ax = match (binary.split_first(), &options.load_state) {
+        (Some((elf_path, argv)), _) => {
+            let binary = std::fs::read(elf_path)
+                .map_err(|e| AxError::from(format!("Failed to read file {elf_path}: {e}")))?;
+
+            println!(
+                "Emulating {} with ax v{}, {}",
+                elf_path,
+                ax_x86::version_info::version(),
+                ax_x86::version_info::commit()
+            );
+
+            let mut ax = Axecutor::from_binary(binary.as_slice())?;
+            ax.init_stack_program_start(0x2000, Vec::from(argv), envp)?;
+            ax.handle_syscalls(vec![Syscall::Exit])?;
+            ax
+        }
+        (None, Some(path)) => {
+            let state = std::fs::read(path)
+                .map_err(|e| AxError::from(format!("Failed to read state file {path}: {e}")))?;
+
+            println!(
+                "Resuming {} with ax v{}, {}",
+                path,
+                ax_x86::version_info::version(),
+                ax_x86::version_info::commit()
+            );
+
+            Axecutor::load_from_bytes(&state)?
+        }
+        (None, None) => unreachable!("parse_args requires a binary or a saved state"),
+    };
+
+    // A saved state keeps the limit it was paused with
+    match options.max_instructions {
+        Some(count) => ax.set_max_instructions(ax.executed_instructions() + count),
+        None => ax.remove_max_instructions(),
+    }
+
+    // Write
+    ax.hook_syscall_native(1, |ax: &mut Axecutor, args| {
+        let (fd, buf, count) = (args.rdi, args.rsi, args.rdx);
+
+        // fd must be 0-2 (stdin, stdout, stderr) -- yes, we allow writing to stdin
+        if fd > 2 {
+            return Ok(SyscallResult::Errno(errno::EBADF));
+        }
+
+        let result_buf = ax
+            .mem_read_bytes(buf, count)
+            .map_err(|e| AxError::from(format!("write: failed to read memory at {buf}: {e}")))?;
+        let output_text = String::from_utf8(result_buf).map_err(|e| {
+            AxError::from(format!(
+                "write: failed to convert memory at {buf} to utf8: {e}"
+            ))
+        })?;
+
+        if fd == 2 {
+            eprint!("{output_text}");
+        } else {
+            print!("{output_text}");
+        }
+
+        // Return number of bytes written
+        Ok(SyscallResult::Return(count))
     })?;
 
-    // add axecutor string to error message
-    ax.execute()
-        .await
-        .map_err(|e| AxError::from(format!("{}\nAxecutor state:\n{}", e, ax.to_string())))?;
+    if let Some(address) = &options.gdb {
+        let listener = TcpListener::bind(address)
+            .map_err(|e| AxError::from(format!("Failed to listen on {address}: {e}")))?;
+        eprintln!("Waiting for GDB to connect on {address}");
+        let (stream, peer) = listener
+            .accept()
+            .map_err(|e| AxError::from(format!("Failed to accept GDB connection: {e}")))?;
+        eprintln!("GDB connected from {peer}");
+
+        let status = GdbServer::new(&mut ax, stream)?.run().await?;
+        return Ok(status.unwrap_or(0) as i32);
+    }
+
+    if let Some(function) = &options.fuzz {
+        return fuzz(&mut ax, function, &options).await;
+    }
+
+    if options.coverage.is_some() {
+        ax.enable_coverage();
+    }
+
+    if options.profile.is_some() {
+        ax.enable_profiling();
+    }
+
+    if options.sanitize {
+        ax.enable_sanitizer();
+        ax.sanitizer_intercept_allocator()?;
+    }
+
+    if options.taint {
+        ax.enable_taint_tracking();
+        ax.taint_read_syscalls(Some(0))?;
+        ax.hook_taint_native(|_, event| {
+            eprintln!("Tainted data: {event}");
+            Ok(())
+        })?;
+    }
+
+    let result = if options.strace {
+        execute_with_strace(&mut ax).await
+    } else {
+        ax.execute().await
+    };
+
+    // Coverage is interesting for crashing programs too
+    if let Some(path) = &options.coverage {
+        let module = match binary.first() {
+            Some(elf_path) => Path::new(elf_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| elf_path.clone()),
+            None => "program".to_string(),
+        };
+        std::fs::write(path, ax.coverage_drcov(&module)?)
+            .map_err(|e| AxError::from(format!("Failed to write coverage to {path}: {e}")))?;
+        eprintln!("Wrote coverage to {path}");
+    }
+
+    if let Some(path) = &options.profile {
+        std::fs::write(path, ax.profile_collapsed_stacks()?)
+            .map_err(|e| AxError::from(format!("Failed to write profile to {path}: {e}")))?;
+        eprint!("{}", ax.profile_table(Some(PROFILE_TABLE_ROWS))?);
+        eprintln!("Wrote profile to {path}");
+    }
+
+    if let Err(e) = result {
+        if let Some(path) = &options.save_state {
+            std::fs::write(path, ax.save_to_bytes()?)
+                .map_err(|e| AxError::from(format!("Failed to save state to {path}: {e}")))?;
+            eprintln!("Saved emulator state to {path}, continue with --load-state {path}");
+
+            // Pausing the program is not an error
+            if let ErrorKind::InstructionLimit { limit } = e.kind() {
+                eprintln!("Paused after {limit} instructions");
+                return Ok(0);
+            }
+        }
+
+        if let Some(path) = &options.core_dump {
+            let core = ax.write_core_dump()?;
+            std::fs::write(path, core)
+                .map_err(|e| AxError::from(format!("Failed to write core dump to {path}: {e}")))?;
+            eprintln!("Wrote core dump to {path}");
+        }
+
+        // add axecutor string to error message
+        return Err(AxError::from(format!(
+            "{}\nAxecutor state:\n{}",
+            e,
+            ax.to_string()
+        )));
+    }
 
     let exit_code = ax.reg_read_64(SupportedRegister::RAX)?;
 
@@ -0,0 +1,286 @@
+use elf::abi::*;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+use crate::state::memory::{PAGE_SIZE, PROT_EXEC, PROT_READ, PROT_WRITE};
+use crate::state::registers::SupportedRegister::{self, *};
+
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+
+// Size of struct elf_prstatus and struct user_fpregs_struct on x86-64 Linux
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_REGS_OFFSET: usize = 112;
+const FPREGSET_SIZE: usize = 512;
+
+// Selectors the Linux kernel uses for 64-bit user space
+const USER_CS: u64 = 0x33;
+const USER_SS: u64 = 0x2b;
+
+// Order of registers in struct user_regs_struct, None marks fields that are filled in separately
+const USER_REGS: [Option<SupportedRegister>; 17] = [
+    Some(R15),
+    Some(R14),
+    Some(R13),
+    Some(R12),
+    Some(RBP),
+    Some(RBX),
+    Some(R11),
+    Some(R10),
+    Some(R9),
+    Some(R8),
+    Some(RAX),
+    Some(RCX),
+    Some(RDX),
+    Some(RSI),
+    Some(RDI),
+    None, // orig_rax
+    Some(RIP),
+];
+
+fn push_note(out: &mut Vec<u8>, n_type: u64, desc: &[u8]) {
+    let name = b"CORE\0";
+    out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(n_type as u32).to_le_bytes());
+    out.extend_from_slice(name);
+    out.resize((out.len() + 3) & !3, 0);
+    out.extend_from_slice(desc);
+    out.resize((out.len() + 3) & !3, 0);
+}
+
+fn push_program_header(
+    out: &mut Vec<u8>,
+    p_type: u32,
+    p_flags: u32,
+    offset: u64,
+    vaddr: u64,
+    size: u64,
+    align: u64,
+) {
+    out.extend_from_slice(&p_type.to_le_bytes());
+    out.extend_from_slice(&p_flags.to_le_bytes());
+    out.extend_from_slice(&offset.to_le_bytes());
+    out.extend_from_slice(&vaddr.to_le_bytes());
+    // p_paddr
+    out.extend_from_slice(&0u64.to_le_bytes());
+    // p_filesz and p_memsz
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&align.to_le_bytes());
+}
+
+fn prot_to_elf_flags(prot: u32) -> u32 {
+    let mut flags = 0;
+    if prot & PROT_READ != 0 {
+        flags |= PF_R;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= PF_W;
+    }
+    if prot & PROT_EXEC != 0 {
+        flags |= PF_X;
+    }
+    flags
+}
+
+impl Axecutor {
+    fn core_prstatus(&self, signal: u32) -> Result<Vec<u8>, AxError> {
+        let mut desc = vec![0u8; PRSTATUS_SIZE];
+        // pr_info.si_signo and pr_cursig
+        desc[0..4].copy_from_slice(&signal.to_le_bytes());
+        desc[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+        // pr_pid, the emulated process doesn't have one, but gdb uses it to name the thread
+        desc[32..36].copy_from_slice(&1u32.to_le_bytes());
+
+        let mut regs = Vec::with_capacity(27);
+        for reg in USER_REGS {
+            regs.push(match reg {
+                Some(reg) => self.reg_read_64(reg)?,
+                None => u64::MAX,
+            });
+        }
+        regs.push(USER_CS);
+        regs.push(self.state.rflags);
+        // Debuggers unwind the stack using the call frame information of the binary, which expects the native RSP
+        regs.push(self.native_rsp()?);
+        regs.push(USER_SS);
+        regs.push(self.state.fs);
+        regs.push(self.state.gs);
+        // ds, es, fs, gs selectors
+        regs.extend_from_slice(&[0, 0, 0, 0]);
+
+        for (i, value) in regs.iter().enumerate() {
+            let offset = PRSTATUS_REGS_OFFSET + i * 8;
+            desc[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        // pr_fpvalid: an NT_FPREGSET note follows
+        let fpvalid_offset = PRSTATUS_REGS_OFFSET + regs.len() * 8;
+        desc[fpvalid_offset..fpvalid_offset + 4].copy_from_slice(&1u32.to_le_bytes());
+
+        Ok(desc)
+    }
+
+    fn core_fpregset(&self) -> Result<Vec<u8>, AxError> {
+        // Same layout as the FXSAVE instruction uses
+        let mut desc = vec![0u8; FPREGSET_SIZE];
+        // Default x87 control word and MXCSR after process start
+        desc[0..2].copy_from_slice(&0x37fu16.to_le_bytes());
+        desc[24..28].copy_from_slice(&0x1f80u32.to_le_bytes());
+        desc[28..32].copy_from_slice(&0xffffu32.to_le_bytes());
+
+        let xmm = [
+            XMM0, XMM1, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7, XMM8, XMM9, XMM10, XMM11, XMM12, XMM13,
+            XMM14, XMM15,
+        ];
+        for (i, reg) in xmm.into_iter().enumerate() {
+            let offset = 160 + i * 16;
+            desc[offset..offset + 16]
+                .copy_from_slice(&self.internal_reg_read_128(reg)?.to_le_bytes());
+        }
+
+        Ok(desc)
+    }
+
+    pub(crate) fn core_dump_with_signal(&self, signal: u32) -> Result<Vec<u8>, AxError> {
+        let mut notes = Vec::new();
+        push_note(&mut notes, NT_PRSTATUS, &self.core_prstatus(signal)?);
+        push_note(&mut notes, NT_FPREGSET, &self.core_fpregset()?);
+
+        let areas = &self.state.memory;
+        let phnum = 1 + areas.len() as u64;
+        let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+        let round_up = |x: u64| (x + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+        let mut out = Vec::new();
+
+        // ELF header
+        out.extend_from_slice(&[0x7f, b'E', b'L', b'F', ELFCLASS64, ELFDATA2LSB, EV_CURRENT]);
+        out.push(ELFOSABI_SYSV);
+        out.resize(16, 0);
+        out.extend_from_slice(&ET_CORE.to_le_bytes());
+        out.extend_from_slice(&EM_X86_64.to_le_bytes());
+        out.extend_from_slice(&(EV_CURRENT as u32).to_le_bytes());
+        // e_entry, e_phoff, e_shoff
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+        // e_flags
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(phnum as u16).to_le_bytes());
+        // e_shentsize, e_shnum, e_shstrndx
+        out.extend_from_slice(&[0u8; 6]);
+
+        push_program_header(&mut out, PT_NOTE, 0, notes_offset, 0, notes.len() as u64, 4);
+
+        // Memory contents start at the next page boundary after the notes
+        let mut offset = round_up(notes_offset + notes.len() as u64);
+        for area in areas {
+            push_program_header(
+                &mut out,
+                PT_LOAD,
+                prot_to_elf_flags(area.access),
+                offset,
+                area.start,
+                area.length,
+                PAGE_SIZE,
+            );
+            offset = round_up(offset + area.length);
+        }
+
+        out.extend_from_slice(&notes);
+        for area in areas {
+            out.resize(round_up(out.len() as u64) as usize, 0);
+            area.data.read_into(0, area.data.len(), &mut out);
+        }
+
+        debug_log!(
+            "Wrote core dump of {} bytes with {} memory areas",
+            out.len(),
+            areas.len()
+        );
+
+        Ok(out)
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Writes the current state as an ELF core dump (`ET_CORE`), which can be opened with e.g. `gdb binary core`.
+    /// It contains the general purpose and XMM registers as well as one `PT_LOAD` segment for every memory area.
+    /// Note that RSP is stored 8 bytes higher than the emulator uses it, as that is what a native process would have at the same point.
+    pub fn write_core_dump(&self) -> Result<Vec<u8>, AxError> {
+        self.core_dump_with_signal(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::syscalls::Syscall;
+    use crate::helpers::tests::test_async;
+    use elf::endian::AnyEndian;
+    use elf::note::Note;
+    use elf::ElfBytes;
+
+    test_async![core_dump_contents; async {
+        let binary = include_bytes!("../../testdata/unwind_c_nostdlib.bin");
+        let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+        ax.handle_syscalls(vec![Syscall::Exit]).expect("Failed to add syscall handlers");
+        ax.init_stack(0x1000).expect("Failed to setup stack");
+        ax.reg_write_128(XMM3, 0x0123456789abcdef_fedcba9876543210).unwrap();
+        ax.execute().await.expect_err("Null pointer write should fail");
+
+        let core = ax.write_core_dump().expect("Failed to write core dump");
+        let file = ElfBytes::<AnyEndian>::minimal_parse(&core).expect("Failed to parse core dump");
+        assert_eq!(file.ehdr.e_type, ET_CORE);
+        assert_eq!(file.ehdr.e_machine, EM_X86_64);
+
+        let segments: Vec<_> = file.segments().expect("No segments").iter().collect();
+        assert_eq!(segments.len(), 1 + ax.state.memory.len());
+        assert_eq!(segments[0].p_type, PT_NOTE);
+
+        // Every memory area is contained with its contents and permissions
+        for (segment, area) in segments[1..].iter().zip(ax.state.memory.iter()) {
+            assert_eq!(segment.p_type, PT_LOAD);
+            assert_eq!(segment.p_vaddr, area.start);
+            assert_eq!(segment.p_filesz, area.length);
+            assert_eq!(segment.p_flags, prot_to_elf_flags(area.access));
+            assert_eq!(file.segment_data(segment).unwrap(), area.data.to_vec().as_slice());
+        }
+
+        let notes: Vec<_> = file
+            .segment_data_as_notes(&segments[0])
+            .expect("Failed to parse notes")
+            .filter_map(|note| match note {
+                Note::Unknown(any) => Some((any.n_type, any.name, any.desc.to_vec())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(notes.len(), 2);
+
+        let (n_type, name, prstatus) = &notes[0];
+        assert_eq!((*n_type, *name), (NT_PRSTATUS, "CORE"));
+        assert_eq!(prstatus.len(), PRSTATUS_SIZE);
+        let reg = |idx: usize| {
+            let offset = PRSTATUS_REGS_OFFSET + idx * 8;
+            u64::from_le_bytes(prstatus[offset..offset + 8].try_into().unwrap())
+        };
+        // rip, rsp and rsi in user_regs_struct
+        assert_eq!(reg(16), ax.reg_read_64(RIP).unwrap());
+        assert_eq!(reg(19), ax.reg_read_64(RSP).unwrap() + 8);
+        assert_eq!(reg(13), 12);
+
+        let (n_type, _, fpregs) = &notes[1];
+        assert_eq!(*n_type, NT_FPREGSET);
+        assert_eq!(
+            u128::from_le_bytes(fpregs[160 + 3 * 16..160 + 4 * 16].try_into().unwrap()),
+            0x0123456789abcdef_fedcba9876543210
+        );
+    }];
+}
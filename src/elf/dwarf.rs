@@ -0,0 +1,292 @@
+use std::fmt;
+
+use elf::endian::AnyEndian;
+use elf::ElfBytes;
+use gimli::{EndianSlice, LittleEndian, SectionId};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+
+impl From<gimli::Error> for AxError {
+    fn from(err: gimli::Error) -> Self {
+        AxError::from(format!("DWARF: Parse error: {err}"))
+    }
+}
+
+/// A location in the source code of the emulated program, as described by its DWARF debug information.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// File name as given in the line table, e.g. `main.c`
+    pub file: String,
+    /// Directory of the file, might be empty
+    pub directory: String,
+    pub line: u32,
+    /// Column within the line, 0 if unknown
+    pub column: u32,
+}
+
+impl SourceLocation {
+    /// Path of the file, including the directory if it is known
+    pub(crate) fn path(&self) -> String {
+        if self.directory.is_empty() || self.file.starts_with('/') {
+            self.file.clone()
+        } else {
+            format!("{}/{}", self.directory, self.file)
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LineRow {
+    address: u64,
+    file: usize,
+    line: u32,
+    column: u32,
+    end_sequence: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    start: u64,
+    end: u64,
+    name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DebugInfo {
+    // (directory, file name) pairs referenced by rows
+    files: Vec<(String, String)>,
+    // Rows of all line programs, sorted by address
+    rows: Vec<LineRow>,
+    // Subprograms from .debug_info, sorted by start address
+    functions: Vec<Function>,
+}
+
+type Reader<'data> = EndianSlice<'data, LittleEndian>;
+
+impl DebugInfo {
+    /// Reads `.debug_line` and `.debug_info` from the binary.
+    /// Binaries without debug information result in an empty `DebugInfo`.
+    pub(crate) fn from_elf(file: &ElfBytes<AnyEndian>) -> Result<DebugInfo, AxError> {
+        let load_section = |id: SectionId| -> Result<Reader, AxError> {
+            let data = match file.section_header_by_name(id.name())? {
+                Some(header) => match file.section_data(&header)? {
+                    (data, None) => data,
+                    (_, Some(_)) => {
+                        debug_log!("DWARF: Ignoring compressed section {}", id.name());
+                        &[]
+                    }
+                },
+                None => &[],
+            };
+            Ok(EndianSlice::new(data, LittleEndian))
+        };
+
+        let dwarf = gimli::Dwarf::load(load_section)?;
+
+        let mut info = DebugInfo::default();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            info.read_line_program(&dwarf, &unit)?;
+            info.read_functions(&dwarf, &unit)?;
+        }
+
+        // At the same address, the start of a new sequence must win over the end of the previous one
+        info.rows
+            .sort_by_key(|row| (row.address, !row.end_sequence));
+        info.functions.sort_by_key(|f| f.start);
+
+        debug_log!(
+            "DWARF: Read {} line table rows and {} functions",
+            info.rows.len(),
+            info.functions.len()
+        );
+
+        Ok(info)
+    }
+
+    fn read_line_program(
+        &mut self,
+        dwarf: &gimli::Dwarf<Reader>,
+        unit: &gimli::Unit<Reader>,
+    ) -> Result<(), AxError> {
+        let program = match unit.line_program.clone() {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+
+        // Line programs refer to files by index into their own file table
+        let file_base = self.files.len();
+        let header = program.header();
+        let first_index = if header.version() >= 5 { 0 } else { 1 };
+        for entry in header.file_names() {
+            let name = dwarf.attr_string(unit, entry.path_name())?;
+            let directory = match entry.directory(header) {
+                Some(dir) => dwarf.attr_string(unit, dir)?.to_string_lossy().to_string(),
+                None => String::new(),
+            };
+            self.files
+                .push((directory, name.to_string_lossy().to_string()));
+        }
+
+        let mut rows = program.rows();
+        while let Some((_, row)) = rows.next_row()? {
+            let file = match (row.file_index() as usize).checked_sub(first_index) {
+                Some(idx) if idx + file_base < self.files.len() => idx + file_base,
+                _ => continue,
+            };
+
+            self.rows.push(LineRow {
+                address: row.address(),
+                file,
+                line: row.line().map(|l| l.get() as u32).unwrap_or(0),
+                column: match row.column() {
+                    gimli::ColumnType::LeftEdge => 0,
+                    gimli::ColumnType::Column(c) => c.get() as u32,
+                },
+                end_sequence: row.end_sequence(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read_functions(
+        &mut self,
+        dwarf: &gimli::Dwarf<Reader>,
+        unit: &gimli::Unit<Reader>,
+    ) -> Result<(), AxError> {
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+
+            let name = match entry.attr_value(gimli::DW_AT_name)? {
+                Some(attr) => dwarf.attr_string(unit, attr)?.to_string_lossy().to_string(),
+                None => continue,
+            };
+
+            let mut ranges = dwarf.die_ranges(unit, entry)?;
+            while let Some(range) = ranges.next()? {
+                if range.begin < range.end {
+                    self.functions.push(Function {
+                        start: range.begin,
+                        end: range.end,
+                        name: name.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn location(&self, addr: u64) -> Option<SourceLocation> {
+        let idx = self.rows.partition_point(|row| row.address <= addr);
+        let row = self.rows.get(idx.checked_sub(1)?)?;
+        // Line 0 is used for compiler-generated code that has no source line
+        if row.end_sequence || row.line == 0 {
+            return None;
+        }
+
+        let (directory, file) = &self.files[row.file];
+        Some(SourceLocation {
+            file: file.clone(),
+            directory: directory.clone(),
+            line: row.line,
+            column: row.column,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // Address, file path and line of each row that belongs to a source line
+    pub(crate) fn lines(&self) -> Vec<(u64, String, u32)> {
+        self.rows
+            .iter()
+            .filter(|row| !row.end_sequence && row.line != 0)
+            .map(|row| {
+                let location = self.location(row.address);
+                (
+                    row.address,
+                    location.map(|l| l.path()).unwrap_or_default(),
+                    row.line,
+                )
+            })
+            .collect()
+    }
+
+    // Name and start address of each function
+    pub(crate) fn functions(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.functions.iter().map(|f| (f.name.as_str(), f.start))
+    }
+
+    pub(crate) fn function(&self, addr: u64) -> Option<(&str, u64)> {
+        let idx = self.functions.partition_point(|f| f.start <= addr);
+        self.functions[..idx]
+            .iter()
+            .rev()
+            .find(|f| addr < f.end)
+            .map(|f| (f.name.as_str(), addr - f.start))
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Get the source location (file, line and column) of the given address from the DWARF line table.
+    /// This only works for ELF binaries that were compiled with debug information (e.g. `gcc -g`).
+    /// If no location is known, None or undefined is returned.
+    pub fn addr_to_line(&self, addr: u64) -> Option<SourceLocation> {
+        self.debug_info.location(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+
+    test_async![line_table_c_loop; async {
+        let binary = include_bytes!("../../testdata/c_loop.bin");
+        let ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+
+        let loc = ax.addr_to_line(0x401036).expect("No line info for loop body");
+        assert_eq!(loc.file, "c_loop.c");
+        assert_eq!(loc.line, 20);
+        assert_eq!(loc.column, 5);
+        assert_eq!(loc.to_string(), "c_loop.c:20");
+
+        // Between rows, the previous row applies
+        assert_eq!(ax.addr_to_line(0x401010).map(|l| l.line), Some(8));
+        assert_eq!(ax.addr_to_line(0x500000), None);
+    }];
+
+    test_async![functions_from_debug_info; async {
+        let binary = include_bytes!("../../testdata/c_loop.bin");
+        let ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+
+        assert_eq!(ax.debug_info.function(0x401034), Some(("_start", 0x1a)));
+        assert_eq!(ax.debug_info.function(0x401000), Some(("sys_exit", 0)));
+    }];
+
+    test_async![no_debug_info; async {
+        let binary = include_bytes!("../../testdata/exit_c_no_symbols.bin");
+        let ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+
+        assert!(ax.debug_info.rows.is_empty());
+        assert_eq!(ax.addr_to_line(ax.reg_read_64(crate::state::registers::SupportedRegister::RIP).unwrap()), None);
+    }];
+}
@@ -4,15 +4,23 @@ use elf::endian::AnyEndian;
 use elf::to_str::p_type_to_str;
 use elf::{ElfBytes, ParseError};
 
+use elf::segment::ProgramHeader;
+use serde::{Deserialize, Serialize};
 use std::string::FromUtf8Error;
 use wasm_bindgen::prelude::wasm_bindgen;
 
+use crate::elf::dwarf::DebugInfo;
+use crate::elf::symbols::{Symbol, SymbolBinding, SymbolTable, SymbolType};
 use crate::helpers::debug::debug_log;
 use crate::helpers::macros::{assert_fatal, fatal_error};
+use crate::helpers::stack::UnwindInfo;
 use crate::helpers::trace::{TraceEntry, TraceVariant};
-use crate::state::memory::{PROT_EXEC, PROT_READ, PROT_WRITE};
+use crate::state::memory::{PAGE_SIZE, PROT_EXEC, PROT_READ, PROT_WRITE};
 use crate::state::registers::SupportedRegister::RIP;
-use crate::{axecutor::Axecutor, helpers::errors::AxError};
+use crate::{
+    axecutor::Axecutor,
+    helpers::errors::{AxError, ErrorKind},
+};
 
 impl From<ParseError> for AxError {
     fn from(err: ParseError) -> Self {
@@ -40,20 +48,130 @@ fn elf_flags_to_prot(flags: u32) -> u32 {
     proc_flags
 }
 
-fn round_up_to_page_size(size: u64) -> u64 {
-    (size + 0xfff) & !0xfff
+fn round_up_to_page_size(size: u64) -> Option<u64> {
+    size.checked_add(PAGE_SIZE - 1)
+        .map(|size| size & !(PAGE_SIZE - 1))
+}
+
+fn round_down_to_page_size(addr: u64) -> u64 {
+    addr & !(PAGE_SIZE - 1)
+}
+
+// End address of the memory described by a segment, which must not wrap around the address space
+fn segment_end(segment: &ProgramHeader) -> Result<u64, AxError> {
+    segment.p_vaddr.checked_add(segment.p_memsz).ok_or_else(|| {
+        AxError::from(format!(
+            "ELF: Segment @ {:#x} with size {:#x} wraps around the address space",
+            segment.p_vaddr, segment.p_memsz
+        ))
+    })
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RelroState {
+    // Page-aligned [start, end) ranges that become read-only once relocation processing has finished
+    pub(crate) pending: Vec<(u64, u64)>,
+    // Address at which the pending ranges are protected automatically, usually `main`
+    pub(crate) apply_at: Option<u64>,
 }
 
 // TODO: System V ABI mentions %rdx should have "a function pointer that the application should register with atexit" at process entry
 
+impl Axecutor {
+    // Maps a PT_LOAD segment at page granularity. Pages that are shared with a previously mapped segment
+    // are split off into their own memory area that gets the access rights of both segments.
+    fn map_load_segment(
+        &mut self,
+        segment: &ProgramHeader,
+        content: &[u8],
+    ) -> Result<(u64, u64), AxError> {
+        if segment.p_align > 1
+            && segment.p_vaddr % segment.p_align != segment.p_offset % segment.p_align
+        {
+            return Err(AxError::from(format!(
+                "ELF: Segment @ {:#x} with offset {:#x} is not aligned to {:#x}",
+                segment.p_vaddr, segment.p_offset, segment.p_align
+            )));
+        }
+
+        if segment.p_filesz > segment.p_memsz || content.len() as u64 != segment.p_filesz {
+            return Err(AxError::from(
+                "ELF: Content is larger than specified in segment header".to_string(),
+            ));
+        }
+
+        let map_start = round_down_to_page_size(segment.p_vaddr);
+        let map_end = round_up_to_page_size(segment_end(segment)?).ok_or_else(|| {
+            AxError::from(format!(
+                "ELF: Segment @ {:#x} with size {:#x} ends in the last page of the address space",
+                segment.p_vaddr, segment.p_memsz
+            ))
+        })?;
+        let prot = elf_flags_to_prot(segment.p_flags);
+
+        let mut current = map_start;
+        while current < map_end {
+            let overlapping = self
+                .state
+                .memory
+                .iter()
+                .find(|area| area.start <= current && current < area.start + area.len())
+                .map(|area| (area.start + area.len(), area.access));
+
+            match overlapping {
+                Some((area_end, access)) => {
+                    let overlap_end = std::cmp::min(area_end, map_end);
+                    debug_log!(
+                        "ELF: Segment @ {:#x} shares {:#x}..{:#x} with a previously mapped segment",
+                        segment.p_vaddr,
+                        current,
+                        overlap_end
+                    );
+                    self.mem_prot_range(current, overlap_end - current, access | prot)?;
+                    current = overlap_end;
+                }
+                None => {
+                    // Map everything up to the next area (or the end of the segment)
+                    let next_area = self
+                        .state
+                        .memory
+                        .iter()
+                        .map(|area| area.start)
+                        .filter(|start| *start > current && *start < map_end)
+                        .min()
+                        .unwrap_or(map_end);
+
+                    self.mem_init_zero_named(
+                        current,
+                        next_area - current,
+                        format!("elf_load_header_{:#x}", segment.p_vaddr),
+                    )?;
+                    self.mem_prot(current, prot)?;
+                    current = next_area;
+                }
+            }
+        }
+
+        // The rest of the segment (p_memsz - p_filesz) stays zeroed, e.g. for .bss
+        self.mem_poke_bytes(segment.p_vaddr, content)?;
+
+        Ok((map_start, map_end))
+    }
+}
+
 #[wasm_bindgen]
 impl Axecutor {
     /// Create a new Axecutor from the bytes of an ELF binary.
-    /// This will load the `.text` section into memory and set the program counter to the entry point.
+    /// This will load all `PT_LOAD` segments into memory at page granularity and set the program counter to the entry point.
+    /// The `PT_GNU_RELRO` region is made read-only once execution reaches `main` (see `apply_relro`).
     /// One thing to note is that you might want to set up the stack via `init_stack_program_start` before running the binary.
     pub fn from_binary(binary: &[u8]) -> Result<Axecutor, AxError> {
         debug_log!("Calling Axecutor::from_binary");
 
+        Self::load_binary(binary).map_err(|e| e.with_kind(ErrorKind::ElfLoad))
+    }
+
+    fn load_binary(binary: &[u8]) -> Result<Axecutor, AxError> {
         // Following reference contains a lot of info about what all these ELF fields mean:
         // https://man7.org/linux/man-pages/man5/elf.5.html
 
@@ -65,9 +183,6 @@ impl Axecutor {
 
         // Tracing: Pretend to call _start
         axecutor.state.call_stack.push(entrypoint);
-        axecutor
-            .symbol_table
-            .insert(entrypoint, "_start".to_string());
         axecutor.state.trace.push(TraceEntry {
             instr_ip: 0,
             target: entrypoint,
@@ -81,6 +196,11 @@ impl Axecutor {
             None => return Err(AxError::from("ELF: No segments found")),
         };
 
+        // [start, end) of each mapped PT_LOAD segment
+        let mut mapped_segments = Vec::new();
+        let mut eh_frame_hdr = None;
+        let mut tls_segment = None;
+
         for segment in segments {
             if segment.p_vaddr == 0 {
                 debug_log!(
@@ -91,10 +211,6 @@ impl Axecutor {
                 continue;
             }
 
-            let content = file.segment_data(&segment)?;
-
-            // TODO: Scale up all allocations to next multiple of page size, also respect alignment
-
             match segment.p_type {
                 // Skippable
                 PT_NULL | PT_NOTE | PT_SHLIB | PT_PHDR => {
@@ -106,10 +222,18 @@ impl Axecutor {
                         segment.p_memsz
                     );
                 }
-                // TODO: map PT_GNU_EH_FRAME
+                // Its contents are part of a PT_LOAD segment, we only need to remember where it is for unwinding
+                PT_GNU_EH_FRAME => {
+                    debug_log!(
+                        "ELF: Found GNU_EH_FRAME segment @ {:#x} with size {:#x}",
+                        segment.p_vaddr,
+                        segment.p_memsz
+                    );
+                    eh_frame_hdr = Some(segment);
+                }
 
                 // Skippable, but we should warn and probably implement them in the future
-                PT_GNU_EH_FRAME | PT_GNU_PROPERTY => {
+                PT_GNU_PROPERTY => {
                     debug_log!(
                         "ELF: Skip loading segment of type {} ({:#x}) @ {:#x} with size {:#x}",
                         p_type_to_str(segment.p_type).unwrap_or("unknown"),
@@ -133,51 +257,25 @@ impl Axecutor {
                     }
                 }
                 PT_TLS => {
-                    // TODO: Make sure implementation is correct, see e.g. https://maskray.me/blog/2021-02-14-all-about-thread-local-storage for some notes
-
-                    // Thread-local storage
-                    debug_log!(
-                        "ELF: Loading TLS segment at {:#x} with size {:#x} and offset {:#x}",
-                        segment.p_vaddr,
-                        segment.p_memsz,
-                        segment.p_offset
-                    );
-
-                    assert_fatal!(axecutor.read_fs() == 0, "ELF: TLS already initialized");
-
-                    // See if we already have a memory area with that address
-                    let end_addr = match axecutor.mem_get_area(segment.p_vaddr) {
-                        Some(a) => {
-                            // We already have an area, let's make sure it's big enough
-                            assert_fatal!(
-                                a.len() >= segment.p_memsz,
-                                "ELF: preexisting TLS area is too small"
-                            );
-                            debug_log!("ELF: TLS area already exists, reusing it");
-                            segment.p_vaddr + a.len()
-                        }
-                        None => Err(AxError::from("ELF: TLS area does not exist, but expected it to be created by previous LOAD program header"))?,
-                    };
-
-                    // TODO: if we write the wanted flags (PROT_READ only), then libc startup will crash writing to it.
-                    // Not sure what to do about this, which is why we'll keep it writable for now
-                    // axecutor.mem_prot(segment.p_vaddr, PROT_READ | PROT_WRITE)?;
-
-                    axecutor.write_fs(end_addr);
+                    // The TLS segment only describes the initialization image, which lives inside a PT_LOAD segment.
+                    // It is set up after all PT_LOAD segments have been mapped
+                    tls_segment = Some(segment);
                 }
                 PT_GNU_RELRO => {
                     // Read-only after relocation
                     debug_log!(
-                        "ELF: Loading RELRO segment at {:#x} with size {:#x} and offset {:#x}",
+                        "ELF: Found RELRO segment at {:#x} with size {:#x} and offset {:#x}",
                         segment.p_vaddr,
                         segment.p_memsz,
                         segment.p_offset
                     );
 
-                    // TODO: check if it matters if the relro size is smaller than the segment size
-                    // Here we *should* set the flags (usually PROT_READ), but libc startup will crash writing to it.
-                    // So let's keep it writable for now
-                    // axecutor.mem_prot(segment.p_vaddr, elf_flags_to_prot(segment.p_flags))?;
+                    // Same as the dynamic loader: only whole pages are protected, so a partial last page stays writable
+                    let start = round_down_to_page_size(segment.p_vaddr);
+                    let end = round_down_to_page_size(segment_end(&segment)?);
+                    if start < end {
+                        axecutor.state.relro.pending.push((start, end));
+                    }
                 }
                 PT_LOAD => {
                     debug_log!(
@@ -188,35 +286,8 @@ impl Axecutor {
                         segment.p_offset,
                     );
 
-                    let memsz = round_up_to_page_size(segment.p_memsz);
-
-                    if memsz == segment.p_filesz {
-                        axecutor.mem_init_area_named(
-                            segment.p_vaddr,
-                            content.to_vec(),
-                            Some(format!("elf_load_header_{:#x}", segment.p_vaddr)),
-                        )?;
-                    } else {
-                        // Make sure we create the memory at full size and then write the first bytes, rest should be zeroed
-                        axecutor.mem_init_zero_named(
-                            segment.p_vaddr,
-                            memsz,
-                            format!("elf_load_zeroed_header_{:#x}", segment.p_vaddr),
-                        )?;
-
-                        if content.len() > segment.p_filesz as usize {
-                            return Err(AxError::from(
-                                "ELF: Content is larger than specified in segment header"
-                                    .to_string(),
-                            ));
-                        }
-
-                        axecutor.mem_write_bytes(
-                            segment.p_vaddr,
-                            &content[..segment.p_filesz as usize],
-                        )?;
-                    }
-                    axecutor.mem_prot(segment.p_vaddr, elf_flags_to_prot(segment.p_flags))?;
+                    let content = file.segment_data(&segment)?;
+                    mapped_segments.push(axecutor.map_load_segment(&segment, content)?);
                 }
                 _ => {
                     fatal_error!(
@@ -228,37 +299,90 @@ impl Axecutor {
             }
         }
 
-        match file.symbol_table() {
-            Ok(Some((symbol_table, str_table))) => {
-                for symbol in symbol_table.iter() {
-                    if symbol.is_undefined() {
-                        debug_log!("ELF: Ignoring undefined symbol in strtab");
-                        continue;
-                    }
-
-                    let name = match str_table.get(symbol.st_name as usize) {
-                        Ok(name) => name,
-                        Err(_) => {
-                            debug_log!("ELF: Invalid symbol name (wrong string table index)");
-                            continue;
-                        }
-                    };
+        if let Some(segment) = tls_segment {
+            // TODO: Make sure implementation is correct, see e.g. https://maskray.me/blog/2021-02-14-all-about-thread-local-storage for some notes
+
+            // Thread-local storage
+            debug_log!(
+                "ELF: Loading TLS segment at {:#x} with size {:#x} and offset {:#x}",
+                segment.p_vaddr,
+                segment.p_memsz,
+                segment.p_offset
+            );
+
+            assert_fatal!(axecutor.read_fs() == 0, "ELF: TLS already initialized");
+
+            // The access rights of the TLS segment are not applied to memory (just like the kernel doesn't),
+            // the image is part of a (usually writable) PT_LOAD segment
+            let tls_end = segment_end(&segment)?;
+            let end_addr = match mapped_segments
+                .iter()
+                .find(|(start, end)| *start <= segment.p_vaddr && tls_end <= *end)
+            {
+                Some((_, end)) => *end,
+                None => Err(AxError::from("ELF: TLS area does not exist, but expected it to be created by previous LOAD program header"))?,
+            };
+
+            axecutor.write_fs(end_addr);
+        }
 
-                    axecutor
-                        .symbol_table
-                        .insert(symbol.st_value, name.to_string());
+        if !axecutor.state.relro.pending.is_empty() {
+            // Make sure the RELRO region is actually covered by what we loaded
+            for (start, end) in &axecutor.state.relro.pending {
+                if !mapped_segments.iter().any(|(s, e)| s <= start && end <= e) {
+                    return Err(AxError::from(format!(
+                        "ELF: RELRO region {start:#x}..{end:#x} is not part of a PT_LOAD segment"
+                    )));
                 }
             }
-            Ok(None) => {
-                debug_log!("ELF: No symbol table");
-            }
-            Err(_) => {
-                debug_log!("ELF: No symbol table");
-            }
+        }
+
+        axecutor.symbol_table = SymbolTable::from_elf(&file)?;
+        if axecutor.symbol_table.get(entrypoint).is_none() {
+            axecutor.symbol_table.insert(Symbol {
+                name: "_start".to_string(),
+                address: entrypoint,
+                size: 0,
+                symbol_type: SymbolType::Function,
+                binding: SymbolBinding::Global,
+                section: None,
+            });
+        }
+
+        // Debug information is optional, a binary with broken DWARF data can still be run
+        axecutor.debug_info = DebugInfo::from_elf(&file).unwrap_or_else(|e| {
+            debug_log!("Ignoring debug information: {}", e);
+            DebugInfo::default()
+        });
+        axecutor.unwind_info =
+            UnwindInfo::from_elf(&axecutor, &file, binary, eh_frame_hdr.as_ref()).unwrap_or_else(
+                |e| {
+                    debug_log!("Ignoring call frame information: {}", e);
+                    UnwindInfo::default()
+                },
+            );
+
+        if !axecutor.state.relro.pending.is_empty() {
+            // By the time main is called, libc startup code has processed all relocations
+            axecutor.state.relro.apply_at = axecutor.address_of("main");
         }
 
         Ok(axecutor)
     }
+
+    /// Makes all memory covered by the PT_GNU_RELRO segment read-only.
+    /// Static binaries process their own relocations during libc startup, so this is done automatically when `main` is reached
+    /// or when the guest calls `mprotect` itself. It can however also be called manually, e.g. for binaries without a `main` symbol.
+    pub fn apply_relro(&mut self) -> Result<(), AxError> {
+        debug_log!("Calling Axecutor::apply_relro");
+
+        for (start, end) in std::mem::take(&mut self.state.relro.pending) {
+            debug_log!("ELF: Applying RELRO protection to {:#x}..{:#x}", start, end);
+            self.mem_prot_range(start, end - start, PROT_READ)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -284,10 +408,10 @@ mod tests {
                     vec!["env1=val1".to_string(), "env2=val2".to_string()],
                 ).expect("Failed to init stack");
 
-                #[allow(non_upper_case_globals)]
-                static mut output: String = String::new();
+                let output = std::rc::Rc::new(std::cell::RefCell::new(String::new()));
 
-                let cb = &move |ax: &mut Axecutor, _: SupportedMnemonic| {
+                let out = output.clone();
+                let cb = move |ax: &mut Axecutor, _: SupportedMnemonic| {
                     let syscall_num = ax.reg_read_64(SupportedRegister::RAX)?;
                     let rdi = ax.reg_read_64(SupportedRegister::RDI)?;
                     let rsi = ax.reg_read_64(SupportedRegister::RSI)?;
@@ -304,9 +428,7 @@ mod tests {
                             let result_buf = ax.mem_read_bytes(rsi, rdx)?;
                             let output_text = String::from_utf8(result_buf)?;
 
-                            unsafe {
-                                output.push_str(&output_text);
-                            }
+                            out.borrow_mut().push_str(&output_text);
 
                             // Return number of bytes written
                             ax.reg_write_64(SupportedRegister::RAX, rdx)?;
@@ -329,7 +451,7 @@ mod tests {
 
                 ax.execute().await.expect("Failed to execute");
 
-                assert_eq!(unsafe { output.clone() }, $expected_output, "Output does not match");
+                assert_eq!(*output.borrow(), $expected_output, "Output does not match");
 
                 let exit_code = ax.reg_read_64(SupportedRegister::RDI).expect("Failed to read exit code from RDI");
                 assert_eq!(exit_code, $expected_exit_code, "Exit code does not match");
@@ -358,6 +480,78 @@ mod tests {
 
     // test_binary![fib_c_nostdlib; "../../testdata/fib_c_nostdlib.bin"; "1\n1\n2\n3\n5\n8\nd\n15\n22\n37\n59\n90\ne9\n179\n262\n3db\n63d\na18\n1055\n1a6d\n2ac2\n452f\n6ff1\nb520\n12511"; 0];
 
+    test_async![segments_mapped_at_page_granularity; async {
+        let ax = Axecutor::from_binary(include_bytes!("../../testdata/exit_c.bin")).expect("Failed to parse binary");
+
+        // The writable segment starts at 0x4bd0c0, but the whole page is mapped
+        ax.mem_read_64(0x4bd000).expect("Failed to read start of page");
+        // .bss ends at 0x4c3980 and is zeroed up to the end of the page
+        assert_eq!(ax.mem_read_64(0x4c3ff8).expect("Failed to read end of page"), 0);
+        ax.mem_read_8(0x4c4000).expect_err("Memory after the segment should not be mapped");
+    }];
+
+    test_async![relro_read_only_after_apply; async {
+        let mut ax = Axecutor::from_binary(include_bytes!("../../testdata/exit_c.bin")).expect("Failed to parse binary");
+
+        // The last partial page is not protected, same as in the dynamic loader
+        assert_eq!(ax.state.relro.pending, vec![(0x4bd000, 0x4c0000)]);
+        assert_eq!(ax.state.relro.apply_at, ax.address_of("main"));
+
+        // libc startup code may still write there before relocation processing has finished
+        ax.mem_write_64(0x4bfff8, 1).expect("RELRO region should be writable at first");
+
+        ax.apply_relro().expect("Failed to apply RELRO");
+        assert!(ax.state.relro.pending.is_empty());
+
+        let err = ax.mem_write_64(0x4bfffc, 2).expect_err("RELRO region should be read-only");
+        assert!(
+            err.to_string().contains("Access violation: write of 8 bytes at address 0x4bfffc faulted at 0x4bfffc"),
+            "Unexpected error: {err}"
+        );
+        ax.mem_write_64(0x4c0000, 2).expect("Rest of the segment should stay writable");
+        assert_eq!(ax.mem_read_64(0x4bfff8).expect("RELRO region should be readable"), 1);
+    }];
+
+    // Sets p_memsz of the first program header of the given type
+    fn with_segment_size(binary: &[u8], p_type: u32, size: u64) -> Vec<u8> {
+        use elf::endian::AnyEndian;
+        use elf::ElfBytes;
+
+        let file = ElfBytes::<AnyEndian>::minimal_parse(binary).unwrap();
+        let index = file
+            .segments()
+            .unwrap()
+            .iter()
+            .position(|s| s.p_type == p_type)
+            .unwrap();
+        let p_memsz = file.ehdr.e_phoff as usize + index * file.ehdr.e_phentsize as usize + 40;
+
+        let mut patched = binary.to_vec();
+        patched[p_memsz..p_memsz + 8].copy_from_slice(&size.to_le_bytes());
+        patched
+    }
+
+    test_async![wrapping_segments_are_rejected; async {
+        use elf::abi::{PT_GNU_RELRO, PT_LOAD};
+
+        let binary = include_bytes!("../../testdata/exit_c.bin");
+        for p_type in [PT_LOAD, PT_GNU_RELRO] {
+            let err = Axecutor::from_binary(&with_segment_size(binary, p_type, u64::MAX))
+                .expect_err("Segment wraps around the address space");
+            assert!(err.to_string().contains("wraps around the address space"), "{err}");
+        }
+    }];
+
+    test_async![code_segment_not_writable; async {
+        let mut ax = Axecutor::from_binary(include_bytes!("../../testdata/hello_world.bin")).expect("Failed to parse binary");
+
+        let err = ax.mem_write_8(0x401000, 0xcc).expect_err("Code should not be writable");
+        assert!(
+            err.to_string().contains("faulted at 0x401000 in memory area elf_load_header_0x401000"),
+            "Unexpected error: {err}"
+        );
+    }];
+
     test_async![binary_without_symbols; async {
         let bin = Axecutor::from_binary(include_bytes!("../../testdata/exit_c_no_symbols.bin")).expect("Failed to parse binary");
         // Should only include the _start symbol
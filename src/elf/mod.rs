@@ -1,2 +1,5 @@
+pub mod coredump;
+pub mod dwarf;
 #[allow(clippy::module_inception)]
 pub mod elf;
+pub mod symbols;
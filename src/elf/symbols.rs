@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+
+use elf::abi::*;
+use elf::endian::AnyEndian;
+use elf::ElfBytes;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymbolType {
+    NoType,
+    Object,
+    Function,
+    Section,
+    File,
+    Other,
+}
+
+impl From<u8> for SymbolType {
+    fn from(st_type: u8) -> Self {
+        match st_type {
+            STT_NOTYPE => SymbolType::NoType,
+            STT_OBJECT => SymbolType::Object,
+            STT_FUNC | STT_GNU_IFUNC => SymbolType::Function,
+            STT_SECTION => SymbolType::Section,
+            STT_FILE => SymbolType::File,
+            _ => SymbolType::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymbolBinding {
+    Local,
+    Global,
+    Weak,
+    Other,
+}
+
+impl From<u8> for SymbolBinding {
+    fn from(st_bind: u8) -> Self {
+        match st_bind {
+            STB_LOCAL => SymbolBinding::Local,
+            STB_GLOBAL | STB_GNU_UNIQUE => SymbolBinding::Global,
+            STB_WEAK => SymbolBinding::Weak,
+            _ => SymbolBinding::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    pub symbol_type: SymbolType,
+    pub binding: SymbolBinding,
+    // Name of the section this symbol is defined in, None for symbols not coming from an ELF file
+    pub section: Option<String>,
+}
+
+impl Symbol {
+    fn contains(&self, addr: u64) -> bool {
+        self.address <= addr && addr - self.address < self.size
+    }
+
+    // Section and file symbols only describe the layout of the binary, they should never show up in lookups
+    fn names_location(&self) -> bool {
+        !matches!(self.symbol_type, SymbolType::Section | SymbolType::File)
+    }
+
+    // If multiple symbols share an address, prefer functions over data and global symbols over local ones
+    fn rank(&self) -> (u8, u8) {
+        let type_rank = match self.symbol_type {
+            SymbolType::Function => 0,
+            SymbolType::Object => 1,
+            SymbolType::NoType => 2,
+            _ => 3,
+        };
+        let binding_rank = match self.binding {
+            SymbolBinding::Global => 0,
+            SymbolBinding::Weak => 1,
+            _ => 2,
+        };
+        (type_rank, binding_rank)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SectionRange {
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SymbolTable {
+    // Sorted by address
+    symbols: Vec<Symbol>,
+    // Indices into `symbols`, rebuilt by `reindex` instead of being saved
+    #[serde(skip)]
+    by_name: HashMap<String, usize>,
+    // Address ranges of all allocated sections, used to decide how far an unsized symbol extends
+    sections: Vec<SectionRange>,
+}
+
+impl SymbolTable {
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    // Adds a single symbol at its place in the sorted table, without sorting and reindexing all symbols like `extend`
+    pub(crate) fn insert(&mut self, symbol: Symbol) {
+        let idx = self
+            .symbols
+            .partition_point(|s| (s.address, &s.name) <= (symbol.address, &symbol.name));
+        if self.symbols[..idx]
+            .iter()
+            .rev()
+            .take_while(|s| s.address == symbol.address && s.name == symbol.name)
+            .any(|s| *s == symbol)
+        {
+            return;
+        }
+
+        self.symbols.insert(idx, symbol);
+        for other in self.by_name.values_mut() {
+            if *other >= idx {
+                *other += 1;
+            }
+        }
+        self.index_symbol(idx);
+    }
+
+    pub(crate) fn extend(&mut self, symbols: impl IntoIterator<Item = Symbol>) {
+        self.symbols.extend(symbols);
+        self.symbols
+            .sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+        // The same symbol might be defined multiple times, e.g. in both .symtab and .dynsym
+        self.symbols.dedup();
+        self.reindex();
+    }
+
+    pub(crate) fn add_section(&mut self, start: u64, size: u64) {
+        if size == 0 {
+            return;
+        }
+        self.sections.push(SectionRange {
+            start,
+            end: start.saturating_add(size),
+        });
+    }
+
+    pub(crate) fn reindex(&mut self) {
+        self.by_name.clear();
+        for idx in 0..self.symbols.len() {
+            self.index_symbol(idx);
+        }
+    }
+
+    // Makes the symbol at `idx` the one found by its name if it ranks better than the current one, or equal and comes first
+    fn index_symbol(&mut self, idx: usize) {
+        let symbol = &self.symbols[idx];
+        if !symbol.names_location() {
+            return;
+        }
+        match self.by_name.get(&symbol.name) {
+            Some(&other) if (self.symbols[other].rank(), other) <= (symbol.rank(), idx) => {}
+            _ => {
+                self.by_name.insert(symbol.name.clone(), idx);
+            }
+        }
+    }
+
+    fn section_of(&self, addr: u64) -> Option<usize> {
+        self.sections
+            .iter()
+            .position(|s| s.start <= addr && addr < s.end)
+    }
+
+    // Returns the best symbol among those at the same address as the symbol at index `idx`
+    fn best_at(&self, idx: usize) -> &Symbol {
+        let address = self.symbols[idx].address;
+        self.symbols
+            .iter()
+            .skip(self.symbols.partition_point(|s| s.address < address))
+            .take_while(|s| s.address == address)
+            .filter(|s| s.names_location())
+            .min_by_key(|s| s.rank())
+            .unwrap_or(&self.symbols[idx])
+    }
+
+    /// Returns the symbol that starts exactly at the given address.
+    pub fn get(&self, addr: u64) -> Option<&Symbol> {
+        let idx = self.symbols.partition_point(|s| s.address < addr);
+        match self.symbols.get(idx) {
+            Some(s) if s.address == addr => {
+                let best = self.best_at(idx);
+                if best.names_location() {
+                    Some(best)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the symbol with the given name, preferring global functions if the name is defined multiple times.
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&idx| &self.symbols[idx])
+    }
+
+    /// Returns the symbol containing the given address and the offset of the address into it.
+    /// Symbols with a size are matched exactly. Symbols without size (e.g. labels in assembly files) extend up to the next symbol,
+    /// but never beyond the end of their section.
+    pub fn lookup(&self, addr: u64) -> Option<(&Symbol, u64)> {
+        let end = self.symbols.partition_point(|s| s.address <= addr);
+        let preceding = || {
+            self.symbols[..end]
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, s)| s.names_location())
+        };
+
+        // Functions and objects don't nest, so only the closest symbol with a size can contain the address
+        let mut sized_end = 0;
+        if let Some((idx, s)) = preceding().find(|(_, s)| s.size > 0) {
+            if s.contains(addr) {
+                let best = self.best_at(idx);
+                if best.contains(addr) {
+                    return Some((best, addr - best.address));
+                }
+                return Some((s, addr - s.address));
+            }
+            sized_end = s.address.saturating_add(s.size);
+        }
+
+        if let Some((idx, s)) = preceding().find(|(_, s)| s.size == 0) {
+            if s.address >= sized_end && self.section_of(s.address) == self.section_of(addr) {
+                let best = self.best_at(idx);
+                return Some((best, addr - best.address));
+            }
+        }
+
+        None
+    }
+
+    /// Reads `.symtab`, or `.dynsym` if the binary has been stripped.
+    /// Undefined, absolute and thread-local symbols are skipped as their value is not an address in memory.
+    pub(crate) fn from_elf(file: &ElfBytes<AnyEndian>) -> Result<SymbolTable, AxError> {
+        let mut table = SymbolTable::default();
+
+        let (section_headers, section_names) = match file.section_headers_with_strtab()? {
+            (Some(headers), Some(names)) => (headers, Some(names)),
+            (Some(headers), None) => (headers, None),
+            (None, _) => {
+                debug_log!("ELF: No section headers, cannot read symbols");
+                return Ok(table);
+            }
+        };
+
+        let mut sections = Vec::new();
+        for header in section_headers.iter() {
+            let name = match &section_names {
+                Some(names) => names.get(header.sh_name as usize).unwrap_or(""),
+                None => "",
+            };
+            sections.push(name.to_string());
+            if header.sh_flags & SHF_ALLOC as u64 != 0 && header.sh_addr != 0 {
+                table.add_section(header.sh_addr, header.sh_size);
+            }
+        }
+
+        let (symbol_table, str_table) = match file.symbol_table() {
+            Ok(Some(tables)) => tables,
+            _ => match file.dynamic_symbol_table() {
+                Ok(Some(tables)) => {
+                    debug_log!("ELF: No symbol table, using dynamic symbol table");
+                    tables
+                }
+                _ => {
+                    debug_log!("ELF: No symbol table");
+                    return Ok(table);
+                }
+            },
+        };
+
+        let mut symbols = Vec::new();
+        for symbol in symbol_table.iter() {
+            if symbol.is_undefined() || symbol.st_shndx == SHN_ABS || symbol.st_shndx == SHN_COMMON
+            {
+                debug_log!("ELF: Ignoring symbol that is not defined in a section");
+                continue;
+            }
+            if symbol.st_symtype() == STT_TLS {
+                continue;
+            }
+
+            let name = match str_table.get(symbol.st_name as usize) {
+                Ok(name) => name,
+                Err(_) => {
+                    debug_log!("ELF: Invalid symbol name (wrong string table index)");
+                    continue;
+                }
+            };
+
+            symbols.push(Symbol {
+                name: name.to_string(),
+                address: symbol.st_value,
+                size: symbol.st_size,
+                symbol_type: SymbolType::from(symbol.st_symtype()),
+                binding: SymbolBinding::from(symbol.st_bind()),
+                section: sections.get(symbol.st_shndx as usize).cloned(),
+            });
+        }
+        table.extend(symbols);
+
+        Ok(table)
+    }
+}
+
+impl Axecutor {
+    /// Returns full information about the symbol containing the given address.
+    pub fn symbol_info(&self, addr: u64) -> Option<&Symbol> {
+        self.symbol_table.lookup(addr).map(|(sym, _)| sym)
+    }
+
+    // Formats an address as e.g. `main+0x1a@0x401126 (main.c:12)` for traces and call stacks
+    pub(crate) fn format_symbolized(&self, addr: u64) -> String {
+        self.format_symbolized_with_location(addr, addr)
+    }
+
+    // Same as format_symbolized, but takes the source location from another address, e.g. the call before a return address
+    pub(crate) fn format_symbolized_with_location(&self, addr: u64, location_addr: u64) -> String {
+        // Fall back to function names from debug info in case the symbol table has been stripped
+        let symbol = self.symbol_at(addr).or_else(|| {
+            self.debug_info
+                .function(addr)
+                .map(|(name, offset)| match offset {
+                    0 => name.to_string(),
+                    _ => format!("{name}+{offset:#x}"),
+                })
+        });
+
+        let mut formatted = match symbol {
+            Some(sym) => format!("{sym}@{addr:#x}"),
+            None => format!("{addr:#x}"),
+        };
+        if let Some(location) = self.addr_to_line(location_addr) {
+            formatted.push_str(&format!(" ({location})"));
+        }
+        formatted
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Get the symbol containing the given address in the form `name+0x10`, or just `name` if the address is the start of the symbol.
+    /// This only works if the ELF binary contains a symbol table. If no symbol is found, None or undefined is returned.
+    pub fn symbol_at(&self, addr: u64) -> Option<String> {
+        self.symbol_table
+            .lookup(addr)
+            .map(|(sym, offset)| match offset {
+                0 => sym.name.clone(),
+                _ => format!("{}+{:#x}", sym.name, offset),
+            })
+    }
+
+    /// Get the address of the symbol with the given name.
+    /// If the name is defined multiple times, global functions are preferred. If no symbol is found, None or undefined is returned.
+    pub fn address_of(&self, name: &str) -> Option<u64> {
+        self.symbol_table.by_name(name).map(|sym| sym.address)
+    }
+
+    /// Get the size of the symbol with the given name, as recorded in the symbol table.
+    /// Symbols without size information, like labels in assembly files, have a size of 0.
+    pub fn symbol_size(&self, name: &str) -> Option<u64> {
+        self.symbol_table.by_name(name).map(|sym| sym.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+
+    fn label(name: &str, address: u64, size: u64, symbol_type: SymbolType) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            address,
+            size,
+            symbol_type,
+            binding: SymbolBinding::Global,
+            section: Some(".text".to_string()),
+        }
+    }
+
+    #[test]
+    fn lookup_sized_and_unsized() {
+        let mut table = SymbolTable::default();
+        table.add_section(0x1000, 0x100);
+        table.extend(vec![
+            label("f", 0x1000, 0x10, SymbolType::Function),
+            label("f_alias", 0x1000, 0x10, SymbolType::NoType),
+            label("label", 0x1020, 0, SymbolType::NoType),
+            label("section", 0x1000, 0, SymbolType::Section),
+            label("end", 0x2000, 0, SymbolType::NoType),
+        ]);
+
+        assert_eq!(
+            table.lookup(0x1004).map(|(s, o)| (s.name.as_str(), o)),
+            Some(("f", 4))
+        );
+        assert_eq!(table.lookup(0x1010), None);
+        assert_eq!(
+            table.lookup(0x1030).map(|(s, o)| (s.name.as_str(), o)),
+            Some(("label", 0x10))
+        );
+        // Outside of the .text section
+        assert_eq!(table.lookup(0x1100), None);
+        assert_eq!(table.get(0x1000).map(|s| s.name.as_str()), Some("f"));
+        assert_eq!(table.by_name("section"), None);
+    }
+
+    #[test]
+    fn insert_keeps_table_sorted_and_indexed() {
+        let symbols = vec![
+            label("b", 0x2000, 0x10, SymbolType::Function),
+            label("a", 0x1000, 0x10, SymbolType::Object),
+            label("a", 0x3000, 0x10, SymbolType::Function),
+            label("c", 0x1000, 0, SymbolType::NoType),
+            label("a", 0x1000, 0x10, SymbolType::Object),
+            label("top", u64::MAX - 4, 0x10, SymbolType::Function),
+        ];
+        let mut extended = SymbolTable::default();
+        extended.extend(symbols.clone());
+        let mut inserted = SymbolTable::default();
+        for symbol in symbols {
+            inserted.insert(symbol);
+        }
+
+        assert_eq!(inserted.symbols, extended.symbols);
+        assert_eq!(inserted.by_name, extended.by_name);
+        assert_eq!(inserted.len(), 5);
+        // Functions are preferred over objects with the same name
+        assert_eq!(inserted.by_name("a").map(|s| s.address), Some(0x3000));
+        assert_eq!(inserted.by_name("b").map(|s| s.address), Some(0x2000));
+        assert_eq!(inserted.by_name("c").map(|s| s.address), Some(0x1000));
+        // A symbol reaching past the end of the address space
+        assert_eq!(
+            inserted.lookup(u64::MAX).map(|(s, o)| (s.name.as_str(), o)),
+            Some(("top", 4))
+        );
+    }
+
+    test_async![symbols_from_binary; async {
+        let binary = include_bytes!("../../testdata/c_loop.bin");
+        let ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+
+        assert_eq!(ax.address_of("_start"), Some(0x40101a));
+        assert_eq!(ax.symbol_size("_start"), Some(55));
+        assert_eq!(ax.symbol_at(0x40101a), Some("_start".to_string()));
+        assert_eq!(ax.symbol_at(0x401034), Some("_start+0x1a".to_string()));
+        assert_eq!(ax.symbol_at(0x401005), Some("sys_exit+0x5".to_string()));
+
+        let info = ax.symbol_info(0x401005).expect("sys_exit not found");
+        assert_eq!(info.symbol_type, SymbolType::Function);
+        assert_eq!(info.binding, SymbolBinding::Global);
+        assert_eq!(info.section.as_deref(), Some(".text"));
+    }];
+
+    test_async![unsized_labels_end_at_section; async {
+        let binary = include_bytes!("../../testdata/trace.bin");
+        let ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+
+        assert_eq!(ax.symbol_at(0x40101e), Some("first_level+0x9".to_string()));
+        assert_eq!(ax.symbol_at(0x401052), Some("third_level+0x16".to_string()));
+        // .text ends at 0x401055, __bss_start is in another section
+        assert_eq!(ax.symbol_at(0x401100), None);
+        assert_eq!(ax.symbol_size("first_level"), Some(0));
+    }];
+
+    #[test]
+    fn stripped_binary_uses_dynsym() {
+        // Position-independent binary with an exported dynamic symbol table, but no .symtab
+        let binary = include_bytes!("../../testdata/dynsym_only.bin");
+        let file = ElfBytes::<AnyEndian>::minimal_parse(binary).expect("Failed to parse binary");
+        let table = SymbolTable::from_elf(&file).expect("Failed to read symbols");
+
+        assert_eq!(table.by_name("compute").map(|s| s.address), Some(0x1008));
+        assert_eq!(
+            table.lookup(0x1012).map(|(s, o)| (s.name.as_str(), o)),
+            Some(("_start", 5))
+        );
+        assert_eq!(table.by_name("sys_exit").map(|s| s.size), Some(8));
+    }
+}
@@ -0,0 +1,620 @@
+//! A server for the GDB Remote Serial Protocol, which allows debugging emulated programs with GDB:
+//!
+//! ```text
+//! ax --gdb 127.0.0.1:1234 program
+//! gdb program -ex "target remote 127.0.0.1:1234"
+//! ```
+//!
+//! See <https://sourceware.org/gdb/current/onlinedocs/gdb/Remote-Protocol.html> for the protocol.
+
+mod target;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::helpers::signals::fault_signal;
+use crate::state::registers::SupportedRegister::*;
+
+// Signal numbers used in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// Ctrl-C from GDB arrives as a single byte outside of any packet
+const INTERRUPT: u8 = 0x03;
+// How many instructions to execute between checks for an interrupt while continuing
+const INTERRUPT_CHECK_INTERVAL: u64 = 4096;
+
+fn io_error(e: io::Error) -> AxError {
+    AxError::from(format!("GDB: Connection error: {e}"))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, AxError> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(AxError::from(format!(
+            "GDB: Odd length of hex string {hex}"
+        )));
+    }
+
+    // Packets may contain any bytes, so don't slice the string, which could split a character
+    let nibble = |b: u8| {
+        char::from(b)
+            .to_digit(16)
+            .ok_or_else(|| AxError::from(format!("GDB: Invalid hex string {hex}")))
+    };
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| Ok((nibble(pair[0])? << 4 | nibble(pair[1])?) as u8))
+        .collect()
+}
+
+fn parse_hex_u64(hex: &str) -> Result<u64, AxError> {
+    u64::from_str_radix(hex, 16)
+        .map_err(|e| AxError::from(format!("GDB: Invalid hex number {hex}: {e}")))
+}
+
+// Parses "addr,length" as used by memory and qXfer packets
+fn parse_range(s: &str) -> Result<(u64, u64), AxError> {
+    let (addr, len) = s
+        .split_once(',')
+        .ok_or_else(|| AxError::from(format!("GDB: Expected address and length in {s}")))?;
+    Ok((parse_hex_u64(addr)?, parse_hex_u64(len)?))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Serves a single GDB connection, controlling the given Axecutor.
+/// The emulated program is stopped until GDB continues or single-steps it.
+pub struct GdbServer<'a> {
+    ax: &'a mut Axecutor,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // Set after GDB sent QStartNoAckMode, packets are no longer acknowledged with +
+    no_ack: bool,
+    // Reply to the last "?" packet, i.e. why the program is currently stopped
+    last_stop: String,
+}
+
+impl<'a> GdbServer<'a> {
+    pub fn new(ax: &'a mut Axecutor, stream: TcpStream) -> Result<GdbServer<'a>, AxError> {
+        stream.set_nodelay(true).map_err(io_error)?;
+        let writer = stream.try_clone().map_err(io_error)?;
+
+        Ok(GdbServer {
+            ax,
+            reader: BufReader::new(stream),
+            writer,
+            no_ack: false,
+            last_stop: format!("S{SIGTRAP:02x}"),
+        })
+    }
+
+    /// Handles requests until GDB detaches, kills the program or closes the connection.
+    /// Returns the exit status of the program if it finished while being debugged.
+    pub async fn run(&mut self) -> Result<Option<u8>, AxError> {
+        while let Some(packet) = self.read_packet()? {
+            debug_log!("GDB: Received packet {}", packet);
+
+            match packet.as_str() {
+                "D" | "D;1" => {
+                    self.write_packet("OK")?;
+                    break;
+                }
+                "k" | "vKill;1" => break,
+                "QStartNoAckMode" => {
+                    // The OK reply itself is still acknowledged
+                    self.write_packet("OK")?;
+                    self.no_ack = true;
+                }
+                _ => {
+                    let reply = self.handle_packet(&packet).await?;
+                    self.write_packet(&reply)?;
+                }
+            }
+        }
+
+        Ok(if self.ax.state.finished {
+            Some(self.exit_status())
+        } else {
+            None
+        })
+    }
+
+    async fn handle_packet(&mut self, packet: &str) -> Result<String, AxError> {
+        // Malformed requests and failed memory accesses are reported to GDB instead of ending the session
+        Ok(match self.dispatch(packet).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                debug_log!("GDB: Error handling packet {}: {}", packet, e);
+                "E01".to_string()
+            }
+        })
+    }
+
+    async fn dispatch(&mut self, packet: &str) -> Result<String, AxError> {
+        if packet.is_empty() || !packet.is_char_boundary(1) {
+            return Ok(String::new());
+        }
+        let (command, args) = packet.split_at(1);
+
+        Ok(match command {
+            "?" => self.last_stop.clone(),
+            "g" => {
+                let mut regs = Vec::new();
+                for regnum in 0..target::register_count() {
+                    regs.extend(target::read_register(self.ax, regnum)?);
+                }
+                to_hex(&regs)
+            }
+            "G" => {
+                let bytes = from_hex(args)?;
+                let mut offset = 0;
+                for regnum in 0..target::register_count() {
+                    let size = target::register_size(regnum).unwrap_or(0);
+                    // GDB may leave out trailing registers
+                    if offset + size > bytes.len() {
+                        break;
+                    }
+                    target::write_register(self.ax, regnum, &bytes[offset..offset + size])?;
+                    offset += size;
+                }
+                "OK".to_string()
+            }
+            "p" => to_hex(&target::read_register(
+                self.ax,
+                parse_hex_u64(args)? as usize,
+            )?),
+            "P" => {
+                let (regnum, value) = args
+                    .split_once('=')
+                    .ok_or_else(|| AxError::from("GDB: Expected register=value"))?;
+                target::write_register(
+                    self.ax,
+                    parse_hex_u64(regnum)? as usize,
+                    &from_hex(value)?,
+                )?;
+                "OK".to_string()
+            }
+            "m" => {
+                let (addr, len) = parse_range(args)?;
+                match self.ax.mem_peek_bytes(addr, len) {
+                    Ok(bytes) => to_hex(&bytes),
+                    // EFAULT, GDB shows "Cannot access memory at address"
+                    Err(_) => "E0e".to_string(),
+                }
+            }
+            "M" => {
+                let (range, data) = args
+                    .split_once(':')
+                    .ok_or_else(|| AxError::from("GDB: Expected addr,length:data"))?;
+                let (addr, _) = parse_range(range)?;
+                match self.ax.mem_poke_bytes(addr, &from_hex(data)?) {
+                    Ok(()) => "OK".to_string(),
+                    Err(_) => "E0e".to_string(),
+                }
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    self.ax.reg_write_64(RIP, parse_hex_u64(args)?)?;
+                }
+                self.resume(command == "s").await?
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", args)?,
+            "H" | "T" => "OK".to_string(),
+            "q" | "Q" | "v" => self.query(packet).await?,
+            // Empty reply for anything unsupported, GDB falls back to other packets (e.g. M instead of X)
+            _ => String::new(),
+        })
+    }
+
+    async fn query(&mut self, packet: &str) -> Result<String, AxError> {
+        Ok(match packet {
+            p if p.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qSymbol::" => "OK".to_string(),
+            p if p.starts_with("qXfer:features:read:target.xml:") => {
+                let (offset, length) = parse_range(&p["qXfer:features:read:target.xml:".len()..])?;
+                let xml = target::target_xml();
+                let start = (offset as usize).min(xml.len());
+                let end = start.saturating_add(length as usize).min(xml.len());
+                // 'l' marks the last chunk, 'm' means there is more data
+                let marker = if end == xml.len() { 'l' } else { 'm' };
+                format!("{marker}{}", &xml[start..end])
+            }
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            p if p.starts_with("vCont;") => {
+                // All actions apply to the only thread, so the first one decides
+                let action = p["vCont;".len()..]
+                    .split(';')
+                    .next()
+                    .and_then(|a| a.chars().next());
+                match action {
+                    Some('c' | 'C') => self.resume(false).await?,
+                    Some('s' | 'S') => self.resume(true).await?,
+                    _ => String::new(),
+                }
+            }
+            _ => String::new(),
+        })
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Result<String, AxError> {
+        let mut parts = args.split(',');
+        // Only software breakpoints (type 0) are supported, GDB falls back to them for hardware breakpoints
+        if parts.next() != Some("0") {
+            return Ok(String::new());
+        }
+        let addr = parse_hex_u64(
+            parts
+                .next()
+                .ok_or_else(|| AxError::from("GDB: Missing breakpoint address"))?,
+        )?;
+
+        if insert {
+            self.ax.add_breakpoint(addr)?;
+        } else {
+            self.ax.remove_breakpoint(addr)?;
+        }
+
+        Ok("OK".to_string())
+    }
+
+    // Programs that finish without calling exit, e.g. by returning from the entry point, are reported as successful
+    fn exit_status(&self) -> u8 {
+        self.ax.state.syscalls.exit_code.unwrap_or(0) as u8
+    }
+
+    // The signal the program would have received for the error, like the one delivered to signal handlers
+    fn error_signal(e: &AxError) -> u8 {
+        match (e.exception(), e.kind()) {
+            (Some(exception), _) => fault_signal(exception.kind) as u8,
+            (None, ErrorKind::Signal { signal }) => *signal as u8,
+            _ => SIGSEGV,
+        }
+    }
+
+    /// Executes instructions until a breakpoint is hit, the program finishes or fails, or GDB interrupts.
+    /// Returns the stop reply for GDB.
+    async fn resume(&mut self, single_step: bool) -> Result<String, AxError> {
+        let reply = self.resume_impl(single_step).await?;
+        self.last_stop = reply.clone();
+        Ok(reply)
+    }
+
+    async fn resume_impl(&mut self, single_step: bool) -> Result<String, AxError> {
+        if self.ax.state.finished {
+            return Ok(format!("W{:02x}", self.exit_status()));
+        }
+
+        let mut executed = 0u64;
+        loop {
+            let rip = self.ax.reg_read_64(RIP)?;
+            // The instruction at the current position is executed even if it has a breakpoint, otherwise continuing would not be possible
+            if executed > 0 && self.ax.breakpoint_hit()?.is_some() {
+                return Ok(format!("T{SIGTRAP:02x}swbreak:;"));
+            }
+
+            match self.ax.step().await {
+                Ok(true) => {}
+                Ok(false) => return Ok(format!("W{:02x}", self.exit_status())),
+                Err(e) => {
+                    // step() advances RIP before executing, so GDB should see the failing instruction instead
+                    self.ax.reg_write_64(RIP, rip)?;
+                    // Console output, so the user sees why the program stopped
+                    self.write_packet(&format!("O{}", to_hex(format!("{e}\n").as_bytes())))?;
+                    return Ok(format!("T{:02x}", Self::error_signal(&e)));
+                }
+            }
+            executed += 1;
+
+            if single_step {
+                return Ok(format!("T{SIGTRAP:02x}"));
+            }
+            if executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && self.interrupt_requested()? {
+                return Ok(format!("T{SIGINT:02x}"));
+            }
+        }
+    }
+
+    fn interrupt_requested(&mut self) -> Result<bool, AxError> {
+        if self.reader.buffer().is_empty() {
+            self.reader
+                .get_ref()
+                .set_nonblocking(true)
+                .map_err(io_error)?;
+            let result = self.reader.fill_buf().map(|buf| buf.len());
+            self.reader
+                .get_ref()
+                .set_nonblocking(false)
+                .map_err(io_error)?;
+
+            match result {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+
+        if self.reader.buffer().first() == Some(&INTERRUPT) {
+            self.reader.consume(1);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, AxError> {
+        let mut byte = [0u8];
+        match self.reader.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    // Returns the next packet without framing, or None if the connection was closed
+    fn read_packet(&mut self) -> Result<Option<String>, AxError> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                // Acknowledgements and interrupts while already stopped
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data).map_err(io_error)?;
+            if data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut sum = [0u8; 2];
+            self.reader.read_exact(&mut sum).map_err(io_error)?;
+
+            if self.no_ack {
+                return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+            }
+
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                == Some(checksum(&data));
+            if valid {
+                self.writer.write_all(b"+").map_err(io_error)?;
+                return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+            }
+
+            debug_log!("GDB: Checksum mismatch, requesting retransmission");
+            self.writer.write_all(b"-").map_err(io_error)?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> Result<(), AxError> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        // '$', '#', '}' and '*' have special meaning and must be escaped
+        for &b in data.as_bytes() {
+            match b {
+                b'$' | b'#' | b'}' | b'*' => packet.extend_from_slice(&[b'}', b ^ 0x20]),
+                _ => packet.push(b),
+            }
+        }
+        let sum = checksum(&packet[1..]);
+        packet.extend_from_slice(format!("#{sum:02x}").as_bytes());
+
+        loop {
+            self.writer.write_all(&packet).map_err(io_error)?;
+            if self.no_ack {
+                return Ok(());
+            }
+
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::{c_loop, test_async};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Minimal GDB client side of the protocol, acknowledging every reply
+    struct Client {
+        stream: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn connect(port: u16) -> Client {
+            let stream = TcpStream::connect(("127.0.0.1", port)).expect("Failed to connect");
+            Client {
+                stream: BufReader::new(stream),
+            }
+        }
+
+        fn send(&mut self, data: &str) {
+            let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+            self.stream.get_mut().write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0u8];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+', "Packet {data} was not acknowledged");
+        }
+
+        fn receive(&mut self) -> String {
+            let mut data = Vec::new();
+            self.stream.read_until(b'$', &mut data).unwrap();
+            data.clear();
+            self.stream.read_until(b'#', &mut data).unwrap();
+            data.pop();
+            let mut sum = [0u8; 2];
+            self.stream.read_exact(&mut sum).unwrap();
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap(),
+                checksum(&data)
+            );
+            self.stream.get_mut().write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.receive()
+        }
+    }
+
+    // Runs the server for ax on a loopback socket while the client function talks to it
+    async fn debug_session<F>(ax: &mut Axecutor, client: F) -> Option<u8>
+    where
+        F: FnOnce(&mut Client) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || client(&mut Client::connect(port)));
+
+        let (stream, _) = listener.accept().expect("Failed to accept");
+        let status = GdbServer::new(ax, stream)
+            .expect("Failed to create server")
+            .run()
+            .await
+            .expect("Server failed");
+        handle.join().expect("Client failed");
+        status
+    }
+
+    test_async![from_hex_rejects_invalid_input; async {
+        assert_eq!(from_hex("00a1FF").unwrap(), [0x00, 0xa1, 0xff]);
+        from_hex("0").expect_err("Odd length");
+        from_hex("0g").expect_err("Invalid digit");
+        // Non-ASCII bytes of a packet become replacement characters, which are longer than one byte
+        from_hex(&String::from_utf8_lossy(b"a\xff")).expect_err("Invalid character");
+        from_hex(&String::from_utf8_lossy(b"a\xffb")).expect_err("Invalid character");
+    }];
+
+    test_async![registers_and_memory; async {
+        let mut ax = c_loop();
+        let rsp = ax.reg_read_64(RSP).unwrap();
+
+        let status = debug_session(&mut ax, move |gdb| {
+            assert!(gdb.request("qSupported:multiprocess+;swbreak+").contains("qXfer:features:read+"));
+            assert_eq!(gdb.request("?"), "S05");
+
+            // The target description is read in chunks
+            let first = gdb.request("qXfer:features:read:target.xml:0,100");
+            assert!(first.starts_with("m<?xml"));
+            let last = gdb.request("qXfer:features:read:target.xml:100,10000");
+            assert!(last.starts_with('l'));
+            assert!(last.contains("<reg name=\"xmm15\" bitsize=\"128\" type=\"vec128\" regnum=\"55\"/>"));
+
+            let regs = gdb.request("g");
+            // 16 GPRs, rip, 6 segment and 8 control registers, 8 x87 registers, 16 XMM registers, mxcsr, fs_base, gs_base
+            assert_eq!(regs.len(), 2 * (17 * 8 + 4 + 14 * 4 + 8 * 10 + 16 * 16 + 4 + 2 * 8));
+            // rip is register 16, rsp is register 7 and adjusted like in a native process
+            assert_eq!(gdb.request("p10"), to_hex(&0x40101au64.to_le_bytes()));
+            assert_eq!(gdb.request("p7"), to_hex(&(rsp + 8).to_le_bytes()));
+
+            assert_eq!(gdb.request(&format!("P0={}", to_hex(&0x1234u64.to_le_bytes()))), "OK");
+            assert_eq!(gdb.request("p0"), "3412000000000000");
+            assert_eq!(gdb.request(&format!("P28={}", to_hex(&7u128.to_le_bytes()))), "OK");
+            assert_eq!(gdb.request("p28"), to_hex(&7u128.to_le_bytes()));
+
+            // endbr64 at _start
+            assert_eq!(gdb.request("m40101a,4"), "f30f1efa");
+            assert_eq!(gdb.request("m0,4"), "E0e");
+
+            gdb.send("QStartNoAckMode");
+            assert_eq!(gdb.receive(), "OK");
+            gdb.stream.get_mut().write_all(b"$D#44").unwrap();
+        }).await;
+
+        assert_eq!(status, None);
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 0x1234);
+        assert_eq!(ax.internal_reg_read_128(XMM0).unwrap(), 7);
+    }];
+
+    test_async![breakpoints_and_steps; async {
+        let mut ax = c_loop();
+
+        let status = debug_session(&mut ax, |gdb| {
+            // Loop body, executed three times
+            assert_eq!(gdb.request("Z0,401036,1"), "OK");
+            assert_eq!(gdb.request("c"), "T05swbreak:;");
+            assert_eq!(gdb.request("p10"), to_hex(&0x401036u64.to_le_bytes()));
+            // Continuing from a breakpoint executes the instruction there first
+            assert_eq!(gdb.request("vCont;c:1"), "T05swbreak:;");
+            assert_eq!(gdb.request("s"), "T05");
+            assert_eq!(gdb.request("p10"), to_hex(&0x40103au64.to_le_bytes()));
+
+            // Change the loop counter in memory, so the next iteration is the last
+            let rbp = u64::from_le_bytes(from_hex(&gdb.request("p6")).unwrap().try_into().unwrap());
+            assert_eq!(gdb.request(&format!("M{:x},4:02000000", rbp - 8)), "OK");
+
+            assert_eq!(gdb.request("z0,401036,1"), "OK");
+            assert_eq!(gdb.request("c"), "W08");
+            gdb.send("k");
+        }).await;
+
+        assert_eq!(status, Some(8));
+    }];
+
+    test_async![exit_status_is_recorded_by_exit; async {
+        // Finishing at the end of the code is not a call to exit, even if rdi has a value
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.reg_write_64(RDI, 5).unwrap();
+
+        let status = debug_session(&mut ax, |gdb| {
+            assert_eq!(gdb.request("c"), "W00");
+            assert_eq!(gdb.request("?"), "W00");
+            gdb.send("k");
+        }).await;
+
+        assert_eq!(status, Some(0));
+    }];
+
+    test_async![divide_error_reports_sigfpe; async {
+        // xor ecx, ecx; div rcx
+        let mut ax = Axecutor::new(&[0x31, 0xc9, 0x48, 0xf7, 0xf1], 0x1000, 0x1000)
+            .expect("Failed to create axecutor");
+
+        debug_session(&mut ax, |gdb| {
+            gdb.send("c");
+            gdb.receive();
+            assert_eq!(gdb.receive(), "T08");
+            assert_eq!(gdb.request("p10"), to_hex(&0x1002u64.to_le_bytes()));
+            gdb.send("k");
+        }).await;
+    }];
+
+    test_async![fault_reports_signal; async {
+        let binary = include_bytes!("../../testdata/unwind_c_nostdlib.bin");
+        let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+        ax.init_stack(0x1000).expect("Failed to setup stack");
+
+        debug_session(&mut ax, |gdb| {
+            gdb.send("c");
+            // The error is printed on the GDB console before the stop reply
+            let output = String::from_utf8(from_hex(&gdb.receive()[1..]).unwrap()).unwrap();
+            assert!(output.contains("level3"), "{output}");
+            assert_eq!(gdb.receive(), "T0b");
+            assert_eq!(gdb.request("?"), "T0b");
+            // The faulting instruction in level3
+            assert_eq!(gdb.request("p10"), to_hex(&0x401000u64.to_le_bytes()));
+            gdb.send("k");
+        }).await;
+    }];
+}
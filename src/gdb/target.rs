@@ -0,0 +1,233 @@
+use crate::axecutor::Axecutor;
+use crate::helpers::errors::AxError;
+use crate::state::registers::SupportedRegister::{self, *};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Register(SupportedRegister),
+    Rsp,
+    Eflags,
+    FsBase,
+    GsBase,
+    // Registers the emulator doesn't model, they always read as the given value and ignore writes
+    Fixed(u64),
+}
+
+struct Register {
+    name: &'static str,
+    bitsize: usize,
+    gdb_type: &'static str,
+    feature: &'static str,
+    kind: Kind,
+}
+
+const CORE: &str = "org.gnu.gdb.i386.core";
+const SSE: &str = "org.gnu.gdb.i386.sse";
+const SEGMENTS: &str = "org.gnu.gdb.i386.segments";
+
+macro_rules! reg {
+    ($name:literal, $bitsize:literal, $gdb_type:literal, $feature:expr, $kind:expr) => {
+        Register {
+            name: $name,
+            bitsize: $bitsize,
+            gdb_type: $gdb_type,
+            feature: $feature,
+            kind: $kind,
+        }
+    };
+}
+
+// Register numbers are the indices into this list, the order is the same as GDB uses for amd64 on Linux
+const REGISTERS: [Register; 59] = [
+    reg!("rax", 64, "int64", CORE, Kind::Register(RAX)),
+    reg!("rbx", 64, "int64", CORE, Kind::Register(RBX)),
+    reg!("rcx", 64, "int64", CORE, Kind::Register(RCX)),
+    reg!("rdx", 64, "int64", CORE, Kind::Register(RDX)),
+    reg!("rsi", 64, "int64", CORE, Kind::Register(RSI)),
+    reg!("rdi", 64, "int64", CORE, Kind::Register(RDI)),
+    reg!("rbp", 64, "data_ptr", CORE, Kind::Register(RBP)),
+    reg!("rsp", 64, "data_ptr", CORE, Kind::Rsp),
+    reg!("r8", 64, "int64", CORE, Kind::Register(R8)),
+    reg!("r9", 64, "int64", CORE, Kind::Register(R9)),
+    reg!("r10", 64, "int64", CORE, Kind::Register(R10)),
+    reg!("r11", 64, "int64", CORE, Kind::Register(R11)),
+    reg!("r12", 64, "int64", CORE, Kind::Register(R12)),
+    reg!("r13", 64, "int64", CORE, Kind::Register(R13)),
+    reg!("r14", 64, "int64", CORE, Kind::Register(R14)),
+    reg!("r15", 64, "int64", CORE, Kind::Register(R15)),
+    reg!("rip", 64, "code_ptr", CORE, Kind::Register(RIP)),
+    reg!("eflags", 32, "i386_eflags", CORE, Kind::Eflags),
+    reg!("cs", 32, "int32", CORE, Kind::Fixed(0x33)),
+    reg!("ss", 32, "int32", CORE, Kind::Fixed(0x2b)),
+    reg!("ds", 32, "int32", CORE, Kind::Fixed(0)),
+    reg!("es", 32, "int32", CORE, Kind::Fixed(0)),
+    reg!("fs", 32, "int32", CORE, Kind::Fixed(0)),
+    reg!("gs", 32, "int32", CORE, Kind::Fixed(0)),
+    reg!("st0", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st1", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st2", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st3", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st4", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st5", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st6", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("st7", 80, "i387_ext", CORE, Kind::Fixed(0)),
+    reg!("fctrl", 32, "int", CORE, Kind::Fixed(0x37f)),
+    reg!("fstat", 32, "int", CORE, Kind::Fixed(0)),
+    reg!("ftag", 32, "int", CORE, Kind::Fixed(0xffff)),
+    reg!("fiseg", 32, "int", CORE, Kind::Fixed(0)),
+    reg!("fioff", 32, "int", CORE, Kind::Fixed(0)),
+    reg!("foseg", 32, "int", CORE, Kind::Fixed(0)),
+    reg!("fooff", 32, "int", CORE, Kind::Fixed(0)),
+    reg!("fop", 32, "int", CORE, Kind::Fixed(0)),
+    reg!("xmm0", 128, "vec128", SSE, Kind::Register(XMM0)),
+    reg!("xmm1", 128, "vec128", SSE, Kind::Register(XMM1)),
+    reg!("xmm2", 128, "vec128", SSE, Kind::Register(XMM2)),
+    reg!("xmm3", 128, "vec128", SSE, Kind::Register(XMM3)),
+    reg!("xmm4", 128, "vec128", SSE, Kind::Register(XMM4)),
+    reg!("xmm5", 128, "vec128", SSE, Kind::Register(XMM5)),
+    reg!("xmm6", 128, "vec128", SSE, Kind::Register(XMM6)),
+    reg!("xmm7", 128, "vec128", SSE, Kind::Register(XMM7)),
+    reg!("xmm8", 128, "vec128", SSE, Kind::Register(XMM8)),
+    reg!("xmm9", 128, "vec128", SSE, Kind::Register(XMM9)),
+    reg!("xmm10", 128, "vec128", SSE, Kind::Register(XMM10)),
+    reg!("xmm11", 128, "vec128", SSE, Kind::Register(XMM11)),
+    reg!("xmm12", 128, "vec128", SSE, Kind::Register(XMM12)),
+    reg!("xmm13", 128, "vec128", SSE, Kind::Register(XMM13)),
+    reg!("xmm14", 128, "vec128", SSE, Kind::Register(XMM14)),
+    reg!("xmm15", 128, "vec128", SSE, Kind::Register(XMM15)),
+    reg!("mxcsr", 32, "i386_mxcsr", SSE, Kind::Fixed(0x1f80)),
+    reg!("fs_base", 64, "int", SEGMENTS, Kind::FsBase),
+    reg!("gs_base", 64, "int", SEGMENTS, Kind::GsBase),
+];
+
+const CORE_TYPES: &str = r#"    <flags id="i386_eflags" size="4">
+      <field name="CF" start="0" end="0"/>
+      <field name="" start="1" end="1"/>
+      <field name="PF" start="2" end="2"/>
+      <field name="AF" start="4" end="4"/>
+      <field name="ZF" start="6" end="6"/>
+      <field name="SF" start="7" end="7"/>
+      <field name="TF" start="8" end="8"/>
+      <field name="IF" start="9" end="9"/>
+      <field name="DF" start="10" end="10"/>
+      <field name="OF" start="11" end="11"/>
+    </flags>
+"#;
+
+const SSE_TYPES: &str = r#"    <vector id="v4f" type="ieee_single" count="4"/>
+    <vector id="v2d" type="ieee_double" count="2"/>
+    <vector id="v16i8" type="int8" count="16"/>
+    <vector id="v8i16" type="int16" count="8"/>
+    <vector id="v4i32" type="int32" count="4"/>
+    <vector id="v2i64" type="int64" count="2"/>
+    <union id="vec128">
+      <field name="v4_float" type="v4f"/>
+      <field name="v2_double" type="v2d"/>
+      <field name="v16_int8" type="v16i8"/>
+      <field name="v8_int16" type="v8i16"/>
+      <field name="v4_int32" type="v4i32"/>
+      <field name="v2_int64" type="v2i64"/>
+      <field name="uint128" type="uint128"/>
+    </union>
+    <flags id="i386_mxcsr" size="4">
+      <field name="IE" start="0" end="0"/>
+      <field name="DE" start="1" end="1"/>
+      <field name="ZE" start="2" end="2"/>
+      <field name="OE" start="3" end="3"/>
+      <field name="UE" start="4" end="4"/>
+      <field name="PE" start="5" end="5"/>
+      <field name="DAZ" start="6" end="6"/>
+      <field name="IM" start="7" end="7"/>
+      <field name="DM" start="8" end="8"/>
+      <field name="ZM" start="9" end="9"/>
+      <field name="OM" start="10" end="10"/>
+      <field name="UM" start="11" end="11"/>
+      <field name="PM" start="12" end="12"/>
+      <field name="FZ" start="15" end="15"/>
+    </flags>
+"#;
+
+/// Target description sent to GDB via `qXfer:features:read:target.xml`
+pub(crate) fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <architecture>i386:x86-64</architecture>\n  <osabi>GNU/Linux</osabi>\n",
+    );
+
+    for (feature, types) in [(CORE, CORE_TYPES), (SSE, SSE_TYPES), (SEGMENTS, "")] {
+        xml.push_str(&format!("  <feature name=\"{feature}\">\n"));
+        xml.push_str(types);
+        for (regnum, reg) in REGISTERS.iter().enumerate() {
+            if reg.feature != feature {
+                continue;
+            }
+            xml.push_str(&format!(
+                "    <reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>\n",
+                reg.name, reg.bitsize, reg.gdb_type, regnum
+            ));
+        }
+        xml.push_str("  </feature>\n");
+    }
+
+    xml.push_str("</target>\n");
+    xml
+}
+
+pub(crate) fn register_count() -> usize {
+    REGISTERS.len()
+}
+
+pub(crate) fn register_size(regnum: usize) -> Option<usize> {
+    REGISTERS.get(regnum).map(|r| r.bitsize / 8)
+}
+
+/// Reads a register in the little-endian target byte order GDB expects
+pub(crate) fn read_register(ax: &Axecutor, regnum: usize) -> Result<Vec<u8>, AxError> {
+    let reg = REGISTERS
+        .get(regnum)
+        .ok_or_else(|| AxError::from(format!("Invalid register number {regnum}")))?;
+
+    let value: u128 = match reg.kind {
+        Kind::Register(r) if reg.bitsize == 128 => ax.internal_reg_read_128(r)?,
+        Kind::Register(r) => ax.reg_read_64(r)? as u128,
+        Kind::Rsp => ax.native_rsp()? as u128,
+        Kind::Eflags => ax.state.rflags as u128,
+        Kind::FsBase => ax.state.fs as u128,
+        Kind::GsBase => ax.state.gs as u128,
+        Kind::Fixed(value) => value as u128,
+    };
+
+    Ok(value.to_le_bytes()[..reg.bitsize / 8].to_vec())
+}
+
+pub(crate) fn write_register(
+    ax: &mut Axecutor,
+    regnum: usize,
+    bytes: &[u8],
+) -> Result<(), AxError> {
+    let reg = REGISTERS
+        .get(regnum)
+        .ok_or_else(|| AxError::from(format!("Invalid register number {regnum}")))?;
+    if bytes.len() != reg.bitsize / 8 {
+        return Err(AxError::from(format!(
+            "Invalid value size {} for register {}",
+            bytes.len(),
+            reg.name
+        )));
+    }
+
+    let mut buf = [0u8; 16];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let value = u128::from_le_bytes(buf);
+
+    match reg.kind {
+        Kind::Register(r) if reg.bitsize == 128 => ax.internal_reg_write_128(r, value)?,
+        Kind::Register(r) => ax.reg_write_64(r, value as u64)?,
+        Kind::Rsp => ax.set_native_rsp(value as u64)?,
+        Kind::Eflags => ax.state.rflags = value as u64,
+        Kind::FsBase => ax.state.fs = value as u64,
+        Kind::GsBase => ax.state.gs = value as u64,
+        Kind::Fixed(_) => {}
+    }
+
+    Ok(())
+}
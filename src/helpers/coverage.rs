@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use iced_x86::{FlowControl, Instruction};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+
+/// A basic block that was executed while coverage was enabled
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageBlock {
+    pub address: u64,
+    /// Size of the block in bytes, up to the end of its last executed instruction
+    pub size: u64,
+    /// How often execution entered the block
+    pub hits: u64,
+}
+
+/// A transition from one basic block to another that was taken while coverage was enabled
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageEdge {
+    /// Start address of the block execution came from
+    pub from: u64,
+    /// Start address of the block execution continued with
+    pub to: u64,
+    pub hits: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Coverage {
+    // Start address to size and hit count
+    blocks: BTreeMap<u64, (u64, u64)>,
+    edges: HashMap<(u64, u64), u64>,
+    // Start of the block the last instruction belonged to
+    block: Option<u64>,
+    // Address the next instruction of the current block would be at, None if the last instruction ended the block
+    next: Option<u64>,
+}
+
+impl Coverage {
+    // Counts the instruction, which starts a new block unless it directly follows the last one
+    pub(crate) fn add(&mut self, instr: &Instruction) {
+        let address = instr.ip();
+        if self.next != Some(address) || self.block.is_none() {
+            if let Some(from) = self.block {
+                *self.edges.entry((from, address)).or_default() += 1;
+            }
+            self.blocks.entry(address).or_default().1 += 1;
+            self.block = Some(address);
+        }
+
+        let start = self.block.unwrap_or(address);
+        if let Some((size, _)) = self.blocks.get_mut(&start) {
+            *size = (*size).max(instr.next_ip() - start);
+        }
+        self.next = match instr.flow_control() {
+            FlowControl::Next => Some(instr.next_ip()),
+            _ => None,
+        };
+    }
+}
+
+impl Axecutor {
+    // Called before each instruction
+    pub(crate) fn coverage_step(&mut self, instr: &Instruction) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.add(instr);
+        }
+    }
+
+    fn coverage(&self) -> Result<&Coverage, AxError> {
+        self.coverage
+            .as_ref()
+            .ok_or_else(|| AxError::from("Coverage is not enabled, enable it with enable_coverage"))
+    }
+
+    // Number of times the instruction at each covered address was executed. Every instruction of a block runs once per hit
+    fn instruction_hits(&self, address: u64) -> u64 {
+        self.coverage
+            .iter()
+            .flat_map(|coverage| coverage.blocks.range(..=address))
+            .filter(|(start, (size, _))| address < *start + size)
+            .map(|(_, (_, hits))| hits)
+            .sum()
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Starts collecting coverage: how often each basic block was entered and each edge between blocks was taken.
+    /// Coverage collected before is kept; use `reset_coverage` to start over.
+    pub fn enable_coverage(&mut self) {
+        debug_log!("Enabling coverage");
+        if self.coverage.is_none() {
+            self.coverage = Some(Coverage::default());
+        }
+    }
+
+    /// Stops collecting coverage and discards it
+    pub fn disable_coverage(&mut self) {
+        self.coverage = None;
+    }
+
+    /// Discards the coverage collected so far
+    pub fn reset_coverage(&mut self) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            *coverage = Coverage::default();
+        }
+    }
+
+    /// The executed basic blocks, ordered by address
+    pub fn coverage_blocks(&self) -> Result<Vec<CoverageBlock>, AxError> {
+        Ok(self
+            .coverage()?
+            .blocks
+            .iter()
+            .map(|(&address, &(size, hits))| CoverageBlock {
+                address,
+                size,
+                hits,
+            })
+            .collect())
+    }
+
+    /// The edges taken between basic blocks, ordered by source and target address
+    pub fn coverage_edges(&self) -> Result<Vec<CoverageEdge>, AxError> {
+        let mut edges: Vec<CoverageEdge> = self
+            .coverage()?
+            .edges
+            .iter()
+            .map(|(&(from, to), &hits)| CoverageEdge { from, to, hits })
+            .collect();
+        edges.sort_by_key(|edge| (edge.from, edge.to));
+        Ok(edges)
+    }
+
+    /// Exports the covered blocks in the drcov format used by DynamoRIO, e.g. for the Lighthouse plugin of IDA and Binary Ninja.
+    /// All blocks are reported relative to a single module called `module_name`, which should be the file name of the binary.
+    /// Its base is the lowest address an ELF segment was loaded to, which is the image base, or the lowest covered block otherwise.
+    pub fn coverage_drcov(&self, module_name: &str) -> Result<Vec<u8>, AxError> {
+        let coverage = self.coverage()?;
+
+        let base = self
+            .state
+            .memory
+            .iter()
+            .filter(|area| {
+                area.name
+                    .as_ref()
+                    .is_some_and(|name| name.starts_with("elf_load_header_"))
+            })
+            .map(|area| area.start)
+            .min()
+            .or_else(|| coverage.blocks.keys().next().copied())
+            .unwrap_or_default();
+        let end = coverage
+            .blocks
+            .iter()
+            .map(|(start, (size, _))| start.saturating_add(*size))
+            .max()
+            .unwrap_or(base);
+
+        let mut header = String::new();
+        let _ = writeln!(header, "DRCOV VERSION: 2");
+        let _ = writeln!(header, "DRCOV FLAVOR: ax");
+        let _ = writeln!(header, "Module Table: version 2, count 1");
+        let _ = writeln!(
+            header,
+            "Columns: id, base, end, entry, checksum, timestamp, path"
+        );
+        let _ = writeln!(
+            header,
+            " 0, {base:#018x}, {end:#018x}, 0x0000000000000000, 0x00000000, 0x00000000, {module_name}"
+        );
+        let _ = writeln!(header, "BB Table: {} bbs", coverage.blocks.len());
+
+        let mut bytes = header.into_bytes();
+        for (&start, &(size, _)) in &coverage.blocks {
+            // Blocks outside of the binary, e.g. in code written at runtime, can be below the base
+            let offset = start
+                .checked_sub(base)
+                .and_then(|offset| u32::try_from(offset).ok())
+                .ok_or_else(|| {
+                    AxError::from(format!(
+                        "Cannot export block at {start:#x} to drcov, it is outside of the module starting at {base:#x}"
+                    ))
+                })?;
+            // struct _bb_entry_t { uint32_t start; uint16_t size; uint16_t mod_id; }
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&(size.min(u16::MAX as u64) as u16).to_le_bytes());
+            bytes.extend_from_slice(&0u16.to_le_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    /// Exports line coverage in the lcov tracefile format, e.g. for genhtml. Needs DWARF line information, i.e. a binary compiled with `-g`.
+    /// Every line in the line table is reported with how often its first instruction was executed; functions are reported like lines.
+    pub fn coverage_lcov(&self) -> Result<String, AxError> {
+        self.coverage()?;
+        if self.debug_info.is_empty() {
+            return Err(AxError::from(
+                "Cannot export lcov coverage: the binary has no DWARF line information",
+            ));
+        }
+
+        // File to line to hit count. A line can have rows at several addresses, its count is the highest of them
+        let mut files: BTreeMap<String, BTreeMap<u32, u64>> = BTreeMap::new();
+        for (address, path, line) in self.debug_info.lines() {
+            let hits = files.entry(path).or_default().entry(line).or_default();
+            *hits = (*hits).max(self.instruction_hits(address));
+        }
+
+        let mut functions: BTreeMap<String, Vec<(u32, &str, u64)>> = BTreeMap::new();
+        for (name, start) in self.debug_info.functions() {
+            if let Some(location) = self.debug_info.location(start) {
+                functions.entry(location.path()).or_default().push((
+                    location.line,
+                    name,
+                    self.instruction_hits(start),
+                ));
+            }
+        }
+
+        let mut lcov = String::from("TN:\n");
+        for (path, lines) in &files {
+            let _ = writeln!(lcov, "SF:{path}");
+
+            let file_functions = functions.get(path).map(Vec::as_slice).unwrap_or_default();
+            for (line, name, _) in file_functions {
+                let _ = writeln!(lcov, "FN:{line},{name}");
+            }
+            for (_, name, hits) in file_functions {
+                let _ = writeln!(lcov, "FNDA:{hits},{name}");
+            }
+            let _ = writeln!(lcov, "FNF:{}", file_functions.len());
+            let _ = writeln!(
+                lcov,
+                "FNH:{}",
+                file_functions
+                    .iter()
+                    .filter(|(_, _, hits)| *hits > 0)
+                    .count()
+            );
+
+            for (line, hits) in lines {
+                let _ = writeln!(lcov, "DA:{line},{hits}");
+            }
+            let _ = writeln!(lcov, "LF:{}", lines.len());
+            let _ = writeln!(
+                lcov,
+                "LH:{}",
+                lines.values().filter(|hits| **hits > 0).count()
+            );
+            let _ = writeln!(lcov, "end_of_record");
+        }
+
+        Ok(lcov)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::{c_loop, test_async};
+    use crate::state::registers::SupportedRegister::*;
+
+    test_async![collects_blocks_and_edges; async {
+        let mut ax = c_loop();
+        ax.coverage_blocks().expect_err("Coverage is not enabled");
+        ax.enable_coverage();
+        ax.execute().await.expect("Failed to execute");
+
+        let blocks = ax.coverage_blocks().unwrap();
+        // The loop body is entered three times
+        let body = blocks.iter().find(|b| b.address == 0x401036).expect("Loop body not covered");
+        assert_eq!(body.hits, 3);
+        assert!(body.size > 0);
+        // Every block but the first is entered through an edge
+        let edges = ax.coverage_edges().unwrap();
+        let entered: u64 = blocks.iter().map(|b| b.hits).sum();
+        assert_eq!(edges.iter().map(|e| e.hits).sum::<u64>(), entered - 1);
+        assert!(edges.iter().all(|e| blocks.iter().any(|b| b.address == e.to)));
+
+        let drcov = ax.coverage_drcov("c_loop.bin").unwrap();
+        let header = String::from_utf8_lossy(&drcov);
+        assert!(header.starts_with("DRCOV VERSION: 2\n"));
+        assert!(header.contains(" 0, 0x0000000000400000, "), "{}", header);
+        assert!(header.contains(", c_loop.bin\n"), "{}", header);
+        let table = format!("BB Table: {} bbs\n", blocks.len());
+        let table_end = header.find(&table).unwrap() + table.len();
+        assert_eq!(drcov.len() - table_end, blocks.len() * 8);
+
+        ax.reset_coverage();
+        assert!(ax.coverage_blocks().unwrap().is_empty());
+    }];
+
+    test_async![skipped_instructions_are_not_covered; async {
+        // nop; nop
+        let mut ax = Axecutor::new(&[0x90, 0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.hook_code_native(0x1000, 1, |ax, info| {
+            ax.reg_write_64(RIP, info.address + info.length)?;
+            Ok(())
+        }).expect("Failed to add hook");
+        ax.enable_coverage();
+        ax.execute().await.expect("Failed to execute");
+
+        let blocks = ax.coverage_blocks().unwrap();
+        assert_eq!(blocks.iter().map(|b| (b.address, b.hits)).collect::<Vec<_>>(), [(0x1001, 1)]);
+    }];
+
+    test_async![drcov_rejects_blocks_below_base; async {
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        // The module base is the lowest ELF segment, which is above the executed code
+        ax.mem_init_zero_named(0x2000, 0x1000, "elf_load_header_0".to_string()).unwrap();
+        ax.enable_coverage();
+        ax.execute().await.expect("Failed to execute");
+
+        let error = ax.coverage_drcov("code").expect_err("Block below the module base");
+        assert!(error.to_string().contains("Cannot export block at 0x1000"), "{error}");
+    }];
+
+    test_async![lcov_from_line_table; async {
+        let mut ax = c_loop();
+        ax.enable_coverage();
+        ax.execute().await.expect("Failed to execute");
+
+        let lcov = ax.coverage_lcov().unwrap();
+        assert!(lcov.starts_with("TN:\nSF:"), "{}", lcov);
+        assert!(lcov.contains("c_loop.c\n"), "{}", lcov);
+        // The loop body at line 20 runs three times
+        assert!(lcov.contains("\nDA:20,3\n"), "{}", lcov);
+        assert!(lcov.contains("FNDA:1,_start\n"), "{}", lcov);
+        assert!(lcov.trim_end().ends_with("end_of_record"));
+
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).unwrap();
+        ax.enable_coverage();
+        ax.execute().await.unwrap();
+        ax.coverage_lcov().expect_err("No debug information");
+    }];
+}
@@ -4,9 +4,15 @@ use std::{
     fmt::{self},
 };
 
+use iced_x86::Instruction;
 use js_sys::Reflect;
+use serde::Serialize;
 use wasm_bindgen::{JsError, JsValue};
 
+use crate::state::exceptions::CpuException;
+use crate::state::memory_hooks::MemoryAccessKind;
+use crate::state::sanitizer::SanitizerViolation;
+
 #[derive(Clone, PartialEq)]
 pub struct AxError {
     detail: Option<String>,
@@ -16,8 +22,71 @@ pub struct AxError {
     trace: Option<String>,
 
     pub(crate) signals_normal_finish: bool,
+
+    // What went wrong and the CPU exception this error corresponds to, if any. Boxed to keep AxError small
+    class: Option<Box<ErrorClass>>,
+}
+
+#[derive(Clone, PartialEq, Default)]
+struct ErrorClass {
+    kind: Option<ErrorKind>,
+    exception: Option<CpuException>,
+}
+
+/// The kind of an error, for handling errors without matching on their messages.
+/// In JavaScript, errors have a `kind` property with the variant name in `type` and its fields, e.g. `{ type: "UnhandledSyscall", nr: 60n }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type")]
+pub enum ErrorKind {
+    /// `len` bytes at `addr` are not (completely) mapped, `op` is the kind of access, e.g. `Read`
+    MemoryUnmapped {
+        addr: u64,
+        len: u64,
+        op: MemoryAccessKind,
+    },
+    /// The memory at `addr` doesn't allow the access
+    ProtectionViolation {
+        addr: u64,
+        len: u64,
+        op: MemoryAccessKind,
+    },
+    /// The instruction is valid, but not implemented by the emulator
+    UnsupportedInstruction { mnemonic: String, code: String },
+    /// The bytes at `addr` are not a valid instruction
+    InvalidInstruction { addr: u64 },
+    /// Division by zero
+    DivideByZero,
+    /// An interrupt instruction such as int3 was executed, but nothing handled it
+    UnhandledInterrupt,
+    /// The limit set by `set_max_instructions` was reached
+    InstructionLimit { limit: u64 },
+    /// No syscall handler or hook handled syscall `nr`
+    UnhandledSyscall { nr: u64 },
+    /// The program was terminated by a signal
+    Signal { signal: u64 },
+    /// A hook or handler returned an error
+    HookError,
+    /// The ELF binary could not be loaded
+    ElfLoad,
+    /// The bytes passed to `load_from_bytes` are not a valid saved state
+    InvalidState,
+    /// The saved state was written by a version of ax using a different format version
+    StateVersionMismatch { found: u32, expected: u32 },
+    /// A replay executed different instructions than the recording, first noticed after `instruction` instructions
+    ReplayDivergence { instruction: u64 },
+    /// The memory sanitizer found an invalid access or free at `addr`, `sanitizer_report` has the details
+    Sanitizer {
+        violation: SanitizerViolation,
+        addr: u64,
+    },
+    /// A method was called after execution has finished
+    ExecutionFinished,
+    /// Any other error
+    Other,
 }
 
+static OTHER: ErrorKind = ErrorKind::Other;
+
 impl Error for AxError {}
 
 // Some convenience addons
@@ -30,6 +99,7 @@ impl AxError {
             detail: self.detail.clone(),
             call_stack: self.call_stack.clone(),
             trace: self.trace.clone(),
+            class: self.class.clone(),
         }
     }
 
@@ -53,6 +123,67 @@ impl AxError {
             } else {
                 self.trace.clone()
             },
+            class: self.class.clone(),
+        }
+    }
+
+    pub(crate) fn with_kind(mut self, kind: ErrorKind) -> AxError {
+        self.class.get_or_insert_with(Default::default).kind = Some(kind);
+        self
+    }
+
+    // Unimplemented opcodes don't know which instruction they belong to, this fills it in
+    pub(crate) fn for_instruction(mut self, instr: &Instruction) -> AxError {
+        if let Some(ErrorKind::UnsupportedInstruction { mnemonic, code }) =
+            self.class.as_mut().and_then(|c| c.kind.as_mut())
+        {
+            if mnemonic.is_empty() {
+                *mnemonic = format!("{:?}", instr.mnemonic());
+                *code = format!("{:?}", instr.code());
+            }
+        }
+        self
+    }
+
+    /// What went wrong, `ErrorKind::Other` if the error has no specific kind
+    pub fn kind(&self) -> &ErrorKind {
+        self.class
+            .as_ref()
+            .and_then(|c| c.kind.as_ref())
+            .unwrap_or(&OTHER)
+    }
+
+    pub(crate) fn with_exception(mut self, exception: CpuException) -> AxError {
+        self.class.get_or_insert_with(Default::default).exception = Some(exception);
+        self
+    }
+
+    // Sets the address of the instruction that raised the exception, which the memory layer doesn't know
+    pub(crate) fn exception_at(mut self, rip: u64) -> AxError {
+        if let Some(exception) = self.class.as_mut().and_then(|c| c.exception.as_mut()) {
+            exception.rip = rip;
+        }
+        self
+    }
+
+    /// The CPU exception that caused this error, if any
+    pub fn exception(&self) -> Option<CpuException> {
+        self.class.as_ref().and_then(|c| c.exception)
+    }
+
+    // The backtrace is shown as part of the call stack, as both describe where execution currently is
+    pub(crate) fn add_backtrace(&self, backtrace: String) -> AxError {
+        if backtrace.is_empty() {
+            return self.clone();
+        }
+
+        AxError {
+            call_stack: Some(format!(
+                "{}\nBacktrace: \n{}",
+                self.call_stack.clone().unwrap_or_default(),
+                backtrace
+            )),
+            ..self.clone()
         }
     }
 }
@@ -69,6 +200,7 @@ impl From<&str> for AxError {
             signals_normal_finish: false,
             call_stack: None,
             trace: None,
+            class: None,
         }
     }
 }
@@ -81,6 +213,7 @@ impl From<String> for AxError {
             signals_normal_finish: false,
             call_stack: None,
             trace: None,
+            class: None,
         }
     }
 }
@@ -93,6 +226,7 @@ impl From<JsError> for AxError {
             signals_normal_finish: false,
             call_stack: None,
             trace: None,
+            class: None,
         }
     }
 }
@@ -105,6 +239,7 @@ impl From<JsValue> for AxError {
             signals_normal_finish: false,
             call_stack: None,
             trace: None,
+            class: None,
         }
     }
 }
@@ -117,6 +252,7 @@ impl From<Box<dyn std::error::Error>> for AxError {
             signals_normal_finish: false,
             call_stack: None,
             trace: None,
+            class: None,
         }
     }
 }
@@ -187,8 +323,21 @@ impl From<AxError> for String {
 
 impl From<AxError> for JsValue {
     fn from(err: AxError) -> Self {
-        let info = String::from(err);
-        JsValue::from(info)
+        let kind = err.kind().clone();
+        let exception = err.exception();
+
+        // Throw an Error object, so the kind can be attached as a property
+        let error = js_sys::Error::new(&String::from(err));
+        let serializer =
+            serde_wasm_bindgen::Serializer::new().serialize_large_number_types_as_bigints(true);
+        if let Ok(kind) = kind.serialize(&serializer) {
+            let _ = Reflect::set(&error, &JsValue::from("kind"), &kind);
+        }
+        if let Some(exception) = exception {
+            let _ = Reflect::set(&error, &JsValue::from("exception"), &exception.into());
+        }
+
+        error.into()
     }
 }
 
@@ -205,3 +354,83 @@ impl fmt::Debug for AxError {
         write!(f, "{s}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auto::generated::SupportedMnemonic;
+    use crate::axecutor::Axecutor;
+    use crate::helpers::tests::test_async;
+    use crate::state::memory::PROT_READ;
+
+    async fn run(code: &[u8], setup: impl FnOnce(&mut Axecutor)) -> ErrorKind {
+        let mut ax = Axecutor::new(code, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x3000, 0x1000).unwrap();
+        setup(&mut ax);
+
+        ax.execute()
+            .await
+            .expect_err("Execution should fail")
+            .kind()
+            .clone()
+    }
+
+    test_async![error_kinds; async {
+        let read_unmapped = [
+            0xb9, 0x08, 0x50, 0x00, 0x00, // mov ecx, 0x5008
+            0x48, 0x8b, 0x01, // mov rax, qword ptr [rcx]
+        ];
+        assert_eq!(
+            run(&read_unmapped, |_| {}).await,
+            ErrorKind::MemoryUnmapped { addr: 0x5008, len: 8, op: MemoryAccessKind::Read }
+        );
+
+        let write_read_only = [
+            0xb9, 0x04, 0x30, 0x00, 0x00, // mov ecx, 0x3004
+            0x48, 0x89, 0x01, // mov qword ptr [rcx], rax
+        ];
+        assert_eq!(
+            run(&write_read_only, |ax| ax.mem_prot(0x3000, PROT_READ).unwrap()).await,
+            ErrorKind::ProtectionViolation { addr: 0x3004, len: 8, op: MemoryAccessKind::Write }
+        );
+
+        let jump_unmapped = [
+            0xb9, 0x00, 0x60, 0x00, 0x00, // mov ecx, 0x6000
+            0xff, 0xe1, // jmp rcx
+        ];
+        assert_eq!(
+            run(&jump_unmapped, |_| {}).await,
+            ErrorKind::MemoryUnmapped { addr: 0x6000, len: 15, op: MemoryAccessKind::Execute }
+        );
+
+        let syscall = [
+            0xb8, 0xe8, 0x03, 0x00, 0x00, // mov eax, 1000
+            0x0f, 0x05, // syscall
+        ];
+        assert_eq!(run(&syscall, |_| {}).await, ErrorKind::UnhandledSyscall { nr: 1000 });
+        assert_eq!(
+            run(&syscall, |ax| ax.set_max_instructions(1)).await,
+            ErrorKind::InstructionLimit { limit: 1 }
+        );
+        assert_eq!(
+            run(&syscall, |ax| {
+                ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, |_, _| {
+                    Err(AxError::from("hook failed").into())
+                })
+                .unwrap();
+            })
+            .await,
+            ErrorKind::HookError
+        );
+
+        assert_eq!(
+            run(&[0x0f, 0xff], |_| {}).await, // ud0
+            ErrorKind::InvalidInstruction { addr: 0x1000 }
+        );
+
+        let err = Axecutor::from_binary(&[0x7f, b'E', b'L', b'F']).expect_err("Invalid ELF file");
+        assert_eq!(*err.kind(), ErrorKind::ElfLoad);
+
+        assert_eq!(*AxError::from("message").kind(), ErrorKind::Other);
+    }];
+}
@@ -0,0 +1,325 @@
+use iced_x86::{FlowControl, Instruction};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::state::exceptions::CpuException;
+use crate::state::registers::SupportedRegister;
+use crate::state::snapshots::SnapshotId;
+
+/// Size of the edge coverage bitmap, the same as AFL's default `MAP_SIZE`
+pub const FUZZ_MAP_SIZE: usize = 1 << 16;
+
+/// How a fuzzing run ended
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FuzzStatus {
+    /// Execution reached the exit address or the program exited
+    Ok,
+    /// The input caused an exception that was not handled or the program was terminated by a signal
+    Crash,
+    /// The instruction budget was used up before the exit address was reached
+    Timeout,
+}
+
+/// The outcome of running the fuzz target with one input
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuzzResult {
+    pub status: FuzzStatus,
+    /// For crashes, the exception that was not handled. Crashes can be told apart by its kind and address
+    pub exception: Option<CpuException>,
+    /// For crashes, the signal that terminated the program
+    pub signal: Option<u64>,
+    /// Number of instructions executed for the input
+    pub instructions: u64,
+    /// Whether the input reached an edge, or took an edge a number of times, that no input before it did
+    pub new_coverage: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Fuzzer {
+    snapshot: SnapshotId,
+    input_address: u64,
+    max_input_size: u64,
+    exit_address: u64,
+    instruction_budget: u64,
+    // Register that is set to the length of each input
+    length_register: Option<SupportedRegister>,
+    // Hit counts of the edges of the last run, indexed like AFL's shared memory
+    bitmap: Vec<u8>,
+    // Hit count buckets seen over all runs
+    seen: Vec<u8>,
+    // Shifted id of the block the last instruction belonged to
+    previous: u64,
+    // Address the next instruction of the current block would be at, None if the last instruction ended the block
+    next: Option<u64>,
+    executions: u64,
+}
+
+impl Fuzzer {
+    fn add(&mut self, instr: &Instruction) {
+        let address = instr.ip();
+        if self.next != Some(address) {
+            // Block ids are derived from the address like in AFL's QEMU mode
+            let current = ((address >> 4) ^ (address << 8)) & (FUZZ_MAP_SIZE as u64 - 1);
+            let index = (current ^ self.previous) as usize;
+            self.bitmap[index] = self.bitmap[index].wrapping_add(1);
+            self.previous = current >> 1;
+        }
+
+        self.next = match instr.flow_control() {
+            FlowControl::Next => Some(instr.next_ip()),
+            _ => None,
+        };
+    }
+
+    // Merges the hit count buckets of the last run into the ones seen before, returns whether there were new ones
+    fn merge(&mut self) -> bool {
+        let mut new_coverage = false;
+        for (seen, &hits) in self.seen.iter_mut().zip(&self.bitmap) {
+            let bucket = hit_bucket(hits);
+            if bucket & !*seen != 0 {
+                *seen |= bucket;
+                new_coverage = true;
+            }
+        }
+        new_coverage
+    }
+}
+
+// AFL only distinguishes hit counts by these buckets, so a loop running once more isn't new coverage
+fn hit_bucket(hits: u8) -> u8 {
+    match hits {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 4,
+        4..=7 => 8,
+        8..=15 => 16,
+        16..=31 => 32,
+        32..=127 => 64,
+        _ => 128,
+    }
+}
+
+impl Axecutor {
+    // Called before each instruction
+    pub(crate) fn fuzz_step(&mut self, instr: &Instruction) {
+        if let Some(fuzzer) = self.fuzzer.as_mut() {
+            fuzzer.add(instr);
+        }
+    }
+
+    fn fuzzer(&self) -> Result<&Fuzzer, AxError> {
+        self.fuzzer
+            .as_ref()
+            .ok_or_else(|| AxError::from("Fuzzing is not set up, call fuzz_init first"))
+    }
+
+    fn fuzz_result(&mut self, status: FuzzStatus, start: u64) -> FuzzResult {
+        let instructions = self.state.executed_instructions_count - start;
+        let new_coverage = self.fuzzer.as_mut().is_some_and(Fuzzer::merge);
+        FuzzResult {
+            status,
+            exception: None,
+            signal: None,
+            instructions,
+            new_coverage,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Sets up fuzzing: executes until RIP is `start_address` and takes a snapshot there, which `fuzz_run` restores for each input.
+    /// Each input is written to the buffer at `input_address`, which must be mapped and writable for `max_input_size` bytes,
+    /// and a run ends when RIP reaches `exit_address` (e.g. the return address of the fuzzed function) or after `instruction_budget` instructions.
+    /// Registers, e.g. a pointer to the input buffer, can be set before calling this function.
+    pub async fn fuzz_init(
+        &mut self,
+        start_address: u64,
+        input_address: u64,
+        max_input_size: u64,
+        exit_address: u64,
+        instruction_budget: u64,
+    ) -> Result<(), AxError> {
+        debug_log!(
+            "Calling Axecutor::fuzz_init, start_address={:#x}, input_address={:#x}, max_input_size={}, exit_address={:#x}, instruction_budget={}",
+            start_address,
+            input_address,
+            max_input_size,
+            exit_address,
+            instruction_budget
+        );
+
+        if let Some(fuzzer) = self.fuzzer.take() {
+            self.delete_snapshot(fuzzer.snapshot);
+        }
+
+        while self.reg_read_64(SupportedRegister::RIP)? != start_address {
+            if !self.step().await? {
+                return Err(AxError::from(format!(
+                    "Cannot set up fuzzing: execution finished before reaching the start address {start_address:#x}"
+                )));
+            }
+        }
+
+        self.fuzzer = Some(Fuzzer {
+            snapshot: self.snapshot(),
+            input_address,
+            max_input_size,
+            exit_address,
+            instruction_budget,
+            length_register: None,
+            bitmap: vec![0; FUZZ_MAP_SIZE],
+            seen: vec![0; FUZZ_MAP_SIZE],
+            previous: 0,
+            next: None,
+            executions: 0,
+        });
+
+        Ok(())
+    }
+
+    /// Sets `register` to the length of the input before each run, e.g. RSI for a function like `LLVMFuzzerTestOneInput(data, size)`
+    pub fn fuzz_set_length_register(&mut self, register: SupportedRegister) -> Result<(), AxError> {
+        self.fuzzer()?;
+        if let Some(fuzzer) = self.fuzzer.as_mut() {
+            fuzzer.length_register = Some(register);
+        }
+        Ok(())
+    }
+
+    /// Restores the snapshot taken by `fuzz_init`, writes `input` to the input buffer and executes until the exit address is reached,
+    /// the budget is used up or the input crashes the program. Errors that aren't crashes, e.g. unsupported instructions, are returned as errors.
+    /// The edges taken are recorded in the bitmap returned by `fuzz_bitmap`.
+    pub async fn fuzz_run(&mut self, input: Vec<u8>) -> Result<FuzzResult, AxError> {
+        let fuzzer = self.fuzzer()?;
+        if input.len() as u64 > fuzzer.max_input_size {
+            return Err(AxError::from(format!(
+                "Fuzzing input of {} bytes is larger than the input buffer of {} bytes",
+                input.len(),
+                fuzzer.max_input_size
+            )));
+        }
+        let (snapshot, input_address, exit_address, budget, length_register) = (
+            fuzzer.snapshot,
+            fuzzer.input_address,
+            fuzzer.exit_address,
+            fuzzer.instruction_budget,
+            fuzzer.length_register,
+        );
+
+        self.restore(snapshot)?;
+        self.mem_write_bytes(input_address, &input)?;
+        if let Some(register) = length_register {
+            self.reg_write_64(register, input.len() as u64)?;
+        }
+        if let Some(fuzzer) = self.fuzzer.as_mut() {
+            fuzzer.bitmap.fill(0);
+            fuzzer.previous = 0;
+            fuzzer.next = None;
+            fuzzer.executions += 1;
+        }
+
+        let start = self.state.executed_instructions_count;
+        loop {
+            if self.reg_read_64(SupportedRegister::RIP)? == exit_address {
+                return Ok(self.fuzz_result(FuzzStatus::Ok, start));
+            }
+            if self.state.executed_instructions_count - start >= budget {
+                return Ok(self.fuzz_result(FuzzStatus::Timeout, start));
+            }
+
+            match self.step().await {
+                Ok(true) => {}
+                Ok(false) => return Ok(self.fuzz_result(FuzzStatus::Ok, start)),
+                Err(e) => {
+                    let signal = match e.kind() {
+                        ErrorKind::Signal { signal } => Some(*signal),
+                        _ => None,
+                    };
+                    if e.exception().is_none() && signal.is_none() {
+                        return Err(e);
+                    }
+
+                    debug_log!("Fuzzing input crashed: {}", e);
+                    return Ok(FuzzResult {
+                        exception: e.exception(),
+                        signal,
+                        ..self.fuzz_result(FuzzStatus::Crash, start)
+                    });
+                }
+            }
+        }
+    }
+
+    /// The AFL-compatible edge coverage bitmap of the last `fuzz_run`, `FUZZ_MAP_SIZE` bytes of hit counts
+    pub fn fuzz_bitmap(&self) -> Result<Vec<u8>, AxError> {
+        Ok(self.fuzzer()?.bitmap.clone())
+    }
+
+    /// Number of inputs run since `fuzz_init`
+    pub fn fuzz_executions(&self) -> Result<u64, AxError> {
+        Ok(self.fuzzer()?.executions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+    use crate::state::exceptions::ExceptionKind;
+    use crate::state::registers::SupportedRegister::*;
+
+    // Crashes if the first input byte is 'A' and loops forever if it is 'B'
+    const TARGET: [u8; 19] = [
+        0x8a, 0x07, // mov al, byte ptr [rdi]
+        0x3c, 0x41, // cmp al, 0x41
+        0x75, 0x08, // jne 0x100e
+        0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00, // mov rax, [0]
+        0x3c, 0x42, // cmp al, 0x42
+        0x74, 0xfe, // je 0x1010
+        0x90, // nop
+    ];
+
+    test_async![fuzz_runs_classify_inputs; async {
+        let mut ax = Axecutor::new(&TARGET, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x8000, 0x100).unwrap();
+        ax.reg_write_64(RDI, 0x8000).unwrap();
+        ax.fuzz_run(vec![0]).await.expect_err("Fuzzing is not set up");
+        ax.fuzz_init(0x1000, 0x8000, 0x100, 0x1012, 1000).await.unwrap();
+        ax.fuzz_set_length_register(RSI).unwrap();
+
+        let result = ax.fuzz_run(b"x".to_vec()).await.unwrap();
+        assert_eq!(result.status, FuzzStatus::Ok);
+        assert!(result.new_coverage);
+        assert_eq!(result.instructions, 5);
+        assert_eq!(ax.reg_read_64(RSI).unwrap(), 1);
+        assert!(ax.fuzz_bitmap().unwrap().iter().any(|hits| *hits > 0));
+
+        // The same path again is nothing new
+        let result = ax.fuzz_run(b"yz".to_vec()).await.unwrap();
+        assert_eq!(result.status, FuzzStatus::Ok);
+        assert!(!result.new_coverage);
+        assert_eq!(ax.reg_read_64(RSI).unwrap(), 2);
+
+        let result = ax.fuzz_run(b"A".to_vec()).await.unwrap();
+        assert_eq!(result.status, FuzzStatus::Crash);
+        assert!(result.new_coverage);
+        let exception = result.exception.expect("Crash has no exception");
+        assert_eq!(exception.kind, ExceptionKind::PageFault);
+        assert_eq!(exception.rip, 0x1006);
+
+        let result = ax.fuzz_run(b"B".to_vec()).await.unwrap();
+        assert_eq!(result.status, FuzzStatus::Timeout);
+        assert_eq!(result.instructions, 1000);
+
+        // Every run starts from the snapshot
+        assert_eq!(ax.fuzz_run(b"x".to_vec()).await.unwrap().status, FuzzStatus::Ok);
+        assert_eq!(ax.fuzz_executions().unwrap(), 5);
+        ax.fuzz_run(vec![0; 0x101]).await.expect_err("Input is too large");
+    }];
+}
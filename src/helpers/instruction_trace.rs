@@ -0,0 +1,455 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter, Write};
+
+use iced_x86::Instruction;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+use crate::state::flags::FLAG_TO_NAMES;
+use crate::state::hooks::{call_native_hook, native_hook, NativeHook};
+use crate::state::registers::SupportedRegister;
+
+/// A register written by an instruction. XMM registers use all 128 bits, other registers 64 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RegisterChange {
+    pub register: SupportedRegister,
+    pub old: u128,
+    pub new: u128,
+}
+
+/// Memory written by an instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryChange {
+    pub address: u64,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+/// An executed instruction and its effects, as recorded by the instruction trace.
+/// Effects of syscall handlers and mnemonic hooks count as effects of the instruction they ran for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstructionTraceEntry {
+    /// Number of instructions executed before this one
+    pub instruction_count: u64,
+    pub rip: u64,
+    pub bytes: Vec<u8>,
+    pub disassembly: String,
+    /// Changed registers except RIP, ordered by register
+    pub registers: Vec<RegisterChange>,
+    /// Memory writes in the order they happened
+    pub memory: Vec<MemoryChange>,
+    pub old_rflags: u64,
+    pub rflags: u64,
+}
+
+// Instruction trace callback, called with each executed instruction
+pub type InstructionTraceFunction = dyn FnMut(&InstructionTraceEntry) -> Result<(), Box<dyn Error>>;
+
+#[derive(Clone, Default)]
+enum TraceMode {
+    #[default]
+    Off,
+    // Keeps the last `capacity` entries
+    Buffer(usize),
+    Native(NativeHook<InstructionTraceFunction>),
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    Js(js_sys::Function),
+}
+
+// State before the instruction that is currently executed
+#[derive(Debug, Clone)]
+struct PendingEntry {
+    entry: InstructionTraceEntry,
+    registers: HashMap<SupportedRegister, u64>,
+    xmm_registers: HashMap<SupportedRegister, u128>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct InstructionTrace {
+    mode: TraceMode,
+    entries: VecDeque<InstructionTraceEntry>,
+    pending: Option<Box<PendingEntry>>,
+}
+
+impl Debug for InstructionTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstructionTrace")
+            .field("enabled", &!matches!(self.mode, TraceMode::Off))
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn flag_names(rflags: u64) -> Vec<&'static str> {
+    let mut flags: Vec<(u64, &'static str)> = FLAG_TO_NAMES
+        .iter()
+        .filter(|(flag, _)| rflags & **flag != 0)
+        .map(|(flag, name)| (*flag, *name))
+        .collect();
+    flags.sort();
+    flags.into_iter().map(|(_, name)| name).collect()
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl Display for InstructionTraceEntry {
+    /// One line per instruction, e.g. `5 0x401004 4889e5 mov rbp,rsp | RBP 0x7ff0 -> 0x7fe8 | flags ZF PF`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:#x} {} {}",
+            self.instruction_count,
+            self.rip,
+            hex_bytes(&self.bytes),
+            self.disassembly
+        )?;
+        for change in &self.registers {
+            write!(
+                f,
+                " | {} {:#x} -> {:#x}",
+                change.register.name(),
+                change.old,
+                change.new
+            )?;
+        }
+        for change in &self.memory {
+            write!(
+                f,
+                " | [{:#x}] {} -> {}",
+                change.address,
+                hex_bytes(&change.old),
+                hex_bytes(&change.new)
+            )?;
+        }
+        write!(f, " | flags {}", flag_names(self.rflags).join(" "))
+    }
+}
+
+impl InstructionTraceEntry {
+    /// The entry as JSON object. Numbers are hex strings, as JavaScript numbers cannot represent all 64-bit values
+    pub fn to_json(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|c| {
+                format!(
+                    r#"{{"register":"{}","old":"{:#x}","new":"{:#x}"}}"#,
+                    c.register.name(),
+                    c.old,
+                    c.new
+                )
+            })
+            .collect();
+        let memory: Vec<String> = self
+            .memory
+            .iter()
+            .map(|c| {
+                format!(
+                    r#"{{"address":"{:#x}","old":"{}","new":"{}"}}"#,
+                    c.address,
+                    hex_bytes(&c.old),
+                    hex_bytes(&c.new)
+                )
+            })
+            .collect();
+        let flags: Vec<String> = flag_names(self.rflags)
+            .into_iter()
+            .map(|name| format!("\"{name}\""))
+            .collect();
+
+        format!(
+            r#"{{"instruction_count":{},"rip":"{:#x}","bytes":"{}","disassembly":{},"registers":[{}],"memory":[{}],"old_rflags":"{:#x}","rflags":"{:#x}","flags":[{}]}}"#,
+            self.instruction_count,
+            self.rip,
+            hex_bytes(&self.bytes),
+            json_string(&self.disassembly),
+            registers.join(","),
+            memory.join(","),
+            self.old_rflags,
+            self.rflags,
+            flags.join(",")
+        )
+    }
+}
+
+impl Axecutor {
+    /// Records every executed instruction and passes it to `callback` instead of keeping it in memory.
+    /// Replaces any other instruction trace mode.
+    pub fn enable_instruction_trace_native(
+        &mut self,
+        callback: impl FnMut(&InstructionTraceEntry) -> Result<(), Box<dyn Error>> + 'static,
+    ) {
+        self.set_instruction_trace_mode(TraceMode::Native(native_hook(Box::new(callback))));
+    }
+
+    /// The instructions recorded by `enable_instruction_trace`, oldest first
+    pub fn instruction_trace_entries(&self) -> Vec<InstructionTraceEntry> {
+        self.instruction_trace.entries.iter().cloned().collect()
+    }
+
+    fn set_instruction_trace_mode(&mut self, mode: TraceMode) {
+        self.instruction_trace = InstructionTrace {
+            mode,
+            ..Default::default()
+        };
+    }
+
+    // Called before an instruction and the hooks for it run
+    pub(crate) fn instruction_trace_begin(&mut self, instr: &Instruction) -> Result<(), AxError> {
+        if matches!(self.instruction_trace.mode, TraceMode::Off) {
+            return Ok(());
+        }
+
+        let entry = InstructionTraceEntry {
+            instruction_count: self.state.executed_instructions_count,
+            rip: instr.ip(),
+            bytes: self.mem_peek_bytes(instr.ip(), instr.len() as u64)?,
+            disassembly: instr.to_string(),
+            registers: Vec::new(),
+            memory: Vec::new(),
+            old_rflags: self.state.rflags,
+            rflags: self.state.rflags,
+        };
+        self.instruction_trace.pending = Some(Box::new(PendingEntry {
+            entry,
+            registers: self.state.registers.clone(),
+            xmm_registers: self.state.xmm_registers.clone(),
+        }));
+
+        Ok(())
+    }
+
+    // Whether memory writes are currently recorded
+    pub(crate) fn instruction_trace_active(&self) -> bool {
+        self.instruction_trace.pending.is_some()
+    }
+
+    pub(crate) fn instruction_trace_memory(&mut self, address: u64, old: Vec<u8>, new: &[u8]) {
+        if let Some(pending) = self.instruction_trace.pending.as_mut() {
+            pending.entry.memory.push(MemoryChange {
+                address,
+                old,
+                new: new.to_vec(),
+            });
+        }
+    }
+
+    // Called after the instruction was executed, completes its entry
+    pub(crate) fn instruction_trace_end(&mut self) -> Result<(), AxError> {
+        let pending = match self.instruction_trace.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let mut entry = pending.entry;
+        for (register, new) in &self.state.registers {
+            let old = pending.registers.get(register).copied().unwrap_or_default();
+            if *register != SupportedRegister::RIP && old != *new {
+                entry.registers.push(RegisterChange {
+                    register: *register,
+                    old: old as u128,
+                    new: *new as u128,
+                });
+            }
+        }
+        for (register, new) in &self.state.xmm_registers {
+            let old = pending
+                .xmm_registers
+                .get(register)
+                .copied()
+                .unwrap_or_default();
+            if old != *new {
+                entry.registers.push(RegisterChange {
+                    register: *register,
+                    old,
+                    new: *new,
+                });
+            }
+        }
+        entry.registers.sort_by_key(|change| change.register as u32);
+        entry.rflags = self.state.rflags;
+
+        match &self.instruction_trace.mode {
+            TraceMode::Off => {}
+            TraceMode::Buffer(capacity) => {
+                let entries = &mut self.instruction_trace.entries;
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+            TraceMode::Native(function) => {
+                call_native_hook(function, |f| f(&entry))?.map_err(|e| {
+                    AxError::from(format!("Instruction trace callback failed: {e}"))
+                })?;
+            }
+            #[cfg(all(target_arch = "wasm32", not(test)))]
+            TraceMode::Js(function) => {
+                let value = js_sys::JSON::parse(&entry.to_json())
+                    .map_err(|e| AxError::from(format!("{:?}", e)))?;
+                function.call1(&JsValue::NULL, &value).map_err(|e| {
+                    AxError::from(format!(
+                        "Instruction trace callback threw an error: {:?}",
+                        e
+                    ))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
+impl Axecutor {
+    /// Records every executed instruction and calls `callback` with it instead of keeping it in memory.
+    /// The entry is passed in the same format as the objects in `instruction_trace_json`.
+    /// Replaces any other instruction trace mode.
+    pub fn enable_instruction_trace_streaming(&mut self, callback: js_sys::Function) {
+        self.set_instruction_trace_mode(TraceMode::Js(callback));
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Records every executed instruction with its address, bytes, disassembly, the registers and memory it wrote and the flags afterwards.
+    /// Only the last `capacity` instructions are kept. Replaces any other instruction trace mode and discards recorded instructions.
+    pub fn enable_instruction_trace(&mut self, capacity: usize) -> Result<(), AxError> {
+        if capacity == 0 {
+            return Err(AxError::from(
+                "The instruction trace needs a capacity of at least one instruction",
+            ));
+        }
+
+        debug_log!("Enabling instruction trace with capacity {}", capacity);
+        self.set_instruction_trace_mode(TraceMode::Buffer(capacity));
+        Ok(())
+    }
+
+    /// Stops recording instructions and discards the instruction trace
+    pub fn disable_instruction_trace(&mut self) {
+        self.set_instruction_trace_mode(TraceMode::Off);
+    }
+
+    /// The recorded instructions in a line-oriented text format, oldest first, e.g.
+    /// `5 0x401004 4889e5 mov rbp,rsp | RBP 0x7ff0 -> 0x7fe8 | flags ZF PF`.
+    /// Each line has the instruction count, address, bytes and disassembly, then changed registers, written memory as `[address] old -> new` and the set flags
+    pub fn instruction_trace_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.instruction_trace.entries {
+            let _ = writeln!(text, "{entry}");
+        }
+        text
+    }
+
+    /// The recorded instructions as JSON array, oldest first. Numbers and bytes are hex strings
+    pub fn instruction_trace_json(&self) -> String {
+        let entries: Vec<String> = self
+            .instruction_trace
+            .entries
+            .iter()
+            .map(|entry| entry.to_json())
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::helpers::tests::test_async;
+    use crate::state::flags::FLAG_ZF;
+    use crate::state::registers::SupportedRegister::*;
+
+    // mov rax, 0x10; sub rax, 0x10; mov [0x2000], rax; push rax
+    const CODE: &[u8] = &[
+        0x48, 0xc7, 0xc0, 0x10, 0x00, 0x00, 0x00, 0x48, 0x83, 0xe8, 0x10, 0x48, 0x89, 0x04, 0x25,
+        0x00, 0x20, 0x00, 0x00, 0x50,
+    ];
+
+    fn axecutor() -> Axecutor {
+        let mut ax = Axecutor::new(CODE, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 8).unwrap();
+        ax.mem_write_64(0x2000, 0x1122334455667788).unwrap();
+        ax.init_stack(0x100).unwrap();
+        ax.reg_write_64(RAX, 5).unwrap();
+        ax
+    }
+
+    test_async![records_register_and_memory_changes; async {
+        let mut ax = axecutor();
+        ax.enable_instruction_trace(3).unwrap();
+        let rsp = ax.reg_read_64(RSP).unwrap();
+        ax.execute().await.expect("Failed to execute");
+
+        // Only the last three instructions are kept
+        let entries = ax.instruction_trace_entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].instruction_count, 1);
+        assert_eq!(entries[0].rip, 0x1007);
+        assert_eq!(entries[0].bytes, vec![0x48, 0x83, 0xe8, 0x10]);
+        assert_eq!(entries[0].disassembly, "sub rax,10h");
+        assert_eq!(entries[0].registers, vec![RegisterChange { register: RAX, old: 0x10, new: 0 }]);
+        assert!(entries[0].rflags & FLAG_ZF != 0);
+
+        assert_eq!(entries[1].registers, vec![]);
+        assert_eq!(entries[1].memory, vec![MemoryChange {
+            address: 0x2000,
+            old: 0x1122334455667788u64.to_le_bytes().to_vec(),
+            new: vec![0; 8],
+        }]);
+
+        assert_eq!(entries[2].registers, vec![RegisterChange { register: RSP, old: rsp as u128, new: rsp as u128 - 8 }]);
+        assert_eq!(entries[2].memory.len(), 1);
+        assert_eq!(entries[2].memory[0].new, vec![0; 8]);
+
+        let text = ax.instruction_trace_text();
+        assert_eq!(text.lines().next().unwrap(), "1 0x1007 4883e810 sub rax,10h | RAX 0x10 -> 0x0 | flags PF ZF");
+        assert!(text.contains("| [0x2000] 8877665544332211 -> 0000000000000000 |"), "{}", text);
+
+        let json = ax.instruction_trace_json();
+        assert!(json.starts_with(r#"[{"instruction_count":1,"rip":"0x1007","bytes":"4883e810","disassembly":"sub rax,10h","registers":[{"register":"RAX","old":"0x10","new":"0x0"}],"memory":[],"#), "{}", json);
+
+        ax.disable_instruction_trace();
+        assert!(ax.instruction_trace_entries().is_empty());
+    }];
+
+    test_async![streams_to_callback; async {
+        let mut ax = axecutor();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let callback_seen = seen.clone();
+        ax.enable_instruction_trace_native(move |entry| {
+            callback_seen.borrow_mut().push(entry.rip);
+            Ok(())
+        });
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(*seen.borrow(), vec![0x1000, 0x1007, 0x100b, 0x1013]);
+        // Nothing is kept in memory
+        assert!(ax.instruction_trace_entries().is_empty());
+    }];
+}
@@ -453,37 +453,37 @@ impl Axecutor {
 
 macro_rules! calculate_rm_r {
     [u8f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_8f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_8f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_16f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_16f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_32f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_32f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_64f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_64f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16f; u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_16f_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_16f_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32f; u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_32f_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_32f_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64f; u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_64f_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_64f_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_16($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_16($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_32($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_32($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_r_64($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_r_64($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u8; $self:expr; $i:expr; $op:expr] => {
         calculate_rm_r![u8; $self; $i; $op; (set: 0; clear: 0)]
@@ -862,28 +862,28 @@ impl Axecutor {
 
 macro_rules! calculate_r_rm {
     [u8f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_8f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_8f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_16f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_16f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_32f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_32f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_64f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_64f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_16($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_16($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_32($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_32($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64; $self:expr; $i:expr; $op:expr;  (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_64($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_64($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u8; $self:expr; $i:expr; $op:expr] => {
         calculate_r_rm![u8; $self; $i; $op; (set: 0; clear: 0)]
@@ -895,16 +895,16 @@ macro_rules! calculate_r_rm {
         calculate_r_rm![u32; $self; $i; $op; (set: 0; clear: 0)]
     };
     [u32; u16; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_32_16($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_32_16($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64; $self:expr; $i:expr; $op:expr] => {
         calculate_r_rm![u64; $self; $i; $op; (set: 0; clear: 0)]
     };
     [u64; u32; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_64_32($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_64_32($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64; u16; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_r_rm_64_16($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_r_rm_64_16($i, $op, $flags_to_set, $flags_to_clear))
     };
 }
 
@@ -1477,28 +1477,28 @@ impl Axecutor {
 
 macro_rules! calculate_rm_imm {
     [u8f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_8f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_8f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_16f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_16f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_32f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_32f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_64f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_64f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_16($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_16($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_32($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_32($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_64($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_64($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u8; $self:expr; $i:expr; $op:expr] => {
         calculate_rm_imm![u8; $self; $i; $op; (set: 0; clear: 0)]
@@ -1525,13 +1525,13 @@ macro_rules! calculate_rm_imm {
         calculate_rm_imm![u64f; $self; $i; $op; (set: 0; clear: 0)]
     };
     [u16f; u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_16f_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_16f_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32f; u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_32f_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_32f_8($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64f; u8; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_imm_64f_8($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_imm_64f_8($i, $op, $flags_to_set, $flags_to_clear))
     };
 }
 
@@ -1698,16 +1698,16 @@ impl Axecutor {
 
 macro_rules! calculate_rm {
     [u8f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_8f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_8f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u16f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_16f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_16f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u32f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_32f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_32f($i, $op, $flags_to_set, $flags_to_clear))
     };
     [u64f; $self:expr; $i:expr; $op:expr; (set: $flags_to_set:expr; clear: $flags_to_clear:expr)] => {
-        $self.calculate_rm_64f($i, $op, $flags_to_set, $flags_to_clear)
+        $self.taint_alu($i, $flags_to_set, |ax| ax.calculate_rm_64f($i, $op, $flags_to_set, $flags_to_clear))
     };
 }
 
@@ -1760,10 +1760,14 @@ macro_rules! opcode_unimplemented {
         #[cfg(target_arch = "wasm32")]
         {
             // In WASM we don't panic, as it's not possible to catch panics from JS
-            return Err(AxError::from(format!(
-                "Executed unimplemented opcode: {}",
-                $message
-            )));
+            return Err(
+                AxError::from(format!("Executed unimplemented opcode: {}", $message)).with_kind(
+                    $crate::helpers::errors::ErrorKind::UnsupportedInstruction {
+                        mnemonic: String::new(),
+                        code: String::new(),
+                    },
+                ),
+            );
         }
 
         #[cfg(not(target_arch = "wasm32"))]
@@ -1,7 +1,14 @@
+pub mod coverage;
 pub(crate) mod debug;
 pub mod errors;
+pub mod fuzz;
+pub mod instruction_trace;
 pub(crate) mod macros;
 pub(crate) mod operand;
+pub mod profile;
+pub mod signals;
+pub mod stack;
+pub mod strace;
 pub mod syscalls;
 pub(crate) mod tests;
 pub mod trace;
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use iced_x86::Instruction;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+
+/// Instructions executed in a function while profiling was enabled
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileFunction {
+    /// Start address of the function, as recorded by the call instruction that called it
+    pub address: u64,
+    pub name: String,
+    /// Instructions executed in the function and the functions it called
+    pub inclusive: u64,
+    /// Instructions executed in the function itself
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Profile {
+    // Instruction counts by call stack, outermost function first
+    stacks: HashMap<Vec<u64>, u64>,
+    total: u64,
+}
+
+impl Profile {
+    fn add(&mut self, stack: &[u64]) {
+        match self.stacks.get_mut(stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(stack.to_vec(), 1);
+            }
+        }
+        self.total += 1;
+    }
+}
+
+impl Axecutor {
+    // Called before each instruction, which is attributed to the functions on the call stack
+    pub(crate) fn profile_step(&mut self, instr: &Instruction) {
+        let Some(profile) = self.profile.as_mut() else {
+            return;
+        };
+
+        if !self.state.call_stack.is_empty() {
+            profile.add(&self.state.call_stack);
+            return;
+        }
+        // Without any calls, e.g. when starting somewhere else than the entry point, fall back to the symbol containing the instruction
+        let function = self
+            .symbol_table
+            .lookup(instr.ip())
+            .map_or(instr.ip(), |(symbol, _)| symbol.address);
+        profile.add(&[function]);
+    }
+
+    fn profile(&self) -> Result<&Profile, AxError> {
+        self.profile.as_ref().ok_or_else(|| {
+            AxError::from("Profiling is not enabled, enable it with enable_profiling")
+        })
+    }
+
+    // Names a function for the collapsed stack format, which separates frames with semicolons
+    fn profile_frame_name(&self, address: u64) -> String {
+        self.symbol_at(address)
+            .or_else(|| {
+                self.debug_info
+                    .function(address)
+                    .map(|(name, offset)| match offset {
+                        0 => name.to_string(),
+                        _ => format!("{name}+{offset:#x}"),
+                    })
+            })
+            .unwrap_or_else(|| format!("{address:#x}"))
+            .replace(';', ":")
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Starts counting executed instructions per call stack. Each instruction is attributed to the functions on the call stack,
+    /// which is built from executed call and return instructions, so e.g. tail calls using jmp stay in the calling function.
+    /// The profile collected before is kept; use `reset_profile` to start over.
+    pub fn enable_profiling(&mut self) {
+        debug_log!("Enabling profiling");
+        if self.profile.is_none() {
+            self.profile = Some(Profile::default());
+        }
+    }
+
+    /// Stops profiling and discards the profile
+    pub fn disable_profiling(&mut self) {
+        self.profile = None;
+    }
+
+    /// Discards the profile collected so far
+    pub fn reset_profile(&mut self) {
+        if let Some(profile) = self.profile.as_mut() {
+            *profile = Profile::default();
+        }
+    }
+
+    /// Exports the profile in the collapsed stack format read by flamegraph.pl, inferno and speedscope:
+    /// one line per call stack with the function names separated by semicolons, outermost first, followed by the instruction count
+    pub fn profile_collapsed_stacks(&self) -> Result<String, AxError> {
+        let profile = self.profile()?;
+
+        // Different addresses may have the same name, e.g. without a symbol table
+        let mut stacks = BTreeMap::new();
+        for (stack, count) in &profile.stacks {
+            let names = stack
+                .iter()
+                .map(|&address| self.profile_frame_name(address))
+                .collect::<Vec<_>>()
+                .join(";");
+            *stacks.entry(names).or_insert(0) += count;
+        }
+
+        let mut collapsed = String::new();
+        for (names, count) in stacks {
+            writeln!(collapsed, "{names} {count}").unwrap();
+        }
+        Ok(collapsed)
+    }
+
+    /// The functions instructions were executed in, ordered by inclusive and then exclusive count, highest first.
+    /// Recursive calls are counted once for the inclusive count
+    pub fn profile_functions(&self) -> Result<Vec<ProfileFunction>, AxError> {
+        let profile = self.profile()?;
+
+        let mut counts = HashMap::<u64, (u64, u64)>::new();
+        for (stack, &count) in &profile.stacks {
+            for (i, address) in stack.iter().enumerate() {
+                if !stack[..i].contains(address) {
+                    counts.entry(*address).or_default().0 += count;
+                }
+            }
+            if let Some(leaf) = stack.last() {
+                counts.entry(*leaf).or_default().1 += count;
+            }
+        }
+
+        let mut functions = counts
+            .into_iter()
+            .map(|(address, (inclusive, exclusive))| ProfileFunction {
+                address,
+                name: self.profile_frame_name(address),
+                inclusive,
+                exclusive,
+            })
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|f| {
+            (
+                std::cmp::Reverse(f.inclusive),
+                std::cmp::Reverse(f.exclusive),
+                f.address,
+            )
+        });
+        Ok(functions)
+    }
+
+    /// Formats `profile_functions` as a table with the instruction counts and their share of all profiled instructions.
+    /// Only the first `limit` functions are listed if it is given
+    pub fn profile_table(&self, limit: Option<u32>) -> Result<String, AxError> {
+        let total = self.profile()?.total;
+        let functions = self.profile_functions()?;
+        let percent = |count: u64| match total {
+            0 => 0.0,
+            _ => count as f64 * 100.0 / total as f64,
+        };
+
+        let mut table = format!(
+            "{:>14} {:>7} {:>14} {:>7} {:>18}  function\n",
+            "inclusive", "%", "exclusive", "%", "address"
+        );
+        for f in functions
+            .iter()
+            .take(limit.map_or(usize::MAX, |l| l as usize))
+        {
+            writeln!(
+                table,
+                "{:>14} {:>6.2}% {:>14} {:>6.2}% {:>#18x}  {}",
+                f.inclusive,
+                percent(f.inclusive),
+                f.exclusive,
+                percent(f.exclusive),
+                f.address,
+                f.name
+            )
+            .unwrap();
+        }
+        writeln!(table, "{total} instructions in total").unwrap();
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+    use crate::state::registers::SupportedRegister::*;
+
+    // _start calls f twice, f calls g once
+    const CODE: [u8; 19] = [
+        0x90, // g: nop
+        0xc3, // ret
+        0xe8, 0xf9, 0xff, 0xff, 0xff, // f: call 0x1000 (g)
+        0xc3, // ret
+        0xe8, 0xf5, 0xff, 0xff, 0xff, // _start: call 0x1002 (f)
+        0xe8, 0xf0, 0xff, 0xff, 0xff, // call 0x1002 (f)
+        0x90, // nop
+    ];
+
+    test_async![profile_counts_instructions_per_stack; async {
+        let mut ax = Axecutor::new(&CODE, 0x1000, 0x1008).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x8000, 0x1010).unwrap();
+        ax.reg_write_64(RSP, 0x9000).unwrap();
+        ax.profile_functions().expect_err("Profiling is not enabled");
+        ax.enable_profiling();
+        ax.execute().await.unwrap();
+
+        // f and g have no symbols
+        assert_eq!(
+            ax.profile_collapsed_stacks().unwrap(),
+            "_start 3\n_start;0x1002 4\n_start;0x1002;0x1000 4\n"
+        );
+
+        let counts = ax
+            .profile_functions()
+            .unwrap()
+            .iter()
+            .map(|f| (f.address, f.inclusive, f.exclusive))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [(0x1008, 11, 3), (0x1002, 8, 4), (0x1000, 4, 4)]);
+
+        let table = ax.profile_table(Some(1)).unwrap();
+        assert!(table.contains("100.00%"));
+        assert!(table.contains("0x1008  _start\n"));
+        assert!(!table.contains("0x1002"));
+        assert!(table.ends_with("11 instructions in total\n"));
+
+        ax.reset_profile();
+        assert_eq!(ax.profile_collapsed_stacks().unwrap(), "");
+    }];
+}
@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    axecutor::Axecutor,
+    helpers::{
+        debug::debug_log,
+        errors::{AxError, ErrorKind},
+        syscalls::{errno, Syscall, SyscallResult},
+    },
+    state::{
+        exceptions::{CpuException, ExceptionKind},
+        registers::SupportedRegister::{self, *},
+    },
+};
+
+pub const SIGILL: u64 = 4;
+pub const SIGTRAP: u64 = 5;
+pub const SIGFPE: u64 = 8;
+pub const SIGKILL: u64 = 9;
+pub const SIGSEGV: u64 = 11;
+pub const SIGSTOP: u64 = 19;
+
+// Number of signals, including real-time signals
+const NSIG: u64 = 64;
+
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+
+const SA_NODEFER: u64 = 0x40000000;
+const SA_RESETHAND: u64 = 0x80000000;
+const SA_RESTORER: u64 = 0x04000000;
+
+// si_code values, see siginfo.h
+const SI_USER: i32 = 0;
+const SI_TKILL: i32 = -6;
+const SI_KERNEL: i32 = 0x80;
+const ILL_ILLOPN: i32 = 2;
+const FPE_INTDIV: i32 = 1;
+const SEGV_MAPERR: i32 = 1;
+const SEGV_ACCERR: i32 = 2;
+
+// Process and thread ID reported to the emulated program
+pub const EMULATED_PID: u64 = 1000;
+
+// Layout of struct rt_sigframe on the stack: return address (the restorer), ucontext, siginfo
+const UCONTEXT_OFFSET: u64 = 8;
+const UCONTEXT_SIZE: u64 = 304;
+const SIGINFO_OFFSET: u64 = UCONTEXT_OFFSET + UCONTEXT_SIZE;
+const SIGINFO_SIZE: u64 = 128;
+const FRAME_SIZE: u64 = SIGINFO_OFFSET + SIGINFO_SIZE;
+
+// Offsets into struct ucontext
+const UC_MCONTEXT: usize = 40;
+const UC_SIGMASK: usize = 296;
+
+// Registers in the order they are stored in struct sigcontext, which starts at uc_mcontext
+const SIGCONTEXT_REGISTERS: [SupportedRegister; 17] = [
+    R8, R9, R10, R11, R12, R13, R14, R15, RDI, RSI, RBP, RBX, RDX, RAX, RCX, RSP, RIP,
+];
+// Offsets of the remaining struct sigcontext fields
+const SC_EFLAGS: usize = 136;
+const SC_CS: usize = 144;
+const SC_SS: usize = 150;
+const SC_ERR: usize = 152;
+const SC_TRAPNO: usize = 160;
+const SC_OLDMASK: usize = 168;
+const SC_CR2: usize = 176;
+
+// The kernel doesn't touch the 128 bytes below the stack pointer (red zone)
+const RED_ZONE: u64 = 128;
+
+/// The signal the kernel sends for a CPU exception
+pub(crate) fn fault_signal(kind: ExceptionKind) -> u64 {
+    match kind {
+        ExceptionKind::PageFault | ExceptionKind::GeneralProtection => SIGSEGV,
+        ExceptionKind::DivideError => SIGFPE,
+        ExceptionKind::InvalidOpcode => SIGILL,
+        ExceptionKind::Breakpoint => SIGTRAP,
+    }
+}
+
+/// Signal names as used in error messages
+pub fn signal_name(signal: u64) -> String {
+    const NAMES: [&str; 31] = [
+        "SIGHUP",
+        "SIGINT",
+        "SIGQUIT",
+        "SIGILL",
+        "SIGTRAP",
+        "SIGABRT",
+        "SIGBUS",
+        "SIGFPE",
+        "SIGKILL",
+        "SIGUSR1",
+        "SIGSEGV",
+        "SIGUSR2",
+        "SIGPIPE",
+        "SIGALRM",
+        "SIGTERM",
+        "SIGSTKFLT",
+        "SIGCHLD",
+        "SIGCONT",
+        "SIGSTOP",
+        "SIGTSTP",
+        "SIGTTIN",
+        "SIGTTOU",
+        "SIGURG",
+        "SIGXCPU",
+        "SIGXFSZ",
+        "SIGVTALRM",
+        "SIGPROF",
+        "SIGWINCH",
+        "SIGIO",
+        "SIGPWR",
+        "SIGSYS",
+    ];
+
+    match signal {
+        1..=31 => NAMES[signal as usize - 1].to_string(),
+        32..=NSIG => format!("SIGRT{}", signal - 32),
+        _ => format!("signal {signal}"),
+    }
+}
+
+fn signal_bit(signal: u64) -> u64 {
+    1 << (signal - 1)
+}
+
+// Signals that are discarded when their action is SIG_DFL
+fn ignored_by_default(signal: u64) -> bool {
+    matches!(signal, 17 | 18 | 23 | 28)
+}
+
+/// struct sigaction as passed to rt_sigaction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SigAction {
+    handler: u64,
+    flags: u64,
+    restorer: u64,
+    mask: u64,
+}
+
+impl SigAction {
+    fn from_bytes(bytes: &[u8]) -> SigAction {
+        let field = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        SigAction {
+            handler: field(0),
+            flags: field(1),
+            restorer: field(2),
+            mask: field(3),
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        [self.handler, self.flags, self.restorer, self.mask]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect()
+    }
+}
+
+// How a signal came to be, this determines the contents of siginfo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignalSource {
+    // Sent by kill or tkill/tgkill
+    User { code: i32 },
+    Exception(CpuException),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SignalState {
+    // Installed actions by signal number, signals without an entry use SIG_DFL
+    actions: HashMap<u64, SigAction>,
+    // Blocked and pending signals, bit n-1 stands for signal n
+    mask: u64,
+    pending: u64,
+    // siginfo codes of pending signals
+    pending_codes: HashMap<u64, i32>,
+}
+
+impl Axecutor {
+    pub(crate) fn register_signal_syscall(&mut self, syscall: Syscall) -> Result<(), AxError> {
+        match syscall {
+            Syscall::RtSigaction => self.register_rt_sigaction(),
+            Syscall::RtSigprocmask => self.register_rt_sigprocmask(),
+            Syscall::RtSigreturn => self.register_rt_sigreturn(),
+            Syscall::Getpid | Syscall::Gettid => {
+                self.hook_syscall_native(syscall as u64, |_, _| {
+                    Ok(SyscallResult::Return(EMULATED_PID))
+                })?;
+                Ok(())
+            }
+            Syscall::Kill | Syscall::Tkill | Syscall::Tgkill => self.register_kill(syscall),
+            _ => Err(AxError::from(format!(
+                "Syscall {syscall:?} is not a signal syscall"
+            ))),
+        }
+    }
+
+    fn register_rt_sigaction(&mut self) -> Result<(), AxError> {
+        self.hook_syscall_native(Syscall::RtSigaction as u64, |ax: &mut Axecutor, args| {
+            let (signal, act, oact, sigsetsize) = (args.rdi, args.rsi, args.rdx, args.r10);
+
+            debug_log!(
+                "Running native rt_sigaction syscall for signal {}, act {:#x}, oact {:#x}",
+                signal,
+                act,
+                oact
+            );
+
+            if sigsetsize != 8 || signal == 0 || signal > NSIG {
+                return Ok(SyscallResult::Errno(errno::EINVAL));
+            }
+
+            let old = ax.signal_action(signal);
+
+            if act != 0 {
+                // The actions of SIGKILL and SIGSTOP cannot be changed
+                if signal == SIGKILL || signal == SIGSTOP {
+                    return Ok(SyscallResult::Errno(errno::EINVAL));
+                }
+
+                let bytes = match ax.mem_read_bytes(act, 32) {
+                    Ok(bytes) => bytes,
+                    Err(_) => return Ok(SyscallResult::Errno(errno::EFAULT)),
+                };
+                let mut action = SigAction::from_bytes(&bytes);
+                action.mask &= !(signal_bit(SIGKILL) | signal_bit(SIGSTOP));
+
+                ax.state.signals.actions.insert(signal, action);
+
+                // Ignoring a signal discards it if it is pending
+                if action.handler == SIG_IGN
+                    || (action.handler == SIG_DFL && ignored_by_default(signal))
+                {
+                    ax.state.signals.pending &= !signal_bit(signal);
+                }
+            }
+
+            if oact != 0 && ax.mem_write_bytes(oact, &old.to_bytes()).is_err() {
+                return Ok(SyscallResult::Errno(errno::EFAULT));
+            }
+
+            Ok(SyscallResult::Return(0))
+        })?;
+
+        Ok(())
+    }
+
+    fn register_rt_sigprocmask(&mut self) -> Result<(), AxError> {
+        self.hook_syscall_native(Syscall::RtSigprocmask as u64, |ax: &mut Axecutor, args| {
+            let (how, set, oset, sigsetsize) = (args.rdi, args.rsi, args.rdx, args.r10);
+
+            debug_log!(
+                "Running native rt_sigprocmask syscall with how {}, set {:#x}, oset {:#x}",
+                how,
+                set,
+                oset
+            );
+
+            if sigsetsize != 8 {
+                return Ok(SyscallResult::Errno(errno::EINVAL));
+            }
+
+            let old = ax.state.signals.mask;
+
+            if set != 0 {
+                let set = match ax.mem_read_64(set) {
+                    Ok(set) => set,
+                    Err(_) => return Ok(SyscallResult::Errno(errno::EFAULT)),
+                };
+
+                let mask = match how {
+                    // SIG_BLOCK
+                    0 => old | set,
+                    // SIG_UNBLOCK
+                    1 => old & !set,
+                    // SIG_SETMASK
+                    2 => set,
+                    _ => return Ok(SyscallResult::Errno(errno::EINVAL)),
+                };
+
+                // SIGKILL and SIGSTOP cannot be blocked
+                ax.state.signals.mask = mask & !(signal_bit(SIGKILL) | signal_bit(SIGSTOP));
+            }
+
+            if oset != 0 && ax.mem_write_64(oset, old).is_err() {
+                return Ok(SyscallResult::Errno(errno::EFAULT));
+            }
+
+            Ok(SyscallResult::Return(0))
+        })?;
+
+        Ok(())
+    }
+
+    fn register_rt_sigreturn(&mut self) -> Result<(), AxError> {
+        self.hook_syscall_native(Syscall::RtSigreturn as u64, |ax: &mut Axecutor, _| {
+            // The handler returned to the restorer, which popped the return address. What's left is the frame after it
+            let ucontext = ax.native_rsp()?;
+
+            debug_log!(
+                "Running native rt_sigreturn syscall with ucontext at {:#x}",
+                ucontext
+            );
+
+            let uc = ax.mem_read_bytes(ucontext, UCONTEXT_SIZE).map_err(|e| {
+                AxError::from(format!(
+                    "rt_sigreturn: failed to read signal frame at {ucontext:#x}: {e}"
+                ))
+            })?;
+            let field =
+                |offset: usize| u64::from_le_bytes(uc[offset..offset + 8].try_into().unwrap());
+
+            for (i, reg) in SIGCONTEXT_REGISTERS.iter().enumerate() {
+                let value = field(UC_MCONTEXT + i * 8);
+                // The stack pointer is saved as a native process would have it
+                if *reg == RSP {
+                    ax.set_native_rsp(value)?;
+                } else {
+                    ax.reg_write_64(*reg, value)?;
+                }
+            }
+            ax.state.rflags = field(UC_MCONTEXT + SC_EFLAGS);
+
+            ax.state.signals.mask =
+                field(UC_SIGMASK) & !(signal_bit(SIGKILL) | signal_bit(SIGSTOP));
+
+            // The registers were restored, RAX included
+            Ok(SyscallResult::NoReturn)
+        })?;
+
+        Ok(())
+    }
+
+    fn register_kill(&mut self, syscall: Syscall) -> Result<(), AxError> {
+        self.hook_syscall_native(syscall as u64, move |ax: &mut Axecutor, args| {
+            let (pid, signal, code) = match syscall {
+                Syscall::Kill => (args.rdi, args.rsi, SI_USER),
+                Syscall::Tkill => (args.rdi, args.rsi, SI_TKILL),
+                // tgkill has to match both the thread group and thread ID
+                _ if args.rdi != args.rsi => return Ok(SyscallResult::Errno(errno::ESRCH)),
+                _ => (args.rsi, args.rdx, SI_TKILL),
+            };
+
+            debug_log!(
+                "Running native {:?} syscall with pid {} and signal {}",
+                syscall,
+                pid,
+                signal
+            );
+
+            if signal > NSIG {
+                return Ok(SyscallResult::Errno(errno::EINVAL));
+            }
+            // kill(0, ...) sends to the process group, which only contains the emulated process
+            if pid != EMULATED_PID && !(syscall == Syscall::Kill && pid == 0) {
+                return Ok(SyscallResult::Errno(errno::ESRCH));
+            }
+
+            // Signal 0 only checks whether the process exists
+            if signal != 0 {
+                ax.queue_signal(signal, code);
+            }
+
+            Ok(SyscallResult::Return(0))
+        })?;
+
+        Ok(())
+    }
+
+    fn signal_action(&self, signal: u64) -> SigAction {
+        self.state
+            .signals
+            .actions
+            .get(&signal)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn queue_signal(&mut self, signal: u64, code: i32) {
+        let action = self.signal_action(signal);
+        if action.handler == SIG_IGN || (action.handler == SIG_DFL && ignored_by_default(signal)) {
+            debug_log!("Discarding ignored signal {}", signal);
+            return;
+        }
+
+        self.state.signals.pending |= signal_bit(signal);
+        self.state.signals.pending_codes.insert(signal, code);
+    }
+
+    // Delivers the lowest pending signal that is not blocked, if any
+    pub(crate) fn deliver_pending_signal(&mut self) -> Result<(), AxError> {
+        let deliverable = self.state.signals.pending & !self.state.signals.mask;
+        if deliverable == 0 || self.state.finished {
+            return Ok(());
+        }
+
+        let signal = deliverable.trailing_zeros() as u64 + 1;
+        self.state.signals.pending &= !signal_bit(signal);
+        let code = self
+            .state
+            .signals
+            .pending_codes
+            .remove(&signal)
+            .unwrap_or(SI_USER);
+
+        let action = self.signal_action(signal);
+        if action.handler == SIG_DFL {
+            return Err(AxError::from(format!(
+                "Program terminated by signal {} ({})",
+                signal,
+                signal_name(signal)
+            ))
+            .with_kind(ErrorKind::Signal { signal }));
+        }
+
+        self.setup_signal_frame(signal, SignalSource::User { code }, action)
+    }
+
+    /// Delivers a CPU exception to the emulated program as a signal. Returns whether the program has a handler for it,
+    /// in that case execution continues in the handler.
+    pub(crate) fn deliver_fault_signal(
+        &mut self,
+        exception: &CpuException,
+    ) -> Result<bool, AxError> {
+        let signal = fault_signal(exception.kind);
+
+        // Blocked or ignored faults kill the process, just like the default action
+        let action = self.signal_action(signal);
+        if action.handler == SIG_DFL
+            || action.handler == SIG_IGN
+            || self.state.signals.mask & signal_bit(signal) != 0
+        {
+            return Ok(false);
+        }
+
+        debug_log!(
+            "Delivering {} for exception {}",
+            signal_name(signal),
+            exception
+        );
+
+        self.setup_signal_frame(signal, SignalSource::Exception(*exception), action)?;
+
+        Ok(true)
+    }
+
+    // Builds a struct rt_sigframe on the stack and transfers control to the signal handler
+    fn setup_signal_frame(
+        &mut self,
+        signal: u64,
+        source: SignalSource,
+        action: SigAction,
+    ) -> Result<(), AxError> {
+        if action.flags & SA_RESTORER == 0 {
+            return Err(AxError::from(format!(
+                "Cannot deliver {}: its handler at {:#x} was installed without SA_RESTORER",
+                signal_name(signal),
+                action.handler
+            )));
+        }
+
+        // The frame is laid out like the kernel does it for a native process
+        let rsp = self.native_rsp()?;
+        let Some(frame) = rsp.checked_sub(RED_ZONE + FRAME_SIZE).and_then(|rsp| {
+            // Leave room for the handler's stack pointer below the frame
+            Some((rsp & !0xf).checked_sub(16)? + 8)
+        }) else {
+            // Linux forces SIGSEGV if the frame cannot be written
+            return Err(AxError::from(format!(
+                "Cannot deliver {}: no room for a signal frame below the stack pointer {:#x}",
+                signal_name(signal),
+                rsp
+            )));
+        };
+
+        let mut bytes = vec![0u8; FRAME_SIZE as usize];
+        bytes[0..8].copy_from_slice(&action.restorer.to_le_bytes());
+
+        let uc = UCONTEXT_OFFSET as usize;
+        let mcontext = uc + UC_MCONTEXT;
+        let mut put = |offset: usize, value: &[u8]| {
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+        };
+        for (i, reg) in SIGCONTEXT_REGISTERS.iter().enumerate() {
+            let value = if *reg == RSP {
+                rsp
+            } else {
+                self.reg_read_64(*reg)?
+            };
+            put(mcontext + i * 8, &value.to_le_bytes());
+        }
+        put(mcontext + SC_EFLAGS, &self.state.rflags.to_le_bytes());
+        // Segment selectors of 64-bit user mode
+        put(mcontext + SC_CS, &0x33u16.to_le_bytes());
+        put(mcontext + SC_SS, &0x2bu16.to_le_bytes());
+        if let SignalSource::Exception(exception) = source {
+            put(mcontext + SC_ERR, &exception.error_code.to_le_bytes());
+            put(mcontext + SC_TRAPNO, &(exception.kind as u64).to_le_bytes());
+            if exception.kind == ExceptionKind::PageFault {
+                put(
+                    mcontext + SC_CR2,
+                    &exception.address.unwrap_or(0).to_le_bytes(),
+                );
+            }
+        }
+        put(
+            mcontext + SC_OLDMASK,
+            &self.state.signals.mask.to_le_bytes(),
+        );
+        put(uc + UC_SIGMASK, &self.state.signals.mask.to_le_bytes());
+
+        let info = SIGINFO_OFFSET as usize;
+        let (code, field) = match source {
+            SignalSource::User { code } => (code, EMULATED_PID),
+            SignalSource::Exception(exception) => match exception.kind {
+                ExceptionKind::PageFault if exception.is_protection_violation() => {
+                    (SEGV_ACCERR, exception.address.unwrap_or(0))
+                }
+                ExceptionKind::PageFault => (SEGV_MAPERR, exception.address.unwrap_or(0)),
+                ExceptionKind::DivideError => (FPE_INTDIV, exception.rip),
+                ExceptionKind::InvalidOpcode => (ILL_ILLOPN, exception.rip),
+                ExceptionKind::GeneralProtection | ExceptionKind::Breakpoint => (SI_KERNEL, 0),
+            },
+        };
+        put(info, &(signal as i32).to_le_bytes());
+        put(info + 8, &code.to_le_bytes());
+        // si_addr for faults, si_pid (and si_uid 0) for signals sent by the program
+        put(info + 16, &field.to_le_bytes());
+
+        self.mem_poke_bytes(frame, &bytes).map_err(|e| {
+            AxError::from(format!(
+                "Cannot deliver {}: failed to write signal frame at {:#x}: {}",
+                signal_name(signal),
+                frame,
+                e
+            ))
+        })?;
+
+        let mut mask = self.state.signals.mask | action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            mask |= signal_bit(signal);
+        }
+        self.state.signals.mask = mask & !(signal_bit(SIGKILL) | signal_bit(SIGSTOP));
+
+        if action.flags & SA_RESETHAND != 0 {
+            self.state.signals.actions.remove(&signal);
+        }
+
+        debug_log!(
+            "Entering handler {:#x} for {} with frame at {:#x}",
+            action.handler,
+            signal_name(signal),
+            frame
+        );
+
+        self.reg_write_64(RDI, signal)?;
+        self.reg_write_64(RSI, frame + SIGINFO_OFFSET)?;
+        self.reg_write_64(RDX, frame + UCONTEXT_OFFSET)?;
+        self.reg_write_64(RAX, 0)?;
+        // The handler starts as if called, with the restorer as its return address
+        self.set_native_rsp(frame)?;
+        self.reg_write_64(RIP, action.handler)?;
+        self.state.call_stack.push(action.handler);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+
+    // Creates an axecutor that starts at `start` and has a struct sigaction for the handler at 0x1000 at 0x3000
+    fn with_handler(code: &[u8], restorer: u64, start: u64) -> Axecutor {
+        let mut ax =
+            Axecutor::new(code, 0x1000, 0x1000 + start).expect("Failed to create axecutor");
+        ax.init_stack(0x1000).expect("Failed to init stack");
+        ax.mem_init_zero(0x3000, 0x200)
+            .expect("Failed to init memory");
+
+        let action = SigAction {
+            handler: 0x1000,
+            flags: SA_RESTORER,
+            restorer: 0x1000 + restorer,
+            mask: 0,
+        };
+        ax.mem_write_bytes(0x3000, &action.to_bytes()).unwrap();
+
+        ax.handle_syscalls(vec![
+            Syscall::RtSigaction,
+            Syscall::RtSigprocmask,
+            Syscall::RtSigreturn,
+            Syscall::Getpid,
+            Syscall::Kill,
+        ])
+        .expect("Failed to register syscalls");
+
+        ax
+    }
+
+    test_async![segv_handler_skips_faulting_instruction; async {
+        let mut ax = with_handler(
+            &[
+                // handler:
+                0x89, 0x3c, 0x25, 0x00, 0x31, 0x00, 0x00, // mov dword ptr [0x3100], edi
+                0x48, 0x8b, 0x46, 0x10, // mov rax, qword ptr [rsi+0x10]
+                0x48, 0x89, 0x04, 0x25, 0x08, 0x31, 0x00, 0x00, // mov qword ptr [0x3108], rax
+                0x48, 0x83, 0x82, 0xa8, 0x00, 0x00, 0x00, 0x03, // add qword ptr [rdx+0xa8], 3
+                0xc3, // ret
+                // restorer:
+                0xb8, 0x0f, 0x00, 0x00, 0x00, // mov eax, 15
+                0x0f, 0x05, // syscall
+                // start:
+                0xb8, 0x0d, 0x00, 0x00, 0x00, // mov eax, 13
+                0xbf, 0x0b, 0x00, 0x00, 0x00, // mov edi, 11
+                0xbe, 0x00, 0x30, 0x00, 0x00, // mov esi, 0x3000
+                0x31, 0xd2, // xor edx, edx
+                0x41, 0xba, 0x08, 0x00, 0x00, 0x00, // mov r10d, 8
+                0x0f, 0x05, // syscall
+                0xb9, 0x10, 0x00, 0x00, 0x00, // mov ecx, 0x10
+                0x48, 0x8b, 0x01, // mov rax, qword ptr [rcx]
+                0xbb, 0x2a, 0x00, 0x00, 0x00, // mov ebx, 42
+            ],
+            0x1c,
+            0x23,
+        );
+        let rsp = ax.reg_read_64(RSP).unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(ax.mem_read_32(0x3100).unwrap(), SIGSEGV);
+        // si_addr
+        assert_eq!(ax.mem_read_64(0x3108).unwrap(), 0x10);
+        assert_eq!(ax.reg_read_64(RBX).unwrap(), 42);
+        assert_eq!(ax.reg_read_64(RCX).unwrap(), 0x10);
+        assert_eq!(ax.reg_read_64(RSP).unwrap(), rsp);
+    }];
+
+    test_async![blocked_signal_is_delivered_when_unblocked; async {
+        let mut ax = with_handler(
+            &[
+                // handler:
+                0x89, 0x3c, 0x25, 0x00, 0x31, 0x00, 0x00, // mov dword ptr [0x3100], edi
+                0xbb, 0x07, 0x00, 0x00, 0x00, // mov ebx, 7
+                0xc3, // ret
+                // restorer:
+                0xb8, 0x0f, 0x00, 0x00, 0x00, // mov eax, 15
+                0x0f, 0x05, // syscall
+                // start:
+                0xb8, 0x0d, 0x00, 0x00, 0x00, // mov eax, 13
+                0xbf, 0x0a, 0x00, 0x00, 0x00, // mov edi, 10
+                0xbe, 0x00, 0x30, 0x00, 0x00, // mov esi, 0x3000
+                0x31, 0xd2, // xor edx, edx
+                0x41, 0xba, 0x08, 0x00, 0x00, 0x00, // mov r10d, 8
+                0x0f, 0x05, // syscall
+                0xb8, 0x0e, 0x00, 0x00, 0x00, // mov eax, 14
+                0x31, 0xff, // xor edi, edi
+                0xbe, 0x40, 0x30, 0x00, 0x00, // mov esi, 0x3040
+                0x31, 0xd2, // xor edx, edx
+                0x41, 0xba, 0x08, 0x00, 0x00, 0x00, // mov r10d, 8
+                0x0f, 0x05, // syscall
+                0xbb, 0x34, 0x12, 0x00, 0x00, // mov ebx, 0x1234
+                0xb8, 0x27, 0x00, 0x00, 0x00, // mov eax, 39
+                0x0f, 0x05, // syscall
+                0x89, 0xc7, // mov edi, eax
+                0xbe, 0x0a, 0x00, 0x00, 0x00, // mov esi, 10
+                0xb8, 0x3e, 0x00, 0x00, 0x00, // mov eax, 62
+                0x0f, 0x05, // syscall
+                0x44, 0x8b, 0x24, 0x25, 0x00, 0x31, 0x00, 0x00, // mov r12d, dword ptr [0x3100]
+                0xb8, 0x0e, 0x00, 0x00, 0x00, // mov eax, 14
+                0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, 1
+                0xbe, 0x40, 0x30, 0x00, 0x00, // mov esi, 0x3040
+                0x31, 0xd2, // xor edx, edx
+                0x41, 0xba, 0x08, 0x00, 0x00, 0x00, // mov r10d, 8
+                0x0f, 0x05, // syscall
+                0x44, 0x8b, 0x2c, 0x25, 0x00, 0x31, 0x00, 0x00, // mov r13d, dword ptr [0x3100]
+            ],
+            0xd,
+            0x14,
+        );
+        // Block SIGUSR1
+        ax.mem_write_64(0x3040, signal_bit(10)).unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(ax.reg_read_64(R12).unwrap(), 0);
+        assert_eq!(ax.reg_read_64(R13).unwrap(), 10);
+        // Registers are restored after the handler, including the result of rt_sigprocmask
+        assert_eq!(ax.reg_read_64(RBX).unwrap(), 0x1234);
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 0);
+        assert_eq!(ax.state.signals.mask, 0);
+    }];
+
+    test_async![invalid_stack_pointers_are_errors; async {
+        let mut ax = with_handler(
+            &[
+                // handler:
+                0xc3, // ret
+                // restorer:
+                0xb8, 0x0f, 0x00, 0x00, 0x00, // mov eax, 15
+                0x0f, 0x05, // syscall
+                // start:
+                0xb8, 0x0d, 0x00, 0x00, 0x00, // mov eax, 13
+                0xbf, 0x0b, 0x00, 0x00, 0x00, // mov edi, 11
+                0xbe, 0x00, 0x30, 0x00, 0x00, // mov esi, 0x3000
+                0x31, 0xd2, // xor edx, edx
+                0x41, 0xba, 0x08, 0x00, 0x00, 0x00, // mov r10d, 8
+                0x0f, 0x05, // syscall
+                0x31, 0xe4, // xor esp, esp
+                0xb9, 0x10, 0x00, 0x00, 0x00, // mov ecx, 0x10
+                0x48, 0x8b, 0x01, // mov rax, qword ptr [rcx]
+            ],
+            0x1,
+            0x8,
+        );
+        let err = ax.execute().await.expect_err("There is no room for the signal frame");
+        assert!(err.to_string().contains("Cannot deliver SIGSEGV"), "{}", err);
+
+        // A forged frame restores a stack pointer of 0
+        let mut ax = with_handler(
+            &[
+                0xb8, 0x0f, 0x00, 0x00, 0x00, // mov eax, 15
+                0x0f, 0x05, // syscall
+            ],
+            0,
+            0,
+        );
+        ax.mem_init_zero(0x4000, 0x1000).unwrap();
+        ax.reg_write_64(RSP, 0x4000).unwrap();
+        ax.execute().await.expect_err("Execution continues at address 0");
+        assert_eq!(ax.reg_read_64(RSP).unwrap(), u64::MAX - 7);
+    }];
+
+    test_async![default_actions; async {
+        let mut ax = with_handler(
+            &[
+                0xb8, 0x3e, 0x00, 0x00, 0x00, // mov eax, 62
+                0xbf, 0xe8, 0x03, 0x00, 0x00, // mov edi, 1000
+                0xbe, 0x0f, 0x00, 0x00, 0x00, // mov esi, 15
+                0x0f, 0x05, // syscall
+                0xbb, 0x2a, 0x00, 0x00, 0x00, // mov ebx, 42
+            ],
+            0,
+            0,
+        );
+
+        let err = ax.execute().await.expect_err("SIGTERM should terminate the program");
+        assert!(err.to_string().contains("signal 15 (SIGTERM)"), "{}", err);
+
+        // Faults without a handler keep their original error
+        let mut ax = Axecutor::new(
+            &[
+                0x31, 0xc9, // xor ecx, ecx
+                0x48, 0xf7, 0xf1, // div rcx
+            ],
+            0x1000,
+            0x1000,
+        )
+        .unwrap();
+        let err = ax.execute().await.expect_err("Expected divide by zero error");
+        assert!(err.to_string().contains("Divide by zero"), "{}", err);
+    }];
+}
@@ -1 +1,404 @@
-// TODO: Implement printing the current stack, see also how GDB does it in case that makes sense.
+use std::collections::HashMap;
+
+use elf::endian::AnyEndian;
+use elf::segment::ProgramHeader;
+use elf::ElfBytes;
+use gimli::{
+    BaseAddresses, CfaRule, EhFrame, EhFrameHdr, LittleEndian, Pointer, RegisterRule,
+    UnwindContext, UnwindSection, X86_64,
+};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+use crate::state::registers::SupportedRegister::*;
+use crate::state::registers::{from_native_rsp, to_native_rsp, SupportedRegister};
+
+// Stop unwinding at some point in case the stack contains a loop
+const MAX_FRAMES: usize = 256;
+
+// Registers in the order they are shown in backtraces, with their DWARF register numbers.
+// The return address column is used as RIP of the calling frame
+const UNWIND_REGISTERS: [(SupportedRegister, gimli::Register); 17] = [
+    (RIP, X86_64::RA),
+    (RSP, X86_64::RSP),
+    (RBP, X86_64::RBP),
+    (RAX, X86_64::RAX),
+    (RBX, X86_64::RBX),
+    (RCX, X86_64::RCX),
+    (RDX, X86_64::RDX),
+    (RSI, X86_64::RSI),
+    (RDI, X86_64::RDI),
+    (R8, X86_64::R8),
+    (R9, X86_64::R9),
+    (R10, X86_64::R10),
+    (R11, X86_64::R11),
+    (R12, X86_64::R12),
+    (R13, X86_64::R13),
+    (R14, X86_64::R14),
+    (R15, X86_64::R15),
+];
+
+// According to the System V ABI, these keep their value across calls unless the CFI says otherwise
+const CALLEE_SAVED: [SupportedRegister; 6] = [RBX, RBP, R12, R13, R14, R15];
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UnwindInfo {
+    eh_frame: Vec<u8>,
+    eh_frame_address: u64,
+    // Binary search table from PT_GNU_EH_FRAME, might be empty
+    eh_frame_hdr: Vec<u8>,
+    eh_frame_hdr_address: u64,
+}
+
+impl UnwindInfo {
+    /// Locates `.eh_frame` via the section headers or, for binaries without them, via the `PT_GNU_EH_FRAME` segment.
+    /// Must be called after all segments have been loaded into memory.
+    pub(crate) fn from_elf(
+        ax: &Axecutor,
+        file: &ElfBytes<AnyEndian>,
+        binary: &[u8],
+        eh_frame_hdr: Option<&ProgramHeader>,
+    ) -> Result<UnwindInfo, AxError> {
+        let mut info = UnwindInfo::default();
+
+        if let Some(segment) = eh_frame_hdr {
+            let start = segment.p_offset as usize;
+            let end = start
+                .checked_add(segment.p_filesz as usize)
+                .filter(|end| *end <= binary.len())
+                .ok_or_else(|| AxError::from("ELF: PT_GNU_EH_FRAME segment out of bounds"))?;
+            info.eh_frame_hdr = binary[start..end].to_vec();
+            info.eh_frame_hdr_address = segment.p_vaddr;
+        }
+
+        if let Some(header) = file.section_header_by_name(".eh_frame")? {
+            let (data, _) = file.section_data(&header)?;
+            info.eh_frame = data.to_vec();
+            info.eh_frame_address = header.sh_addr;
+        } else if !info.eh_frame_hdr.is_empty() {
+            let bases = BaseAddresses::default().set_eh_frame_hdr(info.eh_frame_hdr_address);
+            let hdr = EhFrameHdr::new(&info.eh_frame_hdr, LittleEndian).parse(&bases, 8)?;
+            let address = match hdr.eh_frame_ptr() {
+                Pointer::Direct(address) => address,
+                Pointer::Indirect(_) => {
+                    return Err(AxError::from(
+                        "ELF: Indirect .eh_frame pointer in PT_GNU_EH_FRAME is not supported",
+                    ))
+                }
+            };
+
+            // Without section headers we don't know the size, but parsing stops at the end of the loaded segment
+            if let Some(area) = ax
+                .state
+                .memory
+                .iter()
+                .find(|a| a.start <= address && address < a.start + a.length)
+            {
+                info.eh_frame = ax.mem_peek_bytes(address, area.start + area.length - address)?;
+                info.eh_frame_address = address;
+            }
+        }
+
+        debug_log!(
+            "Read {} bytes of .eh_frame at {:#x}, {} bytes of .eh_frame_hdr",
+            info.eh_frame.len(),
+            info.eh_frame_address,
+            info.eh_frame_hdr.len()
+        );
+
+        Ok(info)
+    }
+}
+
+/// A single frame of a backtrace, as recovered from the call frame information in `.eh_frame`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Instruction pointer of this frame. For all but the innermost frame, this is the return address
+    pub pc: u64,
+    /// Canonical frame address as defined by the DWARF standard, if call frame information for `pc` was found
+    pub cfa: Option<u64>,
+    /// Register values known in this frame. Caller-saved registers are only known for the innermost frame
+    pub registers: HashMap<SupportedRegister, u64>,
+}
+
+impl Axecutor {
+    /// Unwinds the stack using the call frame information from `.eh_frame`, starting with the current register values.
+    /// In contrast to `call_stack`, which only sees executed call and return instructions,
+    /// this also works correctly with tail calls, `longjmp` and functions that were never seen being called.
+    /// The innermost frame is always included, even if the binary has no call frame information.
+    pub fn unwind(&self) -> Result<Vec<StackFrame>, AxError> {
+        let rip = self.reg_read_64(RIP)?;
+        self.unwind_from(rip)
+    }
+
+    pub(crate) fn unwind_from(&self, pc: u64) -> Result<Vec<StackFrame>, AxError> {
+        let mut registers = HashMap::new();
+        for (reg, _) in UNWIND_REGISTERS {
+            registers.insert(reg, self.reg_read_64(reg)?);
+        }
+        registers.insert(RIP, pc);
+
+        let info = &self.unwind_info;
+        if info.eh_frame.is_empty() {
+            return Ok(vec![StackFrame {
+                pc,
+                cfa: None,
+                registers,
+            }]);
+        }
+
+        let eh_frame = EhFrame::new(&info.eh_frame, LittleEndian);
+        let bases = BaseAddresses::default()
+            .set_eh_frame(info.eh_frame_address)
+            .set_eh_frame_hdr(info.eh_frame_hdr_address);
+        let hdr = match info.eh_frame_hdr.is_empty() {
+            true => None,
+            false => EhFrameHdr::new(&info.eh_frame_hdr, LittleEndian)
+                .parse(&bases, 8)
+                .ok(),
+        };
+        let table = hdr.as_ref().and_then(|h| h.table());
+        let mut ctx = UnwindContext::new();
+
+        let mut frames: Vec<StackFrame> = Vec::new();
+        while frames.len() < MAX_FRAMES {
+            let pc = registers[&RIP];
+            // Return addresses point after the call instruction, which might already be the start of the next function
+            let lookup_pc = if frames.is_empty() { pc } else { pc - 1 };
+
+            let row = match &table {
+                Some(table) => table.unwind_info_for_address(
+                    &eh_frame,
+                    &bases,
+                    &mut ctx,
+                    lookup_pc,
+                    EhFrame::cie_from_offset,
+                ),
+                None => eh_frame.unwind_info_for_address(
+                    &bases,
+                    &mut ctx,
+                    lookup_pc,
+                    EhFrame::cie_from_offset,
+                ),
+            };
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => {
+                    debug_log!("Stopping unwind at {:#x}: {}", pc, e);
+                    frames.push(StackFrame {
+                        pc,
+                        cfa: None,
+                        registers,
+                    });
+                    break;
+                }
+            };
+
+            // The CFA rules were written for native stack pointers, but the layout of values in memory is the same,
+            // so only the CFA itself needs to be adjusted
+            let cfa = match row.cfa() {
+                CfaRule::RegisterAndOffset { register, offset } => {
+                    match UNWIND_REGISTERS
+                        .iter()
+                        .find(|(_, r)| r == register)
+                        .and_then(|(reg, _)| registers.get(reg))
+                    {
+                        Some(value) => to_native_rsp(value.wrapping_add(*offset as u64)),
+                        None => {
+                            frames.push(StackFrame {
+                                pc,
+                                cfa: None,
+                                registers,
+                            });
+                            break;
+                        }
+                    }
+                }
+                CfaRule::Expression(_) => {
+                    debug_log!(
+                        "Stopping unwind at {:#x}: CFA expressions are not supported",
+                        pc
+                    );
+                    frames.push(StackFrame {
+                        pc,
+                        cfa: None,
+                        registers,
+                    });
+                    break;
+                }
+            };
+
+            let mut caller = HashMap::new();
+            for reg in CALLEE_SAVED {
+                if let Some(value) = registers.get(&reg) {
+                    caller.insert(reg, *value);
+                }
+            }
+            for (register, rule) in row.registers() {
+                let reg = match UNWIND_REGISTERS.iter().find(|(_, r)| r == register) {
+                    Some((reg, _)) => *reg,
+                    None => continue,
+                };
+                let value = match rule {
+                    RegisterRule::SameValue => registers.get(&reg).copied(),
+                    RegisterRule::Offset(offset) => {
+                        // Peek, so unwinding doesn't trigger memory hooks
+                        self.mem_peek_bytes(cfa.wrapping_add(*offset as u64), 8)
+                            .ok()
+                            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                    }
+                    RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add(*offset as u64)),
+                    RegisterRule::Register(other) => UNWIND_REGISTERS
+                        .iter()
+                        .find(|(_, r)| r == other)
+                        .and_then(|(reg, _)| registers.get(reg))
+                        .copied(),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => caller.insert(reg, value),
+                    None => caller.remove(&reg),
+                };
+            }
+            caller.insert(RSP, from_native_rsp(cfa));
+
+            let previous_cfa = frames.last().and_then(|f| f.cfa);
+            frames.push(StackFrame {
+                pc,
+                cfa: Some(cfa),
+                registers,
+            });
+
+            // The outermost frame (e.g. _start) marks its return address as undefined, or it points to garbage
+            let return_address = match caller.get(&RIP) {
+                Some(&ra) if ra != 0 && self.mem_read_executable_bytes(ra).is_ok() => ra,
+                _ => break,
+            };
+            // The stack grows down, so the caller's frame must be above ours
+            if previous_cfa.is_some_and(|previous| cfa <= previous) {
+                break;
+            }
+
+            debug_log!(
+                "Unwound frame at {:#x}, returning to {:#x}",
+                pc,
+                return_address
+            );
+            registers = caller;
+        }
+
+        Ok(frames)
+    }
+
+    // Backtrace for error messages, empty if the binary has no call frame information
+    pub(crate) fn error_backtrace(&self, pc: u64) -> String {
+        if self.unwind_info.eh_frame.is_empty() {
+            return String::new();
+        }
+        match self.unwind_from(pc) {
+            Ok(frames) => self.format_backtrace(&frames),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    pub(crate) fn format_backtrace(&self, frames: &[StackFrame]) -> String {
+        let mut s = String::new();
+
+        for (i, frame) in frames.iter().enumerate() {
+            // For outer frames, the line of the call instruction is more interesting than the one after it
+            let location_addr = if i == 0 { frame.pc } else { frame.pc - 1 };
+            s.push_str(&format!(
+                "#{:<3}{}\n",
+                i,
+                self.format_symbolized_with_location(frame.pc, location_addr)
+            ));
+
+            let registers: Vec<String> = UNWIND_REGISTERS
+                .iter()
+                .filter_map(|(reg, _)| {
+                    frame
+                        .registers
+                        .get(reg)
+                        .map(|value| format!("{}={:#x}", reg.name(), value))
+                })
+                .collect();
+            s.push_str(&format!("    {}\n", registers.join(" ")));
+        }
+
+        s
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Print a GDB-style backtrace of the current execution state, including the register values known for each frame.
+    /// This requires the binary to contain call frame information (`.eh_frame`), which is the default for binaries compiled by GCC.
+    /// Without it, only the innermost frame is shown.
+    pub fn backtrace(&self) -> Result<String, AxError> {
+        let frames = self.unwind()?;
+        Ok(self.format_backtrace(&frames))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::syscalls::Syscall;
+    use crate::helpers::tests::test_async;
+
+    // See testdata/unwind.c, level3 writes to a null pointer
+    async fn run_until_fault() -> Axecutor {
+        let binary = include_bytes!("../../testdata/unwind_c_nostdlib.bin");
+        let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+        ax.handle_syscalls(vec![Syscall::Exit])
+            .expect("Failed to add syscall handlers");
+        ax.init_stack(0x1000).expect("Failed to setup stack");
+
+        ax.execute()
+            .await
+            .expect_err("Null pointer write should fail");
+        ax
+    }
+
+    test_async![unwind_through_frames; async {
+        let ax = run_until_fault().await;
+
+        let frames = ax.unwind_from(0x401000).expect("Failed to unwind");
+        let pcs: Vec<u64> = frames.iter().map(|f| f.pc).collect();
+        assert_eq!(pcs, vec![0x401000, 0x401011, 0x40101d, 0x401030]);
+
+        // level2 saved rbx on the stack, so it can be recovered for its caller
+        let level2_cfa = frames[1].cfa.expect("No CFA for level2");
+        assert_eq!(level2_cfa, frames[0].cfa.unwrap() + 16);
+        assert_eq!(frames[1].registers.get(&RBX), Some(&6));
+        assert_eq!(frames[2].registers.get(&RBX), Some(&ax.mem_read_64(level2_cfa - 16).unwrap()));
+        assert_eq!(frames[2].registers.get(&RAX), None);
+        assert_eq!(frames[3].registers.get(&RSP), Some(&(frames[2].cfa.unwrap() - 8)));
+    }];
+
+    test_async![backtrace_in_error; async {
+        let binary = include_bytes!("../../testdata/unwind_c_nostdlib.bin");
+        let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+        ax.handle_syscalls(vec![Syscall::Exit])
+            .expect("Failed to add syscall handlers");
+        ax.init_stack(0x1000).expect("Failed to setup stack");
+
+        let err = ax.execute().await.expect_err("Null pointer write should fail").to_string();
+        assert!(err.contains("#0  level3@0x401000 (unwind.c:9)"), "{}", err);
+        assert!(err.contains("#1  level2+0xb@0x401011 (unwind.c:15)"), "{}", err);
+        assert!(err.contains("#2  level1+0x8@0x40101d (unwind.c:21)"), "{}", err);
+        assert!(err.contains("#3  _start+0xf@0x401030 (unwind.c:26)"), "{}", err);
+    }];
+
+    test_async![backtrace_without_cfi; async {
+        let binary = include_bytes!("../../testdata/trace.bin");
+        let ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+
+        let frames = ax.unwind().expect("Failed to unwind");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].cfa, None);
+        assert!(ax.backtrace().unwrap().starts_with("#0  _start@0x401000 (trace.S:6)\n    RIP=0x401000 RSP="));
+    }];
+}
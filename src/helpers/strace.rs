@@ -0,0 +1,370 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::errors::AxError;
+use crate::state::registers::SupportedRegister;
+use crate::state::shared_log::SharedLog;
+
+// Like strace, strings and buffers are cut off after this many bytes
+const MAX_STRING_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgKind {
+    // Signed decimal number
+    Int,
+    // Pointers, flags and other values that are easier to read in hex
+    Hex,
+    // NUL-terminated string
+    Str,
+    // Buffer read by the syscall, its size is the argument at the given index
+    InBuf(usize),
+    // Buffer written by the syscall, its size is the return value
+    OutBuf,
+}
+
+use ArgKind::*;
+
+// Number, name and arguments of a syscall
+type Signature = (u64, &'static str, &'static [ArgKind]);
+
+// Syscalls commonly used by statically linked programs, see syscall(2)
+const SIGNATURES: &[Signature] = &[
+    (0, "read", &[Int, OutBuf, Int]),
+    (1, "write", &[Int, InBuf(2), Int]),
+    (2, "open", &[Str, Hex, Hex]),
+    (3, "close", &[Int]),
+    (4, "stat", &[Str, Hex]),
+    (5, "fstat", &[Int, Hex]),
+    (8, "lseek", &[Int, Int, Int]),
+    (9, "mmap", &[Hex, Int, Hex, Hex, Int, Int]),
+    (10, "mprotect", &[Hex, Int, Hex]),
+    (11, "munmap", &[Hex, Int]),
+    (12, "brk", &[Hex]),
+    (13, "rt_sigaction", &[Int, Hex, Hex, Int]),
+    (14, "rt_sigprocmask", &[Int, Hex, Hex, Int]),
+    (15, "rt_sigreturn", &[]),
+    (16, "ioctl", &[Int, Hex, Hex]),
+    (20, "writev", &[Int, Hex, Int]),
+    (21, "access", &[Str, Hex]),
+    (22, "pipe", &[Hex]),
+    (39, "getpid", &[]),
+    (60, "exit", &[Int]),
+    (62, "kill", &[Int, Int]),
+    (63, "uname", &[Hex]),
+    (79, "getcwd", &[Hex, Int]),
+    (89, "readlink", &[Str, Hex, Int]),
+    (102, "getuid", &[]),
+    (104, "getgid", &[]),
+    (107, "geteuid", &[]),
+    (108, "getegid", &[]),
+    (158, "arch_prctl", &[Hex, Hex]),
+    (186, "gettid", &[]),
+    (200, "tkill", &[Int, Int]),
+    (201, "time", &[Hex]),
+    (218, "set_tid_address", &[Hex]),
+    (228, "clock_gettime", &[Int, Hex]),
+    (231, "exit_group", &[Int]),
+    (234, "tgkill", &[Int, Int, Int]),
+    (257, "openat", &[Int, Str, Hex, Hex]),
+    (262, "newfstatat", &[Int, Str, Hex, Hex]),
+    (273, "set_robust_list", &[Hex, Int]),
+    (302, "prlimit64", &[Int, Int, Hex, Hex]),
+    (318, "getrandom", &[OutBuf, Int, Hex]),
+    (334, "rseq", &[Hex, Int, Hex, Hex]),
+];
+
+// Error numbers and descriptions as printed by strace, see errno(3)
+const ERRNO_NAMES: &[(u64, &str, &str)] = &[
+    (1, "EPERM", "Operation not permitted"),
+    (2, "ENOENT", "No such file or directory"),
+    (3, "ESRCH", "No such process"),
+    (4, "EINTR", "Interrupted system call"),
+    (9, "EBADF", "Bad file descriptor"),
+    (11, "EAGAIN", "Resource temporarily unavailable"),
+    (12, "ENOMEM", "Cannot allocate memory"),
+    (13, "EACCES", "Permission denied"),
+    (14, "EFAULT", "Bad address"),
+    (17, "EEXIST", "File exists"),
+    (20, "ENOTDIR", "Not a directory"),
+    (21, "EISDIR", "Is a directory"),
+    (22, "EINVAL", "Invalid argument"),
+    (25, "ENOTTY", "Inappropriate ioctl for device"),
+    (29, "ESPIPE", "Illegal seek"),
+    (34, "ERANGE", "Numerical result out of range"),
+    (38, "ENOSYS", "Function not implemented"),
+];
+
+fn signature(number: u64) -> Option<&'static Signature> {
+    SIGNATURES.iter().find(|s| s.0 == number)
+}
+
+/// Name of the syscall with the given number, if it is known
+pub fn syscall_name(number: u64) -> Option<&'static str> {
+    signature(number).map(|s| s.1)
+}
+
+fn format_bytes(bytes: &[u8], truncated: bool) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes.iter().take(MAX_STRING_LENGTH) {
+        match b {
+            b'\n' => s.push_str("\\n"),
+            b'\t' => s.push_str("\\t"),
+            b'\r' => s.push_str("\\r"),
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\{b:o}")),
+        }
+    }
+    s.push('"');
+    if truncated || bytes.len() > MAX_STRING_LENGTH {
+        s.push_str("...");
+    }
+    s
+}
+
+/// A syscall made by the emulated program, as recorded by the syscall log
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyscallLogEntry {
+    pub number: u64,
+    /// Name of the syscall, or `syscall_<number>` for unknown syscalls
+    pub name: String,
+    /// Raw argument values; all six argument registers for unknown syscalls
+    pub args: Vec<u64>,
+    /// Arguments formatted like strace does, with strings and buffers read from memory
+    pub decoded_args: Vec<String>,
+    /// The value returned in RAX, or None/undefined if the syscall did not return, e.g. exit or a syscall that caused an error
+    pub result: Option<i64>,
+    /// Number of instructions executed before the syscall
+    pub instruction_count: u64,
+    /// Address of the syscall instruction
+    pub rip: u64,
+}
+
+impl Display for SyscallLogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.name, self.decoded_args.join(", "))?;
+        match self.result {
+            None => write!(f, " = ?"),
+            Some(result) if (-4095..0).contains(&result) => {
+                match ERRNO_NAMES
+                    .iter()
+                    .find(|(n, _, _)| *n == result.unsigned_abs())
+                {
+                    Some((_, name, description)) => write!(f, " = -1 {name} ({description})"),
+                    None => write!(f, " = -1 E{} (Unknown error)", -result),
+                }
+            }
+            // These return addresses
+            Some(result) if matches!(self.name.as_str(), "brk" | "mmap") => {
+                write!(f, " = {result:#x}")
+            }
+            Some(result) => write!(f, " = {result}"),
+        }
+    }
+}
+
+impl Axecutor {
+    fn strace_string(&self, address: u64) -> String {
+        let mut bytes = Vec::new();
+        for offset in 0..=MAX_STRING_LENGTH as u64 {
+            match self.mem_peek_bytes(address + offset, 1) {
+                Ok(b) if b[0] != 0 => bytes.push(b[0]),
+                Ok(_) => return format_bytes(&bytes, false),
+                Err(_) if offset == 0 => return format!("{address:#x}"),
+                Err(_) => break,
+            }
+        }
+        format_bytes(&bytes, true)
+    }
+
+    fn strace_buffer(&self, address: u64, length: u64) -> String {
+        let shown = length.min(MAX_STRING_LENGTH as u64 + 1);
+        match self.mem_peek_bytes(address, shown) {
+            Ok(bytes) => format_bytes(&bytes, length > shown),
+            Err(_) => format!("{address:#x}"),
+        }
+    }
+
+    /// Records the syscall that is about to be executed in the syscall log, if it is enabled
+    pub(crate) fn syscall_log_begin(&mut self, rip: u64) -> Result<(), AxError> {
+        if self.state.syscall_log.is_none() {
+            return Ok(());
+        }
+
+        let args = self.syscall_args()?;
+        let (name, kinds) = match signature(args.number) {
+            Some((_, name, kinds)) => (name.to_string(), *kinds),
+            None => (format!("syscall_{:#x}", args.number), &[Hex; 6][..]),
+        };
+        let values = args.args()[..kinds.len()].to_vec();
+
+        let decoded_args = kinds
+            .iter()
+            .zip(&values)
+            .map(|(kind, &value)| match *kind {
+                Int => (value as i64).to_string(),
+                Hex | OutBuf => format!("{value:#x}"),
+                Str => self.strace_string(value),
+                InBuf(length) => self.strace_buffer(value, values[length]),
+            })
+            .collect();
+
+        let entry = SyscallLogEntry {
+            number: args.number,
+            name,
+            args: values,
+            decoded_args,
+            result: None,
+            instruction_count: self.state.executed_instructions_count,
+            rip,
+        };
+        if let Some(log) = self.state.syscall_log.as_mut() {
+            log.push(entry);
+        }
+
+        Ok(())
+    }
+
+    /// Completes the last syscall log entry with the result of the syscall
+    pub(crate) fn syscall_log_end(&mut self) -> Result<(), AxError> {
+        let result = self.reg_read_64(SupportedRegister::RAX)? as i64;
+        let finished = self.state.finished;
+
+        let mut entry = match self.state.syscall_log.as_mut().and_then(|log| log.pop()) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        if !finished {
+            entry.result = Some(result);
+
+            // Output buffers only contain something useful after a successful syscall
+            if let Some((_, _, kinds)) = signature(entry.number) {
+                for (i, kind) in kinds.iter().enumerate() {
+                    if *kind == OutBuf && result >= 0 {
+                        entry.decoded_args[i] = self.strace_buffer(entry.args[i], result as u64);
+                    }
+                }
+            }
+        }
+
+        if let Some(log) = self.state.syscall_log.as_mut() {
+            log.push(entry);
+        }
+
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Enable or disable recording executed syscalls in the syscall log.
+    /// Disabling the log discards all entries.
+    pub fn set_syscall_log_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.state.syscall_log = None;
+        } else if self.state.syscall_log.is_none() {
+            self.state.syscall_log = Some(SharedLog::default());
+        }
+    }
+
+    /// All syscalls recorded since the log was enabled or last taken
+    pub fn syscall_log(&self) -> Vec<SyscallLogEntry> {
+        self.state
+            .syscall_log
+            .as_ref()
+            .map(SharedLog::to_vec)
+            .unwrap_or_default()
+    }
+
+    /// Returns the recorded syscalls and clears the log, e.g. to print syscalls while the program runs
+    pub fn take_syscall_log(&mut self) -> Vec<SyscallLogEntry> {
+        self.state
+            .syscall_log
+            .as_mut()
+            .map(|log| std::mem::take(log).into())
+            .unwrap_or_default()
+    }
+
+    /// The recorded syscalls in strace format, one per line, e.g. `write(1, "Hello, World!\n", 14) = 14`
+    pub fn syscall_log_strace(&self) -> String {
+        self.state
+            .syscall_log
+            .iter()
+            .flat_map(SharedLog::iter)
+            .map(|entry| format!("{entry}\n"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::syscalls::{Syscall, SyscallResult};
+    use crate::helpers::tests::test_async;
+
+    test_async![hello_world; async {
+        let binary = include_bytes!("../../testdata/hello_world.bin");
+        let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+        ax.init_stack_program_start(0x1000, vec!["/bin/hello_world".to_string()], vec![])
+            .expect("Failed to init stack");
+        ax.handle_syscalls(vec![Syscall::Exit, Syscall::Brk, Syscall::ArchPrctl])
+            .expect("Failed to add syscall handlers");
+        ax.hook_syscall_native(1, |_, args| Ok(SyscallResult::Return(args.rdx)))
+            .expect("Failed to add syscall handler");
+        ax.set_syscall_log_enabled(true);
+
+        ax.execute().await.expect("Failed to execute");
+
+        let log = ax.syscall_log();
+        let write = log.iter().find(|e| e.name == "write").expect("No write syscall logged");
+        assert_eq!(write.number, 1);
+        assert_eq!(write.args[0], 1);
+        assert_eq!(write.decoded_args[1], "\"Hello, World!\\n\"");
+        assert_eq!(write.result, Some(14));
+        assert!(write.instruction_count > 0);
+
+        let exit = log.last().unwrap();
+        assert_eq!(exit.to_string(), "exit(0) = ?");
+        assert!(ax.syscall_log_strace().contains("write(1, \"Hello, World!\\n\", 14) = 14\n"));
+
+        assert_eq!(ax.take_syscall_log().len(), log.len());
+        assert!(ax.syscall_log().is_empty());
+    }];
+
+    test_async![errors_and_unknown_syscalls; async {
+        let mut ax = Axecutor::new(
+            &[
+                0xb8, 0x02, 0, 0, 0, // mov eax, 2
+                0x0f, 0x05, // syscall
+                0xb8, 0xe8, 0x03, 0, 0, // mov eax, 1000
+                0x0f, 0x05, // syscall
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 0x100).unwrap();
+        ax.mem_write_bytes(0x2000, b"/etc/passwd\0").unwrap();
+        ax.reg_write_64(SupportedRegister::RDI, 0x2000).unwrap();
+        ax.reg_write_64(SupportedRegister::RSI, 0).unwrap();
+        ax.reg_write_64(SupportedRegister::RDX, 0).unwrap();
+        ax.hook_syscall_native(2, |_, _| Ok(SyscallResult::Errno(2))).unwrap();
+        ax.set_syscall_log_enabled(true);
+
+        ax.execute().await.expect_err("Syscall 1000 is not handled");
+
+        let log = ax.syscall_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].to_string(), "open(\"/etc/passwd\", 0x0, 0x0) = -1 ENOENT (No such file or directory)");
+        assert_eq!(log[0].rip, 0x1005);
+        assert_eq!(log[1].name, "syscall_0x3e8");
+        assert_eq!(log[1].args.len(), 6);
+        assert_eq!(log[1].result, None);
+    }];
+}
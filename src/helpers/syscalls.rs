@@ -1,35 +1,151 @@
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error::Error,
+    fmt::{Debug, Formatter},
+};
 
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::{
-    auto::generated::SupportedMnemonic,
     axecutor::Axecutor,
     helpers::macros::assert_fatal,
-    state::{hooks::HookResult, registers::SupportedRegister::*},
+    state::{
+        hooks::{call_native_hook, native_hook, sort_hooks, HookHandle, HookOrder, NativeHook},
+        memory::PAGE_SIZE,
+        registers::SupportedRegister::*,
+        replay::{InputSource, InputValue},
+    },
 };
 
+#[cfg(all(target_arch = "wasm32", not(test)))]
+use crate::state::hooks::run_function;
 #[cfg(all(target_arch = "wasm32", not(test)))]
 use wasm_bindgen::JsValue;
 
-use super::{debug::debug_log, errors::AxError};
+use super::{
+    debug::debug_log,
+    errors::{AxError, ErrorKind},
+};
 
 #[wasm_bindgen]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[repr(u16)]
 /// Syscalls that can be registered for automatic handling
 pub enum Syscall {
+    Mprotect = 10,
     Brk = 12,
+    RtSigaction = 13,
+    RtSigprocmask = 14,
+    RtSigreturn = 15,
     Pipe = 22,
+    Getpid = 39,
     Exit = 60,
+    Kill = 62,
     ArchPrctl = 158,
+    Gettid = 186,
+    Tkill = 200,
+    Tgkill = 234,
+}
+
+/// Error numbers returned by syscalls, see errno(3)
+pub mod errno {
+    pub const ESRCH: u16 = 3;
+    pub const EBADF: u16 = 9;
+    pub const ENOMEM: u16 = 12;
+    pub const EFAULT: u16 = 14;
+    pub const EINVAL: u16 = 22;
+    pub const ENOSYS: u16 = 38;
+}
+
+/// What happens when a syscall is not handled by any syscall handler or hook
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnhandledSyscallPolicy {
+    /// Stop execution with an error
+    #[default]
+    Error,
+    /// Return -ENOSYS to the program, like a kernel that doesn't implement the syscall
+    Enosys,
+}
+
+/// Number and arguments of a syscall, as passed to syscall handlers
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallArgs {
+    pub number: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+}
+
+#[wasm_bindgen]
+impl SyscallArgs {
+    /// The argument at `index` (0-5), in the order of the syscall calling convention
+    pub fn arg(&self, index: usize) -> Option<u64> {
+        self.args().get(index).copied()
+    }
+}
+
+impl SyscallArgs {
+    pub fn args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+/// Result of a syscall handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyscallResult {
+    /// Return the value in RAX
+    Return(u64),
+    /// Fail with an error number, RAX is set to its negated value
+    Errno(u16),
+    /// The syscall was handled, but does not return a value, e.g. exit. RAX is not changed
+    NoReturn,
+    /// Let the next handler decide
+    Unhandled,
+}
+
+// Syscall handler, called with the decoded syscall arguments
+pub type SyscallHandler =
+    dyn FnMut(&mut Axecutor, &SyscallArgs) -> Result<SyscallResult, Box<dyn Error>>;
+
+#[derive(Clone)]
+enum SyscallHandlerFunction {
+    Native(NativeHook<SyscallHandler>),
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    Js(js_sys::Function),
+}
+
+#[derive(Clone)]
+pub(crate) struct SyscallHook {
+    pub(crate) order: HookOrder,
+    // None for fallback handlers, which are called for syscalls no other handler handled
+    number: Option<u64>,
+    function: SyscallHandlerFunction,
+}
+
+impl Debug for SyscallHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyscallHook")
+            .field("handle", &self.order.handle)
+            .field("priority", &self.order.priority)
+            .field("number", &self.number)
+            .finish()
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct SyscallState {
-    registered: Vec<Syscall>,
+    pub(crate) registered: Vec<Syscall>,
+
+    #[serde(default)]
+    unhandled_policy: UnhandledSyscallPolicy,
 
     brk_start: u64,
     brk_length: u64,
@@ -41,7 +157,18 @@ pub(crate) struct SyscallState {
 
     // This maps the read end of a pipe to the contents of the pipe
     // To write, resolve the read end of the pipe via pipe_write_ends and write to the pipe_contents
-    pipe_contents: HashMap<u64, Vec<u8>>,
+    pub(crate) pipe_contents: HashMap<u64, Vec<u8>>,
+
+    // The status passed to exit, if the program finished by calling it
+    #[serde(default)]
+    pub(crate) exit_code: Option<u64>,
+}
+
+impl SyscallState {
+    // Start and length of the program break area, once brk has set it up
+    pub(crate) fn brk_area(&self) -> Option<(u64, u64)> {
+        (self.brk_start != 0).then_some((self.brk_start, self.brk_length))
+    }
 }
 
 impl TryFrom<u16> for Syscall {
@@ -49,10 +176,19 @@ impl TryFrom<u16> for Syscall {
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         Ok(match value {
+            10 => Syscall::Mprotect,
             12 => Syscall::Brk,
+            13 => Syscall::RtSigaction,
+            14 => Syscall::RtSigprocmask,
+            15 => Syscall::RtSigreturn,
             22 => Syscall::Pipe,
+            39 => Syscall::Getpid,
             60 => Syscall::Exit,
+            62 => Syscall::Kill,
             158 => Syscall::ArchPrctl,
+            186 => Syscall::Gettid,
+            200 => Syscall::Tkill,
+            234 => Syscall::Tgkill,
             _ => return Err(AxError::from(format!("Unknown syscall: {value}").as_str())),
         })
     }
@@ -91,7 +227,199 @@ fn get_js_list(vec: JsValue) -> Result<Vec<Syscall>, AxError> {
     Ok(result)
 }
 
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
 impl Axecutor {
+    /// Register a function that handles the syscall with the given number.
+    /// The function will be called with the Axecutor object and the SyscallArgs as arguments.
+    /// Like mnemonic hooks, it may be sync or async and *MUST* return the result of instance.commit(), instance.stop() or instance.unchanged().
+    /// It must write the return value, or the negated error number, to RAX itself.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_syscall(
+        &mut self,
+        number: u64,
+        handler: js_sys::Function,
+    ) -> Result<HookHandle, AxError> {
+        self.add_syscall_hook(Some(number), SyscallHandlerFunction::Js(handler))
+    }
+
+    /// Register a function that is called for syscalls no other syscall handler has handled, see `hook_syscall`.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_syscall_fallback(
+        &mut self,
+        handler: js_sys::Function,
+    ) -> Result<HookHandle, AxError> {
+        self.add_syscall_hook(None, SyscallHandlerFunction::Js(handler))
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Set what happens when a syscall is handled by neither syscall handlers nor hooks for the syscall mnemonic.
+    /// The default is to stop with an error.
+    pub fn set_unhandled_syscall_policy(&mut self, policy: UnhandledSyscallPolicy) {
+        self.state.syscalls.unhandled_policy = policy;
+    }
+}
+
+impl Axecutor {
+    pub(crate) fn syscall_args(&self) -> Result<SyscallArgs, AxError> {
+        Ok(SyscallArgs {
+            number: self.reg_read_64(RAX)?,
+            rdi: self.reg_read_64(RDI)?,
+            rsi: self.reg_read_64(RSI)?,
+            rdx: self.reg_read_64(RDX)?,
+            r10: self.reg_read_64(R10)?,
+            r8: self.reg_read_64(R8)?,
+            r9: self.reg_read_64(R9)?,
+        })
+    }
+
+    /// Runs the syscall handlers for the syscall in RAX, returning whether one of them handled it
+    pub(crate) async fn dispatch_syscall(&mut self) -> Result<bool, AxError> {
+        if let Some(value) = self.replay_input(InputSource::Syscall)? {
+            return Ok(value == Some(InputValue::Handled(true)));
+        }
+        if self.hooks.syscall_hooks.is_empty() {
+            return Ok(false);
+        }
+
+        let args = self.syscall_args()?;
+        // Handlers for this syscall come first, fallback handlers last
+        let hooks: Vec<SyscallHook> = self
+            .hooks
+            .syscall_hooks
+            .iter()
+            .filter(|h| h.number == Some(args.number))
+            .chain(
+                self.hooks
+                    .syscall_hooks
+                    .iter()
+                    .filter(|h| h.number.is_none()),
+            )
+            .cloned()
+            .collect();
+
+        debug_log!(
+            "Dispatching syscall {} to {} handler(s)",
+            args.number,
+            hooks.len()
+        );
+
+        let view = self.record_begin();
+        self.hooks.running = true;
+        let result = self.run_syscall_handlers(&hooks, &args).await;
+        self.hooks.running = false;
+
+        let handled = result.map_err(|e| {
+            AxError::from(format!("handling syscall {}: {e}", args.number))
+                .with_kind(ErrorKind::HookError)
+        })?;
+        self.record_end(view, InputSource::Syscall, InputValue::Handled(handled));
+
+        Ok(handled)
+    }
+
+    async fn run_syscall_handlers(
+        &mut self,
+        hooks: &[SyscallHook],
+        args: &SyscallArgs,
+    ) -> Result<bool, AxError> {
+        for hook in hooks {
+            let result = match &hook.function {
+                SyscallHandlerFunction::Native(f) => call_native_hook(f, |f| f(self, args))?
+                    .map_err(|e| AxError::from(e.to_string()))?,
+                // JS handlers set RAX themselves, there is no way for them to pass a syscall on
+                #[cfg(all(target_arch = "wasm32", not(test)))]
+                SyscallHandlerFunction::Js(f) => {
+                    run_function(self, f.clone(), vec![(*args).into()]).await?;
+                    SyscallResult::NoReturn
+                }
+            };
+
+            match result {
+                SyscallResult::Unhandled => continue,
+                SyscallResult::Return(value) => self.reg_write_64(RAX, value)?,
+                SyscallResult::Errno(errno) => self.reg_write_64(RAX, -(errno as i64) as u64)?,
+                SyscallResult::NoReturn => {}
+            }
+
+            debug_log!("Syscall {} returned {:?}", args.number, result);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Applies the unhandled syscall policy to a syscall nothing has handled
+    pub(crate) fn unhandled_syscall(&mut self) -> Result<(), AxError> {
+        let number = self.reg_read_64(RAX)?;
+        match self.state.syscalls.unhandled_policy {
+            UnhandledSyscallPolicy::Error => Err(AxError::from(format!(
+                "Syscall {number} encountered, but no handler available. Use `handle_syscalls` or `hook_syscall` to handle syscalls."
+            ))
+            .with_kind(ErrorKind::UnhandledSyscall { nr: number })),
+            UnhandledSyscallPolicy::Enosys => {
+                debug_log!("Returning ENOSYS for unhandled syscall {}", number);
+                self.reg_write_64(RAX, -(errno::ENOSYS as i64) as u64)
+            }
+        }
+    }
+
+    fn add_syscall_hook(
+        &mut self,
+        number: Option<u64>,
+        function: SyscallHandlerFunction,
+    ) -> Result<HookHandle, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot add syscall handlers while a hook is running",
+            ));
+        }
+
+        let hook = SyscallHook {
+            order: self.hooks.next_order(),
+            number,
+            function,
+        };
+        debug_log!("Adding syscall handler {:?}", hook);
+        let handle = hook.order.handle;
+        self.hooks.syscall_hooks.push(hook);
+        sort_hooks(&mut self.hooks.syscall_hooks, |h| h.order);
+        Ok(handle)
+    }
+
+    /// Register a function that handles the syscall with the given number.
+    /// The function gets the syscall arguments and returns the result; returning `SyscallResult::Unhandled` passes the syscall on to the next handler.
+    /// Handlers for the same syscall run in the order described at `set_hook_priority`, after the handlers registered by `handle_syscalls` with the same priority.
+    /// Syscalls handled this way don't reach hooks for the syscall mnemonic.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_syscall_native(
+        &mut self,
+        number: u64,
+        handler: impl FnMut(&mut Axecutor, &SyscallArgs) -> Result<SyscallResult, Box<dyn Error>>
+            + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_syscall_hook(
+            Some(number),
+            SyscallHandlerFunction::Native(native_hook(Box::new(handler))),
+        )
+    }
+
+    /// Register a function that is called for syscalls no other syscall handler has handled.
+    /// If it returns `SyscallResult::Unhandled`, mnemonic hooks and then the policy set by `set_unhandled_syscall_policy` decide.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_syscall_fallback_native(
+        &mut self,
+        handler: impl FnMut(&mut Axecutor, &SyscallArgs) -> Result<SyscallResult, Box<dyn Error>>
+            + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_syscall_hook(
+            None,
+            SyscallHandlerFunction::Native(native_hook(Box::new(handler))),
+        )
+    }
+
     #[cfg(not(all(target_arch = "wasm32", not(test))))]
     pub fn handle_syscalls(&mut self, list: Vec<Syscall>) -> Result<(), AxError> {
         self.handle_syscalls_impl(list)
@@ -113,7 +441,16 @@ impl Axecutor {
                 Syscall::Exit => self.register_exit()?,
                 Syscall::Pipe => self.register_pipe()?,
                 Syscall::Brk => self.register_brk()?,
+                Syscall::Mprotect => self.register_mprotect()?,
                 Syscall::ArchPrctl => self.register_arch_prctl()?,
+                Syscall::RtSigaction
+                | Syscall::RtSigprocmask
+                | Syscall::RtSigreturn
+                | Syscall::Getpid
+                | Syscall::Gettid
+                | Syscall::Kill
+                | Syscall::Tkill
+                | Syscall::Tgkill => self.register_signal_syscall(syscall)?,
             }
 
             self.state.syscalls.registered.push(syscall);
@@ -123,28 +460,20 @@ impl Axecutor {
     }
 
     fn register_exit(&mut self) -> Result<(), AxError> {
-        self.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|ax: &mut Axecutor, _| {
-            if ax.reg_read_64(RAX)? != Syscall::Exit as u64 {
-                return Ok(HookResult::Unhandled);
-            }
-
-            debug_log!(
-                "Running native exit syscall with code {}",
-                ax.reg_read_64(RDI)?
-            );
+        self.hook_syscall_native(Syscall::Exit as u64, |ax: &mut Axecutor, args| {
+            debug_log!("Running native exit syscall with code {}", args.rdi);
 
+            ax.state.syscalls.exit_code = Some(args.rdi);
             ax.state.finished = true;
 
-            Ok(HookResult::Handled)
-        })
+            Ok(SyscallResult::NoReturn)
+        })?;
+
+        Ok(())
     }
 
     fn register_pipe(&mut self) -> Result<(), AxError> {
-        self.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|ax: &mut Axecutor, _| {
-            if ax.reg_read_64(RAX)? != Syscall::Pipe as u64 {
-                return Ok(HookResult::Unhandled);
-            }
-
+        self.hook_syscall_native(Syscall::Pipe as u64, |ax: &mut Axecutor, args| {
             debug_log!("Running native pipe syscall");
 
             let read_end = rand::thread_rng().gen::<u16>() as u64 + 1024;
@@ -176,36 +505,30 @@ impl Axecutor {
                 .insert(write_end, read_end);
             ax.state.syscalls.pipe_contents.insert(read_end, Vec::new());
 
-            let fd_ptr = ax.reg_read_64(RDI)?;
+            let fd_ptr = args.rdi;
 
             ax.mem_write_64(fd_ptr, read_end)?;
             ax.mem_write_64(fd_ptr + 8, write_end)?;
 
-            ax.reg_write_64(RAX, 0)?;
-
             debug_log!(
                 "pipe syscall created read end {} and write end {}",
                 read_end,
                 write_end
             );
 
-            Ok(HookResult::Handled)
+            Ok(SyscallResult::Return(0))
         })?;
 
         // Read system call for pipes
-        self.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|ax: &mut Axecutor, _| {
-            if ax.reg_read_64(RAX)? != 0u64 {
-                return Ok(HookResult::Unhandled);
-            }
-
-            let fd = ax.reg_read_64(RDI)?;
-            let buf = ax.reg_read_64(RSI)?;
-            let count = ax.reg_read_64(RDX)?;
+        self.hook_syscall_native(0, |ax: &mut Axecutor, args| {
+            let fd = args.rdi;
+            let buf = args.rsi;
+            let count = args.rdx;
 
             let available_content = match ax.state.syscalls.pipe_contents.get(&fd) {
                 Some(bytes) => bytes.clone(),
-                // Maybe another hook will handle this fd
-                None => return Ok(HookResult::Unhandled),
+                // Maybe another handler will handle this fd
+                None => return Ok(SyscallResult::Unhandled),
             };
 
             debug_log!(
@@ -217,31 +540,26 @@ impl Axecutor {
 
             let max_bytes = std::cmp::min(count, available_content.len() as u64);
             ax.mem_write_bytes(buf, &available_content[..max_bytes as usize])?;
-            ax.reg_write_64(RAX, max_bytes)?;
 
             ax.state
                 .syscalls
                 .pipe_contents
                 .insert(fd, available_content[max_bytes as usize..].to_vec());
 
-            // Skip the rest -- that way users that register read syscalls won't ever see this
-            Ok(HookResult::Handled)
+            // Handled -- that way users that register read syscalls won't ever see this
+            Ok(SyscallResult::Return(max_bytes))
         })?;
 
         // Write system call for pipes
-        self.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|ax: &mut Axecutor, _| {
-            if ax.reg_read_64(RAX)? != 1u64 {
-                return Ok(HookResult::Unhandled);
-            }
-
-            let fd = ax.reg_read_64(RDI)?;
-            let buf = ax.reg_read_64(RSI)?;
-            let count = ax.reg_read_64(RDX)?;
+        self.hook_syscall_native(1, |ax: &mut Axecutor, args| {
+            let fd = args.rdi;
+            let buf = args.rsi;
+            let count = args.rdx;
 
             let write_end = match ax.state.syscalls.pipes_write_ends.get(&fd) {
                 Some(write_end) => *write_end,
-                // Maybe another hook will handle this fd
-                None => return Ok(HookResult::Unhandled),
+                // Maybe another handler will handle this fd
+                None => return Ok(SyscallResult::Unhandled),
             };
 
             debug_log!(
@@ -260,22 +578,16 @@ impl Axecutor {
                 .and_modify(|content| content.extend_from_slice(&bytes))
                 .or_insert(bytes);
 
-            ax.reg_write_64(RAX, count)?;
-
-            // Skip the rest -- that way users that register write syscalls won't ever see this
-            Ok(HookResult::Handled)
+            // Handled -- that way users that register write syscalls won't ever see this
+            Ok(SyscallResult::Return(count))
         })?;
 
         Ok(())
     }
 
     fn register_brk(&mut self) -> Result<(), AxError> {
-        self.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|ax: &mut Axecutor, _| {
-            if ax.reg_read_64(RAX)? != Syscall::Brk as u64 {
-                return Ok(HookResult::Unhandled);
-            }
-
-            let brk = ax.reg_read_64(RDI)?;
+        self.hook_syscall_native(Syscall::Brk as u64, |ax: &mut Axecutor, args| {
+            let brk = args.rdi;
             debug_log!(
                 "Running native brk syscall with argument {:#x}, current brk_start={:#x}, len={:#x}",
                 brk, ax.state.syscalls.brk_start, ax.state.syscalls.brk_length
@@ -297,8 +609,7 @@ impl Axecutor {
 
             // If the argument is 0, we just return the current brk_start
             if brk == 0 {
-                ax.reg_write_64(RAX, ax.state.syscalls.brk_start)?;
-                return Ok(HookResult::Handled);
+                return Ok(SyscallResult::Return(ax.state.syscalls.brk_start));
             }
 
             // Otherwise, we resize the brk section to the new size
@@ -307,22 +618,61 @@ impl Axecutor {
 
             ax.state.syscalls.brk_length = new_length;
 
-            ax.reg_write_64(RAX, ax.state.syscalls.brk_start + new_length)?;
+            Ok(SyscallResult::Return(ax.state.syscalls.brk_start + new_length))
+        })?;
 
-            Ok(HookResult::Handled)
-        })
+        Ok(())
     }
 
-    fn register_arch_prctl(&mut self) -> Result<(), AxError> {
-        self.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|ax: &mut Axecutor, _| {
-            if ax.reg_read_64(RAX)? != Syscall::ArchPrctl as u64 {
-                return Ok(HookResult::Unhandled);
+    fn register_mprotect(&mut self) -> Result<(), AxError> {
+        self.hook_syscall_native(Syscall::Mprotect as u64, |ax: &mut Axecutor, args| {
+            let addr = args.rdi;
+            let len = args.rsi;
+            let prot = args.rdx;
+
+            debug_log!(
+                "Running native mprotect syscall with addr {:#x}, len {:#x}, prot {:#x}",
+                addr,
+                len,
+                prot
+            );
+
+            // The address must be page-aligned, the length is rounded up to whole pages
+            if addr % PAGE_SIZE != 0 || prot > 7 {
+                return Ok(SyscallResult::Errno(errno::EINVAL));
+            }
+            // Like Linux, ranges that wrap around the address space are not mapped
+            let Some((len, end)) = len
+                .checked_add(PAGE_SIZE - 1)
+                .map(|len| len & !(PAGE_SIZE - 1))
+                .and_then(|len| Some((len, addr.checked_add(len)?)))
+            else {
+                return Ok(SyscallResult::Errno(errno::ENOMEM));
+            };
+
+            if ax.mem_prot_range(addr, len, prot as u32).is_err() {
+                // Range is not fully mapped
+                return Ok(SyscallResult::Errno(errno::ENOMEM));
             }
 
+            // Usually this is libc protecting its RELRO region, so there's no need to do it again later
+            ax.state
+                .relro
+                .pending
+                .retain(|(start, relro_end)| !(addr <= *start && *relro_end <= end));
+
+            Ok(SyscallResult::Return(0))
+        })?;
+
+        Ok(())
+    }
+
+    fn register_arch_prctl(&mut self) -> Result<(), AxError> {
+        self.hook_syscall_native(Syscall::ArchPrctl as u64, |ax: &mut Axecutor, args| {
             // TODO: Make sure this implements the syscall to spec when the memory implementation has been overhauled
 
-            let code = ax.reg_read_64(RDI)?;
-            let addr = ax.reg_read_64(RSI)?;
+            let code = args.rdi;
+            let addr = args.rsi;
 
             debug_log!(
                 "Running native arch_prctl syscall with code {:#x} and addr {:#x}",
@@ -331,36 +681,31 @@ impl Axecutor {
             );
 
             if ax.mem_read_8(addr).is_err() {
-                // return EFAULT -- invalid address
-                ax.reg_write_64(RAX, 14)?;
-                return Ok(HookResult::Handled);
+                // Invalid address
+                return Ok(SyscallResult::Errno(errno::EFAULT));
             }
 
-            match code {
+            Ok(match code {
                 0x1002 => {
                     // ARCH_SET_FS
                     ax.write_fs(addr);
+                    SyscallResult::Return(0)
                 }
                 0x1003 => {
                     // ARCH_SET_GS
                     ax.write_gs(addr);
+                    SyscallResult::Return(0)
                 }
-                0x1001 => {
-                    // ARCH_GET_FS
-                    ax.reg_write_64(RAX, ax.read_fs())?;
-                }
-                0x1004 => {
-                    // ARCH_GET_GS
-                    ax.reg_write_64(RAX, ax.read_gs())?;
-                }
-                _ => {
-                    // return EINVAL -- invalid code
-                    ax.reg_write_64(RAX, 22)?;
-                }
-            }
+                // ARCH_GET_FS
+                0x1001 => SyscallResult::Return(ax.read_fs()),
+                // ARCH_GET_GS
+                0x1004 => SyscallResult::Return(ax.read_gs()),
+                // Invalid code
+                _ => SyscallResult::Errno(errno::EINVAL),
+            })
+        })?;
 
-            Ok(HookResult::Handled)
-        })
+        Ok(())
     }
 }
 
@@ -385,3 +730,150 @@ mod tests {
     }];
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auto::generated::SupportedMnemonic;
+    use crate::helpers::tests::{test_async, write_reg_value};
+    use crate::state::hooks::HookResult;
+    use iced_x86::Register;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn two_syscalls(second: u8) -> Axecutor {
+        let mut ax = Axecutor::new(
+            &[
+                0xb8, 0xe8, 0x03, 0, 0, // mov eax, 1000
+                0x0f, 0x05, // syscall
+                0x48, 0x89, 0xc3, // mov rbx, rax
+                0xb8, second, 0x03, 0, 0, // mov eax, 1000 - 0xe8 + second
+                0x0f, 0x05, // syscall
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        for (i, reg) in [
+            Register::RDI,
+            Register::RSI,
+            Register::RDX,
+            Register::R10,
+            Register::R8,
+            Register::R9,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            write_reg_value!(q; ax; reg; i + 1);
+        }
+        ax
+    }
+
+    test_async![return_value_and_errno; async {
+        let mut ax = two_syscalls(0xe9);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s = seen.clone();
+        ax.hook_syscall_native(1000, move |_, args| {
+            s.borrow_mut().push(*args);
+            Ok(SyscallResult::Return(args.rdi + args.r9))
+        }).unwrap();
+        ax.hook_syscall_native(1001, |_, _| Ok(SyscallResult::Errno(errno::EINVAL))).unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(
+            *seen.borrow(),
+            vec![SyscallArgs { number: 1000, rdi: 1, rsi: 2, rdx: 3, r10: 4, r8: 5, r9: 6 }]
+        );
+        assert_eq!(seen.borrow()[0].arg(3), Some(4));
+        assert_eq!(ax.reg_read_64(RBX).unwrap(), 7);
+        assert_eq!(ax.reg_read_64(RAX).unwrap() as i64, -22);
+    }];
+
+    test_async![handler_order; async {
+        let mut ax = two_syscalls(0xe8);
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let c = calls.clone();
+        ax.hook_syscall_native(1000, move |_, _| {
+            c.borrow_mut().push("pass");
+            Ok(SyscallResult::Unhandled)
+        }).unwrap();
+        let c = calls.clone();
+        let handle = ax.hook_syscall_native(1000, move |_, _| {
+            c.borrow_mut().push("handle");
+            Ok(SyscallResult::Return(1))
+        }).unwrap();
+        ax.set_hook_priority(handle, 1).unwrap();
+
+        // Handled syscalls don't reach mnemonic hooks
+        ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, |_, _| {
+            Err(AxError::from("Syscall was already handled").into())
+        }).unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(*calls.borrow(), vec!["handle", "handle"]);
+
+        ax.remove_hook(handle).unwrap();
+        ax.reg_write_64(RIP, 0x1000).unwrap();
+        ax.state.finished = false;
+        let err = ax.execute().await.expect_err("Mnemonic hook should have run");
+        assert!(err.to_string().contains("Syscall was already handled"), "{err}");
+        assert_eq!(*calls.borrow(), vec!["handle", "handle", "pass"]);
+    }];
+
+    test_async![unhandled_syscall_policy; async {
+        let mut ax = two_syscalls(0xe9);
+        ax.hook_syscall_native(1000, |_, _| Ok(SyscallResult::Return(0))).unwrap();
+
+        let err = ax.execute().await.expect_err("Syscall 1001 is not handled");
+        assert!(err.to_string().contains("Syscall 1001 encountered, but no handler available"), "{err}");
+
+        let mut ax = two_syscalls(0xe9);
+        ax.set_unhandled_syscall_policy(UnhandledSyscallPolicy::Enosys);
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(ax.reg_read_64(RBX).unwrap() as i64, -38);
+        assert_eq!(ax.reg_read_64(RAX).unwrap() as i64, -38);
+
+        // Native mnemonic hooks that don't handle the syscall don't prevent the policy from applying
+        let mut ax = two_syscalls(0xe9);
+        ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, |_, _| Ok(HookResult::Unhandled)).unwrap();
+        ax.execute().await.expect_err("Syscalls are not handled");
+    }];
+
+    test_async![fallback_handler; async {
+        let mut ax = two_syscalls(0xe9);
+        ax.hook_syscall_native(1000, |_, _| Ok(SyscallResult::Return(5))).unwrap();
+        ax.hook_syscall_fallback_native(|_, args| {
+            assert_eq!(args.number, 1001, "Fallback handler called for handled syscall");
+            Ok(SyscallResult::NoReturn)
+        }).unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(ax.reg_read_64(RBX).unwrap(), 5);
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 1001);
+    }];
+
+    test_async![mprotect_rejects_wrapping_ranges; async {
+        for (addr, len) in [(0x2000, u64::MAX), (0xffff_ffff_ffff_f000, 0x2000)] {
+            let mut ax = Axecutor::new(
+                &[
+                    0xb8, 0x0a, 0, 0, 0, // mov eax, 10
+                    0x0f, 0x05, // syscall
+                ],
+                0x1000,
+                0x1000,
+            )
+            .expect("Failed to create axecutor");
+            ax.handle_syscalls(vec![Syscall::Mprotect]).unwrap();
+            ax.reg_write_64(RDI, addr).unwrap();
+            ax.reg_write_64(RSI, len).unwrap();
+            ax.reg_write_64(RDX, 1).unwrap();
+
+            ax.execute().await.expect("Failed to execute");
+            assert_eq!(ax.reg_read_64(RAX).unwrap() as i64, -(errno::ENOMEM as i64));
+        }
+    }];
+}
@@ -126,6 +126,19 @@ macro_rules! test_async {
 #[cfg(test)]
 pub(crate) use test_async;
 
+// The looping C program from testdata/c_loop.bin, with a handler for its exit syscall and a stack
+#[cfg(test)]
+pub(crate) fn c_loop() -> Axecutor {
+    use crate::helpers::syscalls::Syscall;
+
+    let binary = include_bytes!("../../testdata/c_loop.bin");
+    let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+    ax.handle_syscalls(vec![Syscall::Exit])
+        .expect("Failed to add syscall handlers");
+    ax.init_stack(0x1000).expect("Failed to setup stack");
+    ax
+}
+
 #[cfg(test)]
 macro_rules! assert_reg_value {
     [b; $axecutor:expr; $reg:expr; $value:expr] => {
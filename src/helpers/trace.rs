@@ -4,7 +4,7 @@ use wasm_bindgen::prelude::*;
 
 use crate::{axecutor::Axecutor, state::registers::SupportedRegister};
 
-use super::errors::AxError;
+use super::{errors::AxError, strace::syscall_name};
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct TraceEntry {
@@ -20,7 +20,8 @@ pub(crate) enum TraceVariant {
     Call,
     Return,
     Jump,
-    // TODO: Add syscall
+    // The target of a syscall is its number
+    Syscall,
 }
 
 // This is for the full tracing functionality, not to be confused with call_stack
@@ -40,6 +41,7 @@ impl Axecutor {
             match last.variant {
                 TraceVariant::Call => lvl += 1,
                 TraceVariant::Return => lvl -= 1,
+                TraceVariant::Syscall => {}
                 TraceVariant::Jump => {
                     // If we have seen this exact jump before, we just increment the count
                     if last.instr_ip == instr_ip
@@ -77,16 +79,17 @@ impl Axecutor {
         self.add_trace(i, target, TraceVariant::Jump {})
     }
 
-    /// Generate a trace of the current execution state. This trace is a list of all executed jumps, calls and returns.
+    pub(crate) fn trace_syscall(&mut self, i: Instruction, number: u64) -> Result<(), AxError> {
+        self.add_trace(i, number, TraceVariant::Syscall)
+    }
+
+    /// Generate a trace of the current execution state. This trace is a list of all executed jumps, calls, returns and syscalls.
     /// Conditional jumps that were not taken are **not** included in the trace.
     /// This works best when a symbol table has been provided, which is currently only the case for ELF binaries.
     pub fn trace(&mut self) -> Result<String, AxError> {
         let mut trace = String::new();
 
-        let mut i = 0;
-        while i < self.state.trace.len() {
-            let entry = &self.state.trace[i];
-
+        for entry in self.state.trace.iter() {
             let instruction = if entry.instr_ip == 0 {
                 "entrypoint".to_string()
             } else {
@@ -98,15 +101,15 @@ impl Axecutor {
             let instruction_symbol = if entry.instr_ip == 0 {
                 "<emulation_start>".to_string()
             } else {
-                match self.symbol_table.get(&entry.instr_ip) {
-                    Some(sym) => format!("{}@{:#x}", sym, entry.instr_ip),
-                    None => format!("{:#x}", entry.instr_ip),
-                }
+                self.format_symbolized(entry.instr_ip)
             };
 
-            let target_symbol = match self.symbol_table.get(&entry.target) {
-                Some(sym) => format!("{}@{:#x}", sym, entry.target),
-                None => format!("{:#x}", entry.target),
+            let target_symbol = match entry.variant {
+                TraceVariant::Syscall => match syscall_name(entry.target) {
+                    Some(name) => format!("{name} ({})", entry.target),
+                    None => format!("syscall {}", entry.target),
+                },
+                _ => self.format_symbolized(entry.target),
             };
 
             // If we have a jump, we count how many of the next are equal and then write e.g. x10 instead of 10 times the same line
@@ -128,7 +131,6 @@ impl Axecutor {
                     target_symbol,
                 ));
             }
-            i += 1;
         }
 
         Ok(trace)
@@ -157,20 +159,21 @@ mod tests {
 
         debug_log!("Trace:\n{}", trace);
 
-        assert_eq!(trace, r#"<emulation_start>: entrypoint => _start@0x401000
-  _start@0x401000: call 401015h => first_level@0x401015
-    first_level@0x401015: jmp 40101Eh => 0x40101e
-    0x40101e: call 401031h => second_level@0x401031
-      second_level@0x401031: call 40103Ch => third_level@0x40103c
-        0x401052: jne 40104Ah => 0x40104a (9 times)
-        0x401054: ret => 0x401036
-      0x401036: call 40103Ch => third_level@0x40103c
-        0x401052: jne 40104Ah => 0x40104a (9 times)
-        0x401054: ret => 0x40103b
-      0x40103b: ret => 0x401023
-    0x401023: call 401029h => second_level_two@0x401029
-      0x401030: ret => 0x401028
-    0x401028: ret => 0x401005
+        assert_eq!(trace, r#"<emulation_start>: entrypoint => _start@0x401000 (trace.S:6)
+  _start@0x401000 (trace.S:6): call 401015h => first_level@0x401015 (trace.S:14)
+    first_level@0x401015 (trace.S:14): jmp 40101Eh => first_level+0x9@0x40101e (trace.S:20)
+    first_level+0x9@0x40101e (trace.S:20): call 401031h => second_level@0x401031 (trace.S:32)
+      second_level@0x401031 (trace.S:32): call 40103Ch => third_level@0x40103c (trace.S:39)
+        third_level+0x16@0x401052 (trace.S:45): jne 40104Ah => third_level+0xe@0x40104a (trace.S:42) (9 times)
+        third_level+0x18@0x401054 (trace.S:47): ret => second_level+0x5@0x401036 (trace.S:34)
+      second_level+0x5@0x401036 (trace.S:34): call 40103Ch => third_level@0x40103c (trace.S:39)
+        third_level+0x16@0x401052 (trace.S:45): jne 40104Ah => third_level+0xe@0x40104a (trace.S:42) (9 times)
+        third_level+0x18@0x401054 (trace.S:47): ret => second_level+0xa@0x40103b (trace.S:36)
+      second_level+0xa@0x40103b (trace.S:36): ret => first_level+0xe@0x401023 (trace.S:22)
+    first_level+0xe@0x401023 (trace.S:22): call 401029h => second_level_two@0x401029 (trace.S:27)
+      second_level_two+0x7@0x401030 (trace.S:28): ret => first_level+0x13@0x401028 (trace.S:24)
+    first_level+0x13@0x401028 (trace.S:24): ret => _start+0x5@0x401005 (trace.S:8)
+  _start+0x13@0x401013 (trace.S:10): syscall => exit (60)
 "#);
     }];
 
@@ -181,25 +184,22 @@ mod tests {
 
         ax.handle_syscalls(vec![Syscall::Exit]).expect("Failed to add syscall handlers");
 
-        #[allow(non_upper_case_globals)]
-        static mut jle_count: u64 = 0;
-        #[allow(non_upper_case_globals)]
-        static mut jle_rip: u64 = 0;
-        ax.hook_before_mnemonic_native(SupportedMnemonic::Jle, &move |ax: &mut Axecutor, _| {
-            unsafe {
-                jle_rip = ax.reg_read_64(crate::state::registers::SupportedRegister::RIP)?;
-            };
+        let jle_count = std::rc::Rc::new(std::cell::Cell::new(0u64));
+        let jle_rip = std::rc::Rc::new(std::cell::Cell::new(0u64));
+
+        let rip = jle_rip.clone();
+        ax.hook_before_mnemonic_native(SupportedMnemonic::Jle, move |ax: &mut Axecutor, _| {
+            rip.set(ax.reg_read_64(crate::state::registers::SupportedRegister::RIP)?);
 
             Ok(crate::state::hooks::HookResult::Handled)
         }).expect("Failed to add hook");
 
-        ax.hook_after_mnemonic_native(SupportedMnemonic::Jle, &move |ax: &mut Axecutor, _| {
-            unsafe {
-                // Did we jump somewhere else?
-                if jle_rip != ax.reg_read_64(crate::state::registers::SupportedRegister::RIP)? {
-                    jle_count += 1;
-                }
-            };
+        let count = jle_count.clone();
+        ax.hook_after_mnemonic_native(SupportedMnemonic::Jle, move |ax: &mut Axecutor, _| {
+            // Did we jump somewhere else?
+            if jle_rip.get() != ax.reg_read_64(crate::state::registers::SupportedRegister::RIP)? {
+                count.set(count.get() + 1);
+            }
 
             Ok(crate::state::hooks::HookResult::Handled)
         }).expect("Failed to add hook");
@@ -212,11 +212,12 @@ mod tests {
 
         debug_log!("Trace:\n{}", trace);
 
-        assert_eq!(trace, format!(r#"<emulation_start>: entrypoint => _start@0x40101a
-  0x401034: jmp 40103Eh => 0x40103e
-  0x401042: jle 401036h => 0x401036 ({} times)
-  0x401049: call 401000h => sys_exit@0x401000
-"#, unsafe { jle_count }));
+        assert_eq!(trace, format!(r#"<emulation_start>: entrypoint => _start@0x40101a (c_loop.c:16)
+  _start+0x1a@0x401034 (c_loop.c:18): jmp 40103Eh => _start+0x24@0x40103e (c_loop.c:18)
+  _start+0x28@0x401042 (c_loop.c:18): jle 401036h => _start+0x1c@0x401036 (c_loop.c:20) ({} times)
+  _start+0x2f@0x401049 (c_loop.c:23): call 401000h => sys_exit@0x401000 (c_loop.c:7)
+    sys_exit+0x15@0x401015 (c_loop.c:8): syscall => exit (60)
+"#, jle_count.get()));
     }];
 }
 
@@ -229,10 +230,7 @@ impl Axecutor {
         let mut trace = String::new();
 
         for (i, addr) in self.state.call_stack.iter().enumerate() {
-            let formatted = match self.symbol_table.get(addr) {
-                Some(sym) => format!("{sym}@{addr:#x}"),
-                None => format!("{addr:#x}"),
-            };
+            let formatted = self.format_symbolized(*addr);
 
             if i == self.state.call_stack.len() - 1 {
                 trace.push_str(&format!(
@@ -247,10 +245,15 @@ impl Axecutor {
 
         let rip = self.reg_read_64(SupportedRegister::RIP)?;
         if let Ok(instr) = self.decode_at(rip) {
+            let location = match self.addr_to_line(rip) {
+                Some(loc) => format!(" ({loc})"),
+                None => String::new(),
+            };
             trace.push_str(&format!(
-                "{}  rip@{:#x}            <------------ at or before this instruction pointer\n{}  {} ({:#?})            <------------ at this or the previous instruction",
+                "{}  rip@{:#x}{}            <------------ at or before this instruction pointer\n{}  {} ({:#?})            <------------ at this or the previous instruction",
                 "  ".repeat(self.state.call_stack.len()),
                 rip,
+                location,
                 "  ".repeat(self.state.call_stack.len()),
                 instr,
                 instr.code()
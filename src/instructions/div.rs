@@ -3,7 +3,8 @@ use iced_x86::Instruction;
 use iced_x86::Mnemonic::Div;
 
 use crate::axecutor::Axecutor;
-use crate::helpers::errors::AxError;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::state::exceptions::{CpuException, ExceptionKind};
 
 use crate::helpers::macros::fatal_error;
 
@@ -39,9 +40,11 @@ impl Axecutor {
         } as u16;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Div_rm8: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Div_rm8: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let (quotient, remainder) = (ax / src_val, ax % src_val);
@@ -67,9 +70,11 @@ impl Axecutor {
         } as u32;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Div_rm16: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Div_rm16: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let dst_val = self.reg_read_16(AX)? as u32 | ((self.reg_read_16(DX)? as u32) << 16);
@@ -97,9 +102,11 @@ impl Axecutor {
         };
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Div_rm32: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Div_rm32: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let dst_val = self.reg_read_32(EAX)? | (self.reg_read_32(EDX)? << 32);
@@ -127,9 +134,11 @@ impl Axecutor {
         } as u128;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Div_rm64: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Div_rm64: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let dst_val = (self.reg_read_64(RAX)? as u128) | ((self.reg_read_64(RDX)? as u128) << 64);
@@ -3,7 +3,8 @@ use iced_x86::Instruction;
 use iced_x86::Mnemonic::Idiv;
 
 use crate::axecutor::Axecutor;
-use crate::helpers::errors::AxError;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::state::exceptions::{CpuException, ExceptionKind};
 
 use crate::helpers::macros::fatal_error;
 
@@ -39,9 +40,11 @@ impl Axecutor {
         } as i16;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Idiv_rm8: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Idiv_rm8: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let (quotient, remainder) = (ax / src_val, ax % src_val);
@@ -67,9 +70,11 @@ impl Axecutor {
         } as i32;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Idiv_rm16: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Idiv_rm16: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let dst_val =
@@ -98,9 +103,11 @@ impl Axecutor {
         } as i64;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Idiv_rm32: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Idiv_rm32: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let dst_val = (self.reg_read_32(EAX)? | (self.reg_read_32(EDX)? << 32)) as i64;
@@ -128,9 +135,11 @@ impl Axecutor {
         } as u128 as i128;
 
         if src_val == 0 {
-            return Err(AxError::from(format!(
-                "Divide by zero in Idiv_rm64: operand {op:?} is 0"
-            )));
+            return Err(
+                AxError::from(format!("Divide by zero in Idiv_rm64: operand {op:?} is 0"))
+                    .with_kind(ErrorKind::DivideByZero)
+                    .with_exception(CpuException::new(ExceptionKind::DivideError)),
+            );
         }
 
         let dst_val =
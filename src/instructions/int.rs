@@ -3,7 +3,7 @@ use iced_x86::Instruction;
 use iced_x86::Mnemonic::Int;
 
 use crate::axecutor::Axecutor;
-use crate::helpers::errors::AxError;
+use crate::helpers::errors::{AxError, ErrorKind};
 
 use crate::auto::generated::SupportedMnemonic;
 use crate::helpers::macros::fatal_error;
@@ -30,6 +30,7 @@ impl Axecutor {
 
         Err(AxError::from(
             "Int encountered, but no hook to handle available. Use `hook_before_mnemonic` or `hook_after_mnemonic` to register a hook for interrupts.",
-        ))
+        )
+        .with_kind(ErrorKind::UnhandledInterrupt))
     }
 }
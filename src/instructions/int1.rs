@@ -2,7 +2,7 @@ use iced_x86::Instruction;
 use iced_x86::Mnemonic::Int1;
 
 use crate::axecutor::Axecutor;
-use crate::helpers::errors::AxError;
+use crate::helpers::errors::{AxError, ErrorKind};
 
 use crate::auto::generated::SupportedMnemonic;
 use crate::helpers::macros::fatal_error;
@@ -29,6 +29,7 @@ impl Axecutor {
 
         Err(AxError::from(
             "Int1 encountered, but no hook to handle available. Use `hook_before_mnemonic` or `hook_after_mnemonic` to register a hook for interrupts.",
-        ))
+        )
+        .with_kind(ErrorKind::UnhandledInterrupt))
     }
 }
@@ -2,7 +2,8 @@ use iced_x86::Instruction;
 use iced_x86::Mnemonic::Int3;
 
 use crate::axecutor::Axecutor;
-use crate::helpers::errors::AxError;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::state::exceptions::{CpuException, ExceptionKind};
 
 use crate::auto::generated::SupportedMnemonic;
 use crate::helpers::macros::fatal_error;
@@ -28,7 +29,9 @@ impl Axecutor {
         }
 
         Err(AxError::from(
-            "Int3 encountered, but no hook to handle available. Use `hook_before_mnemonic` or `hook_after_mnemonic` to register a hook for interrupts.",
-        ))
+            "Int3 encountered, but no hook to handle available. Use `hook_before_mnemonic`, `hook_after_mnemonic` or `hook_exception` to handle interrupts.",
+        )
+        .with_kind(ErrorKind::UnhandledInterrupt)
+        .with_exception(CpuException::new(ExceptionKind::Breakpoint)))
     }
 }
@@ -53,6 +53,8 @@ pub mod nop;
 pub mod not;
 pub mod pop;
 pub mod push;
+pub mod rdtsc;
+pub mod rdtscp;
 pub mod ret;
 pub mod setb;
 pub mod sete;
@@ -0,0 +1,57 @@
+use iced_x86::Code;
+use iced_x86::Instruction;
+use iced_x86::Mnemonic::Rdtsc;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::errors::AxError;
+use crate::helpers::macros::fatal_error;
+use crate::state::registers::SupportedRegister::*;
+
+impl Axecutor {
+    pub(crate) fn mnemonic_rdtsc(&mut self, i: Instruction) -> Result<(), AxError> {
+        debug_assert_eq!(i.mnemonic(), Rdtsc);
+
+        match i.code() {
+            Code::Rdtsc => self.instr_rdtsc(i),
+            _ => fatal_error!("Invalid instruction code {:?} for mnemonic Rdtsc", i.code()),
+        }
+    }
+
+    /// RDTSC
+    ///
+    /// 0F 31
+    fn instr_rdtsc(&mut self, i: Instruction) -> Result<(), AxError> {
+        debug_assert_eq!(i.code(), Code::Rdtsc);
+
+        self.instr_rdtsc_impl()
+    }
+
+    pub(crate) fn instr_rdtsc_impl(&mut self) -> Result<(), AxError> {
+        let tsc = self.timestamp_counter()?;
+
+        self.reg_write_64(RAX, tsc & 0xffff_ffff)?;
+        self.reg_write_64(RDX, tsc >> 32)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axecutor::Axecutor;
+    use crate::helpers::tests::test_async;
+    use iced_x86::Register::*;
+
+    test_async![rdtsc_increases; async {
+        // rdtsc; shl rdx, 32; xor rax, rdx; mov rbx, rax; rdtsc
+        let code = [0x0f, 0x31, 0x48, 0xc1, 0xe2, 0x20, 0x48, 0x31, 0xd0, 0x48, 0x89, 0xc3, 0x0f, 0x31];
+        let mut ax = Axecutor::new(&code, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.execute().await.expect("Failed to execute");
+
+        let first = ax.reg_read_64(RBX.into()).unwrap();
+        let second = ax.reg_read_64(RAX.into()).unwrap() | (ax.reg_read_64(RDX.into()).unwrap() << 32);
+        assert!(first > 0);
+        assert!(second >= first, "{second} < {first}");
+        assert_eq!(ax.reg_read_64(RDX.into()).unwrap() >> 32, 0);
+    }];
+}
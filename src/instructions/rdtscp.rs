@@ -0,0 +1,54 @@
+use iced_x86::Code;
+use iced_x86::Instruction;
+use iced_x86::Mnemonic::Rdtscp;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::errors::AxError;
+use crate::helpers::macros::fatal_error;
+use crate::state::registers::SupportedRegister::*;
+
+impl Axecutor {
+    pub(crate) fn mnemonic_rdtscp(&mut self, i: Instruction) -> Result<(), AxError> {
+        debug_assert_eq!(i.mnemonic(), Rdtscp);
+
+        match i.code() {
+            Code::Rdtscp => self.instr_rdtscp(i),
+            _ => fatal_error!(
+                "Invalid instruction code {:?} for mnemonic Rdtscp",
+                i.code()
+            ),
+        }
+    }
+
+    /// RDTSCP
+    ///
+    /// 0F 01 F9
+    fn instr_rdtscp(&mut self, i: Instruction) -> Result<(), AxError> {
+        debug_assert_eq!(i.code(), Code::Rdtscp);
+
+        self.instr_rdtsc_impl()?;
+        // IA32_TSC_AUX, which usually holds the processor number
+        self.reg_write_64(RCX, 0)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axecutor::Axecutor;
+    use crate::helpers::tests::test_async;
+    use iced_x86::Register::*;
+
+    test_async![rdtscp_reads_counter_and_processor; async {
+        // rdtscp
+        let mut ax = Axecutor::new(&[0x0f, 0x01, 0xf9], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.reg_write_64(RCX.into(), 0x1234).unwrap();
+        ax.execute().await.expect("Failed to execute");
+
+        let tsc = ax.reg_read_64(RAX.into()).unwrap() | (ax.reg_read_64(RDX.into()).unwrap() << 32);
+        assert!(tsc > 0);
+        assert_eq!(ax.reg_read_64(RDX.into()).unwrap() >> 32, 0);
+        assert_eq!(ax.reg_read_64(RCX.into()).unwrap(), 0);
+    }];
+}
@@ -27,10 +27,16 @@ impl Axecutor {
     fn instr_syscall(&mut self, i: Instruction) -> Result<(), AxError> {
         debug_assert_eq!(i.code(), iced_x86::Code::Syscall);
 
-        if self.mnemonic_hooks(SupportedMnemonic::Syscall).is_some() {
+        // After hooks run after this instruction and might still handle the syscall
+        if self.hooks.syscall_handled
+            || self.state.finished
+            || self
+                .mnemonic_hooks(SupportedMnemonic::Syscall)
+                .is_some_and(|h| h.has_after())
+        {
             return Ok(());
         }
 
-        Err(AxError::from("Syscall encountered, but no hook to handle available. Use `hook_before_mnemonic` or `hook_after_mnemonic` to register a hook for syscalls."))
+        self.unhandled_syscall()
     }
 }
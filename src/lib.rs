@@ -1,6 +1,8 @@
 pub mod auto;
 pub mod axecutor;
 pub mod elf;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gdb;
 pub mod helpers;
 pub mod instructions;
 pub mod state;
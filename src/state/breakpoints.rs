@@ -0,0 +1,240 @@
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+use crate::state::registers::SupportedRegister;
+
+// Condition deciding whether execution should stop at a breakpoint, called with the state before the instruction at the breakpoint is executed
+pub type BreakpointCondition = dyn Fn(&Axecutor) -> bool;
+
+#[derive(Clone, Default)]
+pub(crate) struct Breakpoint {
+    native_condition: Option<Rc<BreakpointCondition>>,
+
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    js_condition: Option<js_sys::Function>,
+}
+
+impl Debug for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        #[cfg(all(target_arch = "wasm32", not(test)))]
+        {
+            f.debug_struct("Breakpoint")
+                .field("native_condition", &self.native_condition.is_some())
+                .field("js_condition", &self.js_condition.is_some())
+                .finish()
+        }
+        #[cfg(not(all(target_arch = "wasm32", not(test))))]
+        f.debug_struct("Breakpoint")
+            .field("native_condition", &self.native_condition.is_some())
+            .finish()
+    }
+}
+
+impl Breakpoint {
+    fn matches(&self, ax: &Axecutor) -> Result<bool, AxError> {
+        if let Some(condition) = &self.native_condition {
+            if !condition(ax) {
+                return Ok(false);
+            }
+        }
+
+        #[cfg(all(target_arch = "wasm32", not(test)))]
+        if let Some(condition) = &self.js_condition {
+            let result = condition
+                .call1(&wasm_bindgen::JsValue::NULL, &ax.clone().into())
+                .map_err(|e| {
+                    AxError::from(format!("Breakpoint condition threw an error: {:?}", e))
+                })?;
+            if result.is_falsy() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Axecutor {
+    fn insert_breakpoint(&mut self, address: u64, breakpoint: Breakpoint) -> Result<(), AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot add breakpoints while a hook is running",
+            ));
+        }
+
+        debug_log!("Adding breakpoint at {:#x}: {:?}", address, breakpoint);
+        self.hooks.breakpoints.insert(address, breakpoint);
+        Ok(())
+    }
+
+    /// Returns the address of the breakpoint at RIP if there is one and its condition is met
+    pub(crate) fn breakpoint_hit(&self) -> Result<Option<u64>, AxError> {
+        if self.hooks.breakpoints.is_empty() {
+            return Ok(None);
+        }
+
+        let rip = self.reg_read_64(SupportedRegister::RIP)?;
+        match self.hooks.breakpoints.get(&rip) {
+            Some(breakpoint) if breakpoint.matches(self)? => Ok(Some(rip)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Add a breakpoint that stops execution when a condition holds before the instruction at `address` is executed.
+    /// The condition is called with the current state and should return whether execution should stop.
+    /// Replaces any other breakpoint at the same address.
+    pub fn add_conditional_breakpoint_native(
+        &mut self,
+        address: u64,
+        condition: impl Fn(&Axecutor) -> bool + 'static,
+    ) -> Result<(), AxError> {
+        self.insert_breakpoint(
+            address,
+            Breakpoint {
+                native_condition: Some(Rc::new(condition)),
+                #[cfg(all(target_arch = "wasm32", not(test)))]
+                js_condition: None,
+            },
+        )
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
+impl Axecutor {
+    /// Add a breakpoint that stops execution when a condition holds before the instruction at `address` is executed.
+    /// The condition is called synchronously with the Axecutor object and must return a truthy value if execution should stop.
+    /// Changes the condition makes to the Axecutor object are discarded.
+    /// Replaces any other breakpoint at the same address.
+    pub fn add_conditional_breakpoint(
+        &mut self,
+        address: u64,
+        condition: js_sys::Function,
+    ) -> Result<(), AxError> {
+        self.insert_breakpoint(
+            address,
+            Breakpoint {
+                native_condition: None,
+                js_condition: Some(condition),
+            },
+        )
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Add a breakpoint that stops `run_until_breakpoint` before the instruction at `address` is executed.
+    /// Replaces any other breakpoint at the same address.
+    pub fn add_breakpoint(&mut self, address: u64) -> Result<(), AxError> {
+        self.insert_breakpoint(address, Breakpoint::default())
+    }
+
+    /// Add a breakpoint at the start of the given symbol, e.g. a function name from the ELF symbol table.
+    /// Returns the address of the breakpoint.
+    pub fn add_breakpoint_at_symbol(&mut self, name: &str) -> Result<u64, AxError> {
+        let address = self.address_of(name).ok_or_else(|| {
+            AxError::from(format!("Cannot add breakpoint: unknown symbol {name}"))
+        })?;
+        self.add_breakpoint(address)?;
+        Ok(address)
+    }
+
+    /// Remove the breakpoint at `address`, returning whether there was one
+    pub fn remove_breakpoint(&mut self, address: u64) -> Result<bool, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot remove breakpoints while a hook is running",
+            ));
+        }
+
+        debug_log!("Removing breakpoint at {:#x}", address);
+        Ok(self.hooks.breakpoints.remove(&address).is_some())
+    }
+
+    /// Addresses of all breakpoints in ascending order
+    pub fn breakpoints(&self) -> Vec<u64> {
+        self.hooks.breakpoints.keys().copied().collect()
+    }
+
+    /// Execute instructions until a breakpoint is reached, a watchpoint was triggered or execution has stopped.
+    /// Returns the address of the breakpoint that was hit, or None/undefined if execution has finished.
+    /// After a watchpoint was triggered, the address of the next instruction is returned and `watchpoint_hit` describes the access.
+    /// The instruction at the current RIP is always executed, so calling this function again after a breakpoint was hit continues execution.
+    /// Note that `execute` ignores breakpoints.
+    pub async fn run_until_breakpoint(&mut self) -> Result<Option<u64>, AxError> {
+        debug_log!("Calling Axecutor::run_until_breakpoint");
+
+        if !self.step().await? {
+            return Ok(None);
+        }
+
+        loop {
+            if self.hooks.watchpoint_hit.get().is_some() {
+                let rip = self.reg_read_64(SupportedRegister::RIP)?;
+                debug_log!("Stopped at {:#x} after watchpoint was triggered", rip);
+                return Ok(Some(rip));
+            }
+            if let Some(address) = self.breakpoint_hit()? {
+                debug_log!("Hit breakpoint at {:#x}", address);
+                return Ok(Some(address));
+            }
+
+            if !self.step().await? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::helpers::tests::{c_loop, test_async};
+    use crate::state::registers::SupportedRegister::*;
+
+    test_async![run_until_breakpoint; async {
+        let mut ax = c_loop();
+
+        // The loop body is executed three times
+        ax.add_breakpoint(0x401036).unwrap();
+        for _ in 0..3 {
+            assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x401036));
+            assert_eq!(ax.reg_read_64(RIP).unwrap(), 0x401036);
+        }
+
+        assert_eq!(ax.add_breakpoint_at_symbol("sys_exit").unwrap(), 0x401000);
+        assert_eq!(ax.breakpoints(), vec![0x401000, 0x401036]);
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x401000));
+        assert_eq!(ax.reg_read_64(RDI).unwrap(), 9);
+
+        assert!(ax.remove_breakpoint(0x401000).unwrap());
+        assert!(!ax.remove_breakpoint(0x401000).unwrap());
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), None);
+        assert!(ax.state.finished);
+    }];
+
+    test_async![conditional_breakpoint; async {
+        let mut ax = c_loop();
+
+        // Stop in the last loop iteration, where the counter at [rbp-8] is 2
+        ax.add_conditional_breakpoint_native(0x401036, |ax| {
+            let rbp = ax.reg_read_64(RBP).unwrap();
+            ax.mem_read_32(rbp - 8).unwrap() == 2
+        }).unwrap();
+
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x401036));
+        let rbp = ax.reg_read_64(RBP).unwrap();
+        assert_eq!(ax.mem_read_32(rbp - 8).unwrap(), 2);
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), None);
+    }];
+
+    test_async![unknown_symbol; async {
+        let mut ax = c_loop();
+        ax.add_breakpoint_at_symbol("main").expect_err("c_loop has no main function");
+        assert!(ax.breakpoints().is_empty());
+    }];
+}
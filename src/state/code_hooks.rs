@@ -0,0 +1,421 @@
+use std::error::Error;
+use std::fmt::{Debug, Formatter};
+
+use iced_x86::{FlowControl, Instruction};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::auto::generated::SupportedMnemonic;
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+#[cfg(all(target_arch = "wasm32", not(test)))]
+use crate::state::hooks::run_function;
+use crate::state::hooks::{
+    call_native_hook, native_hook, sort_hooks, HookHandle, HookOrder, NativeHook,
+};
+use crate::state::registers::SupportedRegister;
+use crate::state::replay::{InputSource, InputValue};
+
+// Upper limit for the number of instructions in a basic block, in case code runs into e.g. a large area of NOPs
+const MAX_BLOCK_INSTRUCTIONS: u64 = 4096;
+
+/// A decoded instruction, as passed to code hooks
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionInfo {
+    pub address: u64,
+    pub length: u64,
+    pub bytes: Vec<u8>,
+    pub mnemonic: SupportedMnemonic,
+    /// Disassembly of the instruction, e.g. `mov rax,5h`
+    pub text: String,
+}
+
+/// A basic block, i.e. a sequence of instructions that ends with the first instruction that may change the control flow
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub address: u64,
+    /// Size of the block in bytes, including the final jump, call, return etc.
+    pub size: u64,
+    pub instruction_count: u64,
+}
+
+// Called before an instruction within the hooked range is executed
+pub type CodeHookFunction =
+    dyn FnMut(&mut Axecutor, &InstructionInfo) -> Result<(), Box<dyn Error>>;
+
+// Called before the first instruction of a basic block within the hooked range is executed
+pub type BlockHookFunction = dyn FnMut(&mut Axecutor, &BasicBlock) -> Result<(), Box<dyn Error>>;
+
+#[derive(Clone)]
+enum CodeHookFunctions {
+    NativeInstruction(NativeHook<CodeHookFunction>),
+    NativeBlock(NativeHook<BlockHookFunction>),
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    JsInstruction(js_sys::Function),
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    JsBlock(js_sys::Function),
+}
+
+#[derive(Clone)]
+pub(crate) struct CodeHook {
+    pub(crate) order: HookOrder,
+    start: u64,
+    length: u64,
+    function: CodeHookFunctions,
+}
+
+impl CodeHook {
+    fn is_block_hook(&self) -> bool {
+        match self.function {
+            CodeHookFunctions::NativeBlock(_) => true,
+            #[cfg(all(target_arch = "wasm32", not(test)))]
+            CodeHookFunctions::JsBlock(_) => true,
+            _ => false,
+        }
+    }
+
+    fn contains(&self, address: u64) -> bool {
+        self.start <= address && address - self.start < self.length
+    }
+}
+
+impl Debug for CodeHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodeHook")
+            .field("handle", &self.order.handle)
+            .field("priority", &self.order.priority)
+            .field("start", &self.start)
+            .field("length", &self.length)
+            .field("block", &self.is_block_hook())
+            .finish()
+    }
+}
+
+impl Axecutor {
+    fn basic_block_at(&self, address: u64) -> BasicBlock {
+        let mut block = BasicBlock {
+            address,
+            size: 0,
+            instruction_count: 0,
+        };
+
+        while block.instruction_count < MAX_BLOCK_INSTRUCTIONS {
+            let instr = match self.decode_at(address + block.size) {
+                Ok(instr) => instr,
+                Err(_) => break,
+            };
+            block.size += instr.len() as u64;
+            block.instruction_count += 1;
+            if instr.flow_control() != FlowControl::Next {
+                break;
+            }
+        }
+
+        block
+    }
+
+    /// Runs code hooks for the instruction that is about to be executed.
+    /// Returns false if the hooks stopped execution or changed RIP, in which case the instruction must not be executed.
+    pub(crate) async fn run_code_hooks(
+        &mut self,
+        instr: &Instruction,
+        mnemonic: SupportedMnemonic,
+    ) -> Result<bool, AxError> {
+        let block_start = self.hooks.block_start;
+        self.hooks.block_start = instr.flow_control() != FlowControl::Next;
+
+        let address = instr.ip();
+        if let Some(value) = self.replay_input(InputSource::CodeHooks)? {
+            // No code hooks ran here while recording
+            if value.is_none() {
+                return Ok(true);
+            }
+        } else {
+            let hooks: Vec<CodeHook> = self
+                .hooks
+                .code_hooks
+                .iter()
+                .filter(|h| h.contains(address) && (block_start || !h.is_block_hook()))
+                .cloned()
+                .collect();
+            if hooks.is_empty() {
+                return Ok(true);
+            }
+
+            let info = InstructionInfo {
+                address,
+                length: instr.len() as u64,
+                bytes: self.mem_peek_bytes(address, instr.len() as u64)?,
+                mnemonic,
+                text: instr.to_string(),
+            };
+            let block = if block_start {
+                Some(self.basic_block_at(address))
+            } else {
+                None
+            };
+
+            debug_log!("Running {} code hook(s) for {}", hooks.len(), info.text);
+
+            let view = self.record_begin();
+            self.hooks.running = true;
+            let result = self.run_code_hook_functions(&hooks, &info, block).await;
+            self.hooks.running = false;
+            result?;
+            self.record_end(view, InputSource::CodeHooks, InputValue::Handled(true));
+        }
+
+        let rip = self.reg_read_64(SupportedRegister::RIP)?;
+        if self.state.finished || rip != address {
+            debug_log!(
+                "Code hooks skipped instruction at {:#x}, finished={}, rip={:#x}",
+                address,
+                self.state.finished,
+                rip
+            );
+            // Execution continues somewhere else, which starts a new block
+            self.hooks.block_start = true;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    async fn run_code_hook_functions(
+        &mut self,
+        hooks: &[CodeHook],
+        info: &InstructionInfo,
+        block: Option<BasicBlock>,
+    ) -> Result<(), AxError> {
+        for hook in hooks {
+            let result: Result<(), AxError> = match (&hook.function, block) {
+                (CodeHookFunctions::NativeInstruction(f), _) => call_native_hook(f, |f| {
+                    f(self, info).map_err(|e| AxError::from(e.to_string()))
+                })
+                .and_then(|r| r),
+                (CodeHookFunctions::NativeBlock(f), Some(block)) => call_native_hook(f, |f| {
+                    f(self, &block).map_err(|e| AxError::from(e.to_string()))
+                })
+                .and_then(|r| r),
+                #[cfg(all(target_arch = "wasm32", not(test)))]
+                (CodeHookFunctions::JsInstruction(f), _) => {
+                    run_function(self, f.clone(), vec![info.clone().into()])
+                        .await
+                        .map(|_| ())
+                        .map_err(AxError::from)
+                }
+                #[cfg(all(target_arch = "wasm32", not(test)))]
+                (CodeHookFunctions::JsBlock(f), Some(block)) => {
+                    run_function(self, f.clone(), vec![block.into()])
+                        .await
+                        .map(|_| ())
+                        .map_err(AxError::from)
+                }
+                _ => Ok(()),
+            };
+            result.map_err(|e| {
+                AxError::from(format!(
+                    "running code hook for {} at {:#x}: {e}",
+                    info.text, info.address
+                ))
+                .with_kind(ErrorKind::HookError)
+            })?;
+
+            if self.state.finished {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_code_hook(
+        &mut self,
+        start: u64,
+        length: u64,
+        function: CodeHookFunctions,
+    ) -> Result<HookHandle, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot add hooks while another hook is running",
+            ));
+        }
+
+        let hook = CodeHook {
+            order: self.hooks.next_order(),
+            start,
+            length,
+            function,
+        };
+        debug_log!("Adding code hook {:?}", hook);
+        let handle = hook.order.handle;
+        self.hooks.code_hooks.push(hook);
+        sort_hooks(&mut self.hooks.code_hooks, |h| h.order);
+        Ok(handle)
+    }
+
+    /// Register a function to be called before every instruction in `start..start+length` is executed,
+    /// use `hook_code_native(0, u64::MAX, ...)` to hook all instructions.
+    /// The function gets the decoded instruction. If it changes RIP or stops execution, the instruction is not executed.
+    /// Hooks are called in the order described at `set_hook_priority`, before any mnemonic hooks.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_code_native(
+        &mut self,
+        start: u64,
+        length: u64,
+        cb: impl FnMut(&mut Axecutor, &InstructionInfo) -> Result<(), Box<dyn Error>> + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_code_hook(
+            start,
+            length,
+            CodeHookFunctions::NativeInstruction(native_hook(Box::new(cb))),
+        )
+    }
+
+    /// Register a function to be called when execution enters a basic block that starts in `start..start+length`.
+    /// A new block starts at the first executed instruction and after every instruction that may change the control flow.
+    /// If the function changes RIP or stops execution, the first instruction of the block is not executed.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_block_native(
+        &mut self,
+        start: u64,
+        length: u64,
+        cb: impl FnMut(&mut Axecutor, &BasicBlock) -> Result<(), Box<dyn Error>> + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_code_hook(
+            start,
+            length,
+            CodeHookFunctions::NativeBlock(native_hook(Box::new(cb))),
+        )
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
+impl Axecutor {
+    /// Register a function to be called before every instruction in `start..start+length` is executed.
+    /// The function will be called with the Axecutor object and an InstructionInfo as arguments.
+    /// Like mnemonic hooks, it may be sync or async and *MUST* return the result of instance.commit(), instance.stop() or instance.unchanged().
+    /// If the function changes RIP or stops execution, the instruction is not executed.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_code(
+        &mut self,
+        start: u64,
+        length: u64,
+        cb: js_sys::Function,
+    ) -> Result<HookHandle, AxError> {
+        self.add_code_hook(start, length, CodeHookFunctions::JsInstruction(cb))
+    }
+
+    /// Register a function to be called when execution enters a basic block that starts in `start..start+length`.
+    /// The function will be called with the Axecutor object and a BasicBlock as arguments.
+    /// Like mnemonic hooks, it may be sync or async and *MUST* return the result of instance.commit(), instance.stop() or instance.unchanged().
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_block(
+        &mut self,
+        start: u64,
+        length: u64,
+        cb: js_sys::Function,
+    ) -> Result<HookHandle, AxError> {
+        self.add_code_hook(start, length, CodeHookFunctions::JsBlock(cb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::{c_loop, test_async};
+    use crate::state::registers::SupportedRegister::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    test_async![hook_every_instruction; async {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0xc7, 0xc0, 0x5, 0, 0, 0, // mov rax, 5
+                0x48, 0xff, 0xc0, // inc rax
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s = seen.clone();
+        ax.hook_code_native(0, u64::MAX, move |ax, info| {
+            // Hooks run before the instruction, with RIP pointing to it
+            assert_eq!(ax.reg_read_64(RIP).unwrap(), info.address);
+            s.borrow_mut().push(info.clone());
+            Ok(())
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 6);
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                InstructionInfo {
+                    address: 0x1000,
+                    length: 7,
+                    bytes: vec![0x48, 0xc7, 0xc0, 0x5, 0, 0, 0],
+                    mnemonic: SupportedMnemonic::Mov,
+                    text: "mov rax,5h".to_string(),
+                },
+                InstructionInfo {
+                    address: 0x1007,
+                    length: 3,
+                    bytes: vec![0x48, 0xff, 0xc0],
+                    mnemonic: SupportedMnemonic::Inc,
+                    text: "inc rax".to_string(),
+                },
+            ]
+        );
+    }];
+
+    test_async![skip_instruction_by_changing_rip; async {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0xc7, 0xc0, 0x5, 0, 0, 0, // mov rax, 5
+                0x48, 0xff, 0xc0, // inc rax
+                0x48, 0xff, 0xc0, // inc rax
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+
+        ax.hook_code_native(0x1007, 1, |ax, info| {
+            ax.reg_write_64(RIP, info.address + info.length)?;
+            Ok(())
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 6);
+    }];
+
+    test_async![hook_basic_blocks; async {
+        let mut ax = c_loop();
+
+        let blocks = Rc::new(RefCell::new(Vec::new()));
+        let b = blocks.clone();
+        ax.hook_block_native(0x401000, 0x1000, move |_, block| {
+            b.borrow_mut().push((block.address, block.size, block.instruction_count));
+            Ok(())
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+
+        let start = (0x40101a, 0x1c, 7);
+        let body = (0x401036, 0x0e, 4);
+        let condition = (0x40103e, 0x06, 2);
+        let exit = (0x401044, 0x0a, 3);
+        let sys_exit = (0x401000, 0x17, 8);
+        assert_eq!(
+            *blocks.borrow(),
+            vec![start, condition, body, body, body, exit, sys_exit]
+        );
+    }];
+}
@@ -0,0 +1,426 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+#[cfg(all(target_arch = "wasm32", not(test)))]
+use crate::state::hooks::run_function;
+use crate::state::hooks::{
+    call_native_hook, native_hook, sort_hooks, HookHandle, HookOrder, NativeHook,
+};
+use crate::state::memory_hooks::MemoryAccessKind;
+use crate::state::registers::SupportedRegister;
+use crate::state::replay::{InputSource, InputValue};
+
+// Bits of the page fault error code
+const PF_PRESENT: u64 = 1;
+const PF_WRITE: u64 = 2;
+const PF_USER: u64 = 4;
+const PF_INSTRUCTION_FETCH: u64 = 16;
+
+/// Architectural exceptions the emulated CPU raises, the values are their interrupt vectors
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ExceptionKind {
+    /// #DE, division by zero
+    DivideError = 0,
+    /// #BP, raised by int3
+    Breakpoint = 3,
+    /// #UD, an invalid instruction or ud2
+    InvalidOpcode = 6,
+    /// #GP, e.g. an access to a non-canonical address
+    GeneralProtection = 13,
+    /// #PF, an access to unmapped memory or memory that doesn't allow it
+    PageFault = 14,
+}
+
+impl ExceptionKind {
+    /// Mnemonic as used in the Intel SDM, e.g. `#PF`
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            ExceptionKind::DivideError => "#DE",
+            ExceptionKind::Breakpoint => "#BP",
+            ExceptionKind::InvalidOpcode => "#UD",
+            ExceptionKind::GeneralProtection => "#GP",
+            ExceptionKind::PageFault => "#PF",
+        }
+    }
+
+    /// Traps are reported after the instruction, faults before it so it can be restarted
+    pub fn is_trap(&self) -> bool {
+        *self == ExceptionKind::Breakpoint
+    }
+}
+
+/// A CPU exception raised while executing an instruction
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuException {
+    pub kind: ExceptionKind,
+    /// Address of the instruction that raised the exception
+    pub rip: u64,
+    /// For memory faults, the address that could not be accessed
+    pub address: Option<u64>,
+    /// For memory faults, the kind of access that faulted
+    pub access: Option<MemoryAccessKind>,
+    /// The error code the CPU pushes for the exception, for page faults this tells whether the page was present,
+    /// whether the access was a write and whether it was an instruction fetch
+    pub error_code: u64,
+}
+
+impl CpuException {
+    pub(crate) fn new(kind: ExceptionKind) -> CpuException {
+        CpuException {
+            kind,
+            rip: 0,
+            address: None,
+            access: None,
+            error_code: 0,
+        }
+    }
+
+    /// A page fault for an access to `address`; `present` is true if the address is mapped but doesn't allow the access
+    pub(crate) fn page_fault(
+        address: u64,
+        access: MemoryAccessKind,
+        present: bool,
+    ) -> CpuException {
+        let mut error_code = PF_USER;
+        if present {
+            error_code |= PF_PRESENT;
+        }
+        match access {
+            MemoryAccessKind::Write => error_code |= PF_WRITE,
+            MemoryAccessKind::Execute => error_code |= PF_INSTRUCTION_FETCH,
+            MemoryAccessKind::Read => {}
+        }
+
+        CpuException {
+            kind: ExceptionKind::PageFault,
+            rip: 0,
+            address: Some(address),
+            access: Some(access),
+            error_code,
+        }
+    }
+
+    /// A general protection fault for an access to the non-canonical `address`
+    pub(crate) fn general_protection(address: u64, access: MemoryAccessKind) -> CpuException {
+        CpuException {
+            address: Some(address),
+            access: Some(access),
+            ..CpuException::new(ExceptionKind::GeneralProtection)
+        }
+    }
+
+    /// Whether a page fault was caused by missing permissions rather than unmapped memory
+    pub fn is_protection_violation(&self) -> bool {
+        self.kind == ExceptionKind::PageFault && self.error_code & PF_PRESENT != 0
+    }
+}
+
+impl Display for CpuException {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {:#x}", self.kind.mnemonic(), self.rip)?;
+        if let (Some(address), Some(access)) = (self.address, self.access) {
+            write!(f, " ({access:?} of {address:#x})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether `address` is canonical, i.e. bits 48-63 are copies of bit 47
+pub(crate) fn is_canonical(address: u64) -> bool {
+    ((address as i64) << 16 >> 16) as u64 == address
+}
+
+/// What an exception handler did with an exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExceptionAction {
+    /// Continue execution at RIP, e.g. after the handler fixed up memory or moved RIP past the faulting instruction
+    Resume,
+    /// Let the next handler decide, eventually the program's signal handlers or an error
+    Unhandled,
+}
+
+// Called with the exception and RIP set to where execution resumes
+pub type ExceptionHandler =
+    dyn FnMut(&mut Axecutor, &CpuException) -> Result<ExceptionAction, Box<dyn Error>>;
+
+#[derive(Clone)]
+enum ExceptionHandlerFunction {
+    Native(NativeHook<ExceptionHandler>),
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    Js(js_sys::Function),
+}
+
+#[derive(Clone)]
+pub(crate) struct ExceptionHook {
+    pub(crate) order: HookOrder,
+    function: ExceptionHandlerFunction,
+}
+
+impl Debug for ExceptionHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExceptionHook")
+            .field("handle", &self.order.handle)
+            .field("priority", &self.order.priority)
+            .finish()
+    }
+}
+
+impl Axecutor {
+    /// Gives exception handlers and then the program's signal handlers a chance to handle the exception behind `error`.
+    /// Returns whether one did, in that case execution continues at RIP.
+    pub(crate) async fn handle_exception(&mut self, error: &AxError) -> Result<bool, AxError> {
+        let exception = match error.exception() {
+            Some(exception) => exception,
+            None => return Ok(false),
+        };
+
+        debug_log!("Handling exception {}", exception);
+
+        let resume_rip = if exception.kind.is_trap() {
+            self.decode_at(exception.rip)?.next_ip()
+        } else {
+            exception.rip
+        };
+        self.reg_write_64(SupportedRegister::RIP, resume_rip)?;
+
+        let source = InputSource::Exception(exception.kind);
+        let handled = if let Some(value) = self.replay_input(source)? {
+            value == Some(InputValue::Handled(true))
+        } else if !self.hooks.exception_hooks.is_empty() {
+            let hooks = self.hooks.exception_hooks.clone();
+            let view = self.record_begin();
+            self.hooks.running = true;
+            let result = self.run_exception_handlers(&hooks, &exception).await;
+            self.hooks.running = false;
+            let handled = result.map_err(|e| {
+                AxError::from(format!("handling exception {exception}: {e}"))
+                    .with_kind(ErrorKind::HookError)
+            })?;
+            self.record_end(view, source, InputValue::Handled(handled));
+            handled
+        } else {
+            false
+        };
+        if handled {
+            return Ok(true);
+        }
+
+        self.deliver_fault_signal(&exception)
+    }
+
+    async fn run_exception_handlers(
+        &mut self,
+        hooks: &[ExceptionHook],
+        exception: &CpuException,
+    ) -> Result<bool, AxError> {
+        for hook in hooks {
+            let action = match &hook.function {
+                ExceptionHandlerFunction::Native(f) => call_native_hook(f, |f| f(self, exception))?
+                    .map_err(|e| AxError::from(e.to_string()))?,
+                // JS handlers can't pass an exception on
+                #[cfg(all(target_arch = "wasm32", not(test)))]
+                ExceptionHandlerFunction::Js(f) => {
+                    run_function(self, f.clone(), vec![(*exception).into()]).await?;
+                    ExceptionAction::Resume
+                }
+            };
+
+            if action == ExceptionAction::Resume || self.state.finished {
+                debug_log!("Exception {} handled, resuming", exception);
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn add_exception_hook(
+        &mut self,
+        function: ExceptionHandlerFunction,
+    ) -> Result<HookHandle, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot add exception handlers while a hook is running",
+            ));
+        }
+
+        let hook = ExceptionHook {
+            order: self.hooks.next_order(),
+            function,
+        };
+        debug_log!("Adding exception handler {:?}", hook);
+        let handle = hook.order.handle;
+        self.hooks.exception_hooks.push(hook);
+        sort_hooks(&mut self.hooks.exception_hooks, |h| h.order);
+        Ok(handle)
+    }
+
+    /// Register a function that is called when an instruction raises a CPU exception, e.g. a page fault.
+    /// RIP points to the faulting instruction, or after it for traps like int3. The handler may fix up state, e.g. map memory or change RIP,
+    /// and return `ExceptionAction::Resume` to continue execution at RIP. Otherwise the next handler, then the program's signal handlers are tried
+    /// and execution stops with an error if none of them handles it.
+    /// Handlers run in the order described at `set_hook_priority`.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_exception_native(
+        &mut self,
+        handler: impl FnMut(&mut Axecutor, &CpuException) -> Result<ExceptionAction, Box<dyn Error>>
+            + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_exception_hook(ExceptionHandlerFunction::Native(native_hook(Box::new(
+            handler,
+        ))))
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
+impl Axecutor {
+    /// Register a function that is called when an instruction raises a CPU exception, e.g. a page fault.
+    /// The function will be called with the Axecutor object and a CpuException as arguments, RIP points to the faulting instruction
+    /// or after it for traps like int3. Like mnemonic hooks, it may be sync or async and *MUST* return the result of instance.commit(), instance.stop() or instance.unchanged().
+    /// Execution always resumes at RIP afterwards, so the function has to fix the cause of the exception or change RIP.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_exception(&mut self, handler: js_sys::Function) -> Result<HookHandle, AxError> {
+        self.add_exception_hook(ExceptionHandlerFunction::Js(handler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+    use crate::state::memory::PROT_READ;
+    use crate::state::registers::SupportedRegister::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    test_async![page_fault_handler_maps_memory; async {
+        let mut ax = Axecutor::new(
+            &[
+                0xb9, 0x08, 0x50, 0x00, 0x00, // mov ecx, 0x5008
+                0x48, 0x8b, 0x01, // mov rax, qword ptr [rcx]
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s = seen.clone();
+        ax.hook_exception_native(move |ax: &mut Axecutor, exception| {
+            s.borrow_mut().push(*exception);
+            assert_eq!(ax.reg_read_64(RIP).unwrap(), 0x1005);
+
+            ax.mem_init_zero(0x5000, 0x1000)?;
+            ax.mem_write_64(0x5008, 42)?;
+            Ok(ExceptionAction::Resume)
+        })
+        .unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 42);
+        assert_eq!(
+            *seen.borrow(),
+            vec![CpuException {
+                kind: ExceptionKind::PageFault,
+                rip: 0x1005,
+                address: Some(0x5008),
+                access: Some(MemoryAccessKind::Read),
+                error_code: PF_USER,
+            }]
+        );
+    }];
+
+    test_async![unhandled_exceptions; async {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0xb9, 0, 0, 0, 0, 0, 0x80, 0, 0, // movabs rcx, 0x800000000000
+                0x48, 0x89, 0x01, // mov qword ptr [rcx], rax
+                0xb9, 0x00, 0x30, 0x00, 0x00, // mov ecx, 0x3000
+                0x48, 0x89, 0x01, // mov qword ptr [rcx], rax
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        ax.mem_init_zero(0x3000, 0x1000).unwrap();
+        ax.mem_prot(0x3000, PROT_READ).unwrap();
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s = seen.clone();
+        ax.hook_exception_native(move |ax: &mut Axecutor, exception| {
+            s.borrow_mut().push(exception.kind);
+            if exception.kind != ExceptionKind::GeneralProtection {
+                return Ok(ExceptionAction::Unhandled);
+            }
+
+            // Skip the faulting instruction
+            ax.reg_write_64(RIP, exception.rip + 3)?;
+            Ok(ExceptionAction::Resume)
+        })
+        .unwrap();
+
+        let err = ax.execute().await.expect_err("Write to read-only memory should fail");
+        let exception = err.exception().expect("Error should carry the exception");
+
+        assert_eq!(
+            *seen.borrow(),
+            vec![ExceptionKind::GeneralProtection, ExceptionKind::PageFault]
+        );
+        assert_eq!(exception.rip, 0x1012);
+        assert_eq!(exception.address, Some(0x3000));
+        assert_eq!(exception.access, Some(MemoryAccessKind::Write));
+        assert!(exception.is_protection_violation());
+        assert_eq!(exception.error_code, PF_USER | PF_PRESENT | PF_WRITE);
+    }];
+
+    test_async![breakpoint_is_a_trap; async {
+        let mut ax = Axecutor::new(
+            &[
+                0xcc, // int3
+                0xb8, 0x05, 0x00, 0x00, 0x00, // mov eax, 5
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+
+        let seen = Rc::new(RefCell::new(None));
+        let s = seen.clone();
+        ax.hook_exception_native(move |ax: &mut Axecutor, exception| {
+            *s.borrow_mut() = Some((exception.kind, exception.rip, ax.reg_read_64(RIP)?));
+            Ok(ExceptionAction::Resume)
+        })
+        .unwrap();
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(*seen.borrow(), Some((ExceptionKind::Breakpoint, 0x1000, 0x1001)));
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 5);
+
+        // Without a handler, division by zero stops with an error describing the exception
+        let mut ax = Axecutor::new(
+            &[
+                0x31, 0xc9, // xor ecx, ecx
+                0x48, 0xf7, 0xf1, // div rcx
+            ],
+            0x1000,
+            0x1000,
+        )
+        .unwrap();
+        let err = ax.execute().await.expect_err("Expected divide by zero error");
+        assert_eq!(
+            err.exception().map(|e| (e.kind, e.rip)),
+            Some((ExceptionKind::DivideError, 0x1002))
+        );
+    }];
+}
@@ -1,13 +1,19 @@
 use std::convert::TryInto;
 
-use iced_x86::{Decoder, DecoderOptions, Instruction, Register};
+use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic, Register};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::helpers::debug::debug_log;
 
 use crate::{auto::generated::SupportedMnemonic, state::registers::SupportedRegister};
 
-use crate::{axecutor::Axecutor, helpers::errors::AxError};
+use crate::state::exceptions::{CpuException, ExceptionKind};
+use crate::state::memory_hooks::{reads_memory, MemoryAccessKind};
+use crate::state::replay::{InputSource, InputValue};
+use crate::{
+    axecutor::Axecutor,
+    helpers::errors::{AxError, ErrorKind},
+};
 
 #[wasm_bindgen]
 impl Axecutor {
@@ -29,7 +35,9 @@ impl Axecutor {
             return Err(AxError::from(format!(
                 "Invalid instruction at offset {}",
                 dec.position() - instr.len()
-            )));
+            ))
+            .with_kind(ErrorKind::InvalidInstruction { addr: rip })
+            .with_exception(CpuException::new(ExceptionKind::InvalidOpcode)));
         }
 
         Ok(instr)
@@ -47,47 +55,114 @@ impl Axecutor {
             self.state.finished,
             self.reg_read_64(Register::RIP.into())?
         );
+        // Watchpoints report the access of the last executed instruction
+        self.hooks.watchpoint_hit.set(None);
 
         if self.state.finished {
-            return Err(AxError::from(
-                "Cannot advance after execution has already finished",
-            ));
+            return Err(
+                AxError::from("Cannot advance after execution has already finished")
+                    .with_kind(ErrorKind::ExecutionFinished),
+            );
         }
         if let Some(limit) = self.state.max_instructions {
             if self.state.executed_instructions_count >= limit {
                 return Err(AxError::from(format!(
                     "Instruction limit of {limit} has been reached"
-                )));
+                ))
+                .with_kind(ErrorKind::InstructionLimit { limit }));
             }
         }
 
+        // Once execution reaches main, libc startup code has finished processing relocations
+        if !self.state.relro.pending.is_empty()
+            && self.state.relro.apply_at == Some(self.reg_read_64(SupportedRegister::RIP)?)
+        {
+            self.apply_relro()?;
+        }
+
+        self.take_checkpoint();
+
+        // Inputs recorded for instructions that were skipped mean the replay went another way
+        self.replay_check()?;
+
         // Fetch the next instruction
-        let instr = self.decode_next().map_err(|e| {
-            e.add_detail(
-                "fetching next instruction".to_string(),
-                self.call_stack().unwrap_or_else(|e| e.to_string()),
-                self.trace().unwrap_or_else(|e| e.to_string()),
-            )
-        })?;
+        let instr = match self.decode_next() {
+            Ok(instr) => instr,
+            Err(e) => {
+                // Faults while fetching, e.g. jumping to unmapped memory, can be handled by exception handlers
+                let e = e.exception_at(self.reg_read_64(SupportedRegister::RIP)?);
+                if self.handle_exception(&e).await? {
+                    return Ok(!self.state.finished);
+                }
+                return Err(e.add_detail(
+                    "fetching next instruction".to_string(),
+                    self.call_stack().unwrap_or_else(|e| e.to_string()),
+                    self.trace().unwrap_or_else(|e| e.to_string()),
+                ));
+            }
+        };
 
         debug_log!("Fetched instruction {}", instr);
 
-        let rip = instr.next_ip();
-        self.reg_write_64(SupportedRegister::RIP, rip)?;
+        if self.has_memory_hooks() {
+            let bytes = self.mem_peek_bytes(instr.ip(), instr.len() as u64)?;
+            self.run_memory_hooks(MemoryAccessKind::Execute, instr.ip(), &bytes)?;
+        }
+
+        // ud2 is how programs deliberately raise an invalid opcode exception
+        if instr.mnemonic() == Mnemonic::Ud2 {
+            let e = AxError::from(format!("Invalid opcode {instr} at {:#x}", instr.ip()))
+                .with_kind(ErrorKind::InvalidInstruction { addr: instr.ip() })
+                .with_exception(CpuException::new(ExceptionKind::InvalidOpcode))
+                .exception_at(instr.ip());
+            if self.handle_exception(&e).await? {
+                return Ok(!self.state.finished);
+            }
+            return Err(e.add_detail(
+                "".to_string(),
+                self.call_stack().unwrap_or_else(|e| e.to_string()),
+                self.trace().unwrap_or_else(|e| e.to_string()),
+            ));
+        }
 
         let mnem: SupportedMnemonic = instr.mnemonic().try_into().map_err(|e: AxError| {
-            e.add_detail(
+            e.with_kind(ErrorKind::UnsupportedInstruction {
+                mnemonic: format!("{:?}", instr.mnemonic()),
+                code: format!("{:?}", instr.code()),
+            })
+            .add_detail(
                 "".to_string(),
                 self.call_stack().unwrap_or_else(|e| e.to_string()),
                 self.trace().unwrap_or_else(|e| e.to_string()),
             )
         })?;
 
-        let hooks = self.mnemonic_hooks(mnem);
-        if let Some(ref h) = hooks {
-            debug_log!("Calling before hooks for mnemonic {:?}", mnem);
-            h.run_before(self, mnem).await.map_err(|e| {
-                AxError::from(format!("running before hooks for {instr}: {e}")).add_detail(
+        // Code hooks can redirect execution by changing RIP, then this instruction is skipped
+        if (!self.hooks.code_hooks.is_empty() || self.is_replaying())
+            && !self.run_code_hooks(&instr, mnem).await?
+        {
+            return Ok(!self.state.finished);
+        }
+
+        // Instructions skipped by code hooks are not counted
+        self.coverage_step(&instr);
+        self.fuzz_step(&instr);
+        self.profile_step(&instr);
+
+        // Effects of syscall handlers and hooks for this instruction are part of its trace entry
+        self.instruction_trace_begin(&instr)?;
+        self.taint_check(&instr).await?;
+
+        let rip = instr.next_ip();
+        self.reg_write_64(SupportedRegister::RIP, rip)?;
+
+        // Syscalls handled by syscall handlers don't reach the before hooks
+        self.hooks.syscall_handled = false;
+        if mnem == SupportedMnemonic::Syscall {
+            self.trace_syscall(instr, self.reg_read_64(SupportedRegister::RAX)?)?;
+            self.syscall_log_begin(instr.ip())?;
+            self.hooks.syscall_handled = self.dispatch_syscall().await.map_err(|e| {
+                e.add_detail(
                     format!(
                         "executing syscall after executing {} instructions: ",
                         self.state.executed_instructions_count
@@ -96,7 +171,37 @@ impl Axecutor {
                     self.trace().unwrap_or_else(|e| e.to_string()),
                 )
             })?;
-            debug_log!("Finished running before hooks for mnemonic {:?}", mnem);
+        }
+
+        let hooks = self.mnemonic_hooks(mnem);
+        if !self.hooks.syscall_handled {
+            let source = InputSource::BeforeHooks(mnem);
+            let handled = if let Some(value) = self.replay_input(source)? {
+                value == Some(InputValue::Handled(true))
+            } else if let Some(h) = hooks.as_ref() {
+                debug_log!("Calling before hooks for mnemonic {:?}", mnem);
+                let view = self.record_begin();
+                let handled = h.run_before(self, mnem).await.map_err(|e| {
+                    AxError::from(format!("running before hooks for {instr}: {e}"))
+                        .with_kind(ErrorKind::HookError)
+                        .add_detail(
+                            format!(
+                                "executing syscall after executing {} instructions: ",
+                                self.state.executed_instructions_count
+                            ),
+                            self.call_stack().unwrap_or_else(|e| e.to_string()),
+                            self.trace().unwrap_or_else(|e| e.to_string()),
+                        )
+                })?;
+                self.record_end(view, source, InputValue::Handled(handled));
+                debug_log!("Finished running before hooks for mnemonic {:?}", mnem);
+                handled
+            } else {
+                false
+            };
+            if mnem == SupportedMnemonic::Syscall {
+                self.hooks.syscall_handled = handled;
+            }
         }
 
         debug_log!(
@@ -105,12 +210,26 @@ impl Axecutor {
             instr.code(),
             rip
         );
-        if let Err(e) = self.switch_instruction_mnemonic(instr) {
+        self.sanitizer_begin(&instr)?;
+        self.taint_begin(&instr);
+        self.hooks.in_instruction = true;
+        self.hooks.instruction_reads = reads_memory(&instr);
+        let result = self.switch_instruction_mnemonic(instr);
+        self.hooks.in_instruction = false;
+        self.sanitizer_end()?;
+        self.taint_end(&instr, result.is_ok())?;
+        if let Err(e) = result {
             // This is so e.g. the ret instruction can end execution
             if e.signals_normal_finish {
                 self.state.finished = true;
                 debug_log!("Marked execution as finished due to instruction indicating so");
             } else {
+                let e = e.exception_at(instr.ip()).for_instruction(&instr);
+                if self.handle_exception(&e).await? {
+                    debug_log!("Exception raised by {} was handled", instr);
+                    return Ok(!self.state.finished);
+                }
+
                 debug_log!(
                     "Error executing instruction {} (after {} steps): {}",
                     instr,
@@ -118,16 +237,23 @@ impl Axecutor {
                     e
                 );
 
-                let err_info = e.add_detail(
-                    format!(
-                        "executing instruction {} ({:?}) after executing {} instructions: ",
-                        instr,
-                        instr.code(),
-                        self.state.executed_instructions_count
-                    ),
-                    self.call_stack().unwrap_or_else(|e| e.to_string()),
-                    self.trace().unwrap_or_else(|e| e.to_string()),
-                );
+                let location = match self.addr_to_line(instr.ip()) {
+                    Some(loc) => format!(" at {loc}"),
+                    None => String::new(),
+                };
+                let err_info = e
+                    .add_detail(
+                        format!(
+                            "executing instruction {} ({:?}){} after executing {} instructions: ",
+                            instr,
+                            instr.code(),
+                            location,
+                            self.state.executed_instructions_count
+                        ),
+                        self.call_stack().unwrap_or_else(|e| e.to_string()),
+                        self.trace().unwrap_or_else(|e| e.to_string()),
+                    )
+                    .add_backtrace(self.error_backtrace(instr.ip()));
 
                 debug_log!("Throwing error: {}", err_info);
 
@@ -136,6 +262,8 @@ impl Axecutor {
         }
 
         self.state.executed_instructions_count += 1;
+        self.replay_track(&instr)?;
+        self.instruction_trace_end()?;
 
         // If we reached the last instruction (and no jump has been performed etc.), we're done
         if self.reg_read_64(Register::RIP.into())? == self.code_end_addr {
@@ -143,21 +271,35 @@ impl Axecutor {
             debug_log!("Marked execution as finished due to reaching end of instruction sequence");
         }
 
-        if let Some(ref h) = hooks {
-            debug_log!("Calling after hooks for mnemonic {:?}", mnem);
-            h.run_after(self, mnem).await.map_err(|e| {
-                AxError::from(format!("running after hooks for {instr}: {e}")).add_detail(
-                    format!(
-                        "executing syscall after executing {} instructions: ",
-                        self.state.executed_instructions_count
-                    ),
-                    self.call_stack().unwrap_or_else(|e| e.to_string()),
-                    self.trace().unwrap_or_else(|e| e.to_string()),
-                )
-            })?;
-            debug_log!("Finished running after hooks for mnemonic {:?}", mnem);
+        let source = InputSource::AfterHooks(mnem);
+        if self.replay_input(source)?.is_none() {
+            if let Some(ref h) = hooks {
+                debug_log!("Calling after hooks for mnemonic {:?}", mnem);
+                let view = self.record_begin();
+                h.run_after(self, mnem).await.map_err(|e| {
+                    AxError::from(format!("running after hooks for {instr}: {e}"))
+                        .with_kind(ErrorKind::HookError)
+                        .add_detail(
+                            format!(
+                                "executing syscall after executing {} instructions: ",
+                                self.state.executed_instructions_count
+                            ),
+                            self.call_stack().unwrap_or_else(|e| e.to_string()),
+                            self.trace().unwrap_or_else(|e| e.to_string()),
+                        )
+                })?;
+                self.record_end(view, source, InputValue::Handled(false));
+                debug_log!("Finished running after hooks for mnemonic {:?}", mnem);
+            }
         }
 
+        if mnem == SupportedMnemonic::Syscall {
+            self.syscall_log_end()?;
+        }
+
+        // Signals sent or unblocked by this instruction
+        self.deliver_pending_signal()?;
+
         debug_log!("Finished Axecutor::step, finished={}", self.state.finished);
         Ok(!self.state.finished)
     }
@@ -177,6 +319,16 @@ impl Axecutor {
 
         self.state.max_instructions = Some(max);
     }
+
+    /// Removes the limit set by `set_max_instructions`.
+    pub fn remove_max_instructions(&mut self) {
+        self.state.max_instructions = None;
+    }
+
+    /// Returns the number of instructions executed so far.
+    pub fn executed_instructions(&self) -> u64 {
+        self.state.executed_instructions_count
+    }
 }
 
 #[cfg(test)]
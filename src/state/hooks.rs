@@ -2,7 +2,6 @@ extern crate lazy_static;
 
 #[cfg(all(target_arch = "wasm32", not(test)))]
 use js_sys::Array;
-#[cfg(all(target_arch = "wasm32", not(test)))]
 use wasm_bindgen::prelude::*;
 #[cfg(all(target_arch = "wasm32", not(test)))]
 use wasm_bindgen::{JsCast, JsValue};
@@ -10,19 +9,45 @@ use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
 
 use std::{
-    collections::HashMap,
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
     fmt::{Display, Formatter},
+    rc::Rc,
 };
 
 use crate::auto::generated::SupportedMnemonic;
 use crate::helpers::debug::debug_log;
+use crate::helpers::syscalls::SyscallHook;
+use crate::state::breakpoints::Breakpoint;
+use crate::state::code_hooks::CodeHook;
+use crate::state::exceptions::ExceptionHook;
+use crate::state::memory_hooks::{MemoryHook, Watchpoint, WatchpointHit};
+use crate::state::taint::TaintHook;
 use crate::{axecutor::Axecutor, helpers::errors::AxError};
 use std::error::Error;
 use std::fmt::Debug;
 
 // (possible asynchronous) callback function (closure) taking an Axecutor and Mnemonic, returning a result
 pub type RustCallbackFunction =
-    dyn Fn(&mut Axecutor, SupportedMnemonic) -> Result<HookResult, Box<dyn Error>>;
+    dyn FnMut(&mut Axecutor, SupportedMnemonic) -> Result<HookResult, Box<dyn Error>>;
+
+// Native hook functions may own state, so they are shared between clones of an Axecutor instead of being copied
+pub(crate) type NativeHook<F> = Rc<RefCell<Box<F>>>;
+
+pub(crate) fn native_hook<F: ?Sized>(function: Box<F>) -> NativeHook<F> {
+    Rc::new(RefCell::new(function))
+}
+
+/// Calls a native hook function, failing if it is already running, e.g. because it executed instructions itself
+pub(crate) fn call_native_hook<F: ?Sized, R>(
+    hook: &NativeHook<F>,
+    call: impl FnOnce(&mut F) -> R,
+) -> Result<R, AxError> {
+    let mut function = hook.try_borrow_mut().map_err(|_| {
+        AxError::from("Hook is already running, hooks must not be called recursively")
+    })?;
+    Ok(call(&mut **function))
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum HookResult {
@@ -30,97 +55,129 @@ pub enum HookResult {
     Unhandled,
 }
 
-#[derive(Clone)]
-pub(crate) struct Hook {
-    native_before: Vec<&'static RustCallbackFunction>,
-    native_after: Vec<&'static RustCallbackFunction>,
+/// Identifies a registered hook, e.g. to remove it again using `remove_hook`
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HookHandle {
+    id: u64,
+}
 
+/// Position of a hook among the hooks for the same event: higher priorities run first, hooks with the same priority in registration order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct HookOrder {
+    pub(crate) handle: HookHandle,
+    pub(crate) priority: i32,
+}
+
+impl HookOrder {
+    fn sort_key(&self) -> (std::cmp::Reverse<i32>, HookHandle) {
+        (std::cmp::Reverse(self.priority), self.handle)
+    }
+}
+
+/// Sorts hooks into the order they are run in
+pub(crate) fn sort_hooks<T>(hooks: &mut [T], order: impl Fn(&T) -> HookOrder) {
+    hooks.sort_by_key(|h| order(h).sort_key());
+}
+
+#[derive(Clone)]
+enum HookFunction {
+    Native(NativeHook<RustCallbackFunction>),
     #[cfg(all(target_arch = "wasm32", not(test)))]
-    js_before: Vec<js_sys::Function>,
-    #[cfg(all(target_arch = "wasm32", not(test)))]
-    js_after: Vec<js_sys::Function>,
+    Js(js_sys::Function),
+}
+
+#[derive(Clone)]
+struct HookEntry {
+    order: HookOrder,
+    function: HookFunction,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Hook {
+    before: Vec<HookEntry>,
+    after: Vec<HookEntry>,
 }
 
 impl Hook {
-    pub fn new() -> Self {
-        Self {
-            native_before: Vec::new(),
-            native_after: Vec::new(),
-            #[cfg(all(target_arch = "wasm32", not(test)))]
-            js_before: Vec::new(),
-            #[cfg(all(target_arch = "wasm32", not(test)))]
-            js_after: Vec::new(),
-        }
+    fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty()
+    }
+
+    pub(crate) fn has_after(&self) -> bool {
+        !self.after.is_empty()
     }
 
+    // Returns whether a hook handled the instruction
     async fn run_functions(
         &self,
         before: bool,
         ax: &mut Axecutor,
         mnemonic: SupportedMnemonic,
-    ) -> Result<(), AxError> {
+    ) -> Result<bool, AxError> {
         ax.hooks.running = true;
+        let result = Self::run_entries(
+            if before { &self.before } else { &self.after },
+            ax,
+            mnemonic,
+        )
+        .await;
+        ax.hooks.running = false;
 
-        let functions = if before {
-            &self.native_before
-        } else {
-            &self.native_after
-        };
-
-        for function in functions {
-            let res = function(ax, mnemonic)?;
-            if ax.state.finished || res == HookResult::Handled {
-                ax.hooks.running = false;
-                return Ok(());
-            }
-        }
+        result
+    }
 
-        #[cfg(all(target_arch = "wasm32", not(test)))]
-        {
-            for fnt in if before {
-                &self.js_before
-            } else {
-                &self.js_after
-            } {
-                let res = run_function(ax, fnt.clone(), vec![JsValue::from(mnemonic as u32)]).await;
-                if let Err(e) = res {
-                    debug_log!("Error running hook: {:?}", e);
-                    ax.hooks.running = false;
-                    return Err(e.into());
+    async fn run_entries(
+        entries: &[HookEntry],
+        ax: &mut Axecutor,
+        mnemonic: SupportedMnemonic,
+    ) -> Result<bool, AxError> {
+        for entry in entries {
+            match &entry.function {
+                HookFunction::Native(function) => {
+                    let res = call_native_hook(function, |f| f(ax, mnemonic))??;
+                    if res == HookResult::Handled {
+                        return Ok(true);
+                    }
                 }
-                if ax.state.finished {
-                    ax.hooks.running = false;
-                    return Ok(());
+                #[cfg(all(target_arch = "wasm32", not(test)))]
+                HookFunction::Js(function) => {
+                    let res =
+                        run_function(ax, function.clone(), vec![JsValue::from(mnemonic as u32)])
+                            .await;
+                    if let Err(e) = res {
+                        debug_log!("Error running hook: {:?}", e);
+                        return Err(e.into());
+                    }
                 }
             }
-        }
 
-        ax.hooks.running = false;
+            if ax.state.finished {
+                return Ok(true);
+            }
+        }
 
-        Ok(())
+        // JS hooks cannot tell whether they handled the instruction
+        Ok(entries
+            .iter()
+            .any(|e| !matches!(e.function, HookFunction::Native(_))))
     }
 
+    /// Runs the before hooks, returning whether one of them handled the instruction
     pub async fn run_before(
         &self,
         ax: &mut Axecutor,
         mnemonic: SupportedMnemonic,
-    ) -> Result<(), AxError> {
-        #[cfg(all(target_arch = "wasm32", not(test)))]
+    ) -> Result<bool, AxError> {
         debug_log!(
-            "Calling Hook::run_before with {} native and {} JS hook function(s)",
-            self.native_before.len(),
-            self.js_before.len()
-        );
-        #[cfg(not(all(target_arch = "wasm32", not(test))))]
-        debug_log!(
-            "Calling Hook::run_before with {} native hook function(s)",
-            self.native_before.len()
+            "Calling Hook::run_before with {} hook function(s)",
+            self.before.len()
         );
 
-        self.run_functions(true, ax, mnemonic).await?;
+        let handled = self.run_functions(true, ax, mnemonic).await?;
 
-        debug_log!("Finished calling Hook::run_before");
-        Ok(())
+        debug_log!("Finished calling Hook::run_before, handled={}", handled);
+        Ok(handled)
     }
 
     pub async fn run_after(
@@ -128,16 +185,9 @@ impl Hook {
         ax: &mut Axecutor,
         mnemonic: SupportedMnemonic,
     ) -> Result<(), AxError> {
-        #[cfg(all(target_arch = "wasm32", not(test)))]
-        debug_log!(
-            "Calling Hook::run_after with {} native and {} JS hook function(s)",
-            self.native_after.len(),
-            self.js_after.len()
-        );
-        #[cfg(not(all(target_arch = "wasm32", not(test))))]
         debug_log!(
-            "Calling Hook::run_after with {} native hook function(s)",
-            self.native_after.len()
+            "Calling Hook::run_after with {} hook function(s)",
+            self.after.len()
         );
 
         self.run_functions(false, ax, mnemonic).await?;
@@ -149,19 +199,9 @@ impl Hook {
 
 impl Debug for Hook {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        #[cfg(all(target_arch = "wasm32", not(test)))]
-        {
-            f.debug_struct("Hook")
-                .field("js_before", &self.js_before.len())
-                .field("js_after", &self.js_after.len())
-                .field("native_before", &self.native_before.len())
-                .field("native_after", &self.native_after.len())
-                .finish()
-        }
-        #[cfg(not(all(target_arch = "wasm32", not(test))))]
         f.debug_struct("Hook")
-            .field("native_before", &self.native_before.len())
-            .field("native_after", &self.native_after.len())
+            .field("before", &self.before.len())
+            .field("after", &self.after.len())
             .finish()
     }
 }
@@ -170,6 +210,41 @@ impl Debug for Hook {
 pub(crate) struct HookProcessor {
     pub(crate) mnemonic_hooks: HashMap<SupportedMnemonic, Hook>,
 
+    // Breakpoints by address, checked by run_until_breakpoint
+    pub(crate) breakpoints: BTreeMap<u64, Breakpoint>,
+
+    // Hooks for instructions and basic blocks in address ranges, sorted by priority
+    pub(crate) code_hooks: Vec<CodeHook>,
+    // Whether the next instruction starts a new basic block
+    pub(crate) block_start: bool,
+
+    // Hooks for memory accesses, sorted by priority
+    pub(crate) memory_hooks: Vec<MemoryHook>,
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    // The first watchpoint triggered by the instruction executed last.
+    // Memory is read through &Axecutor, so this needs interior mutability
+    pub(crate) watchpoint_hit: Cell<Option<WatchpointHit>>,
+    // Set while memory hooks run, so their own memory accesses don't trigger hooks again
+    pub(crate) in_memory_hook: Cell<bool>,
+    // Set while an instruction executes, so memory accessed through the API doesn't trigger hooks
+    pub(crate) in_instruction: bool,
+    // Whether that instruction reads memory, see `reads_memory`
+    pub(crate) instruction_reads: bool,
+
+    // Syscall handlers, sorted by priority
+    pub(crate) syscall_hooks: Vec<SyscallHook>,
+    // Whether the syscall that is currently executed has been handled by a syscall handler or hook
+    pub(crate) syscall_handled: bool,
+
+    // Exception handlers, sorted by priority
+    pub(crate) exception_hooks: Vec<ExceptionHook>,
+
+    // Hooks for tainted data reaching a sink, sorted by priority
+    pub(crate) taint_hooks: Vec<TaintHook>,
+
+    // Handles are never reused, so a stale handle cannot remove a newer hook
+    next_handle: u64,
+
     pub(crate) running: bool,
 }
 
@@ -177,38 +252,143 @@ impl HookProcessor {
     pub(crate) fn default() -> Self {
         Self {
             mnemonic_hooks: HashMap::new(),
+            breakpoints: BTreeMap::new(),
+            code_hooks: Vec::new(),
+            block_start: true,
+            memory_hooks: Vec::new(),
+            watchpoints: Vec::new(),
+            watchpoint_hit: Cell::new(None),
+            in_memory_hook: Cell::new(false),
+            in_instruction: false,
+            instruction_reads: false,
+            syscall_hooks: Vec::new(),
+            syscall_handled: false,
+            exception_hooks: Vec::new(),
+            taint_hooks: Vec::new(),
+            next_handle: 0,
             running: false,
         }
     }
+
+    /// Order for a newly registered hook with the default priority 0
+    pub(crate) fn next_order(&mut self) -> HookOrder {
+        self.next_handle += 1;
+        HookOrder {
+            handle: HookHandle {
+                id: self.next_handle,
+            },
+            priority: 0,
+        }
+    }
+
+    // Calls update with the order of the hook with the given handle, returning whether it was found
+    fn update_order(&mut self, handle: HookHandle, mut update: impl FnMut(&mut HookOrder)) -> bool {
+        for hook in self.mnemonic_hooks.values_mut() {
+            for entries in [&mut hook.before, &mut hook.after] {
+                if let Some(entry) = entries.iter_mut().find(|e| e.order.handle == handle) {
+                    update(&mut entry.order);
+                    sort_hooks(entries, |e| e.order);
+                    return true;
+                }
+            }
+        }
+
+        if let Some(hook) = self
+            .code_hooks
+            .iter_mut()
+            .find(|h| h.order.handle == handle)
+        {
+            update(&mut hook.order);
+            sort_hooks(&mut self.code_hooks, |h| h.order);
+            return true;
+        }
+
+        if let Some(hook) = self
+            .memory_hooks
+            .iter_mut()
+            .find(|h| h.order.handle == handle)
+        {
+            update(&mut hook.order);
+            sort_hooks(&mut self.memory_hooks, |h| h.order);
+            return true;
+        }
+
+        if let Some(hook) = self
+            .syscall_hooks
+            .iter_mut()
+            .find(|h| h.order.handle == handle)
+        {
+            update(&mut hook.order);
+            sort_hooks(&mut self.syscall_hooks, |h| h.order);
+            return true;
+        }
+
+        if let Some(hook) = self
+            .exception_hooks
+            .iter_mut()
+            .find(|h| h.order.handle == handle)
+        {
+            update(&mut hook.order);
+            sort_hooks(&mut self.exception_hooks, |h| h.order);
+            return true;
+        }
+
+        if let Some(hook) = self
+            .taint_hooks
+            .iter_mut()
+            .find(|h| h.order.handle == handle)
+        {
+            update(&mut hook.order);
+            sort_hooks(&mut self.taint_hooks, |h| h.order);
+            return true;
+        }
+
+        false
+    }
+
+    fn remove(&mut self, handle: HookHandle) -> bool {
+        let count = self.len();
+
+        for hook in self.mnemonic_hooks.values_mut() {
+            hook.before.retain(|e| e.order.handle != handle);
+            hook.after.retain(|e| e.order.handle != handle);
+        }
+        // Mnemonics without hooks must not have an entry, e.g. syscall fails without any hooks
+        self.mnemonic_hooks.retain(|_, hook| !hook.is_empty());
+
+        self.code_hooks.retain(|h| h.order.handle != handle);
+        self.memory_hooks.retain(|h| h.order.handle != handle);
+        self.syscall_hooks.retain(|h| h.order.handle != handle);
+        self.exception_hooks.retain(|h| h.order.handle != handle);
+        self.taint_hooks.retain(|h| h.order.handle != handle);
+
+        self.len() != count
+    }
+
+    fn len(&self) -> usize {
+        self.mnemonic_hooks
+            .values()
+            .map(|h| h.before.len() + h.after.len())
+            .sum::<usize>()
+            + self.code_hooks.len()
+            + self.memory_hooks.len()
+            + self.syscall_hooks.len()
+            + self.exception_hooks.len()
+            + self.taint_hooks.len()
+    }
 }
 
 impl Display for HookProcessor {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{{")?;
         for (mnem, hook) in self.mnemonic_hooks.iter() {
-            #[cfg(all(target_arch = "wasm32", not(test)))]
-            {
-                write!(
-                    f,
-                    "\n    {:?}: {{ js_before: {}, js_after: {}, native_before: {}, native_after: {} }},",
-                    mnem,
-                    hook.js_before.len(),
-                    hook.js_after.len(),
-                    hook.native_before.len(),
-                    hook.native_after.len()
-                )?;
-            }
-
-            #[cfg(not(all(target_arch = "wasm32", not(test))))]
-            {
-                write!(
-                    f,
-                    "\n    {:?}: {{ native_before: {}, native_after: {} }},",
-                    mnem,
-                    hook.native_before.len(),
-                    hook.native_after.len()
-                )?;
-            }
+            write!(
+                f,
+                "\n    {:?}: {{ before: {}, after: {} }},",
+                mnem,
+                hook.before.len(),
+                hook.after.len()
+            )?;
         }
 
         write!(f, "\n}}")
@@ -219,6 +399,42 @@ impl Axecutor {
     pub(crate) fn mnemonic_hooks(&self, mnemonic: SupportedMnemonic) -> Option<Hook> {
         self.hooks.mnemonic_hooks.get(&mnemonic).cloned()
     }
+
+    fn add_mnemonic_hook(
+        &mut self,
+        mnemonic: SupportedMnemonic,
+        before: bool,
+        function: HookFunction,
+    ) -> Result<HookHandle, AxError> {
+        debug_log!(
+            "Calling Axecutor::add_mnemonic_hook, before={}, hooks_running={}",
+            before,
+            self.hooks.running
+        );
+
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot add hooks while another hook is running",
+            ));
+        }
+
+        let order = self.hooks.next_order();
+        let hook = self.hooks.mnemonic_hooks.entry(mnemonic).or_default();
+        let entries = if before {
+            &mut hook.before
+        } else {
+            &mut hook.after
+        };
+        entries.push(HookEntry { order, function });
+        sort_hooks(entries, |e| e.order);
+
+        debug_log!(
+            "Updated entry: {:?}",
+            self.hooks.mnemonic_hooks.entry(mnemonic)
+        );
+
+        Ok(order.handle)
+    }
 }
 
 // WASM implementation
@@ -231,46 +447,19 @@ impl Axecutor {
     ///  - instance.commit(): Continue execution, keep data
     ///  - instance.stop(): Stop execution, keep data
     ///  - instance.unchanged(): Continue execution, but discard data changed in the hook
-    /// You can register multiple functions for the same mnemonic, the order of execution is however not defined.
+    /// You can register multiple functions for the same mnemonic, they run in the order described at `set_hook_priority`.
+    /// Returns a handle that can be passed to `remove_hook`.
     pub fn hook_before_mnemonic(
         &mut self,
         mnemonic: SupportedMnemonic,
         cb: js_sys::Function,
-    ) -> Result<(), AxError> {
-        debug_log!(
-            "Calling Axecutor::hook_before_mnemonic, hooks_running={}",
-            self.hooks.running
-        );
-
-        if self.hooks.running {
-            return Err(AxError::from(
-                "Cannot add hooks while another hook is running",
-            ));
-        }
-
+    ) -> Result<HookHandle, AxError> {
         if cb.has_type::<js_sys::Function>() {
             let function = cb.dyn_into::<js_sys::Function>().map_err(|_| {
                 AxError::from("The provided callback is not a function. Please provide a function.")
             })?;
 
-            debug_log!(
-                "Previous entry: {:?}",
-                self.hooks.mnemonic_hooks.entry(mnemonic)
-            );
-
-            self.hooks
-                .mnemonic_hooks
-                .entry(mnemonic)
-                .or_insert_with(Hook::new)
-                .js_before
-                .push(function);
-
-            debug_log!(
-                "Updated entry: {:?}",
-                self.hooks.mnemonic_hooks.entry(mnemonic)
-            );
-
-            Ok(())
+            self.add_mnemonic_hook(mnemonic, true, HookFunction::Js(function))
         } else {
             debug_log!("hook_before_mnemonic: Provided callback is not a function");
             Err(AxError::from(&*format!(
@@ -286,43 +475,19 @@ impl Axecutor {
     ///  - instance.commit(): Continue execution, keep data
     ///  - instance.stop(): Stop execution, keep data
     ///  - instance.unchanged(): Continue execution, but discard data changed in the hook
-    /// You can register multiple functions for the same mnemonic, the order of execution is however not defined.
+    /// You can register multiple functions for the same mnemonic, they run in the order described at `set_hook_priority`.
+    /// Returns a handle that can be passed to `remove_hook`.
     pub fn hook_after_mnemonic(
         &mut self,
         mnemonic: SupportedMnemonic,
         cb: js_sys::Function,
-    ) -> Result<(), AxError> {
-        debug_log!(
-            "Calling Axecutor::hook_after_mnemonic, hooks_running={}",
-            self.hooks.running
-        );
-        if self.hooks.running {
-            return Err(AxError::from(
-                "Cannot add hooks while another hook is running",
-            ));
-        }
-
+    ) -> Result<HookHandle, AxError> {
         if cb.has_type::<js_sys::Function>() {
             let function = cb.dyn_into::<js_sys::Function>().map_err(|_| {
                 AxError::from("The provided callback is not a function. Please provide a function.")
             })?;
 
-            debug_log!(
-                "Previous entry: {:?}",
-                self.hooks.mnemonic_hooks.entry(mnemonic)
-            );
-            self.hooks
-                .mnemonic_hooks
-                .entry(mnemonic)
-                .or_insert_with(Hook::new)
-                .js_after
-                .push(function);
-
-            debug_log!(
-                "Updated entry: {:?}",
-                self.hooks.mnemonic_hooks.entry(mnemonic)
-            );
-            Ok(())
+            self.add_mnemonic_hook(mnemonic, false, HookFunction::Js(function))
         } else {
             Err(AxError::from(&*format!(
                 "hook_after_mnemonic: expected function or async function argument, but got {:?}",
@@ -337,76 +502,72 @@ impl Axecutor {
     /// Register a function to be called before a mnemonic is executed.
     /// Unlike the JS API, you don't need to return any special values.
     /// The function will be called with the Axecutor object and mnemonic as arguments.
-    /// You can register multiple functions for the same mnemonic, the order of execution is however not defined.
+    /// It may own state, e.g. counters or collected output. Clones of the Axecutor share the function and its state.
+    /// You can register multiple functions for the same mnemonic, they run in the order described at `set_hook_priority`.
+    /// Returns a handle that can be passed to `remove_hook`.
     pub fn hook_before_mnemonic_native(
         &mut self,
         mnemonic: SupportedMnemonic,
-        cb: &'static RustCallbackFunction,
-    ) -> Result<(), AxError> {
-        debug_log!(
-            "Calling Axecutor::hook_before_mnemonic, hooks_running={}",
-            self.hooks.running
-        );
-
-        if self.hooks.running {
-            return Err(AxError::from(
-                "Cannot add hooks while another hook is running",
-            ));
-        }
-
-        debug_log!(
-            "Previous entry: {:?}",
-            self.hooks.mnemonic_hooks.entry(mnemonic)
-        );
-        self.hooks
-            .mnemonic_hooks
-            .entry(mnemonic)
-            .or_insert_with(Hook::new)
-            .native_before
-            .push(cb);
-
-        debug_log!(
-            "Updated entry: {:?}",
-            self.hooks.mnemonic_hooks.entry(mnemonic)
-        );
-        Ok(())
+        cb: impl FnMut(&mut Axecutor, SupportedMnemonic) -> Result<HookResult, Box<dyn Error>> + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_mnemonic_hook(
+            mnemonic,
+            true,
+            HookFunction::Native(native_hook(Box::new(cb))),
+        )
     }
 
     /// Register a function to be called after a mnemonic is executed.
     /// Unlike the JS API, you don't need to return any special values.
     /// The function will be called with the Axecutor object and mnemonic as arguments.
-    /// You can register multiple functions for the same mnemonic, the order of execution is however not defined.
+    /// It may own state, e.g. counters or collected output. Clones of the Axecutor share the function and its state.
+    /// You can register multiple functions for the same mnemonic, they run in the order described at `set_hook_priority`.
+    /// Returns a handle that can be passed to `remove_hook`.
     pub fn hook_after_mnemonic_native(
         &mut self,
         mnemonic: SupportedMnemonic,
-        cb: &'static RustCallbackFunction,
-    ) -> Result<(), AxError> {
-        debug_log!(
-            "Calling Axecutor::hook_after_mnemonic, hooks_running={}",
-            self.hooks.running
-        );
+        cb: impl FnMut(&mut Axecutor, SupportedMnemonic) -> Result<HookResult, Box<dyn Error>> + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_mnemonic_hook(
+            mnemonic,
+            false,
+            HookFunction::Native(native_hook(Box::new(cb))),
+        )
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Remove a mnemonic, code, memory or syscall hook, returning whether it was registered
+    pub fn remove_hook(&mut self, handle: HookHandle) -> Result<bool, AxError> {
+        debug_log!("Calling Axecutor::remove_hook, handle={:?}", handle);
+
         if self.hooks.running {
             return Err(AxError::from(
-                "Cannot add hooks while another hook is running",
+                "Cannot remove hooks while another hook is running",
             ));
         }
 
-        debug_log!(
-            "Previous entry: {:?}",
-            self.hooks.mnemonic_hooks.entry(mnemonic)
-        );
-        self.hooks
-            .mnemonic_hooks
-            .entry(mnemonic)
-            .or_insert_with(Hook::new)
-            .native_after
-            .push(cb);
+        Ok(self.hooks.remove(handle))
+    }
 
-        debug_log!(
-            "Updated entry: {:?}",
-            self.hooks.mnemonic_hooks.entry(mnemonic)
-        );
-        Ok(())
+    /// Change the priority of a hook, returning whether it was registered.
+    /// Hooks for the same event run in order of descending priority, hooks with the same priority in the order they were registered.
+    /// The default priority is 0.
+    pub fn set_hook_priority(
+        &mut self,
+        handle: HookHandle,
+        priority: i32,
+    ) -> Result<bool, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot change hooks while another hook is running",
+            ));
+        }
+
+        Ok(self
+            .hooks
+            .update_order(handle, |order| order.priority = priority))
     }
 }
 
@@ -421,7 +582,7 @@ async fn run_promise(promise_arg: JsValue) -> Result<JsValue, JsValue> {
 }
 
 #[cfg(all(target_arch = "wasm32", not(test)))]
-async fn run_function(
+pub(crate) async fn run_function(
     ax: &mut Axecutor,
     function: js_sys::Function,
     arguments: Vec<JsValue>,
@@ -466,6 +627,8 @@ mod tests {
     use crate::axecutor::Axecutor;
     use crate::helpers::tests::{assert_reg_value, test_async, write_reg_value};
     use iced_x86::Register::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
 
     test_async![hook_before_mnemonic; async {
         let mut ax = Axecutor::new(
@@ -478,13 +641,13 @@ mod tests {
         )
         .expect("Failed to create axecutor");
 
-        let fnt: &RustCallbackFunction = &|ax: &mut Axecutor, mnemonic: SupportedMnemonic| {
+        let fnt = |ax: &mut Axecutor, mnemonic: SupportedMnemonic| {
             assert_eq!(mnemonic, SupportedMnemonic::Syscall, "Wrong mnemonic passed to hook handling Syscall");
 
             assert_reg_value!(q; ax; RAX; 5);
             write_reg_value!(q; ax; RAX; 10);
 
-            Ok(HookResult::Handled)
+            Ok::<_, Box<dyn Error>>(HookResult::Handled)
         };
 
         ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, fnt)
@@ -506,13 +669,13 @@ mod tests {
         )
         .expect("Failed to create axecutor");
 
-        let fnt: &RustCallbackFunction = &|ax: &mut Axecutor, mnemonic: SupportedMnemonic| {
+        let fnt = |ax: &mut Axecutor, mnemonic: SupportedMnemonic| {
             assert_eq!(mnemonic, SupportedMnemonic::Syscall, "Wrong mnemonic passed to hook handling Syscall");
 
             assert_reg_value!(q; ax; RAX; 5);
             write_reg_value!(q; ax; RAX; 10);
 
-            Ok(HookResult::Handled)
+            Ok::<_, Box<dyn Error>>(HookResult::Handled)
         };
 
         ax.hook_after_mnemonic_native(SupportedMnemonic::Syscall, fnt)
@@ -533,7 +696,7 @@ mod tests {
             0x1000,
         ).expect("Failed to create axecutor");
 
-        ax.hook_after_mnemonic_native(SupportedMnemonic::Mov, &|ax: &mut Axecutor, mnemonic: SupportedMnemonic| {
+        ax.hook_after_mnemonic_native(SupportedMnemonic::Mov, |ax: &mut Axecutor, mnemonic: SupportedMnemonic| {
             assert_eq!(mnemonic, SupportedMnemonic::Mov, "Wrong mnemonic passed to hook handling Mov");
 
             ax.stop();
@@ -541,7 +704,7 @@ mod tests {
             Ok(HookResult::Handled)
         }).expect("Failed to add hook");
 
-        ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|_: &mut Axecutor, _: SupportedMnemonic| {
+        ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, |_: &mut Axecutor, _: SupportedMnemonic| {
             unreachable!("Syscall hook should not be called as we stop before it");
         }).expect("Failed to add hook");
 
@@ -560,33 +723,128 @@ mod tests {
             0x1000,
         ).expect("Failed to create axecutor");
 
-        // outside_var should last for static so we can use it in a closure/callback
-        #[allow(non_upper_case_globals)]
-        static mut outside_var: u64 = 0;
+        // Hooks can share state with the code that registered them
+        let outside_var = Rc::new(Cell::new(0u64));
 
-        // Make sure we keep access to the outside variable without "temporary value dropped while borrowed" error
-        ax.hook_after_mnemonic_native(SupportedMnemonic::Mov, &|_: &mut Axecutor, mnemonic: SupportedMnemonic| {
+        let var = outside_var.clone();
+        ax.hook_after_mnemonic_native(SupportedMnemonic::Mov, move |_: &mut Axecutor, mnemonic: SupportedMnemonic| {
             assert_eq!(mnemonic, SupportedMnemonic::Mov, "Wrong mnemonic passed to hook handling Mov");
 
-            unsafe {
-                outside_var = 10;
-            }
+            var.set(10);
 
             Ok(HookResult::Handled)
         }).expect("Failed to add hook");
 
-        ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, &|_: &mut Axecutor, _: SupportedMnemonic| {
-            unsafe {
-                assert_eq!(outside_var, 10, "Outside variable was not modified");
-            }
+        let var = outside_var.clone();
+        ax.hook_before_mnemonic_native(SupportedMnemonic::Syscall, move |_: &mut Axecutor, _: SupportedMnemonic| {
+            assert_eq!(var.get(), 10, "Outside variable was not modified");
 
             Ok(HookResult::Handled)
         }).expect("Failed to add hook");
 
         ax.execute().await.expect("Failed to execute");
 
-        unsafe {
-            assert_eq!(outside_var, 10, "Outside variable was not modified");
+        assert_eq!(outside_var.get(), 10, "Outside variable was not modified");
+    }];
+
+    fn inc_rax() -> Axecutor {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0xff, 0xc0, // inc rax
+                0x48, 0xff, 0xc0, // inc rax
+                0x48, 0xff, 0xc0, // inc rax
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        write_reg_value!(q; ax; RAX; 0);
+        ax
+    }
+
+    test_async![stateful_hook; async {
+        let mut ax = inc_rax();
+
+        // The hook owns its counter
+        let mut count = 0;
+        ax.hook_after_mnemonic_native(SupportedMnemonic::Inc, move |ax, _| {
+            count += 1;
+            write_reg_value!(q; ax; RBX; count);
+            Ok(HookResult::Unhandled)
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_reg_value!(q; ax; RAX; 3);
+        assert_reg_value!(q; ax; RBX; 3);
+    }];
+
+    test_async![remove_hook; async {
+        let mut ax = inc_rax();
+
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let handle = ax.hook_before_mnemonic_native(SupportedMnemonic::Inc, move |ax, _| {
+            c.set(c.get() + 1);
+            ax.stop();
+            Ok(HookResult::Handled)
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(calls.get(), 1);
+        // The instruction is still executed after a before hook stopped execution
+        assert_reg_value!(q; ax; RAX; 1);
+
+        assert!(ax.remove_hook(handle).unwrap());
+        assert!(!ax.remove_hook(handle).unwrap(), "Hook was removed twice");
+        assert!(ax.mnemonic_hooks(SupportedMnemonic::Inc).is_none());
+
+        ax.state.finished = false;
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(calls.get(), 1);
+        assert_reg_value!(q; ax; RAX; 3);
+    }];
+
+    test_async![hook_priorities; async {
+        let mut ax = inc_rax();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut handles = Vec::new();
+        for name in ["first", "second", "third"] {
+            let o = order.clone();
+            handles.push(ax.hook_before_mnemonic_native(SupportedMnemonic::Inc, move |ax, _| {
+                o.borrow_mut().push(name);
+                ax.stop();
+                Ok(HookResult::Unhandled)
+            }).expect("Failed to add hook"));
         }
+        assert!(ax.set_hook_priority(handles[2], 10).unwrap());
+        assert!(ax.set_hook_priority(handles[0], -1).unwrap());
+
+        // Stopping execution also stops running further hooks
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(*order.borrow(), vec!["third"]);
+
+        ax.remove_hook(handles[2]).unwrap();
+        ax.state.finished = false;
+        ax.execute().await.expect("Failed to execute");
+        assert_eq!(*order.borrow(), vec!["third", "second"]);
+    }];
+
+    test_async![cloned_axecutor_keeps_hooks; async {
+        let mut ax = inc_rax();
+
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        ax.hook_after_mnemonic_native(SupportedMnemonic::Inc, move |_, _| {
+            c.set(c.get() + 1);
+            Ok(HookResult::Handled)
+        }).expect("Failed to add hook");
+
+        let mut clone = ax.clone();
+        clone.execute().await.expect("Failed to execute");
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(calls.get(), 6);
     }];
 }
@@ -1,16 +1,23 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::helpers::debug::debug_log;
 use crate::{helpers::macros::assert_fatal, state::registers::SupportedRegister};
 
-use crate::{axecutor::Axecutor, helpers::errors::AxError};
+use crate::state::exceptions::{is_canonical, CpuException};
+use crate::state::memory_hooks::MemoryAccessKind;
+use crate::{
+    axecutor::Axecutor,
+    helpers::errors::{AxError, ErrorKind},
+};
 
 #[cfg(all(target_arch = "wasm32", not(test)))]
 use wasm_bindgen::JsValue;
 
 use std::cmp::min;
 use std::convert::TryInto;
+use std::fmt;
+use std::rc::Rc;
 
 /// The area must not be accessed
 pub const PROT_NONE: u32 = 0x0;
@@ -21,6 +28,18 @@ pub const PROT_WRITE: u32 = 0x2;
 /// The area can be executed
 pub const PROT_EXEC: u32 = 0x4;
 
+/// Size of a memory page, the granularity at which ELF segments are mapped and protected
+pub const PAGE_SIZE: u64 = 0x1000;
+
+// The exception an access to `address` raises, `mapped` is true if the memory exists but doesn't allow the access
+fn memory_exception(address: u64, access: MemoryAccessKind, mapped: bool) -> CpuException {
+    if is_canonical(address) {
+        CpuException::page_fault(address, access, mapped)
+    } else {
+        CpuException::general_protection(address, access)
+    }
+}
+
 fn access_to_string(prot: u32) -> String {
     if prot == PROT_NONE {
         return "PROT_NONE".to_string();
@@ -51,15 +70,317 @@ fn access_to_string(prot: u32) -> String {
     s
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub(crate) struct MemoryArea {
+    pub(crate) name: Option<String>,
+    pub(crate) start: u64,
+    pub(crate) length: u64,
+    pub(crate) data: AreaData,
+    pub(crate) access: u32,
+}
+
+type Page = [u8; PAGE_SIZE as usize];
+
+// The contents of a memory area, split into pages that are shared with snapshots and only copied once they are written to.
+// Bytes after `len` in the last page are not part of the area and may hold stale data
+#[derive(Clone, Default, Serialize)]
+#[serde(into = "SparseAreaData")]
+pub(crate) struct AreaData {
+    len: usize,
+    pages: Vec<Rc<Page>>,
+}
+
+impl AreaData {
+    pub(crate) fn zeroed(len: usize) -> AreaData {
+        // All pages start out as the same zero page, so untouched memory is never allocated
+        let zero = Rc::new([0; PAGE_SIZE as usize]);
+        AreaData {
+            len,
+            pages: vec![zero; len.div_ceil(PAGE_SIZE as usize)],
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // Appends `len` bytes starting at `offset` to `out`
+    pub(crate) fn read_into(&self, offset: usize, len: usize, out: &mut Vec<u8>) {
+        assert!(
+            offset + len <= self.len,
+            "Read past the end of a memory area"
+        );
+
+        let mut current = offset;
+        while current < offset + len {
+            let page_offset = current % PAGE_SIZE as usize;
+            let n = min(PAGE_SIZE as usize - page_offset, offset + len - current);
+            out.extend_from_slice(
+                &self.pages[current / PAGE_SIZE as usize][page_offset..page_offset + n],
+            );
+            current += n;
+        }
+    }
+
+    pub(crate) fn read(&self, offset: usize, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len);
+        self.read_into(offset, len, &mut out);
+        out
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<u8> {
+        self.read(0, self.len)
+    }
+
+    pub(crate) fn write(&mut self, offset: usize, data: &[u8]) {
+        assert!(
+            offset + data.len() <= self.len,
+            "Write past the end of a memory area"
+        );
+
+        let mut written = 0;
+        while written < data.len() {
+            let current = offset + written;
+            let page_offset = current % PAGE_SIZE as usize;
+            let n = min(PAGE_SIZE as usize - page_offset, data.len() - written);
+            // Copies the page if it is shared with a snapshot
+            Rc::make_mut(&mut self.pages[current / PAGE_SIZE as usize])
+                [page_offset..page_offset + n]
+                .copy_from_slice(&data[written..written + n]);
+            written += n;
+        }
+    }
+
+    // Grows or shrinks the area to `len` bytes, new bytes are zero
+    pub(crate) fn resize(&mut self, len: usize) {
+        if len > self.len {
+            let page_offset = self.len % PAGE_SIZE as usize;
+            if page_offset != 0 {
+                let last = self.pages.last_mut().unwrap();
+                if last[page_offset..].iter().any(|&b| b != 0) {
+                    Rc::make_mut(last)[page_offset..].fill(0);
+                }
+            }
+
+            let zero = Rc::new([0; PAGE_SIZE as usize]);
+            self.pages.resize(len.div_ceil(PAGE_SIZE as usize), zero);
+        } else {
+            self.pages.truncate(len.div_ceil(PAGE_SIZE as usize));
+        }
+
+        self.len = len;
+    }
+
+    // Splits off the bytes after `offset` into a new area. Pages are only shared if `offset` is page-aligned
+    pub(crate) fn split_off(&mut self, offset: usize) -> AreaData {
+        let tail = if offset.is_multiple_of(PAGE_SIZE as usize) {
+            AreaData {
+                len: self.len - offset,
+                pages: self.pages.split_off(offset / PAGE_SIZE as usize),
+            }
+        } else {
+            AreaData::from(self.read(offset, self.len - offset))
+        };
+
+        self.resize(offset);
+
+        tail
+    }
+
+    // Ranges of (offset, new bytes) in which this area differs from `old`, which must have the same length.
+    // Pages that are still shared with `old` are not compared
+    pub(crate) fn changes_since(&self, old: &AreaData) -> Vec<(usize, Vec<u8>)> {
+        let mut changes = Vec::new();
+        for (i, (new, old)) in self.pages.iter().zip(old.pages.iter()).enumerate() {
+            if Rc::ptr_eq(new, old) {
+                continue;
+            }
+
+            let end = min(PAGE_SIZE as usize, self.len - i * PAGE_SIZE as usize);
+            let differs = |j: &usize| new[*j] != old[*j];
+            if let Some(first) = (0..end).find(differs) {
+                let last = (0..end).rev().find(differs).unwrap();
+                changes.push((i * PAGE_SIZE as usize + first, new[first..=last].to_vec()));
+            }
+        }
+        changes
+    }
+
+    #[cfg(any(test, target_arch = "wasm32"))]
+    // Shares the pages whose contents are the same as in `old` again, e.g. after the area was rebuilt from a serialized copy
+    pub(crate) fn share_unchanged(&mut self, old: &AreaData) {
+        for (i, (new, old)) in self.pages.iter_mut().zip(old.pages.iter()).enumerate() {
+            let end = min(PAGE_SIZE as usize, self.len - i * PAGE_SIZE as usize);
+            if !Rc::ptr_eq(new, old) && new[..end] == old[..end] {
+                *new = old.clone();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn shares_pages_with(&self, other: &AreaData) -> usize {
+        self.pages
+            .iter()
+            .zip(other.pages.iter())
+            .filter(|(a, b)| Rc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+impl From<Vec<u8>> for AreaData {
+    fn from(data: Vec<u8>) -> Self {
+        AreaData {
+            len: data.len(),
+            pages: data
+                .chunks(PAGE_SIZE as usize)
+                .map(|chunk| {
+                    let mut page = [0; PAGE_SIZE as usize];
+                    page[..chunk.len()].copy_from_slice(chunk);
+                    Rc::new(page)
+                })
+                .collect(),
+        }
+    }
+}
+
+// Serialized form of AreaData that leaves out pages containing only zeroes
+#[derive(Serialize, Deserialize)]
+struct SparseAreaData {
+    len: u64,
+    // Index and contents of the pages that contain non-zero bytes
+    pages: Vec<(u64, Vec<u8>)>,
+}
+
+impl From<AreaData> for SparseAreaData {
+    fn from(data: AreaData) -> Self {
+        let pages = data
+            .pages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, page)| {
+                let bytes = &page[..min(PAGE_SIZE as usize, data.len - i * PAGE_SIZE as usize)];
+                bytes
+                    .iter()
+                    .any(|&b| b != 0)
+                    .then(|| (i as u64, bytes.to_vec()))
+            })
+            .collect();
+
+        SparseAreaData {
+            len: data.len as u64,
+            pages,
+        }
+    }
+}
+
+impl TryFrom<SparseAreaData> for AreaData {
+    type Error = String;
+
+    fn try_from(sparse: SparseAreaData) -> Result<Self, Self::Error> {
+        let mut data = AreaData::zeroed(sparse.len as usize);
+        for (index, bytes) in sparse.pages {
+            let offset = index
+                .checked_mul(PAGE_SIZE)
+                .filter(|&offset| {
+                    bytes.len() as u64 <= PAGE_SIZE
+                        && offset
+                            .checked_add(bytes.len() as u64)
+                            .is_some_and(|end| end <= sparse.len)
+                })
+                .ok_or_else(|| format!("page {index} is outside of memory area"))?;
+            data.write(offset as usize, &bytes);
+        }
+
+        Ok(data)
+    }
+}
+
+// Serialized form of MemoryArea. Saved states may come from anywhere, so areas are only created after all of them were checked
+#[derive(Deserialize)]
+struct SavedMemoryArea {
     name: Option<String>,
     start: u64,
     length: u64,
-    data: Vec<u8>,
+    data: SparseAreaData,
     access: u32,
 }
 
+// Zero pages are not stored, so without a limit a few bytes of saved state could request terabytes of memory.
+// Lengths must also fit into usize, which is 32 bits wide on wasm32
+const MAX_LOADED_MEMORY: u64 = if usize::BITS < 64 {
+    usize::MAX as u64
+} else {
+    1 << 36
+};
+
+fn check_saved_areas(areas: &[SavedMemoryArea]) -> Result<(), String> {
+    let mut ranges = Vec::with_capacity(areas.len());
+    let mut total = 0u64;
+    for area in areas {
+        if area.data.len != area.length {
+            return Err(format!(
+                "memory area at {:#x} has a length of {:#x}, but {:#x} bytes of data",
+                area.start, area.length, area.data.len
+            ));
+        }
+        let end = area.start.checked_add(area.length).ok_or_else(|| {
+            format!(
+                "memory area at {:#x} with length {:#x} wraps around the address space",
+                area.start, area.length
+            )
+        })?;
+        ranges.push((area.start, end));
+        total = total.saturating_add(area.length);
+    }
+
+    if total > MAX_LOADED_MEMORY {
+        return Err(format!(
+            "memory areas have {total:#x} bytes in total, more than the limit of {MAX_LOADED_MEMORY:#x}"
+        ));
+    }
+
+    ranges.sort_unstable();
+    if let Some(pair) = ranges.windows(2).find(|pair| pair[0].1 > pair[1].0) {
+        return Err(format!(
+            "memory areas at {:#x} and {:#x} overlap",
+            pair[0].0, pair[1].0
+        ));
+    }
+
+    Ok(())
+}
+
+// Deserializes the memory areas of a saved state, failing if they are inconsistent
+pub(crate) fn deserialize_memory_areas<'de, D>(deserializer: D) -> Result<Vec<MemoryArea>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let areas = Vec::<SavedMemoryArea>::deserialize(deserializer)?;
+    check_saved_areas(&areas).map_err(de::Error::custom)?;
+
+    areas
+        .into_iter()
+        .map(|area| {
+            Ok(MemoryArea {
+                name: area.name,
+                start: area.start,
+                length: area.length,
+                data: AreaData::try_from(area.data).map_err(de::Error::custom)?,
+                access: area.access,
+            })
+        })
+        .collect()
+}
+
+impl fmt::Debug for AreaData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AreaData")
+            .field("len", &self.len)
+            .field("pages", &self.pages.len())
+            .finish()
+    }
+}
+
 impl MemoryArea {
     pub fn len(&self) -> u64 {
         self.length
@@ -98,11 +419,17 @@ impl MemoryArea {
 
         const MAX_LEN: usize = 255;
 
+        let data = if self.data.len() < MAX_LEN {
+            self.data.to_vec()
+        } else {
+            Vec::new()
+        };
+
         if self.data.len() < MAX_LEN {
-            for (i, byte) in self.data.iter().enumerate() {
+            for (i, byte) in data.iter().enumerate() {
                 s.push_str(&format!("0x{byte:02x}"));
 
-                if i != self.data.len() - 1 {
+                if i != data.len() - 1 {
                     s.push_str(", ");
                 }
             }
@@ -113,15 +440,11 @@ impl MemoryArea {
         s.push_str("],\n");
 
         // Add a string-representation if all bytes are ascii bytes and the last byte is zero
-        if !self.data.is_empty()
-            && self.data.len() < MAX_LEN
-            && self.data[self.data.len() - 1] == 0
-            && self.data.iter().all(|b| b.is_ascii())
-        {
+        if !data.is_empty() && data[data.len() - 1] == 0 && data.iter().all(|b| b.is_ascii()) {
             s.push_str(&format!(
                 "{}    string: {:?},\n",
                 " ".repeat(i * 4),
-                String::from_utf8_lossy(&self.data[..self.data.len() - 1])
+                String::from_utf8_lossy(&data[..data.len() - 1])
             ));
         }
 
@@ -133,8 +456,8 @@ impl MemoryArea {
 
 #[wasm_bindgen]
 impl Axecutor {
-    // TODO: Currently cannot read consecutive sections of memory
-    /// Reads `length` bytes from memory at `address`
+    /// Reads `length` bytes from memory at `address`.
+    /// The range may span multiple memory areas as long as they are directly adjacent.
     pub fn mem_read_bytes(&self, address: u64, length: u64) -> Result<Vec<u8>, AxError> {
         debug_log!(
             "Calling Axecutor::mem_read_bytes, address={:#x}, length={}",
@@ -142,63 +465,54 @@ impl Axecutor {
             length
         );
 
-        let area = self
-            .state
-            .memory
-            .iter()
-            .find(|area| {
-                // Start address is in range of memory area
-                area.start <= address && address < area.start + area.length
-            })
-            .ok_or_else(|| self.collect_mem_error_hints(address, length, "Read".to_string()))?;
-
-        // Make sure it's in range before doing the slice access below
-        if address + length > area.start + area.length {
-            return Err(self.collect_mem_error_hints(address, length, "Read".to_string()));
-        }
+        let parts = self.mem_range_parts(address, length, MemoryAccessKind::Read, "Read")?;
 
-        if area.access & PROT_READ == 0 {
-            return Err(AxError::from(format!(
-                "Cannot read {} bytes from memory area{} @ {:#x}, access is {}",
-                length,
-                match &area.name {
-                    Some(name) => format!(" {name}"),
-                    None => String::new(),
-                },
-                address,
-                access_to_string(area.access)
-            )));
+        for &(idx, offset, _) in &parts {
+            let area = &self.state.memory[idx];
+            if area.access & PROT_READ == 0 {
+                return Err(self.access_violation(
+                    idx,
+                    address,
+                    length,
+                    area.start + offset,
+                    MemoryAccessKind::Read,
+                    "Read",
+                ));
+            }
         }
 
-        let offset = (address - area.start) as usize;
-        let slice = &area.data[offset..offset + length as usize];
+        self.sanitize_read(address, length)?;
+        self.taint_read(address, length);
 
-        let result = slice.to_vec();
+        let mut result = self.mem_read_parts(&parts);
+        if let Some(data) = self.run_memory_hooks(MemoryAccessKind::Read, address, &result)? {
+            result = data;
+        }
 
         #[cfg(debug_assertions)]
         if result.len() <= 100 {
             debug_log!(
                 "Read from memory area{}, start={:#x}, area_length={}, read={:?}{}",
-                match &area.name {
+                match &self.state.memory[parts[0].0].name {
                     Some(name) => format!(" {name}"),
                     None => String::new(),
                 },
-                area.start,
-                area.length,
+                self.state.memory[parts[0].0].start,
+                self.state.memory[parts[0].0].length,
                 result,
                 match result.len() {
                     1 => format!(", formatted=0x{:02x}", result[0]),
                     2 => format!(
                         ", formatted=0x{:04x}",
-                        u16::from_le_bytes(slice.try_into().unwrap())
+                        u16::from_le_bytes(result[..].try_into().unwrap())
                     ),
                     4 => format!(
                         ", formatted=0x{:08x}",
-                        u32::from_le_bytes(slice.try_into().unwrap())
+                        u32::from_le_bytes(result[..].try_into().unwrap())
                     ),
                     8 => format!(
                         ", formatted=0x{:016x}",
-                        u64::from_le_bytes(slice.try_into().unwrap())
+                        u64::from_le_bytes(result[..].try_into().unwrap())
                     ),
                     _ => "".to_string(),
                 }
@@ -207,12 +521,12 @@ impl Axecutor {
             // Only log the first 50 and last 50 bytes of the memory area, with "<too much data to display>" in the middle
             debug_log!(
                         "Read from memory area{}, start={:#x}, length={}, read=[{:?}, <too much data to display>, {:?}]",
-                        match &area.name {
+                        match &self.state.memory[parts[0].0].name {
                             Some(name) => format!(" {name}"),
                             None => String::new(),
                         },
-                        area.start,
-                        area.length,
+                        self.state.memory[parts[0].0].start,
+                        self.state.memory[parts[0].0].length,
                         &result[0..50],
                         &result[result.len() - 50..]
                     );
@@ -223,38 +537,142 @@ impl Axecutor {
 
     pub(crate) fn mem_read_executable_bytes(&self, address: u64) -> Result<Vec<u8>, AxError> {
         // TODO: Optimize these reads by caching a reference to the last section we used?
-        let area = self
+        let idx = self
             .state
             .memory
             .iter()
-            .find(|area| area.start <= address && address < area.start + area.length)
+            .position(|area| area.start <= address && address < area.start + area.length)
             .ok_or_else(|| {
                 self.collect_mem_error_hints(address, 15, "Read executable".to_string())
+                    .with_kind(ErrorKind::MemoryUnmapped {
+                        addr: address,
+                        len: 15,
+                        op: MemoryAccessKind::Execute,
+                    })
+                    .with_exception(memory_exception(address, MemoryAccessKind::Execute, false))
             })?;
 
-        if area.access & PROT_EXEC == 0 {
-            return Err(AxError::from(format!(
-                "Cannot read executable bytes from memory area{} @ {:#x}, access is {} (does not include PROT_EXEC)",
-                match &area.name {
-                    Some(name) => format!(" {name}"),
-                    None => String::new(),
-                },
+        if self.state.memory[idx].access & PROT_EXEC == 0 {
+            return Err(self.access_violation(
+                idx,
                 address,
-                access_to_string(area.access)
-            )));
+                1,
+                address,
+                MemoryAccessKind::Execute,
+                "Read executable",
+            ));
         }
 
-        // Read up to 15 bytes, but only as many as are available in the memory area
-        // Since we use min, we don't need a range check
-
-        let offset = (address - area.start) as usize;
-        let slice = &area.data[offset..min(offset + 15, area.data.len())];
+        // Read up to 15 bytes, but only as many as are available in executable memory.
+        // An instruction may continue in a directly adjacent executable area, e.g. after mprotect split a segment
+        let mut result = Vec::with_capacity(15);
+        let mut current = address;
+        while result.len() < 15 {
+            let area = match self.state.memory.iter().find(|area| {
+                area.start <= current
+                    && current < area.start + area.length
+                    && area.access & PROT_EXEC != 0
+            }) {
+                Some(area) => area,
+                None => break,
+            };
 
-        let result = slice.to_vec();
+            let offset = (current - area.start) as usize;
+            let n = min(15 - result.len(), area.data.len() - offset);
+            area.data.read_into(offset, n, &mut result);
+            current += n as u64;
+        }
 
         Ok(result)
     }
 
+    // Splits the range of `length` bytes at `address` into parts that each lie within a single memory area.
+    // Each part is returned as (area index, offset into area, length). The areas must be directly adjacent.
+    fn mem_range_parts(
+        &self,
+        address: u64,
+        length: u64,
+        access: MemoryAccessKind,
+        operation: &str,
+    ) -> Result<Vec<(usize, u64, u64)>, AxError> {
+        let end = address.checked_add(length).ok_or_else(|| {
+            AxError::from(format!(
+                "Memory {} of length {} at address {:#x} overflows the address space",
+                operation.to_lowercase(),
+                length,
+                address
+            ))
+            .with_kind(ErrorKind::MemoryUnmapped {
+                addr: address,
+                len: length,
+                op: access,
+            })
+        })?;
+
+        let mut parts = Vec::new();
+        let mut current = address;
+        loop {
+            let idx = self
+                .state
+                .memory
+                .iter()
+                .position(|area| area.start <= current && current < area.start + area.length)
+                .ok_or_else(|| {
+                    self.collect_mem_error_hints(address, length, operation.to_string())
+                        .with_kind(ErrorKind::MemoryUnmapped {
+                            addr: address,
+                            len: length,
+                            op: access,
+                        })
+                        .with_exception(memory_exception(current, access, false))
+                })?;
+
+            let area = &self.state.memory[idx];
+            let part_end = min(end, area.start + area.length);
+            parts.push((idx, current - area.start, part_end - current));
+
+            current = part_end;
+            if current >= end {
+                break;
+            }
+        }
+
+        Ok(parts)
+    }
+
+    // Creates a precise error for an access to `fault_address` that is not allowed by the protection of memory area `idx`
+    fn access_violation(
+        &self,
+        idx: usize,
+        address: u64,
+        length: u64,
+        fault_address: u64,
+        access: MemoryAccessKind,
+        operation: &str,
+    ) -> AxError {
+        let area = &self.state.memory[idx];
+        AxError::from(format!(
+            "Access violation: {} of {} bytes at address {:#x} faulted at {:#x} in memory area {} (start {:#x}, length {:#x}), access is {}",
+            operation.to_lowercase(),
+            length,
+            address,
+            fault_address,
+            match &area.name {
+                Some(name) => name,
+                None => "<unnamed>",
+            },
+            area.start,
+            area.length,
+            access_to_string(area.access)
+        ))
+        .with_kind(ErrorKind::ProtectionViolation {
+            addr: fault_address,
+            len: length,
+            op: access,
+        })
+        .with_exception(memory_exception(fault_address, access, true))
+    }
+
     fn collect_mem_error_hints(&self, address: u64, length: u64, operation: String) -> AxError {
         // check if start or end address is within any of the memory areas
         for area in &self.state.memory {
@@ -344,19 +762,8 @@ impl Axecutor {
         Ok(bytes[0] as u64)
     }
 
-    pub(crate) fn mem_get_area(&self, start_addr: u64) -> Option<MemoryArea> {
-        for area in &self.state.memory {
-            if start_addr == area.start {
-                return Some(area.clone());
-            }
-        }
-
-        None
-    }
-
-    // TODO: Currently cannot write consecutive sections of memory
-    // It would also make sense to give better error messages, e.g. if the write start address is within an area, but the data is too long
-    /// Writes bytes of `data` to memory at `address`
+    /// Writes bytes of `data` to memory at `address`.
+    /// The range may span multiple memory areas as long as they are directly adjacent.
     pub fn mem_write_bytes(&mut self, address: u64, data: &[u8]) -> Result<(), AxError> {
         debug_log!(
             "Calling Axecutor::mem_write_bytes, address={:#x}, data_len={:?}",
@@ -364,53 +771,41 @@ impl Axecutor {
             data.len()
         );
 
-        let area = match self
-            .state
-            .memory
-            .iter_mut()
-            .find(|area| area.start <= address && address < area.start + area.length)
-        {
-            Some(area) => area,
-            None => {
-                return Err(self.collect_mem_error_hints(
+        let parts =
+            self.mem_range_parts(address, data.len() as u64, MemoryAccessKind::Write, "Write")?;
+
+        for &(idx, offset, _) in &parts {
+            let area = &self.state.memory[idx];
+            if area.access & PROT_WRITE == 0 {
+                return Err(self.access_violation(
+                    idx,
                     address,
                     data.len() as u64,
-                    "Write".to_string(),
-                ))
+                    area.start + offset,
+                    MemoryAccessKind::Write,
+                    "Write",
+                ));
             }
-        };
-
-        // Range check before doing the copy_from_slice below
-        if address + data.len() as u64 > area.start + area.length {
-            return Err(self.collect_mem_error_hints(
-                address,
-                data.len() as u64,
-                "Write".to_string(),
-            ));
         }
 
-        if area.access & PROT_WRITE == 0 {
-            return Err(AxError::from(format!(
-                "Cannot write {} bytes to memory area{} @ {:#x}, access is {}",
-                data.len(),
-                match &area.name {
-                    Some(name) => format!(" {name}"),
-                    None => String::new(),
-                },
-                address,
-                access_to_string(area.access)
-            )));
-        }
+        self.sanitize_write(address, data.len() as u64)?;
 
-        let offset = (address - area.start) as usize;
-        area.data[offset..offset + data.len()].copy_from_slice(data);
+        let replaced = self.run_memory_hooks(MemoryAccessKind::Write, address, data)?;
+        let written = replaced.as_deref().unwrap_or(data);
+        if self.instruction_trace_active() {
+            let old = self.mem_read_parts(&parts);
+            self.instruction_trace_memory(address, old, written);
+        }
+        self.mem_write_parts(&parts, written);
+        self.sanitizer_written(address, written.len() as u64);
+        self.taint_written(address, written.len() as u64);
 
         #[cfg(debug_assertions)]
         if data.len() <= 100 {
             debug_log!(
                 "Wrote to memory area, start={:#x}, length={}, wrote={:?}{}",
-                area.start,
-                area.length,
+                self.state.memory[parts[0].0].start,
+                self.state.memory[parts[0].0].length,
                 data,
                 match data.len() {
                     1 => format!(", formatted=0x{:02x}", data[0]),
@@ -433,8 +828,8 @@ impl Axecutor {
             // Only log the first 50 and last 50 bytes of data, with "<too much data to display>" in the middle
             debug_log!(
                         "Wrote to memory area, start={:#x}, length={}, wrote=[{:?}, <too much data to display>, {:?}]",
-                        area.start,
-                        area.length,
+                        self.state.memory[parts[0].0].start,
+                        self.state.memory[parts[0].0].length,
                         &data[0..50],
                         &data[data.len() - 50..]
                     );
@@ -443,6 +838,45 @@ impl Axecutor {
         Ok(())
     }
 
+    fn mem_read_parts(&self, parts: &[(usize, u64, u64)]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(parts.iter().map(|p| p.2 as usize).sum());
+        for &(idx, offset, len) in parts {
+            let offset = offset as usize;
+            self.state.memory[idx]
+                .data
+                .read_into(offset, len as usize, &mut result);
+        }
+        result
+    }
+
+    /// Reads memory like a debugger would, ignoring the access permissions of the memory areas.
+    pub(crate) fn mem_peek_bytes(&self, address: u64, length: u64) -> Result<Vec<u8>, AxError> {
+        let parts = self.mem_range_parts(address, length, MemoryAccessKind::Read, "Peek")?;
+        Ok(self.mem_read_parts(&parts))
+    }
+
+    fn mem_write_parts(&mut self, parts: &[(usize, u64, u64)], data: &[u8]) {
+        let mut written = 0;
+        for &(idx, offset, len) in parts {
+            let (offset, len) = (offset as usize, len as usize);
+            self.state.memory[idx]
+                .data
+                .write(offset, &data[written..written + len]);
+            written += len;
+        }
+    }
+
+    /// Writes memory like a debugger would, ignoring the access permissions of the memory areas.
+    pub(crate) fn mem_poke_bytes(&mut self, address: u64, data: &[u8]) -> Result<(), AxError> {
+        let parts =
+            self.mem_range_parts(address, data.len() as u64, MemoryAccessKind::Write, "Poke")?;
+        self.mem_write_parts(&parts, data);
+        self.sanitizer_written(address, data.len() as u64);
+        self.taint_written(address, data.len() as u64);
+
+        Ok(())
+    }
+
     /// Writes a 64-bit value to memory at `address`
     pub fn mem_write_64(&mut self, address: u64, data: u64) -> Result<(), AxError> {
         self.mem_write_bytes(address, &data.to_le_bytes())
@@ -509,15 +943,7 @@ impl Axecutor {
 
         if let Some(i) = area_to_resize {
             // Resize the area -- this works for both shrinking and growing
-            let mut new_data = vec![0; new_size as usize];
-            let old_data = &self.state.memory[i].data;
-
-            // Copy the old data into the new data
-            let copy_len = std::cmp::min(old_data.len(), new_data.len());
-            new_data[..copy_len].copy_from_slice(&old_data[..copy_len]);
-
-            // Update the area
-            self.state.memory[i].data = new_data;
+            self.state.memory[i].data.resize(new_size as usize);
             self.state.memory[i].length = new_size;
 
             return Ok(());
@@ -535,6 +961,15 @@ impl Axecutor {
         start: u64,
         data: Vec<u8>,
         name: Option<String>,
+    ) -> Result<(), AxError> {
+        self.mem_init_area_data(start, AreaData::from(data), name)
+    }
+
+    pub(crate) fn mem_init_area_data(
+        &mut self,
+        start: u64,
+        data: AreaData,
+        name: Option<String>,
     ) -> Result<(), AxError> {
         for area in &self.state.memory {
             if start >= area.start && start < area.start + area.length {
@@ -615,13 +1050,82 @@ impl Axecutor {
         )))
     }
 
+    /// Set the access permissions of all memory in the range of `length` bytes starting at `start`.
+    /// Unlike `mem_prot`, the range does not need to match memory area boundaries; areas are split as necessary.
+    /// The whole range must be mapped, otherwise no permissions are changed.
+    pub fn mem_prot_range(&mut self, start: u64, length: u64, prot: u32) -> Result<(), AxError> {
+        assert_fatal!(
+            prot <= 7,
+            "Invalid access permissions {:#x} for memory range, must be a bitmask of PROT_READ (1), PROT_WRITE (2), and PROT_EXEC (4)",
+            prot
+        );
+
+        debug_log!(
+            "Calling Axecutor::mem_prot_range, start={:#x}, length={:#x}, prot={}",
+            start,
+            length,
+            access_to_string(prot)
+        );
+
+        // Make sure the whole range is mapped before changing anything
+        self.mem_range_parts(start, length, MemoryAccessKind::Read, "Protect")?;
+
+        self.mem_split_area_at(start);
+        self.mem_split_area_at(start + length);
+
+        for area in &mut self.state.memory {
+            if area.start >= start && area.start + area.length <= start + length {
+                area.access = prot;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Splits the memory area containing `address` so that a separate area starts exactly at `address`.
+    // Both parts keep the name and access rights of the original area.
+    pub(crate) fn mem_split_area_at(&mut self, address: u64) {
+        let idx = match self
+            .state
+            .memory
+            .iter()
+            .position(|area| area.start < address && address < area.start + area.length)
+        {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        let area = &mut self.state.memory[idx];
+        let offset = address - area.start;
+
+        let tail = MemoryArea {
+            name: area.name.clone(),
+            start: address,
+            length: area.length - offset,
+            data: area.data.split_off(offset as usize),
+            access: area.access,
+        };
+        area.length = offset;
+
+        debug_log!(
+            "Split memory area{} at {:#x}",
+            match &tail.name {
+                Some(name) => format!(" {name}"),
+                None => String::new(),
+            },
+            address
+        );
+
+        self.state.memory.insert(idx + 1, tail);
+    }
+
     /// Initialize a memory area with the given data.
     pub fn mem_init_area(&mut self, start: u64, data: Vec<u8>) -> Result<(), AxError> {
         self.mem_init_area_named(start, data, None)
     }
     /// Initialize a memory area with the given length.
     pub fn mem_init_zero(&mut self, start: u64, length: u64) -> Result<(), AxError> {
-        self.mem_init_area_named(start, vec![0; length as usize], None)
+        self.mem_init_area_data(start, AreaData::zeroed(length as usize), None)
     }
 
     /// Initialize a memory area with the given length and name.
@@ -631,7 +1135,7 @@ impl Axecutor {
         length: u64,
         name: String,
     ) -> Result<(), AxError> {
-        self.mem_init_area_named(start, vec![0; length as usize], Some(name))
+        self.mem_init_area_data(start, AreaData::zeroed(length as usize), Some(name))
     }
 
     /// Initialize a memory area of the given length at a random address.
@@ -899,3 +1403,78 @@ impl Axecutor {
         self.internal_mem_read_128(address)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+
+    test_async![saved_areas_are_checked; async {
+        let area = |start: u64, length: u64| SavedMemoryArea {
+            name: None,
+            start,
+            length,
+            data: SparseAreaData { len: length, pages: Vec::new() },
+            access: PROT_READ,
+        };
+
+        check_saved_areas(&[area(0x1000, 0x1000), area(0x2000, 0x1000)]).unwrap();
+        check_saved_areas(&[area(0x2000, 0x1000), area(0x1000, 0x1001)]).expect_err("Overlap");
+        check_saved_areas(&[area(u64::MAX - 0xfff, 0x2000)]).expect_err("Wraps around");
+        check_saved_areas(&[area(0, 1 << 40)]).expect_err("Too large");
+        check_saved_areas(&[area(0, 1 << 35), area(1 << 40, 1 << 35), area(1 << 41, 1 << 35)]).expect_err("Too large in total");
+    }];
+
+    test_async![read_write_across_adjacent_areas; async {
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 0x10).expect("Failed to init memory");
+        ax.mem_init_zero(0x2010, 0x10).expect("Failed to init memory");
+
+        ax.mem_write_64(0x200c, 0x1122334455667788).expect("Failed to write across areas");
+        assert_eq!(ax.mem_read_64(0x200c).expect("Failed to read across areas"), 0x1122334455667788);
+        assert_eq!(ax.mem_read_32(0x2010).expect("Failed to read second area"), 0x11223344);
+
+        // There is no area after 0x2020
+        ax.mem_read_64(0x201c).expect_err("Read over the end of memory should fail");
+    }];
+
+    test_async![prot_range_splits_areas; async {
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero_named(0x4000, 3 * PAGE_SIZE, "data".to_string()).expect("Failed to init memory");
+
+        ax.mem_prot_range(0x5000, PAGE_SIZE, PROT_READ).expect("Failed to protect range");
+
+        ax.mem_write_8(0x4fff, 1).expect("First page should stay writable");
+        ax.mem_write_8(0x6000, 1).expect("Last page should stay writable");
+        let err = ax.mem_write_16(0x4fff, 0x102).expect_err("Write into protected page should fail");
+        assert!(
+            err.to_string().contains("Access violation: write of 2 bytes at address 0x4fff faulted at 0x5000 in memory area data"),
+            "Unexpected error: {err}"
+        );
+
+        assert_eq!(ax.mem_read_16(0x4fff).expect("Reads should still work"), 1);
+
+        // Ranges that aren't fully mapped are rejected without changing anything
+        ax.mem_prot_range(0x6000, 2 * PAGE_SIZE, PROT_NONE).expect_err("Protecting unmapped memory should fail");
+        ax.mem_write_8(0x6000, 2).expect("Last page should stay writable");
+    }];
+
+    test_async![area_data_pages; async {
+        let mut data = AreaData::from((0..3 * PAGE_SIZE).map(|i| i as u8).collect::<Vec<u8>>());
+        data.write(PAGE_SIZE as usize - 2, &[0xaa; 4]);
+        assert_eq!(data.read(PAGE_SIZE as usize - 3, 6), vec![0xfd, 0xaa, 0xaa, 0xaa, 0xaa, 0x02]);
+
+        // Splitting at page boundaries shares pages, other offsets copy the tail
+        let tail = data.split_off(2 * PAGE_SIZE as usize);
+        assert_eq!(tail.len(), PAGE_SIZE as usize);
+        assert_eq!(tail.read(0, 2), vec![0x00, 0x01]);
+        let tail = data.split_off(10);
+        assert_eq!(tail.len(), 2 * PAGE_SIZE as usize - 10);
+        assert_eq!(tail.read(0, 2), vec![10, 11]);
+
+        // Growing zeroes the new bytes, even if the last page held data before
+        data.resize(PAGE_SIZE as usize + 4);
+        assert_eq!(data.read(8, 4), vec![8, 9, 0, 0]);
+        assert_eq!(data.read(PAGE_SIZE as usize, 4), vec![0; 4]);
+    }];
+}
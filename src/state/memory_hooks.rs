@@ -0,0 +1,507 @@
+use std::error::Error;
+use std::fmt::{Debug, Formatter};
+
+use iced_x86::{Instruction, InstructionInfoFactory, OpAccess};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::state::hooks::{
+    call_native_hook, native_hook, sort_hooks, HookHandle, HookOrder, NativeHook,
+};
+use crate::state::memory::{PROT_EXEC, PROT_READ, PROT_WRITE};
+use crate::state::replay::{InputSource, InputValue};
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MemoryAccessKind {
+    Read,
+    Write,
+    /// Fetching an instruction for execution
+    Execute,
+}
+
+impl MemoryAccessKind {
+    // Memory hooks and watchpoints select access kinds using the same flags as memory protection
+    fn prot_flag(self) -> u32 {
+        match self {
+            MemoryAccessKind::Read => PROT_READ,
+            MemoryAccessKind::Write => PROT_WRITE,
+            MemoryAccessKind::Execute => PROT_EXEC,
+        }
+    }
+}
+
+/// A memory access by the emulated program, as passed to memory hooks
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u64,
+    pub kind: MemoryAccessKind,
+    /// The bytes read from memory, written to memory or fetched for execution
+    pub data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl MemoryAccess {
+    /// Number of bytes accessed
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    /// The accessed data as little-endian number, only meaningful for accesses of at most 8 bytes
+    pub fn value(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        let len = self.data.len().min(8);
+        bytes[..len].copy_from_slice(&self.data[..len]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// A watchpoint that stopped `run_until_breakpoint`
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// Start address of the watchpoint
+    pub watchpoint: u64,
+    /// Address of the access that triggered the watchpoint
+    pub address: u64,
+    pub size: u64,
+    pub kind: MemoryAccessKind,
+}
+
+// Memory hook function, returning Some(data) to replace the data that is read or written
+pub type MemoryHookFunction =
+    dyn FnMut(&Axecutor, &MemoryAccess) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+#[derive(Clone)]
+pub(crate) struct MemoryHook {
+    pub(crate) order: HookOrder,
+    start: u64,
+    length: u64,
+    kinds: u32,
+
+    native: Option<NativeHook<MemoryHookFunction>>,
+
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    js: Option<js_sys::Function>,
+}
+
+impl Debug for MemoryHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryHook")
+            .field("handle", &self.order.handle)
+            .field("priority", &self.order.priority)
+            .field("start", &self.start)
+            .field("length", &self.length)
+            .field("kinds", &self.kinds)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Watchpoint {
+    start: u64,
+    length: u64,
+    kinds: u32,
+}
+
+// Whether an instruction reads memory. The implementation of instructions that only write memory may still read it internally, e.g. mov to memory,
+// which is not a read by the program
+pub(crate) fn reads_memory(instr: &Instruction) -> bool {
+    InstructionInfoFactory::new()
+        .info(instr)
+        .used_memory()
+        .iter()
+        .any(|memory| {
+            matches!(
+                memory.access(),
+                OpAccess::Read | OpAccess::CondRead | OpAccess::ReadWrite | OpAccess::ReadCondWrite
+            )
+        })
+}
+
+fn overlaps(start: u64, length: u64, address: u64, size: u64) -> bool {
+    address < start.saturating_add(length) && start < address.saturating_add(size.max(1))
+}
+
+impl MemoryHook {
+    fn call(&self, ax: &Axecutor, access: &MemoryAccess) -> Result<Option<Vec<u8>>, AxError> {
+        if let Some(function) = &self.native {
+            return call_native_hook(function, |f| f(ax, access))?
+                .map_err(|e| AxError::from(format!("Memory hook failed: {e}")));
+        }
+
+        #[cfg(all(target_arch = "wasm32", not(test)))]
+        if let Some(function) = &self.js {
+            use wasm_bindgen::JsCast;
+
+            let result = function
+                .call2(
+                    &wasm_bindgen::JsValue::NULL,
+                    &ax.clone().into(),
+                    &access.clone().into(),
+                )
+                .map_err(|e| AxError::from(format!("Memory hook threw an error: {:?}", e)))?;
+            if result.is_undefined() || result.is_null() {
+                return Ok(None);
+            }
+            let array = result.dyn_into::<js_sys::Uint8Array>().map_err(|_| {
+                AxError::from("Memory hook must return undefined, null or a Uint8Array")
+            })?;
+            return Ok(Some(array.to_vec()));
+        }
+
+        Ok(None)
+    }
+}
+
+impl Axecutor {
+    pub(crate) fn has_memory_hooks(&self) -> bool {
+        !self.hooks.memory_hooks.is_empty() || !self.hooks.watchpoints.is_empty()
+    }
+
+    /// Runs memory hooks and checks watchpoints for an access by the emulated program.
+    /// Returns the data hooks want to be read or written instead, if any.
+    pub(crate) fn run_memory_hooks(
+        &self,
+        kind: MemoryAccessKind,
+        address: u64,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, AxError> {
+        // Memory accessed by the hooks themselves is not reported again
+        if !(self.has_memory_hooks() || self.is_replaying()) || self.hooks.in_memory_hook.get() {
+            return Ok(None);
+        }
+        // Only the emulated program's accesses are reported, instructions are fetched before they execute
+        if kind != MemoryAccessKind::Execute && !self.hooks.in_instruction {
+            return Ok(None);
+        }
+        if kind == MemoryAccessKind::Read && !self.hooks.instruction_reads {
+            return Ok(None);
+        }
+
+        let size = data.len() as u64;
+        let flag = kind.prot_flag();
+
+        if self.hooks.watchpoint_hit.get().is_none() {
+            if let Some(watchpoint) = self
+                .hooks
+                .watchpoints
+                .iter()
+                .find(|w| w.kinds & flag != 0 && overlaps(w.start, w.length, address, size))
+            {
+                debug_log!(
+                    "Watchpoint at {:#x} triggered by {:?} access at {:#x}",
+                    watchpoint.start,
+                    kind,
+                    address
+                );
+                self.hooks.watchpoint_hit.set(Some(WatchpointHit {
+                    watchpoint: watchpoint.start,
+                    address,
+                    size,
+                    kind,
+                }));
+            }
+        }
+
+        // Data replaced when executing code is ignored, so it is neither recorded nor replayed
+        if kind != MemoryAccessKind::Execute {
+            if let Some(data) = self.replay_memory_input(kind, address)? {
+                return Ok(data);
+            }
+        }
+
+        let mut access = MemoryAccess {
+            address,
+            kind,
+            data: data.to_vec(),
+        };
+        let mut replaced = false;
+
+        self.hooks.in_memory_hook.set(true);
+        let result = (|| {
+            for hook in &self.hooks.memory_hooks {
+                if hook.kinds & flag == 0 || !overlaps(hook.start, hook.length, address, size) {
+                    continue;
+                }
+
+                if let Some(new_data) = hook.call(self, &access)? {
+                    if new_data.len() != data.len() {
+                        return Err(AxError::from(format!(
+                            "Memory hook returned {} bytes for an access of {} bytes at {:#x}",
+                            new_data.len(),
+                            data.len(),
+                            address
+                        )));
+                    }
+                    // Later hooks see the data earlier hooks decided on
+                    access.data = new_data;
+                    replaced = true;
+                }
+            }
+            Ok(())
+        })();
+        self.hooks.in_memory_hook.set(false);
+        result.map_err(|e| e.with_kind(ErrorKind::HookError))?;
+
+        if replaced && kind != MemoryAccessKind::Execute {
+            self.record_value(
+                InputSource::MemoryHooks(kind, address),
+                InputValue::Data(access.data.clone()),
+            );
+            Ok(Some(access.data))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn check_hook_kinds(&self, kinds: u32) -> Result<(), AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot change memory hooks while another hook is running",
+            ));
+        }
+        if kinds == 0 || kinds & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+            return Err(AxError::from(format!(
+                "Invalid memory access kinds {kinds:#x}, expected a combination of PROT_READ, PROT_WRITE and PROT_EXEC"
+            )));
+        }
+        Ok(())
+    }
+
+    fn insert_memory_hook(&mut self, mut hook: MemoryHook) -> Result<HookHandle, AxError> {
+        self.check_hook_kinds(hook.kinds)?;
+        hook.order = self.hooks.next_order();
+        debug_log!("Adding memory hook {:?}", hook);
+        let handle = hook.order.handle;
+        self.hooks.memory_hooks.push(hook);
+        sort_hooks(&mut self.hooks.memory_hooks, |h| h.order);
+        Ok(handle)
+    }
+
+    /// Register a function that is called for every memory access of the emulated program overlapping `start..start+length`.
+    /// `kinds` selects the accesses using the `PROT_READ`, `PROT_WRITE` and `PROT_EXEC` flags.
+    /// The function can return `Some(data)` to replace the data that is read or written, e.g. to emulate memory-mapped devices.
+    /// Replacing fetched instruction bytes is not supported.
+    /// Hooks are called in the order described at `set_hook_priority`; memory accessed by hooks themselves does not trigger hooks.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_memory_native(
+        &mut self,
+        start: u64,
+        length: u64,
+        kinds: u32,
+        cb: impl FnMut(&Axecutor, &MemoryAccess) -> Result<Option<Vec<u8>>, Box<dyn Error>> + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.insert_memory_hook(MemoryHook {
+            order: HookOrder::default(),
+            start,
+            length,
+            kinds,
+            native: Some(native_hook(Box::new(cb))),
+            #[cfg(all(target_arch = "wasm32", not(test)))]
+            js: None,
+        })
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
+impl Axecutor {
+    /// Register a function that is called for every memory access of the emulated program overlapping `start..start+length`.
+    /// `kinds` selects the accesses using the `PROT_READ`, `PROT_WRITE` and `PROT_EXEC` flags.
+    /// The function is called synchronously with the Axecutor object and a MemoryAccess; changes to the Axecutor object are discarded.
+    /// It can return a Uint8Array of the same size to replace the data that is read or written, or undefined to keep it.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_memory(
+        &mut self,
+        start: u64,
+        length: u64,
+        kinds: u32,
+        cb: js_sys::Function,
+    ) -> Result<HookHandle, AxError> {
+        self.insert_memory_hook(MemoryHook {
+            order: HookOrder::default(),
+            start,
+            length,
+            kinds,
+            native: None,
+            js: Some(cb),
+        })
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Add a watchpoint that stops `run_until_breakpoint` after an instruction accessed memory overlapping `start..start+length`.
+    /// `kinds` selects the accesses using the `PROT_READ`, `PROT_WRITE` and `PROT_EXEC` flags.
+    /// Replaces any other watchpoint starting at the same address.
+    pub fn add_watchpoint(&mut self, start: u64, length: u64, kinds: u32) -> Result<(), AxError> {
+        self.check_hook_kinds(kinds)?;
+        self.hooks.watchpoints.retain(|w| w.start != start);
+        self.hooks.watchpoints.push(Watchpoint {
+            start,
+            length,
+            kinds,
+        });
+        Ok(())
+    }
+
+    /// Remove the watchpoint starting at `start`, returning whether there was one
+    pub fn remove_watchpoint(&mut self, start: u64) -> Result<bool, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot remove watchpoints while a hook is running",
+            ));
+        }
+
+        let count = self.hooks.watchpoints.len();
+        self.hooks.watchpoints.retain(|w| w.start != start);
+        Ok(self.hooks.watchpoints.len() != count)
+    }
+
+    /// The watchpoint triggered by the last executed instruction, e.g. the one that stopped `run_until_breakpoint`
+    pub fn watchpoint_hit(&self) -> Option<WatchpointHit> {
+        self.hooks.watchpoint_hit.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::{c_loop, test_async};
+    use crate::state::registers::SupportedRegister::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    test_async![override_read_value; async {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0x8b, 0x04, 0x25, 0x00, 0x20, 0x00, 0x00, // mov rax, [0x2000]
+                0x48, 0x89, 0x04, 0x25, 0x08, 0x20, 0x00, 0x00, // mov [0x2008], rax
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 0x10).expect("Failed to init memory");
+        ax.mem_write_64(0x2000, 0x1111).unwrap();
+
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let seen = accesses.clone();
+        ax.hook_memory_native(0x2000, 0x10, PROT_READ | PROT_WRITE, move |_, access| {
+            seen.borrow_mut().push((access.address, access.size(), access.value(), access.kind));
+            // A device register that always reads as 0x42
+            if access.kind == MemoryAccessKind::Read {
+                return Ok(Some(0x42u64.to_le_bytes().to_vec()));
+            }
+            Ok(None)
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 0x42);
+        // Accesses through the API are not hooked
+        assert_eq!(ax.mem_read_64(0x2000).unwrap(), 0x1111);
+        ax.mem_write_64(0x2008, 0x43).unwrap();
+        assert_eq!(
+            *accesses.borrow(),
+            vec![
+                (0x2000, 8, 0x1111, MemoryAccessKind::Read),
+                // The destination of mov is only written, so there is no read of it
+                (0x2008, 8, 0x42, MemoryAccessKind::Write),
+            ]
+        );
+    }];
+
+    test_async![execute_hook; async {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0xc7, 0xc0, 0x5, 0, 0, 0, // mov rax, 5
+                0x48, 0xff, 0xc0, // inc rax
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+
+        let fetches = Rc::new(RefCell::new(Vec::new()));
+        let seen = fetches.clone();
+        ax.hook_memory_native(0x1007, 1, PROT_EXEC, move |_, access| {
+            seen.borrow_mut().push((access.address, access.data.clone()));
+            Ok(None)
+        }).expect("Failed to add hook");
+
+        ax.execute().await.expect("Failed to execute");
+
+        assert_eq!(*fetches.borrow(), vec![(0x1007, vec![0x48, 0xff, 0xc0])]);
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 6);
+    }];
+
+    test_async![write_watchpoint; async {
+        let mut ax = c_loop();
+
+        // Run to the loop to know where the counter at [rbp-8] lives
+        ax.add_breakpoint(0x401036).unwrap();
+        ax.run_until_breakpoint().await.unwrap();
+        ax.remove_breakpoint(0x401036).unwrap();
+        let counter = ax.reg_read_64(RBP).unwrap() - 8;
+
+        ax.add_watchpoint(counter, 4, PROT_WRITE).unwrap();
+        // addl $0x1,-0x8(%rbp) writes the counter, the next instruction is the comparison
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x40103e));
+        assert_eq!(
+            ax.watchpoint_hit(),
+            Some(WatchpointHit { watchpoint: counter, address: counter, size: 4, kind: MemoryAccessKind::Write })
+        );
+        // Neither reading the counter through the API nor executing the comparison, which reads it, triggers the watchpoint
+        let value = ax.mem_read_32(counter).unwrap();
+        ax.mem_write_32(counter, value).unwrap();
+        ax.step().await.unwrap();
+        assert_eq!(ax.watchpoint_hit(), None);
+
+        assert!(ax.remove_watchpoint(counter).unwrap());
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), None);
+        assert_eq!(ax.watchpoint_hit(), None);
+    }];
+
+    test_async![read_watchpoint_ignores_write_only_destination; async {
+        let mut ax = Axecutor::new(
+            &[
+                0x48, 0x89, 0x04, 0x25, 0x00, 0x20, 0x00, 0x00, // mov [0x2000], rax
+                0x48, 0x8b, 0x1c, 0x25, 0x00, 0x20, 0x00, 0x00, // mov rbx, [0x2000]
+                0x90, // nop
+            ],
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 8).expect("Failed to init memory");
+
+        ax.add_watchpoint(0x2000, 8, PROT_READ).unwrap();
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x1010));
+        assert_eq!(
+            ax.watchpoint_hit(),
+            Some(WatchpointHit { watchpoint: 0x2000, address: 0x2000, size: 8, kind: MemoryAccessKind::Read })
+        );
+    }];
+
+    test_async![invalid_hook_result; async {
+        let mut ax = Axecutor::new(
+            &[0x48, 0x8b, 0x04, 0x25, 0x00, 0x20, 0x00, 0x00], // mov rax, [0x2000]
+            0x1000,
+            0x1000,
+        )
+        .expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 0x10).expect("Failed to init memory");
+
+        ax.hook_memory_native(0, u64::MAX, PROT_READ, |_, _| Ok(Some(vec![1]))).unwrap();
+        ax.hook_memory_native(0, u64::MAX, 0, |_, _| Ok(None)).expect_err("Kinds must not be empty");
+
+        let err = ax.execute().await.expect_err("Hook returned wrong size");
+        assert!(err.to_string().contains("Memory hook returned 1 bytes for an access of 8 bytes"), "{err}");
+    }];
+}
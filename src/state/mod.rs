@@ -1,5 +1,16 @@
+pub mod breakpoints;
+pub mod code_hooks;
+pub mod exceptions;
 pub mod execute;
 pub mod flags;
 pub mod hooks;
 pub mod memory;
+pub mod memory_hooks;
 pub mod registers;
+pub mod replay;
+pub mod reverse;
+pub mod sanitizer;
+pub mod session;
+pub mod shared_log;
+pub mod snapshots;
+pub mod taint;
@@ -670,6 +670,43 @@ impl Axecutor {
     }
 }
 
+// The emulator stores a pushed value before decrementing RSP (see push.rs), so its RSP is always 8 bytes below the one
+// a native process would have at the same point, e.g. the one the compiler assumed in call frame information.
+// The same goes for frame pointers copied from RSP. Values are at the same addresses in memory though,
+// so e.g. right after a call the return address is at RSP + 8 instead of RSP.
+// Code showing RSP to the outside or locating values on the stack should use the functions below.
+const NATIVE_RSP_OFFSET: u64 = 8;
+
+/// Converts a stack pointer of the emulator, or a frame pointer copied from it, to the one a native process would have
+pub(crate) fn to_native_rsp(rsp: u64) -> u64 {
+    rsp.wrapping_add(NATIVE_RSP_OFFSET)
+}
+
+/// Converts a stack pointer of a native process to the one the emulator uses
+pub(crate) fn from_native_rsp(rsp: u64) -> u64 {
+    rsp.wrapping_sub(NATIVE_RSP_OFFSET)
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Reads RSP as a native process would have it, which is 8 bytes higher than the value of the RSP register in the emulator.
+    pub fn native_rsp(&self) -> Result<u64, AxError> {
+        Ok(to_native_rsp(self.reg_read_64(SupportedRegister::RSP)?))
+    }
+
+    /// The address of the return address right after a call instruction or right before a return instruction.
+    pub fn return_address_slot(&self) -> Result<u64, AxError> {
+        self.native_rsp()
+    }
+}
+
+impl Axecutor {
+    /// Sets RSP from the value a native process would have
+    pub(crate) fn set_native_rsp(&mut self, value: u64) -> Result<(), AxError> {
+        self.reg_write_64(SupportedRegister::RSP, from_native_rsp(value))
+    }
+}
+
 #[wasm_bindgen]
 #[cfg(all(target_arch = "wasm32", not(test)))]
 impl Axecutor {
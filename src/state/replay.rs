@@ -0,0 +1,725 @@
+use std::collections::HashMap;
+
+use bincode::Options;
+use iced_x86::Instruction;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::auto::generated::SupportedMnemonic;
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::helpers::signals::SignalState;
+use crate::helpers::syscalls::SyscallState;
+use crate::state::exceptions::ExceptionKind;
+use crate::state::memory::{AreaData, MemoryArea};
+use crate::state::memory_hooks::MemoryAccessKind;
+use crate::state::registers::SupportedRegister;
+use crate::state::session::read_session;
+
+/// Version of the replay log format written by `stop_recording`
+pub const REPLAY_FORMAT_VERSION: u32 = 1;
+
+const REPLAY_MAGIC: &[u8; 8] = b"AXREPLAY";
+
+// Where a value from outside of the emulated program came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum InputSource {
+    CodeHooks,
+    Syscall,
+    BeforeHooks(SupportedMnemonic),
+    AfterHooks(SupportedMnemonic),
+    Exception(ExceptionKind),
+    MemoryHooks(MemoryAccessKind, u64),
+    Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum InputValue {
+    // Whether hooks handled the event, e.g. a syscall
+    Handled(bool),
+    // Data memory hooks replaced an access with
+    Data(Vec<u8>),
+    // Value of the timestamp counter
+    Counter(u64),
+}
+
+// Name, start, length and access of a memory area
+type AreaLayout = (Option<String>, u64, u64, u32);
+
+// Everything hooks changed about the machine state
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StateDelta {
+    registers: Vec<(SupportedRegister, u64)>,
+    xmm_registers: Vec<(SupportedRegister, u128)>,
+    rflags: Option<u64>,
+    fs: Option<u64>,
+    gs: Option<u64>,
+    // All memory areas, if hooks mapped, unmapped or protected memory
+    layout: Option<Vec<AreaLayout>>,
+    // Address and new contents of memory changed by hooks
+    memory: Vec<(u64, Vec<u8>)>,
+    syscalls: Option<SyscallState>,
+    signals: Option<SignalState>,
+    call_stack: Option<Vec<u64>>,
+    finished: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InputEvent {
+    // Number of executed instructions and RIP when the input happened
+    instruction: u64,
+    rip: u64,
+    source: InputSource,
+    value: InputValue,
+    delta: StateDelta,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ReplayLog {
+    // State when recording started, as written by `save_to_bytes`
+    initial_state: Vec<u8>,
+    events: Vec<InputEvent>,
+    // Instruction count and new RIP after every instruction that didn't continue with the next one, e.g. a taken jump.
+    // Execution in between is the same as long as the same inputs are replayed, so comparing these finds the first different instruction
+    branches: Vec<(u64, u64)>,
+    // Instruction count when recording stopped
+    instructions: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ReplayMode {
+    #[default]
+    Off,
+    Recording,
+    Replaying,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReplayState {
+    mode: ReplayMode,
+    log: ReplayLog,
+    // Index of the next event and branch to compare against when replaying
+    next_event: usize,
+    next_branch: usize,
+}
+
+// The parts of the machine state hooks can change, taken before running them
+pub(crate) struct StateView {
+    registers: HashMap<SupportedRegister, u64>,
+    xmm_registers: HashMap<SupportedRegister, u128>,
+    rflags: u64,
+    fs: u64,
+    gs: u64,
+    memory: Vec<MemoryArea>,
+    syscalls: SyscallState,
+    signals: SignalState,
+    call_stack: Vec<u64>,
+    finished: bool,
+    rip: u64,
+}
+
+fn layout(memory: &[MemoryArea]) -> Vec<AreaLayout> {
+    memory
+        .iter()
+        .map(|area| (area.name.clone(), area.start, area.length, area.access))
+        .collect()
+}
+
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+    (old != new).then(|| new.clone())
+}
+
+fn replay_divergence(instruction: u64, message: String) -> AxError {
+    AxError::from(format!(
+        "Replay diverged from the recording at instruction {instruction}: {message}"
+    ))
+    .with_kind(ErrorKind::ReplayDivergence { instruction })
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Starts recording all values that flow into the emulated program from outside, i.e. the effects of hooks and syscall handlers on registers and memory,
+    /// data replaced by memory hooks and values read by RDTSC. The current state is saved as the starting point of the recording.
+    /// Use `stop_recording` to get the replay log.
+    pub fn start_recording(&mut self) -> Result<(), AxError> {
+        if self.replay.borrow().mode != ReplayMode::Off {
+            return Err(AxError::from(
+                "Cannot start recording while already recording or replaying",
+            ));
+        }
+
+        let initial_state = self.save_to_bytes()?;
+        *self.replay.borrow_mut() = ReplayState {
+            mode: ReplayMode::Recording,
+            log: ReplayLog {
+                initial_state,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        debug_log!("Started recording");
+
+        Ok(())
+    }
+
+    /// Stops recording and returns the replay log, which can be passed to `start_replay`
+    pub fn stop_recording(&mut self) -> Result<Vec<u8>, AxError> {
+        let mut replay = self.replay.take();
+        if replay.mode != ReplayMode::Recording {
+            *self.replay.borrow_mut() = replay;
+            return Err(AxError::from("Cannot stop recording, nothing is recorded"));
+        }
+
+        replay.log.instructions = self.state.executed_instructions_count;
+        debug_log!(
+            "Stopped recording after {} instructions with {} inputs",
+            replay.log.instructions,
+            replay.log.events.len()
+        );
+
+        let mut bytes = Vec::from(&REPLAY_MAGIC[..]);
+        bytes.extend_from_slice(&REPLAY_FORMAT_VERSION.to_le_bytes());
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut bytes, &replay.log)
+            .map_err(|e| AxError::from(format!("Failed to serialize replay log: {e}")))?;
+
+        Ok(bytes)
+    }
+
+    /// Restores the state from the start of a recording made with `start_recording` and replays it:
+    /// instead of running hooks and syscall handlers, their recorded effects are applied, so execution is the same as when recording.
+    /// Execution stops with a `ReplayDivergence` error at the first difference, e.g. if other instructions are executed.
+    /// Once all recorded instructions have been executed, hooks run normally again.
+    pub fn start_replay(&mut self, log: &[u8]) -> Result<(), AxError> {
+        if self.hooks.running {
+            return Err(AxError::from("Cannot start replay while a hook is running"));
+        }
+        if self.replay.borrow().mode != ReplayMode::Off {
+            return Err(AxError::from(
+                "Cannot start replay while already recording or replaying",
+            ));
+        }
+
+        if log.len() < REPLAY_MAGIC.len() + 4 || &log[..REPLAY_MAGIC.len()] != REPLAY_MAGIC {
+            return Err(AxError::from("Cannot start replay: not a replay log"));
+        }
+        let (version, body) = log[REPLAY_MAGIC.len()..].split_at(4);
+        let version = u32::from_le_bytes(version.try_into().unwrap());
+        if version != REPLAY_FORMAT_VERSION {
+            return Err(AxError::from(format!(
+                "Cannot start replay: the log has format version {version}, but this version of ax only supports version {REPLAY_FORMAT_VERSION}"
+            )));
+        }
+        // Lengths in the log are not trusted to allocate more than the log itself could contain
+        let log: ReplayLog = bincode::DefaultOptions::new()
+            .with_limit(body.len() as u64)
+            .deserialize(body)
+            .map_err(|e| AxError::from(format!("Cannot start replay: {e}")))?;
+
+        let session = read_session(&log.initial_state)?;
+        let start = session.state.executed_instructions_count;
+        if log.instructions < start {
+            return Err(AxError::from(format!(
+                "Cannot start replay: the log ends at instruction {}, before its initial state at instruction {start}",
+                log.instructions
+            )));
+        }
+
+        self.apply_session(session);
+        debug_log!(
+            "Replaying {} instructions with {} inputs",
+            log.instructions.saturating_sub(start),
+            log.events.len()
+        );
+        *self.replay.borrow_mut() = ReplayState {
+            mode: ReplayMode::Replaying,
+            log,
+            ..Default::default()
+        };
+
+        Ok(())
+    }
+
+    /// Whether a replay started with `start_replay` is running
+    pub fn is_replaying(&self) -> bool {
+        self.replay.borrow().mode == ReplayMode::Replaying
+    }
+}
+
+impl Axecutor {
+    fn fail_replay(&self, message: String) -> AxError {
+        self.replay.take();
+        replay_divergence(self.state.executed_instructions_count, message)
+    }
+
+    pub(crate) fn replay_active(&self) -> bool {
+        self.replay.borrow().mode != ReplayMode::Off
+    }
+
+    // Takes a view of the state before hooks run, if recording
+    pub(crate) fn record_begin(&self) -> Option<Box<StateView>> {
+        if self.replay.borrow().mode != ReplayMode::Recording {
+            return None;
+        }
+
+        Some(Box::new(StateView {
+            registers: self.state.registers.clone(),
+            xmm_registers: self.state.xmm_registers.clone(),
+            rflags: self.state.rflags,
+            fs: self.state.fs,
+            gs: self.state.gs,
+            memory: self.state.memory.clone(),
+            syscalls: self.state.syscalls.clone(),
+            signals: self.state.signals.clone(),
+            call_stack: self.state.call_stack.clone(),
+            finished: self.state.finished,
+            rip: self.state.registers[&SupportedRegister::RIP],
+        }))
+    }
+
+    // Records what hooks changed since `record_begin` and the value they produced
+    pub(crate) fn record_end(
+        &self,
+        view: Option<Box<StateView>>,
+        source: InputSource,
+        value: InputValue,
+    ) {
+        let view = match view {
+            Some(view) => view,
+            None => return,
+        };
+
+        let state = &self.state;
+        let mut delta = StateDelta {
+            registers: state
+                .registers
+                .iter()
+                .filter(|(register, value)| view.registers.get(register) != Some(value))
+                .map(|(register, value)| (*register, *value))
+                .collect(),
+            xmm_registers: state
+                .xmm_registers
+                .iter()
+                .filter(|(register, value)| view.xmm_registers.get(register) != Some(value))
+                .map(|(register, value)| (*register, *value))
+                .collect(),
+            rflags: changed(&view.rflags, &state.rflags),
+            fs: changed(&view.fs, &state.fs),
+            gs: changed(&view.gs, &state.gs),
+            layout: changed(&layout(&view.memory), &layout(&state.memory)),
+            memory: Vec::new(),
+            syscalls: changed(&view.syscalls, &state.syscalls),
+            signals: changed(&view.signals, &state.signals),
+            call_stack: changed(&view.call_stack, &state.call_stack),
+            finished: changed(&view.finished, &state.finished),
+        };
+
+        for area in &state.memory {
+            let changes = match view
+                .memory
+                .iter()
+                .find(|old| old.start == area.start && old.length == area.length)
+            {
+                Some(old) => area.data.changes_since(&old.data),
+                // Memory that was mapped by hooks
+                None => area
+                    .data
+                    .changes_since(&AreaData::zeroed(area.length as usize)),
+            };
+            delta.memory.extend(
+                changes
+                    .into_iter()
+                    .map(|(offset, bytes)| (area.start + offset as u64, bytes)),
+            );
+        }
+
+        self.record_event(view.rip, source, value, delta);
+    }
+
+    // Records a value that doesn't come with state changes, e.g. data returned by memory hooks.
+    // Memory accessed by hooks themselves is part of the hook's state changes
+    pub(crate) fn record_value(&self, source: InputSource, value: InputValue) {
+        if self.replay.borrow().mode != ReplayMode::Recording || self.hooks.running {
+            return;
+        }
+
+        let rip = self.state.registers[&SupportedRegister::RIP];
+        self.record_event(rip, source, value, StateDelta::default());
+    }
+
+    fn record_event(&self, rip: u64, source: InputSource, value: InputValue, delta: StateDelta) {
+        self.replay.borrow_mut().log.events.push(InputEvent {
+            instruction: self.state.executed_instructions_count,
+            rip,
+            source,
+            value,
+            delta,
+        });
+    }
+
+    // When replaying, returns the next recorded input if it comes from `source` at the current instruction.
+    // Its state changes must be applied with `apply_replayed`
+    fn next_replayed(&self, source: InputSource) -> Result<Option<InputEvent>, AxError> {
+        let mut replay = self.replay.borrow_mut();
+        if replay.mode != ReplayMode::Replaying || self.hooks.running {
+            return Ok(None);
+        }
+
+        let event = match replay.log.events.get(replay.next_event) {
+            Some(event)
+                if event.source == source
+                    && event.instruction == self.state.executed_instructions_count =>
+            {
+                event.clone()
+            }
+            _ => return Ok(None),
+        };
+
+        let rip = self.state.registers[&SupportedRegister::RIP];
+        if event.rip != rip {
+            drop(replay);
+            return Err(self.fail_replay(format!(
+                "expected input from {:?} at {:#x}, but reached it at {:#x} after executing different instructions",
+                source, event.rip, rip
+            )));
+        }
+
+        replay.next_event += 1;
+        Ok(Some(event))
+    }
+
+    // When replaying, applies the state changes of the input recorded for `source` at this point instead of running hooks and returns its value.
+    // Returns None if not replaying, then hooks must run as usual
+    pub(crate) fn replay_input(
+        &mut self,
+        source: InputSource,
+    ) -> Result<Option<Option<InputValue>>, AxError> {
+        if !self.is_replaying() {
+            return Ok(None);
+        }
+
+        let event = match self.next_replayed(source)? {
+            Some(event) => event,
+            // No hooks ran at this point while recording
+            None => return Ok(Some(None)),
+        };
+
+        let delta = event.delta;
+        for (register, value) in delta.registers {
+            self.state.registers.insert(register, value);
+        }
+        for (register, value) in delta.xmm_registers {
+            self.state.xmm_registers.insert(register, value);
+        }
+        self.state.rflags = delta.rflags.unwrap_or(self.state.rflags);
+        self.state.fs = delta.fs.unwrap_or(self.state.fs);
+        self.state.gs = delta.gs.unwrap_or(self.state.gs);
+
+        if let Some(layout) = delta.layout {
+            let mut old = std::mem::take(&mut self.state.memory);
+            self.state.memory = layout
+                .into_iter()
+                .map(|(name, start, length, access)| {
+                    let data = match old
+                        .iter()
+                        .position(|area| area.start == start && area.length == length)
+                    {
+                        Some(i) => old.swap_remove(i).data,
+                        None => AreaData::zeroed(length as usize),
+                    };
+                    MemoryArea {
+                        name,
+                        start,
+                        length,
+                        data,
+                        access,
+                    }
+                })
+                .collect();
+        }
+        for (address, bytes) in delta.memory {
+            self.mem_poke_bytes(address, &bytes)?;
+        }
+
+        if let Some(syscalls) = delta.syscalls {
+            self.state.syscalls = syscalls;
+        }
+        if let Some(signals) = delta.signals {
+            self.state.signals = signals;
+        }
+        if let Some(call_stack) = delta.call_stack {
+            self.state.call_stack = call_stack;
+        }
+        self.state.finished = delta.finished.unwrap_or(self.state.finished);
+
+        Ok(Some(Some(event.value)))
+    }
+
+    // When replaying, returns the data memory hooks replaced this access with while recording, if any.
+    // Returns None if not replaying, then hooks must run as usual
+    pub(crate) fn replay_memory_input(
+        &self,
+        kind: MemoryAccessKind,
+        address: u64,
+    ) -> Result<Option<Option<Vec<u8>>>, AxError> {
+        if !self.is_replaying() || self.hooks.running {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            match self.next_replayed(InputSource::MemoryHooks(kind, address))? {
+                Some(InputEvent {
+                    value: InputValue::Data(data),
+                    ..
+                }) => Some(data),
+                _ => None,
+            },
+        ))
+    }
+
+    // Called before and after executing an instruction when replaying: makes sure no recorded input was skipped and ends the replay once all recorded instructions ran
+    pub(crate) fn replay_check(&self) -> Result<(), AxError> {
+        let mut replay = self.replay.borrow_mut();
+        if replay.mode != ReplayMode::Replaying {
+            return Ok(());
+        }
+
+        let count = self.state.executed_instructions_count;
+        if let Some(event) = replay.log.events.get(replay.next_event) {
+            if event.instruction < count {
+                let message = format!(
+                    "expected input from {:?} at instruction {} ({:#x}), but execution went past it",
+                    event.source, event.instruction, event.rip
+                );
+                drop(replay);
+                return Err(self.fail_replay(message));
+            }
+        } else if count >= replay.log.instructions {
+            debug_log!("Replay finished after {} instructions", count);
+            *replay = ReplayState::default();
+        }
+
+        Ok(())
+    }
+
+    // Called after `instr` was executed while recording or replaying
+    pub(crate) fn replay_track(&self, instr: &Instruction) -> Result<(), AxError> {
+        let mut replay = self.replay.borrow_mut();
+        if replay.mode == ReplayMode::Off {
+            return Ok(());
+        }
+
+        let count = self.state.executed_instructions_count;
+        let rip = self.state.registers[&SupportedRegister::RIP];
+        let branched = rip != instr.next_ip();
+        if replay.mode == ReplayMode::Recording {
+            if branched {
+                replay.log.branches.push((count, rip));
+            }
+        } else {
+            let expected = match replay.log.branches.get(replay.next_branch) {
+                Some(&(instruction, target)) if instruction == count => target,
+                _ => instr.next_ip(),
+            };
+            if rip != expected {
+                drop(replay);
+                return Err(self.fail_replay(format!(
+                    "the instruction at {:#x} continued at {:#x}, but at {:#x} while recording",
+                    instr.ip(),
+                    rip,
+                    expected
+                )));
+            }
+            if branched {
+                replay.next_branch += 1;
+            }
+        }
+
+        // Ends the replay right after the last recorded instruction
+        drop(replay);
+        self.replay_check()
+    }
+
+    // Reads the timestamp counter, which is recorded and replayed like other inputs
+    pub(crate) fn timestamp_counter(&mut self) -> Result<u64, AxError> {
+        if let Some(value) = self.replay_input(InputSource::Timestamp)? {
+            return match value {
+                Some(InputValue::Counter(tsc)) => Ok(tsc),
+                _ => Err(self.fail_replay(
+                    "the timestamp counter was read, but not while recording".to_string(),
+                )),
+            };
+        }
+
+        let tsc = host_timestamp();
+        self.record_value(InputSource::Timestamp, InputValue::Counter(tsc));
+        Ok(tsc)
+    }
+}
+
+// Nanoseconds since the Unix epoch, which is about the rate of the timestamp counter of a real CPU.
+// Like the real counter, it must not go backwards, so it is based on a monotonic clock instead of the wall clock
+#[cfg(all(target_arch = "wasm32", not(test)))]
+fn host_timestamp() -> u64 {
+    use js_sys::{Function, Reflect};
+    use wasm_bindgen::JsCast;
+
+    // performance.timeOrigin is the wall clock time when performance.now() started counting
+    let millis = Reflect::get(&js_sys::global(), &"performance".into())
+        .ok()
+        .and_then(|performance| {
+            let now = Reflect::get(&performance, &"now".into())
+                .ok()?
+                .dyn_into::<Function>()
+                .ok()?;
+            let origin = Reflect::get(&performance, &"timeOrigin".into())
+                .ok()?
+                .as_f64()?;
+            Some(origin + now.call0(&performance).ok()?.as_f64()?)
+        })
+        // Without the Performance API, only the wall clock is available
+        .unwrap_or_else(js_sys::Date::now);
+    (millis * 1_000_000.0) as u64
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(test))))]
+fn host_timestamp() -> u64 {
+    use lazy_static::lazy_static;
+    use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+    lazy_static! {
+        // The wall clock is only read once, afterwards the time is advanced by the monotonic clock
+        static ref START: (Instant, u64) = (
+            Instant::now(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        );
+    }
+
+    START.1 + START.0.elapsed().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::helpers::syscalls::SyscallResult;
+    use crate::helpers::tests::test_async;
+    use crate::state::memory::PROT_READ;
+    use crate::state::registers::SupportedRegister::*;
+
+    // mov eax, 0x27; syscall; mov rbx, rax; rdtsc; shl rdx, 32; xor rdx, rax; mov rsi, [0x2000]; nop
+    const CODE: &[u8] = &[
+        0xb8, 0x27, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0xc3, 0x0f, 0x31, 0x48, 0xc1, 0xe2,
+        0x20, 0x48, 0x31, 0xc2, 0x48, 0x8b, 0x34, 0x25, 0x00, 0x20, 0x00, 0x00, 0x90,
+    ];
+
+    // Sets up an axecutor whose syscall results and memory at 0x2000 change every time they are used
+    fn nondeterministic_axecutor(calls: Rc<Cell<u64>>) -> Axecutor {
+        let mut ax = Axecutor::new(CODE, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 8).unwrap();
+
+        let syscall_calls = calls.clone();
+        ax.hook_syscall_native(0x27, move |_, _| {
+            syscall_calls.set(syscall_calls.get() + 1);
+            Ok(SyscallResult::Return(1000 + syscall_calls.get()))
+        })
+        .unwrap();
+        ax.hook_memory_native(0x2000, 8, PROT_READ, move |_, _| {
+            calls.set(calls.get() + 1);
+            Ok(Some((2000 + calls.get()).to_le_bytes().to_vec()))
+        })
+        .unwrap();
+
+        ax
+    }
+
+    test_async![replay_reproduces_inputs; async {
+        let calls = Rc::new(Cell::new(0));
+        let mut ax = nondeterministic_axecutor(calls.clone());
+
+        ax.start_recording().unwrap();
+        ax.start_recording().expect_err("Already recording");
+        ax.execute().await.expect("Failed to execute");
+        let log = ax.stop_recording().unwrap();
+        assert_eq!(calls.get(), 2);
+
+        let (rbx, rdx, rsi) = (ax.reg_read_64(RBX).unwrap(), ax.reg_read_64(RDX).unwrap(), ax.reg_read_64(RSI).unwrap());
+        assert_eq!(rbx, 1001);
+        assert_eq!(rsi, 2002);
+
+        ax.start_replay(&log).expect("Failed to start replay");
+        assert!(ax.is_replaying());
+        assert!(!ax.state.finished);
+        assert_eq!(ax.reg_read_64(RIP).unwrap(), 0x1000);
+
+        ax.execute().await.expect("Failed to replay");
+        assert_eq!(ax.reg_read_64(RBX).unwrap(), rbx);
+        assert_eq!(ax.reg_read_64(RDX).unwrap(), rdx);
+        assert_eq!(ax.reg_read_64(RSI).unwrap(), rsi);
+        // Hooks didn't run, their results came from the log
+        assert_eq!(calls.get(), 2);
+        assert!(!ax.is_replaying());
+
+        ax.start_replay(b"AXREPLAY").expect_err("Truncated log");
+        ax.start_replay(&log[1..]).expect_err("Not a replay log");
+    }];
+
+    test_async![replay_rejects_log_ending_before_its_start; async {
+        let mut ax = Axecutor::new(&[0x90, 0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.step().await.expect("Failed to step");
+
+        let log = ReplayLog {
+            initial_state: ax.save_to_bytes().unwrap(),
+            ..Default::default()
+        };
+        let mut bytes = Vec::from(&REPLAY_MAGIC[..]);
+        bytes.extend_from_slice(&REPLAY_FORMAT_VERSION.to_le_bytes());
+        bincode::DefaultOptions::new().serialize_into(&mut bytes, &log).unwrap();
+
+        ax.start_replay(&bytes).expect_err("The log ends before it starts");
+        assert!(!ax.is_replaying());
+        assert_eq!(ax.reg_read_64(RIP).unwrap(), 0x1001);
+    }];
+
+    test_async![replay_detects_divergence; async {
+        let calls = Rc::new(Cell::new(0));
+        let mut ax = nondeterministic_axecutor(calls.clone());
+
+        ax.start_recording().unwrap();
+        ax.execute().await.expect("Failed to execute");
+        let log = ax.stop_recording().unwrap();
+
+        ax.start_replay(&log).expect("Failed to start replay");
+        // jmp over the syscall straight to rdtsc
+        ax.mem_poke_bytes(0x1000, &[0xeb, 0x08]).unwrap();
+
+        let err = ax.execute().await.expect_err("Replay should diverge");
+        assert_eq!(*err.kind(), ErrorKind::ReplayDivergence { instruction: 1 });
+        assert!(!ax.is_replaying());
+        assert_eq!(calls.get(), 2);
+    }];
+
+    test_async![replay_reports_first_diverging_instruction; async {
+        // mov ecx, 3000; l: dec ecx; jnz l; nop
+        let code = [0xb9, 0xb8, 0x0b, 0x00, 0x00, 0xff, 0xc9, 0x75, 0xfc, 0x90];
+        let mut ax = Axecutor::new(&code, 0x1000, 0x1000).expect("Failed to create axecutor");
+
+        ax.start_recording().unwrap();
+        ax.execute().await.expect("Failed to execute");
+        let log = ax.stop_recording().unwrap();
+
+        ax.start_replay(&log).expect("Failed to start replay");
+        // mov ecx, 2999: the last jnz falls through instead of jumping
+        ax.mem_poke_bytes(0x1001, &[0xb7]).unwrap();
+
+        let err = ax.execute().await.expect_err("Replay should diverge");
+        assert_eq!(*err.kind(), ErrorKind::ReplayDivergence { instruction: 1 + 2 * 2999 });
+        assert_eq!(ax.reg_read_64(RIP).unwrap(), 0x1009);
+    }];
+}
@@ -0,0 +1,316 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::{Axecutor, MachineState};
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+use crate::state::sanitizer::Sanitizer;
+use crate::state::taint::TaintState;
+
+// When there are more checkpoints, every other one is dropped and the interval doubled, so checkpoints always cover the whole execution
+const MAX_CHECKPOINTS: usize = 256;
+
+// Checkpoints share memory pages with the machine state like snapshots, so taking one is cheap
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Checkpoints {
+    // Number of instructions between checkpoints, 0 if checkpoints are disabled
+    interval: u64,
+    // Instruction count and state of each checkpoint, oldest first
+    checkpoints: Vec<(u64, Checkpoint)>,
+}
+
+// Besides the machine state, the shadow state of the sanitizer and taint tracking must go back too, or they would describe later memory contents
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    state: MachineState,
+    sanitizer: Option<Sanitizer>,
+    taint: Option<TaintState>,
+}
+
+impl Checkpoints {
+    pub(crate) fn clear(&mut self) {
+        self.checkpoints.clear();
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Enables automatic checkpoints every `interval` executed instructions, which `step_back` and `reverse_continue_to_breakpoint` go back to.
+    /// Passing 0 disables checkpoints and drops all existing ones.
+    /// The first checkpoint is taken before the next instruction is executed. Smaller intervals make going back faster, but need more memory;
+    /// if there are too many checkpoints, the interval is increased automatically.
+    /// Checkpoints include the state of the sanitizer and taint tracking, so going back to before either was enabled disables it again.
+    pub fn set_checkpoint_interval(&mut self, interval: u64) {
+        debug_log!("Setting checkpoint interval to {}", interval);
+
+        self.checkpoints.interval = interval;
+        if interval == 0 {
+            self.checkpoints.clear();
+        }
+    }
+
+    /// Goes back `n` instructions by restoring the nearest checkpoint before that point and executing instructions from there.
+    /// Hooks run again while re-executing, so they should only depend on the emulator state.
+    /// Checkpoints must have been enabled with `set_checkpoint_interval` before executing the instructions to go back over.
+    pub async fn step_back(&mut self, n: u64) -> Result<(), AxError> {
+        debug_log!("Calling Axecutor::step_back, n={}", n);
+        self.check_reverse()?;
+
+        let count = self.state.executed_instructions_count;
+        if n > count {
+            return Err(AxError::from(format!(
+                "Cannot step back {n} instructions, only {count} instructions have been executed"
+            )));
+        }
+
+        self.rewind_to(count - n).await
+    }
+
+    /// Goes back to the last time before the current instruction that a breakpoint was hit, the reverse of `run_until_breakpoint`.
+    /// Returns the address of the breakpoint, or None/undefined if no breakpoint was hit since the oldest checkpoint, which is where execution is then.
+    /// Like `step_back`, this needs checkpoints and re-executes instructions from them.
+    pub async fn reverse_continue_to_breakpoint(&mut self) -> Result<Option<u64>, AxError> {
+        debug_log!("Calling Axecutor::reverse_continue_to_breakpoint");
+        self.check_reverse()?;
+
+        // Search for the last breakpoint hit between each checkpoint and the next one, newest first
+        let mut end = self.state.executed_instructions_count;
+        while let Some(start) = self.checkpoint_before(end) {
+            self.restore_checkpoint(start);
+
+            let mut hit = None;
+            while self.state.executed_instructions_count < end {
+                if let Some(address) = self.breakpoint_hit()? {
+                    hit = Some((self.state.executed_instructions_count, address));
+                }
+                if !self.step().await? {
+                    break;
+                }
+            }
+
+            if let Some((target, address)) = hit {
+                self.rewind_to(target).await?;
+                debug_log!("Reverse continue hit breakpoint at {:#x}", address);
+                return Ok(Some(address));
+            }
+
+            end = start;
+        }
+
+        // Execution is at the oldest checkpoint, which may be a bit later than where it was before
+        if let Some(&(oldest, _)) = self.checkpoints.checkpoints.first() {
+            self.restore_checkpoint(oldest);
+        }
+        Ok(None)
+    }
+}
+
+impl Axecutor {
+    // Called before each instruction, takes a checkpoint if the interval has passed since the last one
+    pub(crate) fn take_checkpoint(&mut self) {
+        let checkpoints = &mut self.checkpoints;
+        if checkpoints.interval == 0 {
+            return;
+        }
+
+        let count = self.state.executed_instructions_count;
+        if let Some((last, _)) = checkpoints.checkpoints.last() {
+            if count < last.saturating_add(checkpoints.interval) {
+                return;
+            }
+        }
+
+        debug_log!("Taking checkpoint at instruction {}", count);
+        checkpoints.checkpoints.push((
+            count,
+            Checkpoint {
+                state: self.state.clone(),
+                sanitizer: self.sanitizer.clone(),
+                taint: self.taint.clone(),
+            },
+        ));
+
+        if checkpoints.checkpoints.len() > MAX_CHECKPOINTS {
+            let mut index = 0;
+            checkpoints.checkpoints.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            checkpoints.interval = checkpoints.interval.saturating_mul(2);
+        }
+    }
+
+    fn check_reverse(&self) -> Result<(), AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot execute in reverse while a hook is running",
+            ));
+        }
+        // Re-executed instructions would be recorded again
+        if self.replay_active() {
+            return Err(AxError::from(
+                "Cannot execute in reverse while recording or replaying",
+            ));
+        }
+        Ok(())
+    }
+
+    // Instruction count of the newest checkpoint before the given one
+    fn checkpoint_before(&self, count: u64) -> Option<u64> {
+        self.checkpoints
+            .checkpoints
+            .iter()
+            .rev()
+            .map(|(checkpoint, _)| *checkpoint)
+            .find(|checkpoint| *checkpoint < count)
+    }
+
+    fn restore_checkpoint(&mut self, count: u64) {
+        if let Some((_, checkpoint)) = self
+            .checkpoints
+            .checkpoints
+            .iter()
+            .find(|(c, _)| *c == count)
+        {
+            debug_log!("Restoring checkpoint at instruction {}", count);
+            self.state = checkpoint.state.clone();
+            self.sanitizer = checkpoint.sanitizer.clone();
+            self.taint = checkpoint.taint.clone();
+            self.hooks.watchpoint_hit.set(None);
+        }
+    }
+
+    // Executes from the nearest checkpoint until `target` instructions have been executed.
+    // Later checkpoints are dropped, as execution may take another path from there
+    async fn rewind_to(&mut self, target: u64) -> Result<(), AxError> {
+        let start = self.checkpoint_before(target + 1).ok_or_else(|| {
+            AxError::from(format!(
+                "Cannot go back to instruction {target}: there is no checkpoint before it, checkpoints can be enabled with set_checkpoint_interval"
+            ))
+        })?;
+
+        self.checkpoints
+            .checkpoints
+            .retain(|(checkpoint, _)| *checkpoint <= target);
+        self.restore_checkpoint(start);
+
+        while self.state.executed_instructions_count < target {
+            if !self.step().await? {
+                break;
+            }
+        }
+
+        let count = self.state.executed_instructions_count;
+        if count != target {
+            return Err(AxError::from(format!(
+                "Cannot go back to instruction {target}: execution from the checkpoint at instruction {start} finished after {count} instructions"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::errors::ErrorKind;
+    use crate::helpers::tests::{c_loop, test_async};
+    use crate::state::registers::SupportedRegister::*;
+    use crate::state::sanitizer::SanitizerViolation;
+
+    test_async![step_back_restores_earlier_states; async {
+        let mut ax = c_loop();
+        ax.step_back(0).await.expect_err("Checkpoints are disabled");
+        ax.set_checkpoint_interval(4);
+
+        let mut states = Vec::new();
+        for _ in 0..20 {
+            states.push((ax.reg_read_64(RIP).unwrap(), ax.reg_read_64(RSP).unwrap(), ax.reg_read_64(RAX).unwrap()));
+            assert!(ax.step().await.unwrap());
+        }
+
+        ax.step_back(7).await.unwrap();
+        assert_eq!(ax.state.executed_instructions_count, 13);
+        assert_eq!((ax.reg_read_64(RIP).unwrap(), ax.reg_read_64(RSP).unwrap(), ax.reg_read_64(RAX).unwrap()), states[13]);
+
+        ax.step_back(13).await.unwrap();
+        assert_eq!(ax.reg_read_64(RIP).unwrap(), states[0].0);
+        ax.step_back(1).await.expect_err("Nothing was executed before");
+
+        // Going forward again works as before
+        ax.execute().await.unwrap();
+        assert_eq!(ax.reg_read_64(RDI).unwrap(), 9);
+    }];
+
+    test_async![reverse_continue_finds_previous_hits; async {
+        let mut ax = c_loop();
+        ax.set_checkpoint_interval(5);
+
+        // The loop body is executed three times with counters 0, 1 and 2 at [rbp-8]
+        ax.add_breakpoint(0x401036).unwrap();
+        let mut hits = Vec::new();
+        while ax.run_until_breakpoint().await.unwrap().is_some() {
+            hits.push(ax.state.executed_instructions_count);
+        }
+        assert_eq!(hits.len(), 3);
+        assert!(ax.state.finished);
+
+        for (i, count) in hits.iter().enumerate().rev() {
+            assert_eq!(ax.reverse_continue_to_breakpoint().await.unwrap(), Some(0x401036));
+            assert_eq!(ax.state.executed_instructions_count, *count);
+            let rbp = ax.reg_read_64(RBP).unwrap();
+            assert_eq!(ax.mem_read_32(rbp - 8).unwrap(), i as u64);
+        }
+
+        assert_eq!(ax.reverse_continue_to_breakpoint().await.unwrap(), None);
+        assert_eq!(ax.state.executed_instructions_count, 0);
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x401036));
+        assert_eq!(ax.state.executed_instructions_count, hits[0]);
+    }];
+
+    test_async![huge_intervals_saturate; async {
+        let mut ax = c_loop();
+        ax.set_checkpoint_interval(u64::MAX);
+        for _ in 0..5 {
+            assert!(ax.step().await.unwrap());
+        }
+        assert_eq!(ax.checkpoints.checkpoints.len(), 1);
+
+        // The checkpoint after the last one that fits is taken at the end of the counter, then the interval can't double any further
+        let checkpoint = ax.checkpoints.checkpoints[0].1.clone();
+        ax.checkpoints.checkpoints = (0..MAX_CHECKPOINTS as u64).map(|count| (count, checkpoint.clone())).collect();
+        ax.state.executed_instructions_count = u64::MAX;
+        ax.take_checkpoint();
+        assert_eq!(ax.checkpoints.checkpoints.len(), MAX_CHECKPOINTS / 2 + 1);
+        assert_eq!(ax.checkpoints.interval, u64::MAX);
+    }];
+
+    test_async![checkpoints_restore_sanitizer_and_taint_state; async {
+        // sub rsp, 8; mov qword ptr [rsp], 0; mov rax, rbx; mov rax, [rsp]
+        let code = [
+            0x48, 0x83, 0xec, 0x08, 0x48, 0xc7, 0x04, 0x24, 0x00, 0x00, 0x00, 0x00, 0x48, 0x89,
+            0xd8, 0x48, 0x8b, 0x04, 0x24,
+        ];
+        let mut ax = Axecutor::new(&code, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.init_stack(0x1000).unwrap();
+        ax.enable_sanitizer();
+        ax.enable_taint_tracking();
+        ax.taint_register(RBX, 1).unwrap();
+        ax.set_checkpoint_interval(1);
+
+        for _ in 0..3 {
+            assert!(ax.step().await.unwrap());
+        }
+        assert_ne!(ax.register_taint(RAX).unwrap(), 0);
+
+        // Back to before [rsp] was initialized and RAX was tainted
+        ax.step_back(2).await.unwrap();
+        assert_eq!(ax.register_taint(RAX).unwrap(), 0);
+
+        // Skip initializing [rsp] and read it
+        ax.reg_write_64(RIP, 0x100f).unwrap();
+        let error = ax.step().await.expect_err("Reads uninitialized stack memory");
+        let rsp = ax.reg_read_64(RSP).unwrap();
+        assert_eq!(error.kind(), &ErrorKind::Sanitizer { violation: SanitizerViolation::UninitializedRead, addr: rsp });
+    }];
+}
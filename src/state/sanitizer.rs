@@ -0,0 +1,699 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+use iced_x86::Instruction;
+use serde::Serialize;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::state::memory::PAGE_SIZE;
+use crate::state::memory_hooks::reads_memory;
+use crate::state::registers::SupportedRegister;
+
+const SHADOW_WORDS: usize = (PAGE_SIZE / 64) as usize;
+
+// A larger decrease of RSP is assumed to switch to another stack rather than to allocate stack memory
+const STACK_SWITCH_LIMIT: u64 = 0x10_0000;
+
+// End of a block or access. Sizes come from the program, so a block reaching past the end of the address space ends there
+fn end_of(start: u64, length: u64) -> u64 {
+    start.saturating_add(length)
+}
+
+/// Kinds of invalid memory use the sanitizer reports
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum SanitizerViolation {
+    /// A read of memory that was never written, e.g. an uninitialized local variable or a fresh malloc block
+    UninitializedRead,
+    /// An access to heap memory outside of any allocated block, e.g. past the end of a malloc block
+    HeapOverflow,
+    /// An access to a heap block that was already freed
+    UseAfterFree,
+    /// free or realloc of a pointer that was not returned by the allocator
+    InvalidFree,
+    /// free or realloc of a block that was already freed
+    DoubleFree,
+}
+
+/// Allocator functions the sanitizer can intercept to learn the bounds of heap blocks
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AllocatorFunction {
+    /// `void *malloc(size_t size)`
+    Malloc,
+    /// `void *calloc(size_t count, size_t size)`
+    Calloc,
+    /// `void *realloc(void *ptr, size_t size)`
+    Realloc,
+    /// `void free(void *ptr)`
+    Free,
+}
+
+/// The first invalid memory use found by the sanitizer
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizerReport {
+    pub violation: SanitizerViolation,
+    /// Address of the instruction that made the access
+    pub rip: u64,
+    /// First invalid address, for invalid frees the pointer that was passed
+    pub address: u64,
+    /// Number of bytes accessed
+    pub length: u64,
+    /// Start of the heap block the access is closest to, if any
+    pub block_start: Option<u64>,
+    /// Size of that heap block
+    pub block_size: Option<u64>,
+    /// The call stack at the time of the access, as returned by `call_stack`
+    pub call_stack: String,
+}
+
+impl Display for SanitizerReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let what = match self.violation {
+            SanitizerViolation::UninitializedRead => "Read of uninitialized memory",
+            SanitizerViolation::HeapOverflow => "Heap access outside of any block",
+            SanitizerViolation::UseAfterFree => "Access to freed heap block",
+            SanitizerViolation::InvalidFree => "Invalid free",
+            SanitizerViolation::DoubleFree => "Double free",
+        };
+        write!(
+            f,
+            "{what} at {:#x} ({} bytes) by instruction at {:#x}",
+            self.address, self.length, self.rip
+        )?;
+
+        if let (Some(start), Some(size)) = (self.block_start, self.block_size) {
+            let position = if self.address < start {
+                format!("{} bytes before", start - self.address)
+            } else if self.address >= end_of(start, size) {
+                format!("{} bytes after", self.address - end_of(start, size))
+            } else {
+                format!("{} bytes inside", self.address - start)
+            };
+            write!(f, ", {position} a block of {size} bytes at {start:#x}")?;
+        }
+
+        write!(f, "\nCall stack:\n{}", self.call_stack)
+    }
+}
+
+// An intercepted allocator call that has not returned yet
+#[derive(Debug, Clone)]
+struct AllocatorCall {
+    function: AllocatorFunction,
+    arguments: (u64, u64),
+    return_address: u64,
+    rsp: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sanitizer {
+    // Bit set of uninitialized bytes for each page, pages without an entry are fully initialized.
+    // Pages are shared with checkpoints until they change
+    shadow: HashMap<u64, Rc<[u64; SHADOW_WORDS]>>,
+    // Heap areas besides the program break, e.g. from custom mmap handlers
+    heap_regions: Vec<(u64, u64)>,
+    // Start and size of live and freed heap blocks
+    allocations: BTreeMap<u64, u64>,
+    freed: BTreeMap<u64, u64>,
+    intercepted: HashMap<u64, AllocatorFunction>,
+    // The outermost allocator call in progress, its own accesses to heap metadata are not checked
+    allocator_call: Option<AllocatorCall>,
+    // Functions whose accesses, including those of functions they call, are not checked
+    suppressed: HashSet<u64>,
+    // Set while an instruction is executed, accesses by hooks and the host are not checked
+    in_instruction: bool,
+    // Whether the instruction reads memory, see `reads_memory`
+    instruction_reads: bool,
+    rip: u64,
+    rsp: u64,
+    report: RefCell<Option<SanitizerReport>>,
+}
+
+impl Sanitizer {
+    fn set_uninitialized(&mut self, address: u64, length: u64, uninitialized: bool) {
+        let end = end_of(address, length);
+        let mut current = address;
+        while current < end {
+            let page = current / PAGE_SIZE;
+            let page_end = end_of(page * PAGE_SIZE, PAGE_SIZE).min(end);
+            if uninitialized || self.shadow.contains_key(&page) {
+                let bits = Rc::make_mut(
+                    self.shadow
+                        .entry(page)
+                        .or_insert_with(|| Rc::new([0; SHADOW_WORDS])),
+                );
+                for byte in current..page_end {
+                    let offset = (byte % PAGE_SIZE) as usize;
+                    if uninitialized {
+                        bits[offset / 64] |= 1 << (offset % 64);
+                    } else {
+                        bits[offset / 64] &= !(1 << (offset % 64));
+                    }
+                }
+                if bits.iter().all(|word| *word == 0) {
+                    self.shadow.remove(&page);
+                }
+            }
+            current = page_end;
+        }
+    }
+
+    fn is_uninitialized(&self, address: u64) -> bool {
+        let offset = (address % PAGE_SIZE) as usize;
+        self.shadow
+            .get(&(address / PAGE_SIZE))
+            .is_some_and(|bits| bits[offset / 64] & (1 << (offset % 64)) != 0)
+    }
+
+    fn first_uninitialized(&self, address: u64, length: u64) -> Option<u64> {
+        if self.shadow.is_empty() {
+            return None;
+        }
+        (address..end_of(address, length)).find(|byte| self.is_uninitialized(*byte))
+    }
+
+    // Copies the initialization state of `length` bytes, e.g. when realloc moves a block
+    fn copy_state(&mut self, from: u64, to: u64, length: u64) {
+        for (source, target) in (from..end_of(from, length)).zip(to..end_of(to, length)) {
+            let uninitialized = self.is_uninitialized(source);
+            self.set_uninitialized(target, 1, uninitialized);
+        }
+    }
+
+    fn block_containing(blocks: &BTreeMap<u64, u64>, address: u64) -> Option<(u64, u64)> {
+        blocks
+            .range(..=address)
+            .next_back()
+            .filter(|(start, size)| address < end_of(**start, **size))
+            .map(|(start, size)| (*start, *size))
+    }
+
+    // The live block closest to the address, to tell which block an overflow came from
+    fn nearest_block(&self, address: u64) -> Option<(u64, u64)> {
+        let before = self.allocations.range(..=address).next_back();
+        let after = self.allocations.range(address..).next();
+        let distance = |(start, size): (&u64, &u64)| {
+            if address < *start {
+                *start - address
+            } else {
+                address.saturating_sub(end_of(*start, *size))
+            }
+        };
+        before
+            .into_iter()
+            .chain(after)
+            .min_by_key(|block| distance(*block))
+            .map(|(start, size)| (*start, *size))
+    }
+
+    fn allocate(&mut self, start: u64, size: u64, initialized: bool) {
+        let end = end_of(start, size);
+        self.freed.retain(|freed_start, freed_size| {
+            end_of(*freed_start, *freed_size) <= start || *freed_start >= end
+        });
+        self.allocations.insert(start, size);
+        self.set_uninitialized(start, size, !initialized);
+    }
+
+    // Records the block an allocator call returned
+    fn allocator_returned(&mut self, call: AllocatorCall, result: u64) {
+        debug_log!("Allocator call {:?} returned {:#x}", call, result);
+        let (first, second) = call.arguments;
+        match call.function {
+            AllocatorFunction::Malloc if result != 0 => self.allocate(result, first, false),
+            AllocatorFunction::Calloc if result != 0 => {
+                self.allocate(result, first.saturating_mul(second), true)
+            }
+            AllocatorFunction::Realloc if result != 0 => {
+                let old_size = self.allocations.get(&first).copied().unwrap_or(0);
+                if first != result {
+                    self.free(first);
+                }
+                self.allocate(result, second, false);
+                self.copy_state(first, result, old_size.min(second));
+            }
+            // realloc(ptr, 0) may free the block
+            AllocatorFunction::Realloc if second == 0 => self.free(first),
+            _ => {}
+        }
+    }
+
+    fn free(&mut self, start: u64) {
+        if let Some(size) = self.allocations.remove(&start) {
+            self.freed.insert(start, size);
+        }
+    }
+}
+
+impl Axecutor {
+    fn sanitizer(&mut self) -> Result<&mut Sanitizer, AxError> {
+        self.sanitizer.as_mut().ok_or_else(|| {
+            AxError::from("The sanitizer is not enabled, enable it with enable_sanitizer")
+        })
+    }
+
+    fn sanitizer_violation(
+        &self,
+        violation: SanitizerViolation,
+        address: u64,
+        length: u64,
+        block: Option<(u64, u64)>,
+    ) -> AxError {
+        let Some(sanitizer) = self.sanitizer.as_ref() else {
+            return AxError::from("The sanitizer is not enabled");
+        };
+
+        let report = SanitizerReport {
+            violation,
+            rip: sanitizer.rip,
+            address,
+            length,
+            block_start: block.map(|(start, _)| start),
+            block_size: block.map(|(_, size)| size),
+            call_stack: self.call_stack().unwrap_or_else(|e| e.to_string()),
+        };
+        debug_log!("Sanitizer found {:?} at {:#x}", violation, address);
+
+        let error = AxError::from(report.to_string()).with_kind(ErrorKind::Sanitizer {
+            violation,
+            addr: address,
+        });
+        sanitizer.report.borrow_mut().get_or_insert(report);
+        error
+    }
+
+    // Whether accesses are currently checked: during instructions, but not in allocator calls or suppressed functions
+    fn sanitizer_checking(&self) -> Option<&Sanitizer> {
+        let sanitizer = self.sanitizer.as_ref()?;
+        if !sanitizer.in_instruction
+            || sanitizer.allocator_call.is_some()
+            || (!sanitizer.suppressed.is_empty()
+                && self
+                    .state
+                    .call_stack
+                    .iter()
+                    .any(|function| sanitizer.suppressed.contains(function)))
+        {
+            return None;
+        }
+        Some(sanitizer)
+    }
+
+    fn sanitizer_in_heap(&self, sanitizer: &Sanitizer, address: u64, length: u64) -> bool {
+        let end = end_of(address, length);
+        self.state
+            .syscalls
+            .brk_area()
+            .iter()
+            .chain(&sanitizer.heap_regions)
+            .any(|(start, size)| address < end_of(*start, *size) && *start < end)
+    }
+
+    fn sanitize_heap_access(&self, address: u64, length: u64) -> Result<(), AxError> {
+        let Some(sanitizer) = self.sanitizer_checking() else {
+            return Ok(());
+        };
+        // Without an intercepted allocator, block bounds are unknown
+        if sanitizer.intercepted.is_empty() || !self.sanitizer_in_heap(sanitizer, address, length) {
+            return Ok(());
+        }
+
+        if let Some((start, size)) = Sanitizer::block_containing(&sanitizer.allocations, address) {
+            if end_of(address, length) <= end_of(start, size) {
+                return Ok(());
+            }
+            return Err(self.sanitizer_violation(
+                SanitizerViolation::HeapOverflow,
+                end_of(start, size),
+                length,
+                Some((start, size)),
+            ));
+        }
+
+        if let Some(block) = Sanitizer::block_containing(&sanitizer.freed, address) {
+            return Err(self.sanitizer_violation(
+                SanitizerViolation::UseAfterFree,
+                address,
+                length,
+                Some(block),
+            ));
+        }
+
+        Err(self.sanitizer_violation(
+            SanitizerViolation::HeapOverflow,
+            address,
+            length,
+            sanitizer.nearest_block(address),
+        ))
+    }
+
+    // Called by mem_read_bytes before memory is read
+    pub(crate) fn sanitize_read(&self, address: u64, length: u64) -> Result<(), AxError> {
+        self.sanitize_heap_access(address, length)?;
+
+        let Some(sanitizer) = self.sanitizer_checking() else {
+            return Ok(());
+        };
+        if !sanitizer.instruction_reads {
+            return Ok(());
+        }
+        if let Some(uninitialized) = sanitizer.first_uninitialized(address, length) {
+            let block = Sanitizer::block_containing(&sanitizer.allocations, uninitialized);
+            return Err(self.sanitizer_violation(
+                SanitizerViolation::UninitializedRead,
+                uninitialized,
+                length,
+                block,
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Called by mem_write_bytes before memory is written
+    pub(crate) fn sanitize_write(&self, address: u64, length: u64) -> Result<(), AxError> {
+        self.sanitize_heap_access(address, length)
+    }
+
+    // Called after memory was written, written bytes are initialized
+    pub(crate) fn sanitizer_written(&mut self, address: u64, length: u64) {
+        if let Some(sanitizer) = self.sanitizer.as_mut() {
+            sanitizer.set_uninitialized(address, length, false);
+        }
+    }
+
+    // Called right before an instruction is executed; tracks calls of intercepted allocator functions
+    pub(crate) fn sanitizer_begin(&mut self, instr: &Instruction) -> Result<(), AxError> {
+        if self.sanitizer.is_none() {
+            return Ok(());
+        }
+
+        let rsp = self.reg_read_64(SupportedRegister::RSP)?;
+        let rax = self.reg_read_64(SupportedRegister::RAX)?;
+        let sanitizer = self.sanitizer()?;
+        sanitizer.rip = instr.ip();
+        sanitizer.rsp = rsp;
+        sanitizer.instruction_reads = reads_memory(instr);
+
+        let returned = sanitizer
+            .allocator_call
+            .as_ref()
+            .is_some_and(|call| call.return_address == instr.ip() && rsp > call.rsp);
+        if returned {
+            if let Some(call) = sanitizer.allocator_call.take() {
+                sanitizer.allocator_returned(call, rax);
+            }
+        } else if sanitizer.allocator_call.is_none() {
+            if let Some(&function) = sanitizer.intercepted.get(&instr.ip()) {
+                self.sanitizer_allocator_called(function, rsp)?;
+            }
+        }
+
+        self.sanitizer()?.in_instruction = true;
+        Ok(())
+    }
+
+    // Checks the pointer passed to free and realloc and remembers the call until it returns
+    fn sanitizer_allocator_called(
+        &mut self,
+        function: AllocatorFunction,
+        rsp: u64,
+    ) -> Result<(), AxError> {
+        let rdi = self.reg_read_64(SupportedRegister::RDI)?;
+        let rsi = self.reg_read_64(SupportedRegister::RSI)?;
+        let Some(return_address) = self
+            .mem_peek_bytes(self.return_address_slot()?, 8)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u64::from_le_bytes)
+        else {
+            return Ok(());
+        };
+        debug_log!(
+            "Allocator call {:?}({:#x}, {:#x}) returning to {:#x}",
+            function,
+            rdi,
+            rsi,
+            return_address
+        );
+
+        if matches!(
+            function,
+            AllocatorFunction::Free | AllocatorFunction::Realloc
+        ) && rdi != 0
+        {
+            let sanitizer = self.sanitizer()?;
+            if !sanitizer.allocations.contains_key(&rdi) {
+                let (violation, block) = match Sanitizer::block_containing(&sanitizer.freed, rdi) {
+                    Some(block) => (SanitizerViolation::DoubleFree, Some(block)),
+                    None => (
+                        SanitizerViolation::InvalidFree,
+                        sanitizer.nearest_block(rdi),
+                    ),
+                };
+                return Err(self.sanitizer_violation(violation, rdi, 0, block));
+            }
+            if function == AllocatorFunction::Free {
+                sanitizer.free(rdi);
+            }
+        }
+
+        self.sanitizer()?.allocator_call = Some(AllocatorCall {
+            function,
+            arguments: (rdi, rsi),
+            return_address,
+            rsp,
+        });
+        Ok(())
+    }
+
+    // Called right after an instruction was executed; stack memory the instruction allocated is uninitialized
+    pub(crate) fn sanitizer_end(&mut self) -> Result<(), AxError> {
+        if self.sanitizer.is_none() {
+            return Ok(());
+        }
+
+        let rsp = self.reg_read_64(SupportedRegister::RSP)?;
+        let sanitizer = self.sanitizer()?;
+        sanitizer.in_instruction = false;
+        if rsp < sanitizer.rsp && sanitizer.rsp - rsp <= STACK_SWITCH_LIMIT {
+            let old = sanitizer.rsp;
+            sanitizer.set_uninitialized(rsp, old - rsp, true);
+        }
+
+        Ok(())
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Enables the memory sanitizer, which stops execution at the first read of uninitialized memory, similar to Valgrind's memcheck.
+    /// Memory that is mapped when the sanitizer is enabled counts as initialized, stack memory below RSP becomes uninitialized whenever RSP decreases,
+    /// and the program break from brk is initialized like the kernel zeroes it.
+    /// Heap overflows and invalid frees are found once the allocator functions are intercepted with `sanitizer_intercept_allocator`.
+    /// Only accesses by instructions are checked, not those of hooks or syscall handlers. The sanitizer state is not part of snapshots.
+    pub fn enable_sanitizer(&mut self) {
+        debug_log!("Enabling sanitizer");
+        if self.sanitizer.is_none() {
+            self.sanitizer = Some(Sanitizer::default());
+        }
+    }
+
+    /// Disables the memory sanitizer and discards its state
+    pub fn disable_sanitizer(&mut self) {
+        self.sanitizer = None;
+    }
+
+    /// Intercepts the allocator functions `malloc`, `calloc`, `realloc` and `free` found in the symbol table to track the bounds of heap blocks.
+    /// Returns the number of functions found
+    pub fn sanitizer_intercept_allocator(&mut self) -> Result<u32, AxError> {
+        let mut found = 0;
+        for (name, function) in [
+            ("malloc", AllocatorFunction::Malloc),
+            ("calloc", AllocatorFunction::Calloc),
+            ("realloc", AllocatorFunction::Realloc),
+            ("free", AllocatorFunction::Free),
+        ] {
+            if let Some(address) = self.address_of(name) {
+                self.sanitizer_intercept_function(function, address)?;
+                found += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Intercepts an allocator function at `address`, e.g. a custom allocator with the same signature as `function`.
+    /// Heap blocks are only checked within the program break and regions added with `sanitizer_add_heap_region`.
+    pub fn sanitizer_intercept_function(
+        &mut self,
+        function: AllocatorFunction,
+        address: u64,
+    ) -> Result<(), AxError> {
+        debug_log!("Sanitizer intercepting {:?} at {:#x}", function, address);
+        self.sanitizer()?.intercepted.insert(address, function);
+        Ok(())
+    }
+
+    /// Marks memory as heap in which accesses must be within allocated blocks, e.g. memory a custom mmap handler returns to the allocator
+    pub fn sanitizer_add_heap_region(&mut self, start: u64, length: u64) -> Result<(), AxError> {
+        self.sanitizer()?.heap_regions.push((start, length));
+        Ok(())
+    }
+
+    /// Stops checking accesses while the function at `address` is running, e.g. optimized string functions that read past the end of strings
+    pub fn sanitizer_suppress(&mut self, address: u64) -> Result<(), AxError> {
+        self.sanitizer()?.suppressed.insert(address);
+        Ok(())
+    }
+
+    /// The first invalid memory use the sanitizer found, None/undefined if there was none
+    pub fn sanitizer_report(&self) -> Option<SanitizerReport> {
+        self.sanitizer
+            .as_ref()
+            .and_then(|sanitizer| sanitizer.report.borrow().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+    use crate::state::registers::SupportedRegister::*;
+
+    const MALLOC: u64 = 0x1000;
+    const FREE: u64 = 0x1010;
+    const MAIN: u64 = 0x1020;
+    const HEAP: u64 = 0x8000;
+
+    // A program with a bump allocator returning HEAP, which calls it and then runs `body` with the block in rbx
+    fn heap_program(body: &[u8]) -> Axecutor {
+        let mut code = vec![0x90; (MAIN - MALLOC) as usize];
+        // malloc: mov rax, HEAP; ret
+        code[0..2].copy_from_slice(&[0x48, 0xb8]);
+        code[2..10].copy_from_slice(&HEAP.to_le_bytes());
+        code[10] = 0xc3;
+        // free: ret
+        code[(FREE - MALLOC) as usize] = 0xc3;
+
+        // mov edi, 16; call malloc; mov rbx, rax
+        code.extend_from_slice(&[0xbf, 0x10, 0x00, 0x00, 0x00, 0xe8]);
+        let after_call = MALLOC + code.len() as u64 + 4;
+        code.extend_from_slice(&((MALLOC as i64 - after_call as i64) as i32).to_le_bytes());
+        code.extend_from_slice(&[0x48, 0x89, 0xc3]);
+        code.extend_from_slice(body);
+
+        let mut ax = Axecutor::new(&code, MALLOC, MAIN).expect("Failed to create axecutor");
+        ax.init_stack(0x1000).unwrap();
+        ax.mem_init_zero(HEAP, 0x100).unwrap();
+        ax.enable_sanitizer();
+        ax.sanitizer_add_heap_region(HEAP, 0x100).unwrap();
+        ax.sanitizer_intercept_function(AllocatorFunction::Malloc, MALLOC)
+            .unwrap();
+        ax.sanitizer_intercept_function(AllocatorFunction::Free, FREE)
+            .unwrap();
+        ax
+    }
+
+    // Appends `call FREE` with the block as argument
+    fn free_block(body: &mut Vec<u8>) {
+        // mov rdi, rbx
+        body.extend_from_slice(&[0x48, 0x89, 0xdf, 0xe8]);
+        let after_call = MAIN + 13 + body.len() as u64 + 4;
+        body.extend_from_slice(&((FREE as i64 - after_call as i64) as i32).to_le_bytes());
+    }
+
+    test_async![uninitialized_stack_read; async {
+        // sub rsp, 16; mov qword ptr [rsp], 5; mov rax, [rsp]; mov rax, [rsp+8]
+        let code = [
+            0x48, 0x83, 0xec, 0x10, 0x48, 0xc7, 0x04, 0x24, 0x05, 0x00, 0x00, 0x00, 0x48, 0x8b,
+            0x04, 0x24, 0x48, 0x8b, 0x44, 0x24, 0x08,
+        ];
+        let mut ax = Axecutor::new(&code, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.init_stack(0x1000).unwrap();
+        ax.enable_sanitizer();
+
+        let error = ax.execute().await.expect_err("Reads uninitialized stack memory");
+        let rsp = ax.reg_read_64(RSP).unwrap();
+        assert_eq!(error.kind(), &ErrorKind::Sanitizer { violation: SanitizerViolation::UninitializedRead, addr: rsp + 8 });
+        assert_eq!(ax.reg_read_64(RAX).unwrap(), 5);
+
+        let report = ax.sanitizer_report().expect("No report");
+        assert_eq!(report.rip, 0x1010);
+        assert_eq!(report.length, 8);
+        assert!(error.to_string().contains("Read of uninitialized memory"), "{}", error);
+        // The host can still read the memory
+        ax.mem_read_64(rsp + 8).unwrap();
+    }];
+
+    test_async![heap_overflow_and_use_after_free; async {
+        // mov byte ptr [rbx+15], 1; mov al, [rbx+15]
+        let mut ax = heap_program(&[0xc6, 0x43, 0x0f, 0x01, 0x8a, 0x43, 0x0f]);
+        ax.execute().await.unwrap();
+        assert_eq!(ax.sanitizer_report(), None);
+
+        // mov byte ptr [rbx+16], 1
+        let mut ax = heap_program(&[0xc6, 0x43, 0x10, 0x01]);
+        let error = ax.execute().await.expect_err("Writes past the block");
+        assert_eq!(error.kind(), &ErrorKind::Sanitizer { violation: SanitizerViolation::HeapOverflow, addr: HEAP + 16 });
+        let report = ax.sanitizer_report().unwrap();
+        assert_eq!((report.block_start, report.block_size), (Some(HEAP), Some(16)));
+        assert!(report.to_string().contains("0 bytes after a block of 16 bytes at 0x8000"), "{}", report);
+
+        // mov al, [rbx+1]
+        let mut ax = heap_program(&[0x8a, 0x43, 0x01]);
+        let error = ax.execute().await.expect_err("Reads a fresh block");
+        assert_eq!(error.kind(), &ErrorKind::Sanitizer { violation: SanitizerViolation::UninitializedRead, addr: HEAP + 1 });
+
+        // free, then mov byte ptr [rbx], 1
+        let mut body = Vec::new();
+        free_block(&mut body);
+        body.extend_from_slice(&[0xc6, 0x03, 0x01]);
+        let mut ax = heap_program(&body);
+        let error = ax.execute().await.expect_err("Writes a freed block");
+        assert_eq!(error.kind(), &ErrorKind::Sanitizer { violation: SanitizerViolation::UseAfterFree, addr: HEAP });
+
+        // free twice
+        let mut body = Vec::new();
+        free_block(&mut body);
+        free_block(&mut body);
+        let mut ax = heap_program(&body);
+        let error = ax.execute().await.expect_err("Frees twice");
+        assert_eq!(error.kind(), &ErrorKind::Sanitizer { violation: SanitizerViolation::DoubleFree, addr: HEAP });
+        assert_eq!(ax.sanitizer_report().unwrap().rip, FREE);
+    }];
+
+    test_async![blocks_at_the_end_of_the_address_space; async {
+        let mut sanitizer = Sanitizer::default();
+        sanitizer.allocate(u64::MAX - 7, 16, false);
+        sanitizer.allocator_returned(
+            AllocatorCall {
+                function: AllocatorFunction::Realloc,
+                arguments: (u64::MAX - 7, 16),
+                return_address: 0,
+                rsp: 0,
+            },
+            0x8000,
+        );
+        assert!(sanitizer.is_uninitialized(0x8007));
+        assert_eq!(Sanitizer::block_containing(&sanitizer.freed, u64::MAX - 1), Some((u64::MAX - 7, 16)));
+        assert_eq!(sanitizer.nearest_block(u64::MAX), Some((0x8000, 16)));
+
+        let report = SanitizerReport {
+            violation: SanitizerViolation::HeapOverflow,
+            rip: 0x1000,
+            address: u64::MAX - 1,
+            length: 1,
+            block_start: Some(u64::MAX - 7),
+            block_size: Some(16),
+            call_stack: String::new(),
+        };
+        assert!(report.to_string().contains("6 bytes inside"), "{}", report);
+    }];
+}
@@ -0,0 +1,199 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::{Axecutor, MachineState};
+use crate::elf::symbols::SymbolTable;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+
+/// Version of the format written by `save_to_bytes`. It must be increased whenever the serialized state changes
+pub const STATE_FORMAT_VERSION: u32 = 1;
+
+const STATE_MAGIC: &[u8; 8] = b"AXSTATE\0";
+
+// Everything that is needed to continue execution later. Memory areas only store pages that are not all zeroes
+#[derive(Serialize)]
+struct SavedSession<'a> {
+    stack_top: u64,
+    code_end_addr: u64,
+    state: &'a MachineState,
+    symbol_table: &'a SymbolTable,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoadedSession {
+    stack_top: u64,
+    code_end_addr: u64,
+    pub(crate) state: MachineState,
+    symbol_table: SymbolTable,
+}
+
+fn invalid_state(message: String) -> AxError {
+    AxError::from(format!("Cannot load state: {message}")).with_kind(ErrorKind::InvalidState)
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Serializes the emulator session to bytes that can be loaded with `load_from_bytes`, e.g. on another machine.
+    /// This includes registers, memory, syscall state such as pipe contents, signal handlers and the symbol table.
+    /// Hooks are not saved and must be registered again after loading; syscalls registered with `handle_syscalls` are kept.
+    pub fn save_to_bytes(&self) -> Result<Vec<u8>, AxError> {
+        debug_log!("Calling Axecutor::save_to_bytes");
+
+        let session = SavedSession {
+            stack_top: self.stack_top,
+            code_end_addr: self.code_end_addr,
+            state: &self.state,
+            symbol_table: &self.symbol_table,
+        };
+
+        let mut bytes = Vec::from(&STATE_MAGIC[..]);
+        bytes.extend_from_slice(&STATE_FORMAT_VERSION.to_le_bytes());
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut bytes, &session)
+            .map_err(|e| AxError::from(format!("Failed to serialize state: {e}")))?;
+
+        Ok(bytes)
+    }
+
+    /// Creates a new Axecutor from bytes written by `save_to_bytes`.
+    /// Fails with a `StateVersionMismatch` error if the bytes were written by a version of ax with another state format.
+    pub fn load_from_bytes(bytes: &[u8]) -> Result<Axecutor, AxError> {
+        debug_log!("Calling Axecutor::load_from_bytes");
+
+        let mut ax = Axecutor::empty();
+        ax.load_session(bytes)?;
+
+        // The handlers for built-in syscalls are hooks, which are not saved
+        let registered = std::mem::take(&mut ax.state.syscalls.registered);
+        ax.handle_syscalls_impl(registered)?;
+
+        Ok(ax)
+    }
+}
+
+impl Axecutor {
+    // Replaces the state with the one saved in `bytes`, keeping hooks
+    pub(crate) fn load_session(&mut self, bytes: &[u8]) -> Result<(), AxError> {
+        let session = read_session(bytes)?;
+        self.apply_session(session);
+
+        Ok(())
+    }
+
+    pub(crate) fn apply_session(&mut self, session: LoadedSession) {
+        self.stack_top = session.stack_top;
+        self.code_end_addr = session.code_end_addr;
+        self.state = session.state;
+        self.symbol_table = session.symbol_table;
+        self.symbol_table.reindex();
+        self.checkpoints.clear();
+    }
+}
+
+// Reads a state saved by `save_to_bytes` without changing any axecutor
+pub(crate) fn read_session(bytes: &[u8]) -> Result<LoadedSession, AxError> {
+    if bytes.len() < STATE_MAGIC.len() + 4 || &bytes[..STATE_MAGIC.len()] != STATE_MAGIC {
+        return Err(invalid_state("not a saved ax state".to_string()));
+    }
+
+    let (version, body) = bytes[STATE_MAGIC.len()..].split_at(4);
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != STATE_FORMAT_VERSION {
+        return Err(AxError::from(format!(
+                "Cannot load state: it was saved in format version {version}, but this version of ax only supports version {STATE_FORMAT_VERSION}"
+            ))
+            .with_kind(ErrorKind::StateVersionMismatch {
+                found: version,
+                expected: STATE_FORMAT_VERSION,
+            }));
+    }
+
+    // Lengths in the state are not trusted to allocate more than the state itself could contain
+    bincode::DefaultOptions::new()
+        .with_limit(bytes.len() as u64)
+        .deserialize(body)
+        .map_err(|e| invalid_state(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::syscalls::Syscall;
+    use crate::helpers::tests::test_async;
+    use crate::state::memory::AreaData;
+    use crate::state::registers::SupportedRegister::*;
+
+    test_async![save_and_load_resumes_execution; async {
+        let binary = include_bytes!("../../testdata/c_loop.bin");
+        let mut ax = Axecutor::from_binary(binary).expect("Failed to parse binary");
+        ax.handle_syscalls(vec![Syscall::Exit, Syscall::Pipe])
+            .expect("Failed to add syscall handlers");
+        ax.init_stack(0x100000).expect("Failed to setup stack");
+        ax.state.syscalls.pipe_contents.insert(1030, b"pipe contents".to_vec());
+
+        ax.add_breakpoint(0x401036).unwrap();
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x401036));
+
+        let bytes = ax.save_to_bytes().expect("Failed to save state");
+        // The stack is mostly zeroes, which are not stored
+        assert!(bytes.len() < 0x10000, "Saved state is {} bytes", bytes.len());
+
+        let mut loaded = Axecutor::load_from_bytes(&bytes).expect("Failed to load state");
+        assert_eq!(loaded.state.registers, ax.state.registers);
+        assert_eq!(loaded.state.memory.len(), ax.state.memory.len());
+        assert_eq!(loaded.state.syscalls, ax.state.syscalls);
+        assert_eq!(loaded.reg_read_64(RIP).unwrap(), 0x401036);
+        assert_eq!(loaded.resolve_symbol(0x401000), Some("sys_exit".to_string()));
+
+        // Both run to the same end, which needs the exit syscall handler
+        ax.execute().await.expect("Failed to execute");
+        loaded.execute().await.expect("Failed to execute loaded state");
+        assert_eq!(loaded.reg_read_64(RDI).unwrap(), ax.reg_read_64(RDI).unwrap());
+        assert_eq!(loaded.state.executed_instructions_count, ax.state.executed_instructions_count);
+    }];
+
+    test_async![invalid_states; async {
+        let ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        let mut bytes = ax.save_to_bytes().unwrap();
+
+        let err = Axecutor::load_from_bytes(&bytes[..bytes.len() - 1]).expect_err("Truncated state");
+        assert_eq!(*err.kind(), ErrorKind::InvalidState);
+        let err = Axecutor::load_from_bytes(b"\x7fELF").expect_err("Not a state");
+        assert_eq!(*err.kind(), ErrorKind::InvalidState);
+
+        bytes[8..12].copy_from_slice(&(STATE_FORMAT_VERSION + 1).to_le_bytes());
+        let err = Axecutor::load_from_bytes(&bytes).expect_err("Future version");
+        assert_eq!(
+            *err.kind(),
+            ErrorKind::StateVersionMismatch { found: STATE_FORMAT_VERSION + 1, expected: STATE_FORMAT_VERSION }
+        );
+    }];
+
+    test_async![inconsistent_memory_is_rejected; async {
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x2000, 0x1000).unwrap();
+        let loaded = Axecutor::load_from_bytes(&ax.save_to_bytes().unwrap()).expect("Failed to load state");
+        // The name index is rebuilt instead of being loaded
+        assert_eq!(loaded.symbol_table.by_name("_start").map(|s| s.address), Some(0x1000));
+
+        let invalid = [
+            // Length doesn't match the data
+            (0x3000, 0x1000, 0x800),
+            // Overlaps the code
+            (0x800, 0x1000, 0x1000),
+        ];
+        for (start, length, data_length) in invalid {
+            let mut ax = ax.clone();
+            let mut area = ax.state.memory[1].clone();
+            area.start = start;
+            area.length = length;
+            area.data = AreaData::zeroed(data_length);
+            ax.state.memory.push(area);
+
+            let err = Axecutor::load_from_bytes(&ax.save_to_bytes().unwrap()).expect_err("Invalid memory area");
+            assert_eq!(*err.kind(), ErrorKind::InvalidState, "{err}");
+        }
+    }];
+}
@@ -0,0 +1,129 @@
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+const CHUNK_SIZE: usize = 256;
+
+// A list that only grows or shrinks at its end, like the trace or the syscall log, and is cheap to clone for snapshots and checkpoints.
+// Full chunks are shared between clones, so a clone only copies the chunk list and the entries after the last full chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<T>", into = "Vec<T>")]
+pub(crate) struct SharedLog<T: Clone> {
+    chunks: Vec<Rc<Vec<T>>>,
+    // Only empty if the whole log is, so the last entry can always be changed without copying a chunk
+    tail: Vec<T>,
+}
+
+impl<T: Clone> Default for SharedLog<T> {
+    fn default() -> Self {
+        Self {
+            chunks: Vec::new(),
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> SharedLog<T> {
+    pub(crate) fn push(&mut self, value: T) {
+        if self.tail.len() == CHUNK_SIZE {
+            let full = std::mem::replace(&mut self.tail, Vec::with_capacity(CHUNK_SIZE));
+            self.chunks.push(Rc::new(full));
+        }
+        self.tail.push(value);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        let value = self.tail.pop();
+        if self.tail.is_empty() {
+            if let Some(chunk) = self.chunks.pop() {
+                self.tail = Rc::unwrap_or_clone(chunk);
+            }
+        }
+        value
+    }
+
+    pub(crate) fn last_mut(&mut self) -> Option<&mut T> {
+        self.tail.last_mut()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter())
+            .chain(self.tail.iter())
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+#[cfg(any(test, target_arch = "wasm32"))]
+impl<T: Clone + PartialEq> SharedLog<T> {
+    // Shares the chunks whose entries are the same as in `old` again, e.g. after the log was rebuilt from a serialized copy
+    pub(crate) fn share_unchanged(&mut self, old: &SharedLog<T>) {
+        for (new, old) in self.chunks.iter_mut().zip(old.chunks.iter()) {
+            if !Rc::ptr_eq(new, old) && new == old {
+                *new = old.clone();
+            }
+        }
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for SharedLog<T> {
+    fn from(values: Vec<T>) -> Self {
+        let mut log = Self::default();
+        for value in values {
+            log.push(value);
+        }
+        log
+    }
+}
+
+impl<T: Clone> From<SharedLog<T>> for Vec<T> {
+    fn from(log: SharedLog<T>) -> Self {
+        log.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::test_async;
+
+    test_async![clones_share_full_chunks; async {
+        let mut log = SharedLog::default();
+        for i in 0..(2 * CHUNK_SIZE + 1) {
+            log.push(i);
+        }
+        let clone = log.clone();
+        assert!(Rc::ptr_eq(&log.chunks[1], &clone.chunks[1]));
+
+        // Popping into a shared chunk copies it instead of changing the clone
+        for _ in 0..2 {
+            log.pop();
+        }
+        *log.last_mut().unwrap() = 1000;
+        log.push(1001);
+
+        assert_eq!(log.iter().count(), 2 * CHUNK_SIZE);
+        assert_eq!(log.iter().nth(2 * CHUNK_SIZE - 2), Some(&1000));
+        assert_eq!(clone.to_vec(), (0..(2 * CHUNK_SIZE + 1)).collect::<Vec<_>>());
+
+        while log.pop().is_some() {}
+        assert!(log.chunks.is_empty());
+        assert_eq!(log.iter().count(), 0);
+    }];
+
+    test_async![unchanged_chunks_are_shared_again; async {
+        let log = SharedLog::from((0..(2 * CHUNK_SIZE + 1)).collect::<Vec<_>>());
+        let mut values = log.to_vec();
+        values[0] = 1000;
+        let mut rebuilt = SharedLog::from(values);
+
+        rebuilt.share_unchanged(&log);
+        assert!(!Rc::ptr_eq(&rebuilt.chunks[0], &log.chunks[0]));
+        assert!(Rc::ptr_eq(&rebuilt.chunks[1], &log.chunks[1]));
+        assert_eq!(rebuilt.iter().next(), Some(&1000));
+    }];
+}
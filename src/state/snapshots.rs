@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::{Axecutor, MachineState};
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::AxError;
+
+/// Identifies a snapshot taken with `snapshot`
+pub type SnapshotId = u32;
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    stack_top: u64,
+    code_end_addr: u64,
+    state: MachineState,
+}
+
+// Snapshots share memory pages and full chunks of the trace and syscall log with the machine state, so taking one only copies
+// the page and chunk lists. Pages are copied when they are first written to after a snapshot or restore
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Snapshots {
+    next_id: SnapshotId,
+    snapshots: HashMap<SnapshotId, Snapshot>,
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Takes a snapshot of the current machine state, i.e. registers, flags, memory, syscall and signal state, the call stack and trace.
+    /// The returned id can be passed to `restore` any number of times to rewind to this point.
+    /// Snapshots are cheap: memory is shared with the running state and only copied page-wise when it is written to,
+    /// and the trace and syscall log share all but their most recent entries.
+    /// Hooks, breakpoints and symbols are not part of snapshots.
+    pub fn snapshot(&mut self) -> SnapshotId {
+        let id = self.snapshots.next_id;
+        self.snapshots.next_id += 1;
+
+        self.snapshots.snapshots.insert(
+            id,
+            Snapshot {
+                stack_top: self.stack_top,
+                code_end_addr: self.code_end_addr,
+                state: self.state.clone(),
+            },
+        );
+        debug_log!("Took snapshot {}", id);
+
+        id
+    }
+
+    /// Restores the machine state to the snapshot with the given id. The snapshot stays available, so it can be restored again.
+    /// Hooks and breakpoints stay registered; note that state kept by hook functions themselves is not restored.
+    pub fn restore(&mut self, id: SnapshotId) -> Result<(), AxError> {
+        let snapshot = self
+            .snapshots
+            .snapshots
+            .get(&id)
+            .ok_or_else(|| AxError::from(format!("Cannot restore unknown snapshot {id}")))?;
+
+        self.stack_top = snapshot.stack_top;
+        self.code_end_addr = snapshot.code_end_addr;
+        self.state = snapshot.state.clone();
+        // Checkpoints belong to the execution that is left
+        self.checkpoints.clear();
+        debug_log!("Restored snapshot {}", id);
+
+        Ok(())
+    }
+
+    /// Deletes the snapshot with the given id, freeing memory only it still references. Returns whether the snapshot existed
+    pub fn delete_snapshot(&mut self, id: SnapshotId) -> bool {
+        self.snapshots.snapshots.remove(&id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::tests::{c_loop, test_async};
+    use crate::state::memory::PAGE_SIZE;
+    use crate::state::registers::SupportedRegister::*;
+
+    test_async![restore_rewinds_execution; async {
+        let mut ax = c_loop();
+
+        ax.add_breakpoint(0x401036).unwrap();
+        assert_eq!(ax.run_until_breakpoint().await.unwrap(), Some(0x401036));
+        let rbp = ax.reg_read_64(RBP).unwrap();
+        let counter = ax.mem_read_32(rbp - 8).unwrap();
+
+        let id = ax.snapshot();
+        let count = ax.state.executed_instructions_count;
+
+        // Run to the end a few times, rewinding to the breakpoint each time
+        for _ in 0..3 {
+            ax.remove_breakpoint(0x401036).unwrap();
+            assert_eq!(ax.run_until_breakpoint().await.unwrap(), None);
+            assert!(ax.state.finished);
+            assert_ne!(ax.mem_read_32(rbp - 8).unwrap(), counter);
+
+            ax.restore(id).unwrap();
+            assert!(!ax.state.finished);
+            assert_eq!(ax.reg_read_64(RIP).unwrap(), 0x401036);
+            assert_eq!(ax.reg_read_64(RBP).unwrap(), rbp);
+            assert_eq!(ax.mem_read_32(rbp - 8).unwrap(), counter);
+            assert_eq!(ax.state.executed_instructions_count, count);
+        }
+
+        assert!(ax.delete_snapshot(id));
+        assert!(!ax.delete_snapshot(id));
+        ax.restore(id).expect_err("Deleted snapshots cannot be restored");
+    }];
+
+    test_async![memory_is_copied_on_write; async {
+        let mut ax = Axecutor::new(&[0x90], 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x10000, 4 * PAGE_SIZE).unwrap();
+        ax.mem_write_64(0x10000, 0x1122334455667788).unwrap();
+
+        let first = ax.snapshot();
+        ax.mem_write_64(0x11ffc, 0xaabbccddeeff0011).unwrap();
+        let second = ax.snapshot();
+        ax.mem_write_8(0x10000, 0xff).unwrap();
+
+        // Only the written pages were copied, the others are still shared with the snapshots
+        let area = &ax.state.memory[1].data;
+        assert_eq!(area.shares_pages_with(&ax.snapshots.snapshots[&first].state.memory[1].data), 1);
+        assert_eq!(area.shares_pages_with(&ax.snapshots.snapshots[&second].state.memory[1].data), 3);
+
+        ax.restore(first).unwrap();
+        assert_eq!(ax.mem_read_64(0x10000).unwrap(), 0x1122334455667788);
+        assert_eq!(ax.mem_read_64(0x11ffc).unwrap(), 0);
+
+        ax.restore(second).unwrap();
+        assert_eq!(ax.mem_read_64(0x10000).unwrap(), 0x1122334455667788);
+        assert_eq!(ax.mem_read_64(0x11ffc).unwrap(), 0xaabbccddeeff0011);
+
+        // Areas created after a snapshot are gone after restoring it
+        ax.mem_init_zero(0x20000, PAGE_SIZE).unwrap();
+        ax.restore(first).unwrap();
+        ax.mem_read_8(0x20000).expect_err("Area should not exist in snapshot");
+    }];
+}
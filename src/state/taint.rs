@@ -0,0 +1,751 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+use iced_x86::{
+    FlowControl, Instruction, InstructionInfoFactory, Mnemonic, OpAccess, OpKind, Register,
+};
+use serde::Serialize;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::axecutor::Axecutor;
+use crate::helpers::debug::debug_log;
+use crate::helpers::errors::{AxError, ErrorKind};
+use crate::helpers::macros::NO_WRITEBACK;
+use crate::helpers::operand::Operand;
+use crate::state::flags::FLAGS_UNAFFECTED;
+#[cfg(all(target_arch = "wasm32", not(test)))]
+use crate::state::hooks::run_function;
+use crate::state::hooks::{
+    call_native_hook, native_hook, sort_hooks, HookHandle, HookOrder, NativeHook,
+};
+use crate::state::memory_hooks::reads_memory;
+use crate::state::registers::{SupportedRegister, REGISTER_TO_QWORD};
+
+// Registers holding the syscall number and arguments
+const SYSCALL_REGISTERS: [SupportedRegister; 7] = [
+    SupportedRegister::RAX,
+    SupportedRegister::RDI,
+    SupportedRegister::RSI,
+    SupportedRegister::RDX,
+    SupportedRegister::R10,
+    SupportedRegister::R8,
+    SupportedRegister::R9,
+];
+
+/// Where tainted data was used
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum TaintSink {
+    /// An indirect jump or call or a return continues at a tainted address
+    InstructionPointer,
+    /// A syscall is executed with a tainted number or argument
+    SyscallArgument,
+    /// A conditional jump depends on tainted flags or registers
+    ConditionalJump,
+}
+
+/// Tainted data reaching a sink, passed to taint hooks before the instruction using it is executed
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TaintEvent {
+    pub sink: TaintSink,
+    /// Address of the instruction using the tainted data
+    pub rip: u64,
+    /// Tags of the data, bit n is set for tag n
+    pub tags: u64,
+    /// The syscall argument register, or the register holding the target of an indirect jump or call
+    pub register: Option<SupportedRegister>,
+}
+
+impl Display for TaintEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} at {:#x} with tags {:#x}",
+            self.sink, self.rip, self.tags
+        )?;
+        if let Some(register) = self.register {
+            write!(f, " in {register:?}")?;
+        }
+        Ok(())
+    }
+}
+
+pub type TaintHandler = dyn FnMut(&mut Axecutor, &TaintEvent) -> Result<(), Box<dyn Error>>;
+
+#[derive(Clone)]
+enum TaintHandlerFunction {
+    Native(NativeHook<TaintHandler>),
+    #[cfg(all(target_arch = "wasm32", not(test)))]
+    Js(js_sys::Function),
+}
+
+#[derive(Clone)]
+pub(crate) struct TaintHook {
+    pub(crate) order: HookOrder,
+    function: TaintHandlerFunction,
+}
+
+impl Debug for TaintHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaintHook")
+            .field("handle", &self.order.handle)
+            .field("priority", &self.order.priority)
+            .finish()
+    }
+}
+
+// How taint flows through one instruction: everything it writes gets the tags of everything it reads
+#[derive(Debug, Clone, Default)]
+struct TaintFlow {
+    sources: u64,
+    // Written registers, and whether the write replaces the whole register
+    registers: Vec<(Register, bool)>,
+    flags: bool,
+    // Whether tags of memory read by the instruction are sources
+    memory: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TaintState {
+    // Tags by byte address, untainted bytes have no entry
+    memory: HashMap<u64, u64>,
+    // Tags by 64-bit general purpose register, so e.g. AL and RAX share theirs
+    registers: HashMap<SupportedRegister, u64>,
+    flags: u64,
+    // Tag for bytes returned by read syscalls
+    read_tag: Option<u8>,
+    // Buffer of the read syscall that is currently executed
+    pending_read: Option<u64>,
+    in_instruction: bool,
+    // Whether the instruction reads memory, see `reads_memory`
+    instruction_reads: bool,
+    // Tags of the memory read by the current instruction. Memory is read through &Axecutor, so this needs interior mutability
+    read_tags: Cell<u64>,
+    // Memory written by the current instruction
+    written: Vec<(u64, u64)>,
+    // Set by the ALU helpers, which know the flow better than the generic instruction info
+    flow: Option<TaintFlow>,
+}
+
+fn full_register(register: Register) -> Option<SupportedRegister> {
+    if register.is_gpr() && register.full_register() != Register::RSP {
+        Some(register.full_register().into())
+    } else {
+        None
+    }
+}
+
+fn tag_bit(tag: u8) -> Result<u64, AxError> {
+    if tag >= 64 {
+        return Err(AxError::from(format!(
+            "Invalid taint tag {tag}, tags must be less than 64"
+        )));
+    }
+    Ok(1 << tag)
+}
+
+impl TaintState {
+    fn memory_tags(&self, address: u64, length: u64) -> u64 {
+        (0..length)
+            .filter_map(|offset| self.memory.get(&address.wrapping_add(offset)))
+            .fold(0, |tags, byte| tags | byte)
+    }
+
+    fn set_memory(&mut self, address: u64, length: u64, tags: u64) {
+        for offset in 0..length {
+            let byte = address.wrapping_add(offset);
+            if tags == 0 {
+                self.memory.remove(&byte);
+            } else {
+                self.memory.insert(byte, tags);
+            }
+        }
+    }
+
+    fn register_tags(&self, register: Register) -> u64 {
+        full_register(register)
+            .and_then(|r| self.registers.get(&r).copied())
+            .unwrap_or(0)
+    }
+
+    fn set_register(&mut self, register: Register, tags: u64, replace: bool) {
+        let Some(full) = full_register(register) else {
+            return;
+        };
+        let tags = if replace {
+            tags
+        } else {
+            tags | self.registers.get(&full).copied().unwrap_or(0)
+        };
+        if tags == 0 {
+            self.registers.remove(&full);
+        } else {
+            self.registers.insert(full, tags);
+        }
+    }
+
+    fn alu_flow(&self, i: &Instruction, flags_to_set: u64) -> TaintFlow {
+        let mut flow = TaintFlow {
+            registers: Vec::new(),
+            flags: flags_to_set != FLAGS_UNAFFECTED,
+            ..Default::default()
+        };
+        if flags_to_set & NO_WRITEBACK == 0 && i.op0_kind() == OpKind::Register {
+            flow.registers
+                .push((i.op0_register(), i.op0_register().size() >= 4));
+        }
+
+        // xor eax, eax and sub eax, eax always result in zero
+        let zeroing = matches!(i.mnemonic(), Mnemonic::Xor | Mnemonic::Sub)
+            && i.op_count() == 2
+            && i.op0_kind() == OpKind::Register
+            && i.op1_kind() == OpKind::Register
+            && i.op0_register() == i.op1_register();
+        if zeroing {
+            return flow;
+        }
+
+        let op0_access = InstructionInfoFactory::new().info(i).op0_access();
+        for idx in 0..i.op_count() {
+            if i.op_kind(idx) == OpKind::Register && (idx > 0 || op0_access != OpAccess::Write) {
+                flow.sources |= self.register_tags(i.op_register(idx));
+            }
+        }
+        if i.rflags_read() != 0 {
+            flow.sources |= self.flags;
+        }
+        flow.memory = true;
+        flow
+    }
+
+    fn generic_flow(&self, i: &Instruction) -> TaintFlow {
+        let mut flow = TaintFlow {
+            flags: i.rflags_modified() != 0,
+            memory: true,
+            ..Default::default()
+        };
+        // Taint of pointers doesn't flow into the data they point to
+        let address_registers = [i.memory_base(), i.memory_index()];
+
+        let mut factory = InstructionInfoFactory::new();
+        for used in factory.info(i).used_registers() {
+            let register = used.register();
+            let (read, write) = match used.access() {
+                OpAccess::Read | OpAccess::CondRead => (true, false),
+                OpAccess::Write | OpAccess::CondWrite => (false, true),
+                OpAccess::ReadWrite | OpAccess::ReadCondWrite => (true, true),
+                _ => (false, false),
+            };
+            if read && !address_registers.contains(&register) {
+                flow.sources |= self.register_tags(register);
+            }
+            if write {
+                // Registers that may not be written keep their tags
+                let replace = used.access() == OpAccess::Write && register.size() >= 4;
+                flow.registers.push((register, replace));
+            }
+        }
+        if i.rflags_read() != 0 {
+            flow.sources |= self.flags;
+        }
+        flow
+    }
+
+    fn apply(&mut self, flow: TaintFlow) {
+        let mut sources = flow.sources;
+        if flow.memory && self.instruction_reads {
+            sources |= self.read_tags.get();
+        }
+
+        for (register, replace) in flow.registers {
+            self.set_register(register, sources, replace);
+        }
+        for (address, length) in std::mem::take(&mut self.written) {
+            self.set_memory(address, length, sources);
+        }
+        if flow.flags {
+            self.flags = sources;
+        }
+    }
+}
+
+impl Axecutor {
+    fn taint(&mut self) -> Result<&mut TaintState, AxError> {
+        self.taint.as_mut().ok_or_else(Self::taint_disabled)
+    }
+
+    fn taint_state(&self) -> Result<&TaintState, AxError> {
+        self.taint.as_ref().ok_or_else(Self::taint_disabled)
+    }
+
+    fn taint_disabled() -> AxError {
+        AxError::from("Taint tracking is not enabled, call enable_taint_tracking first")
+    }
+
+    fn taint_qword(register: SupportedRegister) -> Result<SupportedRegister, AxError> {
+        match REGISTER_TO_QWORD.get(&register) {
+            Some(&qword) if qword != SupportedRegister::RSP => Ok(qword),
+            _ => Err(AxError::from(format!(
+                "Taint is not tracked for register {register:?}"
+            ))),
+        }
+    }
+
+    // Called by mem_read_bytes, tags of the read memory flow into what the instruction writes
+    pub(crate) fn taint_read(&self, address: u64, length: u64) {
+        if let Some(taint) = self.taint.as_ref() {
+            if taint.in_instruction {
+                let tags = taint.memory_tags(address, length);
+                taint.read_tags.set(taint.read_tags.get() | tags);
+            }
+        }
+    }
+
+    // Called after memory was written, it is untainted until the instruction writing it has finished
+    pub(crate) fn taint_written(&mut self, address: u64, length: u64) {
+        if let Some(taint) = self.taint.as_mut() {
+            taint.set_memory(address, length, 0);
+            if taint.in_instruction {
+                taint.written.push((address, length));
+            }
+        }
+    }
+
+    // Wraps the ALU helpers of the calculate_* macros, which know how taint flows through the instruction
+    pub(crate) fn taint_alu(
+        &mut self,
+        i: Instruction,
+        flags_to_set: u64,
+        calculate: impl FnOnce(&mut Axecutor) -> Result<(), AxError>,
+    ) -> Result<(), AxError> {
+        if let Some(taint) = self.taint.as_mut() {
+            if taint.in_instruction {
+                taint.flow = Some(taint.alu_flow(&i, flags_to_set));
+            }
+        }
+        calculate(self)
+    }
+
+    // Called right before an instruction is executed
+    pub(crate) fn taint_begin(&mut self, instr: &Instruction) {
+        if let Some(taint) = self.taint.as_mut() {
+            taint.in_instruction = true;
+            taint.instruction_reads = reads_memory(instr);
+            taint.read_tags.set(0);
+            taint.written.clear();
+            taint.flow = None;
+        }
+    }
+
+    // Called right after an instruction was executed, propagates taint if it succeeded
+    pub(crate) fn taint_end(
+        &mut self,
+        instr: &Instruction,
+        succeeded: bool,
+    ) -> Result<(), AxError> {
+        let Some(taint) = self.taint.as_mut() else {
+            return Ok(());
+        };
+        taint.in_instruction = false;
+        if !succeeded {
+            taint.pending_read = None;
+            return Ok(());
+        }
+
+        if instr.mnemonic() != Mnemonic::Syscall {
+            let flow = match taint.flow.take() {
+                Some(flow) => flow,
+                None => taint.generic_flow(instr),
+            };
+            taint.apply(flow);
+            return Ok(());
+        }
+
+        // Syscall results are untainted, except for data returned by read
+        for register in [Register::RAX, Register::RCX, Register::R11] {
+            taint.set_register(register, 0, true);
+        }
+        if let Some(buffer) = taint.pending_read.take() {
+            let result = self.reg_read_64(SupportedRegister::RAX)? as i64;
+            let taint = self.taint()?;
+            if let (Some(tag), true) = (taint.read_tag, result > 0) {
+                debug_log!(
+                    "Tainting {} bytes read to {:#x} with tag {}",
+                    result,
+                    buffer,
+                    tag
+                );
+                taint.set_memory(buffer, result as u64, 1 << tag);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Called before an instruction is executed, runs taint hooks if it uses tainted data in a sink
+    pub(crate) async fn taint_check(&mut self, instr: &Instruction) -> Result<(), AxError> {
+        let Some(taint) = self.taint.as_ref() else {
+            return Ok(());
+        };
+
+        let event = |sink, tags, register| TaintEvent {
+            sink,
+            rip: instr.ip(),
+            tags,
+            register,
+        };
+        let mut events = Vec::new();
+        match instr.flow_control() {
+            FlowControl::ConditionalBranch => {
+                let mut tags = taint.flags;
+                for used in InstructionInfoFactory::new().info(instr).used_registers() {
+                    tags |= taint.register_tags(used.register());
+                }
+                events.push(event(TaintSink::ConditionalJump, tags, None));
+            }
+            FlowControl::IndirectBranch | FlowControl::IndirectCall => {
+                match self.instruction_operand(*instr, 0)? {
+                    Operand::Register(register) => events.push(event(
+                        TaintSink::InstructionPointer,
+                        taint.register_tags(instr.op0_register()),
+                        Some(register),
+                    )),
+                    Operand::Memory(m) => events.push(event(
+                        TaintSink::InstructionPointer,
+                        taint.memory_tags(self.mem_addr(m), 8),
+                        None,
+                    )),
+                    _ => {}
+                }
+            }
+            FlowControl::Return => {
+                events.push(event(
+                    TaintSink::InstructionPointer,
+                    taint.memory_tags(self.return_address_slot()?, 8),
+                    None,
+                ));
+            }
+            _ => {}
+        }
+
+        let mut pending_read = None;
+        if instr.mnemonic() == Mnemonic::Syscall {
+            for register in SYSCALL_REGISTERS {
+                let tags = taint.registers.get(&register).copied().unwrap_or(0);
+                events.push(event(TaintSink::SyscallArgument, tags, Some(register)));
+            }
+            if taint.read_tag.is_some() && self.reg_read_64(SupportedRegister::RAX)? == 0 {
+                pending_read = Some(self.reg_read_64(SupportedRegister::RSI)?);
+            }
+        }
+        self.taint()?.pending_read = pending_read;
+
+        events.retain(|e| e.tags != 0);
+        if events.is_empty() || self.hooks.taint_hooks.is_empty() {
+            return Ok(());
+        }
+
+        let hooks = self.hooks.taint_hooks.clone();
+        self.hooks.running = true;
+        let result = self.run_taint_hooks(&hooks, &events).await;
+        self.hooks.running = false;
+        result.map_err(|e| {
+            AxError::from(format!("running taint hooks for {instr}: {e}"))
+                .with_kind(ErrorKind::HookError)
+        })
+    }
+
+    async fn run_taint_hooks(
+        &mut self,
+        hooks: &[TaintHook],
+        events: &[TaintEvent],
+    ) -> Result<(), AxError> {
+        for event in events {
+            debug_log!("Tainted data reached sink: {}", event);
+            for hook in hooks {
+                match &hook.function {
+                    TaintHandlerFunction::Native(f) => call_native_hook(f, |f| f(self, event))?
+                        .map_err(|e| AxError::from(e.to_string()))?,
+                    #[cfg(all(target_arch = "wasm32", not(test)))]
+                    TaintHandlerFunction::Js(f) => {
+                        run_function(self, f.clone(), vec![(*event).into()]).await?;
+                    }
+                }
+
+                if self.state.finished {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_taint_hook(&mut self, function: TaintHandlerFunction) -> Result<HookHandle, AxError> {
+        if self.hooks.running {
+            return Err(AxError::from(
+                "Cannot add taint hooks while a hook is running",
+            ));
+        }
+
+        let hook = TaintHook {
+            order: self.hooks.next_order(),
+            function,
+        };
+        debug_log!("Adding taint hook {:?}", hook);
+        let handle = hook.order.handle;
+        self.hooks.taint_hooks.push(hook);
+        sort_hooks(&mut self.hooks.taint_hooks, |h| h.order);
+        Ok(handle)
+    }
+
+    /// Register a function that is called when tainted data reaches a sink: the target of an indirect jump, call or return,
+    /// a syscall number or argument, or the flags or registers a conditional jump depends on.
+    /// It is called before the instruction is executed, once per sink, and may stop execution using `stop`.
+    /// Hooks run in the order described at `set_hook_priority`.
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_taint_native(
+        &mut self,
+        handler: impl FnMut(&mut Axecutor, &TaintEvent) -> Result<(), Box<dyn Error>> + 'static,
+    ) -> Result<HookHandle, AxError> {
+        self.add_taint_hook(TaintHandlerFunction::Native(native_hook(Box::new(handler))))
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(test)))]
+#[wasm_bindgen]
+impl Axecutor {
+    /// Register a function that is called when tainted data reaches a sink: the target of an indirect jump, call or return,
+    /// a syscall number or argument, or the flags or registers a conditional jump depends on.
+    /// The function will be called with the Axecutor object and a TaintEvent as arguments before the instruction is executed.
+    /// Like mnemonic hooks, it may be sync or async and *MUST* return the result of instance.commit(), instance.stop() or instance.unchanged().
+    /// Returns a handle that can be passed to `remove_hook`.
+    pub fn hook_taint(&mut self, handler: js_sys::Function) -> Result<HookHandle, AxError> {
+        self.add_taint_hook(TaintHandlerFunction::Js(handler))
+    }
+}
+
+#[wasm_bindgen]
+impl Axecutor {
+    /// Starts tracking how data flows through registers, memory and flags, dropping all taint tracked before.
+    /// Tainted data is labeled with up to 64 tags. Data is tainted using `taint_memory`, `taint_register` or `taint_read_syscalls`,
+    /// and taint hooks are called when it reaches a sink.
+    /// XMM registers and implicit flows, e.g. through conditional jumps or pointers, are not tracked.
+    pub fn enable_taint_tracking(&mut self) {
+        debug_log!("Calling Axecutor::enable_taint_tracking");
+        self.taint = Some(TaintState::default());
+    }
+
+    pub fn disable_taint_tracking(&mut self) {
+        debug_log!("Calling Axecutor::disable_taint_tracking");
+        self.taint = None;
+    }
+
+    /// Adds `tag` (0-63) to `length` bytes at `address`
+    pub fn taint_memory(&mut self, address: u64, length: u64, tag: u8) -> Result<(), AxError> {
+        debug_log!(
+            "Calling Axecutor::taint_memory, address={:#x}, length={}, tag={}",
+            address,
+            length,
+            tag
+        );
+        let bit = tag_bit(tag)?;
+        let taint = self.taint()?;
+        for offset in 0..length {
+            *taint
+                .memory
+                .entry(address.wrapping_add(offset))
+                .or_insert(0) |= bit;
+        }
+        Ok(())
+    }
+
+    /// Removes all tags from `length` bytes at `address`
+    pub fn untaint_memory(&mut self, address: u64, length: u64) -> Result<(), AxError> {
+        debug_log!(
+            "Calling Axecutor::untaint_memory, address={:#x}, length={}",
+            address,
+            length
+        );
+        self.taint()?.set_memory(address, length, 0);
+        Ok(())
+    }
+
+    /// Adds `tag` (0-63) to a general purpose register. Taint is tracked per 64-bit register, so e.g. tainting AL also taints RAX
+    pub fn taint_register(&mut self, register: SupportedRegister, tag: u8) -> Result<(), AxError> {
+        debug_log!(
+            "Calling Axecutor::taint_register, register={:?}, tag={}",
+            register,
+            tag
+        );
+        let (qword, bit) = (Self::taint_qword(register)?, tag_bit(tag)?);
+        *self.taint()?.registers.entry(qword).or_insert(0) |= bit;
+        Ok(())
+    }
+
+    /// Removes all tags from a general purpose register
+    pub fn untaint_register(&mut self, register: SupportedRegister) -> Result<(), AxError> {
+        let qword = Self::taint_qword(register)?;
+        self.taint()?.registers.remove(&qword);
+        Ok(())
+    }
+
+    /// Taints the bytes returned by each `read` syscall with `tag`, or stops doing so if it is undefined
+    pub fn taint_read_syscalls(&mut self, tag: Option<u8>) -> Result<(), AxError> {
+        debug_log!("Calling Axecutor::taint_read_syscalls, tag={:?}", tag);
+        if let Some(tag) = tag {
+            tag_bit(tag)?;
+        }
+        self.taint()?.read_tag = tag;
+        Ok(())
+    }
+
+    /// Tags of each of the `length` bytes at `address`
+    pub fn memory_taint(&self, address: u64, length: u64) -> Result<Vec<u64>, AxError> {
+        let taint = self.taint_state()?;
+        Ok((0..length)
+            .map(|offset| taint.memory_tags(address.wrapping_add(offset), 1))
+            .collect())
+    }
+
+    /// Tags of a general purpose register
+    pub fn register_taint(&self, register: SupportedRegister) -> Result<u64, AxError> {
+        let qword = Self::taint_qword(register)?;
+        Ok(self
+            .taint_state()?
+            .registers
+            .get(&qword)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    /// Tags of the flags, i.e. of the data the last instruction that changed them depended on
+    pub fn flags_taint(&self) -> Result<u64, AxError> {
+        Ok(self.taint_state()?.flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::syscalls::SyscallResult;
+    use crate::helpers::tests::test_async;
+    use crate::state::registers::SupportedRegister::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn record_events(ax: &mut Axecutor) -> Rc<RefCell<Vec<TaintEvent>>> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        ax.hook_taint_native(move |_, event| {
+            recorded.borrow_mut().push(*event);
+            Ok(())
+        })
+        .unwrap();
+        events
+    }
+
+    test_async![taint_propagates_through_alu; async {
+        let code = [
+            0x8a, 0x07, // mov al, byte ptr [rdi]
+            0x48, 0x01, 0xc3, // add rbx, rax
+            0x31, 0xc9, // xor ecx, ecx
+            0x3c, 0x41, // cmp al, 0x41
+            0x75, 0x00, // jne 0x100b
+            0x48, 0x89, 0x1e, // mov [rsi], rbx
+            0x31, 0xdb, // xor ebx, ebx
+            0x90, // nop
+        ];
+        let mut ax = Axecutor::new(&code, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x8000, 0x200).unwrap();
+        ax.reg_write_64(RDI, 0x8000).unwrap();
+        ax.reg_write_64(RSI, 0x8100).unwrap();
+        ax.taint_memory(0, 1, 0).expect_err("Taint tracking is not enabled");
+
+        ax.enable_taint_tracking();
+        let events = record_events(&mut ax);
+        ax.taint_memory(0x8000, 1, 3).unwrap();
+        ax.taint_register(CL, 5).unwrap();
+        ax.taint_memory(0x8000, 1, 64).expect_err("Invalid tag");
+        ax.taint_register(RSP, 1).expect_err("RSP is not tracked");
+        assert_eq!(ax.register_taint(RCX).unwrap(), 1 << 5);
+
+        for _ in 0..5 {
+            ax.step().await.unwrap();
+        }
+        assert_eq!(ax.register_taint(RAX).unwrap(), 1 << 3);
+        assert_eq!(ax.register_taint(RBX).unwrap(), 1 << 3);
+        assert_eq!(ax.register_taint(ECX).unwrap(), 0);
+        assert_eq!(ax.flags_taint().unwrap(), 1 << 3);
+        assert_eq!(
+            *events.borrow(),
+            vec![TaintEvent {
+                sink: TaintSink::ConditionalJump,
+                rip: 0x1009,
+                tags: 1 << 3,
+                register: None,
+            }]
+        );
+
+        ax.execute().await.unwrap();
+        assert_eq!(ax.memory_taint(0x80ff, 10).unwrap(), [0, 8, 8, 8, 8, 8, 8, 8, 8, 0]);
+        assert_eq!(ax.register_taint(RBX).unwrap(), 0);
+        assert_eq!(ax.flags_taint().unwrap(), 0);
+
+        ax.untaint_memory(0x8100, 4).unwrap();
+        assert_eq!(ax.memory_taint(0x8100, 8).unwrap(), [0, 0, 0, 0, 8, 8, 8, 8]);
+        ax.disable_taint_tracking();
+        ax.register_taint(RAX).expect_err("Taint tracking is not enabled");
+    }];
+
+    test_async![taint_read_syscalls_and_sinks; async {
+        let code = [
+            0x31, 0xc0, // xor eax, eax
+            0xba, 0x08, 0x00, 0x00, 0x00, // mov edx, 8
+            0x0f, 0x05, // syscall (read)
+            0x48, 0x8b, 0x16, // mov rdx, [rsi]
+            0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1
+            0x0f, 0x05, // syscall (write)
+            0xff, 0x26, // jmp [rsi]
+            0x90, // nop
+        ];
+        let mut ax = Axecutor::new(&code, 0x1000, 0x1000).expect("Failed to create axecutor");
+        ax.mem_init_zero(0x8000, 0x100).unwrap();
+        ax.reg_write_64(RSI, 0x8000).unwrap();
+        ax.hook_syscall_native(0, |ax, args| {
+            ax.mem_write_64(args.rsi, 0x1015)?;
+            Ok(SyscallResult::Return(args.rdx))
+        })
+        .unwrap();
+        ax.hook_syscall_native(1, |_, _| Ok(SyscallResult::Return(0))).unwrap();
+
+        ax.enable_taint_tracking();
+        ax.taint_read_syscalls(Some(1)).unwrap();
+        let events = record_events(&mut ax);
+        ax.execute().await.unwrap();
+
+        assert_eq!(ax.memory_taint(0x8000, 9).unwrap(), [2, 2, 2, 2, 2, 2, 2, 2, 0]);
+        assert_eq!(ax.register_taint(RDX).unwrap(), 2);
+        assert_eq!(ax.register_taint(RAX).unwrap(), 0);
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                TaintEvent {
+                    sink: TaintSink::SyscallArgument,
+                    rip: 0x1011,
+                    tags: 2,
+                    register: Some(RDX),
+                },
+                TaintEvent {
+                    sink: TaintSink::InstructionPointer,
+                    rip: 0x1013,
+                    tags: 2,
+                    register: None,
+                },
+            ]
+        );
+    }];
+}
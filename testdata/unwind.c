@@ -0,0 +1,27 @@
+// gcc -O1 -g -nostdlib -static -fno-pie -no-pie -Wl,--eh-frame-hdr -o unwind_c_nostdlib.bin unwind.c
+static void sys_exit(int code)
+{
+	__asm__ volatile("mov $60, %%eax\n\tsyscall" ::"D"(code));
+}
+
+__attribute__((noinline)) int level3(volatile int *p, int x)
+{
+	*p = x;
+	return x + 1;
+}
+
+__attribute__((noinline)) int level2(volatile int *p, int x)
+{
+	int r = level3(p, x * 2);
+	return r + x;
+}
+
+__attribute__((noinline)) int level1(volatile int *p, int x)
+{
+	return level2(p, x + 1) * 3;
+}
+
+void _start(void)
+{
+	sys_exit(level1((volatile int *)0, 5));
+}